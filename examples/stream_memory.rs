@@ -0,0 +1,86 @@
+//! Compares the memory streams take per item with the macro nodes they're stored in, against a
+//! map of IDs to their fields. Run it with `cargo run --release --example stream_memory`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use redis_starter_rust::stream::{ItemData, ItemId, Stream};
+
+/// Counts the bytes allocated on the heap that weren't freed yet.
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const ITEMS: u64 = 100_000;
+
+/// Bytes per item `build` keeps allocated after adding [`ITEMS`] items to what it returns.
+fn bytes_per_item<T>(build: impl FnOnce(&mut dyn FnMut() -> (ItemId, ItemData)) -> T) -> f64 {
+    let mut i = 0;
+    let mut next = || {
+        i += 1;
+        // Sensor readings, like the small items streams typically hold
+        let data = vec![
+            ("sensor".to_string(), (i % 16).to_string()),
+            (
+                "temperature".to_string(),
+                format!("{}.{}", 15 + i % 10, i % 7),
+            ),
+            ("humidity".to_string(), (40 + i % 30).to_string()),
+        ];
+        (ItemId::new(1_700_000_000_000 + i / 4, i % 4), data)
+    };
+
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let built = build(&mut next);
+    let after = ALLOCATED.load(Ordering::Relaxed);
+    drop(built);
+    (after - before) as f64 / ITEMS as f64
+}
+
+fn main() {
+    let now = SystemTime::now();
+    let nodes = bytes_per_item(|next| {
+        let mut stream = Stream::new();
+        for _ in 0..ITEMS {
+            let (id, data) = next();
+            stream.insert(id.into(), data, now).unwrap();
+        }
+        stream
+    });
+    let map = bytes_per_item(|next| {
+        let mut map = BTreeMap::new();
+        for _ in 0..ITEMS {
+            let (id, data) = next();
+            map.insert(id, data);
+        }
+        map
+    });
+
+    println!("{ITEMS} items with 3 fields each");
+    println!("macro nodes:        {nodes:>6.1} bytes per item");
+    println!("BTreeMap of fields: {map:>6.1} bytes per item");
+    println!("ratio:              {:>6.1}x", map / nodes);
+}
@@ -0,0 +1,360 @@
+use std::str::FromStr;
+
+/// Largest bit offset Redis accepts, strings are limited to 512MB.
+pub const MAX_BIT_OFFSET: u64 = (512 * 1024 * 1024 * 8) - 1;
+
+/// Unit of the optional ranges in BITCOUNT and BITPOS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeUnit {
+    Byte,
+    Bit,
+}
+
+/// Bits are addressed from the most significant bit of the first byte, like in Redis.
+pub fn get_bit(data: &[u8], offset: usize) -> bool {
+    data.get(offset / 8)
+        .is_some_and(|byte| byte & (0x80 >> (offset % 8)) != 0)
+}
+
+/// Sets the bit at `offset`, growing `data` with zeroes as needed. Returns the previous value.
+pub fn set_bit(data: &mut Vec<u8>, offset: usize, value: bool) -> bool {
+    let index = offset / 8;
+    if data.len() <= index {
+        data.resize(index + 1, 0);
+    }
+
+    let mask = 0x80 >> (offset % 8);
+    let previous = data[index] & mask != 0;
+    if value {
+        data[index] |= mask;
+    } else {
+        data[index] &= !mask;
+    }
+    previous
+}
+
+/// Turns a Redis style `start`/`end` pair, where negative values count from the end, into an
+/// inclusive range of bits. Returns `None` when the range is empty.
+pub fn bit_range(len: usize, start: i64, end: i64, unit: RangeUnit) -> Option<(usize, usize)> {
+    let total = match unit {
+        RangeUnit::Byte => len as i64,
+        RangeUnit::Bit => len as i64 * 8,
+    };
+    let start = if start < 0 { total + start } else { start }.max(0);
+    let end = if end < 0 { total + end } else { end }
+        .max(0)
+        .min(total - 1);
+    if start > end {
+        return None;
+    }
+
+    let (start, end) = (start as usize, end as usize);
+    Some(match unit {
+        RangeUnit::Byte => (start * 8, end * 8 + 7),
+        RangeUnit::Bit => (start, end),
+    })
+}
+
+/// Counts the set bits in the inclusive bit range `start..=end`.
+pub fn count_ones(data: &[u8], start: usize, end: usize) -> usize {
+    let (first, last) = (start / 8, end / 8);
+    let head_mask = 0xFFu8 >> (start % 8);
+    let tail_mask = 0xFFu8 << (7 - end % 8);
+
+    if first == last {
+        return (data[first] & head_mask & tail_mask).count_ones() as usize;
+    }
+
+    let middle: usize = data[first + 1..last]
+        .iter()
+        .map(|byte| byte.count_ones() as usize)
+        .sum();
+
+    (data[first] & head_mask).count_ones() as usize
+        + middle
+        + (data[last] & tail_mask).count_ones() as usize
+}
+
+/// Position of the first bit equal to `bit` within the inclusive bit range `start..=end`.
+pub fn position(data: &[u8], bit: bool, start: usize, end: usize) -> Option<usize> {
+    let skip = if bit { 0x00 } else { 0xFF };
+
+    let mut offset = start;
+    while offset <= end {
+        if offset.is_multiple_of(8) && offset + 7 <= end && data[offset / 8] == skip {
+            offset += 8;
+            continue;
+        }
+        if get_bit(data, offset) == bit {
+            return Some(offset);
+        }
+        offset += 1;
+    }
+
+    None
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOperation {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+impl FromStr for BitOperation {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "AND" => Ok(Self::And),
+            "OR" => Ok(Self::Or),
+            "XOR" => Ok(Self::Xor),
+            "NOT" => Ok(Self::Not),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Combines `sources` byte by byte, treating shorter ones as padded with zeroes.
+pub fn bit_operation(operation: BitOperation, sources: &[&[u8]]) -> Vec<u8> {
+    let len = sources.iter().map(|s| s.len()).max().unwrap_or(0);
+    let byte = |source: &[u8], i: usize| source.get(i).copied().unwrap_or(0);
+
+    (0..len)
+        .map(|i| {
+            let mut values = sources.iter().map(|source| byte(source, i));
+            let first = values.next().unwrap_or(0);
+            match operation {
+                BitOperation::And => values.fold(first, |acc, v| acc & v),
+                BitOperation::Or => values.fold(first, |acc, v| acc | v),
+                BitOperation::Xor => values.fold(first, |acc, v| acc ^ v),
+                BitOperation::Not => !first,
+            }
+        })
+        .collect()
+}
+
+/// Integer type of a BITFIELD operation, like `i5` or `u16`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldType {
+    pub signed: bool,
+    pub bits: u32,
+}
+
+impl FromStr for FieldType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (signed, bits) = match s.split_at_checked(1) {
+            Some(("i" | "I", bits)) => (true, bits),
+            Some(("u" | "U", bits)) => (false, bits),
+            _ => return Err(()),
+        };
+        let bits: u32 = bits.parse().map_err(|_| ())?;
+
+        match (signed, bits) {
+            (true, 1..=64) | (false, 1..=63) => Ok(Self { signed, bits }),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FieldType {
+    fn min(&self) -> i128 {
+        if self.signed {
+            -(1i128 << (self.bits - 1))
+        } else {
+            0
+        }
+    }
+
+    fn max(&self) -> i128 {
+        if self.signed {
+            (1i128 << (self.bits - 1)) - 1
+        } else {
+            (1i128 << self.bits) - 1
+        }
+    }
+
+    /// Keeps the low `bits` bits of `value`, sign extending them for signed types.
+    fn wrap(&self, value: i128) -> i128 {
+        let modulus = 1i128 << self.bits;
+        let value = value.rem_euclid(modulus);
+        if self.signed && value > self.max() {
+            value - modulus
+        } else {
+            value
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    #[default]
+    Wrap,
+    Sat,
+    Fail,
+}
+
+impl FromStr for Overflow {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "WRAP" => Ok(Self::Wrap),
+            "SAT" => Ok(Self::Sat),
+            "FAIL" => Ok(Self::Fail),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Overflow {
+    /// Brings `value` into the range of `ty`, or returns `None` if it overflows in FAIL mode.
+    pub fn apply(&self, ty: FieldType, value: i128) -> Option<i64> {
+        let value = if value > ty.max() || value < ty.min() {
+            match self {
+                Self::Wrap => ty.wrap(value),
+                Self::Sat if value > ty.max() => ty.max(),
+                Self::Sat => ty.min(),
+                Self::Fail => return None,
+            }
+        } else {
+            value
+        };
+
+        Some(value as i64)
+    }
+}
+
+/// Reads the integer of type `ty` stored at bit `offset`. Bits past the end read as zeroes.
+pub fn get_field(data: &[u8], ty: FieldType, offset: usize) -> i64 {
+    let mut value: u64 = 0;
+    for i in 0..ty.bits as usize {
+        value = (value << 1) | get_bit(data, offset + i) as u64;
+    }
+
+    if ty.signed && ty.bits < 64 && value & (1 << (ty.bits - 1)) != 0 {
+        (value | (u64::MAX << ty.bits)) as i64
+    } else {
+        value as i64
+    }
+}
+
+/// Writes the low bits of `value` as an integer of type `ty` at bit `offset`, growing `data`.
+pub fn set_field(data: &mut Vec<u8>, ty: FieldType, offset: usize, value: i64) {
+    let value = value as u64;
+    for i in 0..ty.bits as usize {
+        let bit = (value >> (ty.bits as usize - 1 - i)) & 1 != 0;
+        set_bit(data, offset + i, bit);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_and_get_bits() {
+        let mut data = Vec::new();
+        assert!(!set_bit(&mut data, 7, true));
+        assert!(!set_bit(&mut data, 9, true));
+        assert_eq!(data, vec![0x01, 0x40]);
+        assert!(set_bit(&mut data, 7, false));
+        assert!(get_bit(&data, 9));
+        assert!(!get_bit(&data, 1000));
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(bit_range(3, 0, -1, RangeUnit::Byte), Some((0, 23)));
+        assert_eq!(bit_range(3, -2, -1, RangeUnit::Byte), Some((8, 23)));
+        assert_eq!(bit_range(3, 5, 100, RangeUnit::Bit), Some((5, 23)));
+        assert_eq!(bit_range(3, 2, 1, RangeUnit::Bit), None);
+        assert_eq!(bit_range(0, 0, -1, RangeUnit::Byte), None);
+    }
+
+    #[test]
+    fn counting() {
+        let data = b"foobar";
+        assert_eq!(count_ones(data, 0, 47), 26);
+        assert_eq!(count_ones(data, 8, 15), 6);
+        assert_eq!(count_ones(data, 5, 30), 17);
+    }
+
+    #[test]
+    fn positions() {
+        let data = [0xFF, 0xF0, 0x00];
+        assert_eq!(position(&data, false, 0, 23), Some(12));
+        assert_eq!(position(&data, true, 0, 23), Some(0));
+        assert_eq!(position(&data, true, 12, 23), None);
+        assert_eq!(position(&[0x00, 0x01], true, 0, 15), Some(15));
+    }
+
+    #[test]
+    fn operations() {
+        let a: &[u8] = &[0b1100, 0xFF];
+        let b: &[u8] = &[0b1010];
+        assert_eq!(bit_operation(BitOperation::And, &[a, b]), vec![0b1000, 0]);
+        assert_eq!(bit_operation(BitOperation::Or, &[a, b]), vec![0b1110, 0xFF]);
+        assert_eq!(
+            bit_operation(BitOperation::Xor, &[a, b]),
+            vec![0b0110, 0xFF]
+        );
+        assert_eq!(bit_operation(BitOperation::Not, &[b]), vec![!0b1010]);
+    }
+
+    #[test]
+    fn field_types() {
+        assert_eq!(
+            "i64".parse(),
+            Ok(FieldType {
+                signed: true,
+                bits: 64
+            })
+        );
+        assert_eq!(
+            "u1".parse(),
+            Ok(FieldType {
+                signed: false,
+                bits: 1
+            })
+        );
+        assert!("u64".parse::<FieldType>().is_err());
+        assert!("i0".parse::<FieldType>().is_err());
+        assert!("x8".parse::<FieldType>().is_err());
+    }
+
+    #[test]
+    fn fields() {
+        let u8_type: FieldType = "u8".parse().unwrap();
+        let i4_type: FieldType = "i4".parse().unwrap();
+        let mut data = Vec::new();
+
+        set_field(&mut data, u8_type, 4, 0xAB);
+        assert_eq!(data, vec![0x0A, 0xB0]);
+        assert_eq!(get_field(&data, u8_type, 4), 0xAB);
+        assert_eq!(get_field(&data, i4_type, 4), -6);
+
+        set_field(&mut data, "i64".parse().unwrap(), 0, -1);
+        assert_eq!(get_field(&data, "i64".parse().unwrap(), 0), -1);
+    }
+
+    #[test]
+    fn overflow() {
+        let i8_type: FieldType = "i8".parse().unwrap();
+        let u2_type: FieldType = "u2".parse().unwrap();
+
+        assert_eq!(Overflow::Wrap.apply(i8_type, 128), Some(-128));
+        assert_eq!(Overflow::Wrap.apply(i8_type, -129), Some(127));
+        assert_eq!(Overflow::Sat.apply(i8_type, 200), Some(127));
+        assert_eq!(Overflow::Sat.apply(i8_type, -200), Some(-128));
+        assert_eq!(Overflow::Fail.apply(i8_type, 128), None);
+        assert_eq!(Overflow::Fail.apply(i8_type, 127), Some(127));
+
+        assert_eq!(Overflow::Wrap.apply(u2_type, 5), Some(1));
+        assert_eq!(Overflow::Wrap.apply(u2_type, -1), Some(3));
+        assert_eq!(Overflow::Sat.apply(u2_type, -1), Some(0));
+    }
+}
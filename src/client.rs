@@ -1,32 +1,100 @@
 use std::{
-    collections::HashMap,
+    collections::HashSet,
     net::SocketAddr,
-    ops::Bound,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
 };
 
-use tokio::{net::TcpStream, sync::mpsc};
+use tokio::{
+    io::{self, AsyncBufReadExt, AsyncWriteExt, BufStream},
+    net::TcpStream,
+    sync::mpsc,
+};
 
 use crate::{
+    clock::ManualClock,
     error::{Error, WithContext},
     resp::Type,
-    store::{DataStore, Value},
-    stream::{Item, ItemId},
+    store::{
+        DataStore, EventFlags, ExpiryStats, KeyListener, Message, Subscriber, TrackingOptions,
+        Value,
+    },
     Result,
 };
 
+mod bitmap;
+mod commands;
+mod connection;
+mod debug;
+mod expire;
+mod geo;
+mod hyperloglog;
+mod keyspace;
+mod pubsub;
+mod scan;
+mod sorted_set;
+mod stream;
+mod transaction;
+
+use expire::{ExpireTime, TtlOutput};
+use pubsub::Subscription;
+use sorted_set::SetOperation;
+use transaction::{Transaction, WatchedKey};
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
 pub struct Client {
-    stream: TcpStream,
+    /// Replies are buffered and flushed once a command is done.
+    stream: BufStream<TcpStream>,
     addr: SocketAddr,
+    /// Identifies the client in the Pub/Sub subscriptions and the tracking table.
+    id: u64,
     store: DataStore,
+    /// RESP version chosen with HELLO, 2 or 3.
+    protocol: u8,
+    /// Handed out to the Pub/Sub registry and the tracking table, delivering published
+    /// messages and invalidations to `messages`.
+    subscriber: Subscriber,
+    messages: mpsc::UnboundedReceiver<Message>,
+    /// Channels, patterns and shard channels subscribed to with SUBSCRIBE, PSUBSCRIBE and
+    /// SSUBSCRIBE.
+    channels: HashSet<String>,
+    patterns: HashSet<String>,
+    shard_channels: HashSet<String>,
+    /// Commands queued since MULTI, `None` outside of a transaction.
+    transaction: Option<Transaction>,
+    /// Keys watched for writes until the next EXEC, DISCARD or UNWATCH.
+    watched: Vec<WatchedKey>,
+    /// Set while EXEC runs queued commands, which then must not block.
+    in_exec: bool,
+    /// How the client tracks keys for client-side caching, `None` while tracking is off.
+    tracking: Option<TrackingOptions>,
+    /// Set by CLIENT CACHING for the next command, in OPTIN or OPTOUT mode.
+    caching: Option<bool>,
 }
 
 impl Client {
-    pub fn new(stream: TcpStream, addr: SocketAddr, store: DataStore) -> Self {
+    pub fn new(stream: TcpStream, addr: SocketAddr, mut store: DataStore) -> Self {
+        let (subscriber, messages) = mpsc::unbounded_channel();
+        let id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+        store.set_client(id);
+        store.tracking().connect(id, &subscriber);
         Self {
-            stream,
+            stream: BufStream::new(stream),
             addr,
+            id,
             store,
+            protocol: 2,
+            subscriber,
+            messages,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            shard_channels: HashSet::new(),
+            transaction: None,
+            watched: Vec::new(),
+            in_exec: false,
+            tracking: None,
+            caching: None,
         }
     }
 
@@ -36,53 +104,238 @@ impl Client {
             .run_int()
             .await
             .context(&format!("Client {}", self.addr));
+        self.unsubscribe_all();
+        self.store.tracking().disconnect(self.id);
         if let Err(error) = res {
             eprintln!("[ERROR] {}", error.with_trace());
         }
     }
 
+    /// Waits for either the next command or a message published to one of the client's
+    /// subscriptions. Only waiting for buffered input is cancel safe, so a command is read in
+    /// full once its first bytes arrived.
     async fn run_int(&mut self) -> Result<()> {
         loop {
-            let cmd = self.read_command().await.context("Reading command")?;
-            eprintln!("Received CMD: {:?}", &cmd);
-            let command = cmd
-                .first()
-                .map(|s| s.as_str())
-                .unwrap_or("")
-                .to_ascii_uppercase();
-
-            if let Err(err) = self.run_command(cmd).await {
-                if err.is_fatal() {
-                    return Err(err);
-                } else {
-                    Type::SimpleError(err.kind(), err.redis_error_message(&command))
-                        .write(&mut self.stream)
-                        .await?;
+            tokio::select! {
+                received = self.stream.fill_buf() => {
+                    received?;
+                    let cmd = self.read_command().await.context("Reading command")?;
+                    let printed: Vec<_> = cmd.iter().map(|arg| String::from_utf8_lossy(arg)).collect();
+                    eprintln!("Received CMD: {:?}", printed);
+                    // CLIENT CACHING applies to the next command, or the next transaction
+                    let keeps_caching = self.transaction.is_some()
+                        || (cmd.len() > 1
+                            && cmd[0].eq_ignore_ascii_case(b"client")
+                            && cmd[1].eq_ignore_ascii_case(b"caching"));
+                    self.run_command_with_reply(cmd).await?;
+                    if !keeps_caching {
+                        self.caching = None;
+                    }
                 }
+                Some(message) = self.messages.recv() => self.write_message(message).await?,
+            }
+            self.stream.flush().await?;
+        }
+    }
+
+    /// Runs or queues a command, replying with an error if it fails. Only fatal errors, after
+    /// which the connection can't be used anymore, are returned.
+    async fn run_command_with_reply(&mut self, cmd: Vec<Vec<u8>>) -> Result<()> {
+        let command = cmd
+            .first()
+            .map(|s| String::from_utf8_lossy(s))
+            .unwrap_or_default()
+            .to_ascii_uppercase();
+
+        // Like Redis, empty commands are skipped without a reply
+        if cmd.is_empty() {
+            return Ok(());
+        }
+
+        let name = command.to_ascii_lowercase();
+        let result = if let Err(err) = commands::check_arity(&name, cmd.len()) {
+            // Unknown commands and a wrong number of arguments abort a transaction
+            self.abort_transaction();
+            Err(err)
+        } else if let Err(err) = self.check_subscribed_command(&name) {
+            Err(err)
+        } else if self.queues(&name) {
+            self.queue_command(cmd).await
+        } else {
+            self.track_reads(&cmd);
+            self.run_command(cmd).await
+        };
+
+        match result {
+            Err(err) if err.is_fatal() => Err(err),
+            Err(err) => {
+                Type::SimpleError(err.kind(), err.redis_error_message(&command))
+                    .write(&mut self.stream)
+                    .await
             }
+            Ok(()) => Ok(()),
         }
     }
 
-    async fn run_command(&mut self, cmd: Vec<String>) -> Result<()> {
+    /// Runs a command. Only the values commands store are taken as they are, the other
+    /// arguments have to be valid UTF-8.
+    async fn run_command(&mut self, cmd: Vec<Vec<u8>>) -> Result<()> {
         let mut args = cmd.into_iter();
-        let cmd = args.next().map(|s| s.to_ascii_lowercase());
+        let cmd = args
+            .next()
+            .map(|s| String::from_utf8_lossy(&s).to_ascii_lowercase());
+
+        match cmd.as_deref() {
+            Some("set") => return self.handle_set(args).await,
+            Some("pfadd") => return self.handle_pfadd(args).await,
+            _ => {}
+        }
 
-        match cmd.as_ref().map(|s| s.as_str()) {
+        let args = utf8_args(args)?;
+        match cmd.as_deref() {
+            Some("ping") if self.in_subscribed_mode() => self.handle_subscribed_ping(args).await?,
             Some("ping") => self.handle_ping(args).await?,
             Some("echo") => self.handle_echo(args).await?,
             Some("get") => self.handle_get(args).await?,
             Some("type") => self.handle_type(args).await?,
-            Some("set") => self.handle_set(args).await?,
+            Some("expire") => self.handle_expire(args, ExpireTime::Seconds).await?,
+            Some("pexpire") => self.handle_expire(args, ExpireTime::Milliseconds).await?,
+            Some("expireat") => self.handle_expire(args, ExpireTime::UnixSeconds).await?,
+            Some("pexpireat") => {
+                self.handle_expire(args, ExpireTime::UnixMilliseconds)
+                    .await?
+            }
+            Some("ttl") => self.handle_ttl(args, TtlOutput::Remaining, false).await?,
+            Some("pttl") => self.handle_ttl(args, TtlOutput::Remaining, true).await?,
+            Some("expiretime") => self.handle_ttl(args, TtlOutput::Absolute, false).await?,
+            Some("pexpiretime") => self.handle_ttl(args, TtlOutput::Absolute, true).await?,
+            Some("persist") => self.handle_persist(args).await?,
             Some("xadd") => self.handle_xadd(args).await?,
-            Some("xrange") => self.handle_xrange(args).await?,
+            Some("xlen") => self.handle_xlen(args).await?,
+            Some("xdel") => self.handle_xdel(args).await?,
+            Some("xtrim") => self.handle_xtrim(args).await?,
+            Some("xrange") => self.handle_xrange(args, false).await?,
+            Some("xrevrange") => self.handle_xrange(args, true).await?,
             Some("xread") => self.handle_xread(args).await?,
+            Some("xgroup") => self.handle_xgroup(args).await?,
+            Some("xreadgroup") => self.handle_xreadgroup(args).await?,
+            Some("xack") => self.handle_xack(args).await?,
+            Some("xpending") => self.handle_xpending(args).await?,
+            Some("xclaim") => self.handle_xclaim(args).await?,
+            Some("xsetid") => self.handle_xsetid(args).await?,
+            Some("xinfo") => self.handle_xinfo(args).await?,
+            Some("xautoclaim") => self.handle_xautoclaim(args).await?,
+            Some("zadd") => self.handle_zadd(args).await?,
+            Some("zincrby") => self.handle_zincrby(args).await?,
+            Some("zrem") => self.handle_zrem(args).await?,
+            Some("zcard") => self.handle_zcard(args).await?,
+            Some("zscore") => self.handle_zscore(args).await?,
+            Some("zmscore") => self.handle_zmscore(args).await?,
+            Some("zrank") => self.handle_zrank(args, false).await?,
+            Some("zrevrank") => self.handle_zrank(args, true).await?,
+            Some("zcount") => self.handle_zcount(args, "score").await?,
+            Some("zlexcount") => self.handle_zcount(args, "lex").await?,
+            Some("zrange") => self.handle_zrange("zrange", args, None).await?,
+            Some("zrevrange") => {
+                self.handle_zrange("zrevrange", args, Some(("rank", true)))
+                    .await?
+            }
+            Some("zrangebyscore") => {
+                self.handle_zrange("zrangebyscore", args, Some(("score", false)))
+                    .await?
+            }
+            Some("zrevrangebyscore") => {
+                self.handle_zrange("zrevrangebyscore", args, Some(("score", true)))
+                    .await?
+            }
+            Some("zrangebylex") => {
+                self.handle_zrange("zrangebylex", args, Some(("lex", false)))
+                    .await?
+            }
+            Some("zrevrangebylex") => {
+                self.handle_zrange("zrevrangebylex", args, Some(("lex", true)))
+                    .await?
+            }
+            Some("zremrangebyrank") => self.handle_zremrange(args, "rank").await?,
+            Some("zremrangebyscore") => self.handle_zremrange(args, "score").await?,
+            Some("zremrangebylex") => self.handle_zremrange(args, "lex").await?,
+            Some("zunion") => self.handle_zcombine(args, SetOperation::Union).await?,
+            Some("zinter") => self.handle_zcombine(args, SetOperation::Inter).await?,
+            Some("zdiff") => self.handle_zcombine(args, SetOperation::Diff).await?,
+            Some("zunionstore") => self.handle_zcombinestore(args, SetOperation::Union).await?,
+            Some("zinterstore") => self.handle_zcombinestore(args, SetOperation::Inter).await?,
+            Some("zdiffstore") => self.handle_zcombinestore(args, SetOperation::Diff).await?,
+            Some("zrangestore") => self.handle_zrangestore(args).await?,
+            Some("zpopmin") => self.handle_zpop(args, false).await?,
+            Some("zpopmax") => self.handle_zpop(args, true).await?,
+            Some("bzpopmin") => self.handle_bzpop(args, false).await?,
+            Some("bzpopmax") => self.handle_bzpop(args, true).await?,
+            Some("zmpop") => self.handle_zmpop(args).await?,
+            Some("bzmpop") => self.handle_bzmpop(args).await?,
+            Some("setbit") => self.handle_setbit(args).await?,
+            Some("getbit") => self.handle_getbit(args).await?,
+            Some("bitcount") => self.handle_bitcount(args).await?,
+            Some("bitpos") => self.handle_bitpos(args).await?,
+            Some("bitop") => self.handle_bitop(args).await?,
+            Some("bitfield") => self.handle_bitfield(args, false).await?,
+            Some("bitfield_ro") => self.handle_bitfield(args, true).await?,
+            Some("geoadd") => self.handle_geoadd(args).await?,
+            Some("geopos") => self.handle_geopos(args).await?,
+            Some("geodist") => self.handle_geodist(args).await?,
+            Some("geohash") => self.handle_geohash(args).await?,
+            Some("geosearch") => self.handle_geosearch(args).await?,
+            Some("geosearchstore") => self.handle_geosearchstore(args).await?,
+            Some("pfcount") => self.handle_pfcount(args).await?,
+            Some("pfmerge") => self.handle_pfmerge(args).await?,
             Some("keys") => self.handle_keys(args).await?,
+            Some("scan") => self.handle_scan(args).await?,
+            Some("zscan") => self.handle_zscan(args).await?,
+            Some("hscan") => self.handle_collection_scan(args, "hscan", "hash").await?,
+            Some("sscan") => self.handle_collection_scan(args, "sscan", "set").await?,
+            Some("del") => self.handle_del(args, false).await?,
+            Some("unlink") => self.handle_del(args, true).await?,
+            Some("exists") => self.handle_exists(args, "exists").await?,
+            Some("touch") => self.handle_exists(args, "touch").await?,
+            Some("rename") => self.handle_rename(args, false).await?,
+            Some("renamenx") => self.handle_rename(args, true).await?,
+            Some("copy") => self.handle_copy(args).await?,
+            Some("randomkey") => self.handle_randomkey().await?,
+            Some("dbsize") => self.handle_dbsize().await?,
+            Some("move") => self.handle_move(args).await?,
+            Some("select") => self.handle_select(args).await?,
+            Some("swapdb") => self.handle_swapdb(args).await?,
+            Some("flushdb") => self.handle_flush(args, false).await?,
+            Some("flushall") => self.handle_flush(args, true).await?,
+            Some("save") => self.handle_save().await?,
+            Some("bgsave") => self.handle_bgsave().await?,
+            Some("multi") => self.handle_multi().await?,
+            Some("exec") => self.handle_exec().await?,
+            Some("discard") => self.handle_discard().await?,
+            Some("watch") => self.handle_watch(args).await?,
+            Some("unwatch") => self.handle_unwatch().await?,
+            Some("subscribe") => self.handle_subscribe(args, Subscription::Channel).await?,
+            Some("psubscribe") => self.handle_subscribe(args, Subscription::Pattern).await?,
+            Some("ssubscribe") => {
+                self.handle_subscribe(args, Subscription::ShardChannel)
+                    .await?
+            }
+            Some("unsubscribe") => self.handle_unsubscribe(args, Subscription::Channel).await?,
+            Some("punsubscribe") => self.handle_unsubscribe(args, Subscription::Pattern).await?,
+            Some("sunsubscribe") => {
+                self.handle_unsubscribe(args, Subscription::ShardChannel)
+                    .await?
+            }
+            Some("publish") => self.handle_publish(args, false).await?,
+            Some("spublish") => self.handle_publish(args, true).await?,
+            Some("pubsub") => self.handle_pubsub(args).await?,
+            Some("client") => self.handle_client(args).await?,
+            Some("hello") => self.handle_hello(args).await?,
             Some("config") => self.handle_config(args).await?,
+            Some("debug") => self.handle_debug(args).await?,
             Some("info") => self.handle_info(args).await?,
             Some("replconf") => self.handle_replconf(args).await?,
             Some("psync") => self.handle_psync(args).await?,
-            Some(cmd) => return Err(Error::UnimplementedCommand(cmd.into())),
-            None => todo!(),
+            cmd => return Err(Error::UnimplementedCommand(cmd.unwrap_or_default().into())),
         }
 
         Ok(())
@@ -118,26 +371,76 @@ impl Client {
         Ok(())
     }
 
-    async fn handle_info(&mut self, _args: impl Iterator<Item = String>) -> Result<()> {
-        let info = self.store.info().await;
-        let resp = format!(
-            "role:{}\r\nmaster_replid:{}\r\nmaster_repl_offset:{}",
-            info.role(),
-            info.replication_id_str(),
-            info.replication_offset()
-        );
+    async fn handle_info(&mut self, args: impl Iterator<Item = String>) -> Result<()> {
+        let sections: Vec<_> = args.map(|s| s.to_ascii_lowercase()).collect();
+        let all = sections.is_empty()
+            || sections
+                .iter()
+                .any(|s| matches!(s.as_str(), "all" | "default" | "everything"));
+        let wanted = |name: &str| all || sections.iter().any(|s| s == name);
 
-        Type::BulkString(resp).write(&mut self.stream).await?;
+        let mut resp = Vec::new();
+        if wanted("replication") {
+            let info = self.store.info().await;
+            resp.push(format!(
+                "# Replication\r\nrole:{}\r\nmaster_replid:{}\r\nmaster_repl_offset:{}\r\n",
+                info.role(),
+                info.replication_id_str(),
+                info.replication_offset()
+            ));
+        }
+        if wanted("stats") {
+            let stats = self
+                .store
+                .with_databases(|dbs| {
+                    dbs.iter()
+                        .fold(ExpiryStats::default(), |mut stats, keyspace| {
+                            stats.merge(keyspace.expiry_stats());
+                            stats
+                        })
+                })
+                .await;
+            resp.push(format!(
+                "# Stats\r\nexpired_keys:{}\r\nexpired_stale_perc:{:.2}\r\n\
+                 expired_time_cap_reached_count:{}\r\nexpire_cycle_cpu_milliseconds:{}\r\n",
+                stats.expired_keys,
+                stats.expired_stale_perc * 100.0,
+                stats.expired_time_cap_reached_count,
+                stats.expire_cycle_cpu_milliseconds
+            ));
+        }
+        if wanted("keyspace") {
+            let counts: Vec<_> = self
+                .store
+                .with_databases(|dbs| {
+                    dbs.iter()
+                        .map(|keyspace| (keyspace.len(), keyspace.volatile_len()))
+                        .collect()
+                })
+                .await;
+            let mut section = "# Keyspace\r\n".to_owned();
+            for (db, (keys, expires)) in counts.into_iter().enumerate() {
+                if keys > 0 {
+                    section.push_str(&format!(
+                        "db{db}:keys={keys},expires={expires},avg_ttl=0\r\n"
+                    ));
+                }
+            }
+            resp.push(section);
+        }
 
-        Ok(())
+        Type::BulkString(resp.join("\r\n"))
+            .write(&mut self.stream)
+            .await
     }
 
     async fn handle_config(&mut self, mut args: impl Iterator<Item = String>) -> Result<()> {
         let subcmd = args.next().map(|s| s.to_ascii_lowercase());
-        match subcmd.as_ref().map(|v| v.as_str()) {
+        match subcmd.as_deref() {
             Some("get") => self.handle_config_get(args).await,
+            Some("set") => self.handle_config_set(args).await,
             Some(cmd) => Err(Error::UnimplementedCommand(format!("CONFIG {cmd}"))),
-            None => todo!(),
+            None => Err(Error::WrongArity("config".into())),
         }
     }
 
@@ -147,8 +450,14 @@ impl Client {
             .ok_or(Error::MissingArgument("config get", "key"))?
             .to_ascii_lowercase();
 
-        self.store
-            .get_config(&key)
+        // The only setting CONFIG SET can change, so it's not read from the startup config
+        let events = self.store.keyspace_events().to_string();
+        let value = match key.as_str() {
+            "notify-keyspace-events" => Some(events.as_str()),
+            _ => self.store.get_config(&key),
+        };
+
+        value
             .map_or(Type::NullString, |s| {
                 Type::Array(vec![Type::BulkString(key), Type::BulkString(s.into())])
             })
@@ -156,23 +465,34 @@ impl Client {
             .await
     }
 
-    async fn handle_keys(&mut self, mut args: impl Iterator<Item = String>) -> Result<()> {
-        let key = args.next().ok_or(Error::MissingArgument("keys", "key"))?;
-        if key != "*" {
-            return Err(Error::Unimplemented).context("Only `KEYS *` is implemented");
+    /// `CONFIG SET parameter value [parameter value ...]`, which only supports
+    /// `notify-keyspace-events` so far.
+    async fn handle_config_set(&mut self, args: impl Iterator<Item = String>) -> Result<()> {
+        let args: Vec<_> = args.collect();
+        if args.is_empty() || args.len() % 2 != 0 {
+            return Err(Error::WrongArity("config|set".into()));
         }
 
-        let keys = self
-            .store
-            .keys()
-            .await
-            .into_iter()
-            .map(|k| Type::BulkString(k))
-            .collect();
-
-        Type::Array(keys).write(&mut self.stream).await?;
+        let mut events = None;
+        for pair in args.chunks(2) {
+            let parameter = pair[0].to_ascii_lowercase();
+            match parameter.as_str() {
+                "notify-keyspace-events" => {
+                    events = Some(EventFlags::parse(&pair[1]).ok_or(Error::InvalidConfigValue(
+                        parameter,
+                        "Invalid event class character. Use 'Ag$lshzxeKEtmn'.",
+                    ))?);
+                }
+                _ => return Err(Error::UnknownConfigOption(pair[0].clone())),
+            }
+        }
+        if let Some(events) = events {
+            self.store.set_keyspace_events(events);
+        }
 
-        Ok(())
+        Type::SimpleString("OK".into())
+            .write(&mut self.stream)
+            .await
     }
 
     async fn handle_get(&mut self, mut args: impl Iterator<Item = String>) -> Result<()> {
@@ -182,8 +502,8 @@ impl Client {
             .get(&key)
             .await
             .map_or(Ok(Type::NullString), |s| match s {
-                Value::String(x) => Ok(Type::BulkString(x)),
-                _ => Err(Error::Unimplemented),
+                Value::String(x) => Ok(Type::BulkBytes(x)),
+                _ => Err(Error::ExpectedOtherType("string")),
             })?
             .write(&mut self.stream)
             .await
@@ -202,210 +522,112 @@ impl Client {
             .await
     }
 
-    async fn handle_set(&mut self, mut args: impl Iterator<Item = String>) -> Result<()> {
-        let key = args.next().ok_or(Error::MissingArgument("set", "key"))?;
+    async fn handle_set(&mut self, mut args: impl Iterator<Item = Vec<u8>>) -> Result<()> {
+        let key = utf8_arg(args.next().ok_or(Error::MissingArgument("set", "key"))?)?;
         let value = args.next().ok_or(Error::MissingArgument("set", "value"))?;
+        let mut args = utf8_args(args)?;
 
         let expires_at = match (
             args.next().map(|v| v.to_ascii_lowercase()),
             args.next().and_then(|v| v.parse::<u64>().ok()),
         ) {
             (Some(cmd), Some(arg)) if cmd == "px" => {
-                Some(SystemTime::now() + Duration::from_millis(arg))
+                Some(self.store.now() + Duration::from_millis(arg))
             }
             _ => None,
         };
 
-        self.store.set(key, Value::String(value), expires_at).await;
+        self.store
+            .set(key.clone(), Value::String(value), expires_at)
+            .await;
+        self.store.notify(EventFlags::STRING, "set", &key);
+        if expires_at.is_some() {
+            self.store.notify(EventFlags::GENERIC, "expire", &key);
+        }
         Type::SimpleString("OK".into())
             .write(&mut self.stream)
             .await
     }
 
-    async fn handle_xadd(&mut self, mut args: impl Iterator<Item = String>) -> Result<()> {
-        let key = args.next().ok_or(Error::MissingArgument("xadd", "key"))?;
-        let id = args.next().ok_or(Error::MissingArgument("xadd", "id"))?;
-        let mut items = HashMap::new();
-
-        while let Some(key) = args.next() {
-            let value = args.next().ok_or(Error::MissingArgument("xadd", "value"))?;
-            items.insert(key, value);
-        }
-
-        let id = self
-            .store
-            .insert_stream_item(key, id.as_str().try_into()?, items)
-            .await?;
-        Type::BulkString(id.to_string())
-            .write(&mut self.stream)
-            .await?;
+    async fn handle_echo(&mut self, mut args: impl Iterator<Item = String>) -> Result<()> {
+        let reply = args.next().unwrap_or_default();
 
+        Type::BulkString(reply).write(&mut self.stream).await?;
         Ok(())
     }
 
-    async fn handle_xrange(&mut self, mut args: impl Iterator<Item = String>) -> Result<()> {
-        let key = args.next().ok_or(Error::MissingArgument("xrange", "key"))?;
-        let start = args
-            .next()
-            .ok_or(Error::MissingArgument("xrange", "start"))?;
-        let end = args.next().ok_or(Error::MissingArgument("xrange", "end"))?;
-
-        let start = if start == "-" {
-            Bound::Unbounded
-        } else {
-            Bound::Included(start.as_str().try_into()?)
-        };
-
-        let end = if end == "+" {
-            Bound::Unbounded
+    async fn handle_ping(&mut self, mut args: impl Iterator<Item = String>) -> Result<()> {
+        let reply = if let Some(arg) = args.next() {
+            Type::BulkString(arg)
         } else {
-            Bound::Included(end.as_str().try_into()?)
+            Type::SimpleString("PONG".into())
         };
 
-        self.store
-            .get_ref(&key, move |value| -> Result<_> {
-                let value = value
-                    .as_stream()
-                    .ok_or(Error::ExpectedOtherType("stream"))?;
-
-                let range = value.range(start, end).map(|v| v.into()).collect();
-                Ok(Type::Array(range))
-            })
-            .await
-            .unwrap_or(Ok(Type::NullString))?
-            .write(&mut self.stream)
-            .await?;
-
+        reply.write(&mut self.stream).await?;
         Ok(())
     }
 
-    async fn handle_xread(&mut self, mut args: impl Iterator<Item = String>) -> Result<()> {
-        let mut streams = Vec::new();
-        let mut block = None;
-        let now: u64 = SystemTime::now()
-            .duration_since(UNIX_EPOCH)?
-            .as_millis()
-            .try_into()?;
-
-        fn parse_item_id(value: &str, now: u64) -> Result<ItemId> {
-            if value == "$" {
-                Ok(ItemId::new(now, 0))
-            } else {
-                Ok(value.try_into()?)
-            }
-        }
-
-        while let Some(arg) = args.next() {
-            match arg.to_ascii_uppercase().as_str() {
-                "STREAMS" => {
-                    let args: Vec<_> = args.collect();
-                    if args.len() % 2 != 0 {
-                        return Err(Error::MissingArgument("xread", "item_id"));
-                    }
-
-                    let half_point = args.len() / 2;
-                    for i in 0..half_point {
-                        streams.push((args[i].clone(), parse_item_id(&args[i + half_point], now)?));
-                    }
-
-                    break;
+    /// Waits until `listener`, which a blocking command registered on `keys` after finding
+    /// nothing to reply with, is told that one of them was written to. Returns `false` once the
+    /// store's clock reaches `deadline`. The listener is unregistered either way, and waiting
+    /// fails if the client disconnects meanwhile.
+    async fn wait_for_keys(
+        &self,
+        keys: &[String],
+        (listener, mut ready): (KeyListener, mpsc::Receiver<String>),
+        deadline: Option<SystemTime>,
+    ) -> Result<bool> {
+        let received = async {
+            let Some(deadline) = deadline else {
+                return ready.recv().await.is_some();
+            };
+            loop {
+                let clock = self.store.clock();
+                // Created before reading the time so that moving the clock in between isn't missed
+                let moved = clock.as_manual().map(ManualClock::moved);
+                let remaining = deadline.duration_since(clock.now()).unwrap_or_default();
+                if remaining.is_zero() {
+                    return ready.try_recv().is_ok();
                 }
-                "BLOCK" => {
-                    let duration = args
-                        .next()
-                        .ok_or(Error::MissingArgument("xread", "block duration"))?;
-                    block = Some(u64::from_str_radix(&duration, 10)?);
-                }
-                _ => {
-                    return Err(Error::UnexpectedArgument(arg));
-                }
-            }
-        }
-
-        let mut resp = Vec::new();
-
-        for (key, start) in &streams {
-            let values = self
-                .store
-                .get_ref(&key, move |value| -> Result<_> {
-                    let value = value
-                        .as_stream()
-                        .ok_or(Error::ExpectedOtherType("stream"))?;
-
-                    let range = value
-                        .range(Bound::Excluded(*start), Bound::Unbounded)
-                        .map(|v| v.into())
-                        .collect();
-                    Ok(Type::Array(range))
-                })
-                .await
-                .unwrap_or(Ok(Type::NullString))?;
-
-            match values {
-                Type::Array(arr) if arr.is_empty() => {}
-                values => resp.push(Type::Array(vec![Type::BulkString(key.clone()), values])),
-            }
-        }
-
-        if resp.is_empty() && block.is_some() {
-            let block = block.expect("Just check that it is some");
-            let (tx, mut rx) = mpsc::channel(1);
-
-            for (key, _) in streams {
-                self.store.notify_on_stream_insert(key, tx.clone()).await?;
-            }
-
-            if block > 0 {
-                let timeout = tokio::time::sleep(Duration::from_millis(block));
-
-                tokio::select! {
-                    Some((key, id, data)) = rx.recv() => {
-                        resp.push(Type::Array(vec![
-                            Type::BulkString(key),
-                            Type::Array(vec![Item::new(id, &data).into()]),
-                        ]));
-                    }
-                    _ = timeout => {
+                match moved {
+                    // A manual clock only moves through DEBUG CLOCK, so there is nothing to time
+                    Some(moved) => tokio::select! {
+                        received = ready.recv() => return received.is_some(),
+                        () = moved => {}
+                    },
+                    None => {
+                        return tokio::time::timeout(remaining, ready.recv())
+                            .await
+                            .ok()
+                            .flatten()
+                            .is_some()
                     }
                 }
-            } else if let Some((key, id, data)) = rx.recv().await {
-                resp.push(Type::Array(vec![
-                    Type::BulkString(key),
-                    Type::Array(vec![Item::new(id, &data).into()]),
-                ]));
             }
-        }
-
-        let resp = if resp.is_empty() {
-            Type::NullString
-        } else {
-            Type::Array(resp)
         };
-
-        resp.write(&mut self.stream).await?;
-
-        Ok(())
-    }
-
-    async fn handle_echo(&mut self, mut args: impl Iterator<Item = String>) -> Result<()> {
-        let reply = args.next().unwrap_or_default();
-
-        Type::BulkString(reply).write(&mut self.stream).await?;
-        Ok(())
-    }
-
-    async fn handle_ping(&mut self, mut args: impl Iterator<Item = String>) -> Result<()> {
-        let reply = if let Some(arg) = args.next() {
-            Type::BulkString(arg)
-        } else {
-            Type::SimpleString("PONG".into())
+        // Commands pipelined after the blocking one keep the connection readable, a
+        // disconnection can only be noticed without them
+        let disconnected = async {
+            if let Ok(1..) = self.stream.get_ref().peek(&mut [0]).await {
+                std::future::pending::<()>().await;
+            }
+        };
+        let result = tokio::select! {
+            received = received => Ok(received),
+            () = disconnected => Err(io::Error::from(io::ErrorKind::ConnectionAborted).into()),
         };
 
-        reply.write(&mut self.stream).await?;
-        Ok(())
+        self.store
+            .with_keyspace(|keyspace| {
+                for key in keys {
+                    keyspace.stop_notifying(key, &listener);
+                }
+            })
+            .await;
+        result
     }
 
-    async fn read_command(&mut self) -> Result<Vec<String>> {
+    async fn read_command(&mut self) -> Result<Vec<Vec<u8>>> {
         let parsed = Type::parse(&mut std::pin::Pin::new(&mut self.stream))
             .await
             .context("Parsing command")?;
@@ -413,8 +635,8 @@ impl Client {
             let ret = cmds
                 .into_iter()
                 .map(|c| match c {
-                    Type::BulkString(str) => Ok(str),
-                    Type::SimpleString(str) => Ok(str),
+                    Type::BulkString(str) | Type::SimpleString(str) => Ok(str.into_bytes()),
+                    Type::BulkBytes(bytes) => Ok(bytes),
                     other => Err(Error::UnexpectedCommandType(other)),
                 })
                 .collect::<Result<_>>()
@@ -426,3 +648,161 @@ impl Client {
         }
     }
 }
+
+fn utf8_arg(arg: Vec<u8>) -> Result<String> {
+    String::from_utf8(arg).map_err(|err| err.utf8_error().into())
+}
+
+fn utf8_args(args: impl Iterator<Item = Vec<u8>>) -> Result<std::vec::IntoIter<String>> {
+    let args = args.map(utf8_arg).collect::<Result<Vec<_>>>()?;
+    Ok(args.into_iter())
+}
+
+fn parse_integer(value: &str) -> Result<i64> {
+    value.parse().map_err(|_| Error::NotAnInteger)
+}
+
+/// Parses a double the way Redis does, accepting `inf` and `-inf` but rejecting NaN.
+fn parse_double(value: &str) -> Result<f64> {
+    match value.parse::<f64>() {
+        Ok(value) if !value.is_nan() => Ok(value),
+        _ => Err(Error::NotAFloat),
+    }
+}
+
+/// Formats a double the way Redis replies with it: the shortest representation that round
+/// trips, switching to exponent notation for very large and very small magnitudes.
+fn format_double(value: f64) -> String {
+    if value.is_infinite() {
+        return if value > 0.0 { "inf" } else { "-inf" }.into();
+    }
+
+    let magnitude = value.abs();
+    if magnitude != 0.0 && !(1e-5..1e17).contains(&magnitude) {
+        let formatted = format!("{value:e}");
+        match formatted.split_once('e') {
+            Some((mantissa, exponent)) if !exponent.starts_with('-') => {
+                format!("{mantissa}e+{exponent}")
+            }
+            _ => formatted,
+        }
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        sync::Arc,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::Client;
+    use crate::{
+        clock::ManualClock,
+        store::{DataStore, Role},
+    };
+
+    /// Sends `command` as an array of bulk strings and reads `len` bytes of reply.
+    async fn call(stream: &mut TcpStream, command: &[&[u8]], len: usize) -> Vec<u8> {
+        let mut request = format!("*{}\r\n", command.len()).into_bytes();
+        for arg in command {
+            request.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            request.extend_from_slice(arg);
+            request.extend_from_slice(b"\r\n");
+        }
+        stream.write_all(&request).await.unwrap();
+
+        let mut reply = vec![0; len];
+        stream.read_exact(&mut reply).await.unwrap();
+        reply
+    }
+
+    #[tokio::test]
+    async fn binary_values_round_trip() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (accepted, addr) = listener.accept().await.unwrap();
+        let store = DataStore::new(HashMap::new(), Role::Master);
+        tokio::spawn(Client::new(accepted, addr, store).run());
+
+        assert_eq!(
+            call(&mut stream, &[b"SET", b"k", b"\xff\x00\r"], 5).await,
+            b"+OK\r\n"
+        );
+        assert_eq!(
+            call(&mut stream, &[b"SETBIT", b"k", b"7", b"0"], 4).await,
+            b":1\r\n"
+        );
+        assert_eq!(
+            call(&mut stream, &[b"GET", b"k"], 9).await,
+            b"$3\r\n\xfe\x00\r\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_writes_keep_watched_keys_clean() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (accepted, addr) = listener.accept().await.unwrap();
+        let store = DataStore::new(HashMap::new(), Role::Master);
+        tokio::spawn(Client::new(accepted, addr, store).run());
+
+        assert_eq!(
+            call(&mut stream, &[b"SET", b"k", b"v"], 5).await,
+            b"+OK\r\n"
+        );
+        assert_eq!(call(&mut stream, &[b"WATCH", b"k"], 5).await, b"+OK\r\n");
+        assert_eq!(
+            call(&mut stream, &[b"ZREM", b"k", b"m"], 68).await,
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+        assert_eq!(call(&mut stream, &[b"MULTI"], 5).await, b"+OK\r\n");
+        assert_eq!(call(&mut stream, &[b"GET", b"k"], 9).await, b"+QUEUED\r\n");
+        assert_eq!(
+            call(&mut stream, &[b"EXEC"], 11).await,
+            b"*1\r\n$1\r\nv\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn blocking_timeouts_follow_the_store_clock() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (accepted, addr) = listener.accept().await.unwrap();
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let store = DataStore::with_clock(HashMap::new(), Role::Master, clock.clone());
+        tokio::spawn(Client::new(accepted, addr, store).run());
+
+        stream
+            .write_all(b"*3\r\n$8\r\nBZPOPMIN\r\n$1\r\nk\r\n$4\r\n3600\r\n")
+            .await
+            .unwrap();
+        // Time doesn't pass on its own, and moving it before the client blocks just moves the
+        // deadline along, so keep moving it until the client gives up
+        let mut first = [0];
+        loop {
+            clock.advance(Duration::from_secs(3600));
+            let readable = tokio::time::timeout(Duration::from_millis(10), stream.peek(&mut first));
+            if readable.await.is_ok() {
+                break;
+            }
+        }
+        let mut reply = [0; 5];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"*-1\r\n");
+    }
+}
@@ -0,0 +1,396 @@
+use super::{parse_integer, Client};
+use crate::{
+    bitmap::{self, BitOperation, FieldType, Overflow, RangeUnit, MAX_BIT_OFFSET},
+    error::Error,
+    resp::Type,
+    store::{EventFlags, Value},
+    Result,
+};
+
+fn parse_bit_offset(value: &str) -> Result<usize> {
+    value
+        .parse::<u64>()
+        .ok()
+        .filter(|offset| *offset <= MAX_BIT_OFFSET)
+        .map(|offset| offset as usize)
+        .ok_or(Error::InvalidArgument(
+            "bit offset is not an integer or out of range",
+        ))
+}
+
+fn parse_bit(value: &str) -> Result<bool> {
+    match value {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(Error::InvalidArgument(
+            "bit is not an integer or out of range",
+        )),
+    }
+}
+
+fn parse_range_unit(value: Option<String>) -> Result<RangeUnit> {
+    match value.map(|v| v.to_ascii_uppercase()).as_deref() {
+        None | Some("BYTE") => Ok(RangeUnit::Byte),
+        Some("BIT") => Ok(RangeUnit::Bit),
+        Some(_) => Err(Error::SyntaxError),
+    }
+}
+
+/// Runs `op` on the string in `slot`, creating an empty one first if there is none. Strings
+/// that were created here but stay empty are not kept.
+pub(super) fn with_string<T>(
+    slot: &mut Option<Value>,
+    op: impl FnOnce(&mut Vec<u8>) -> Result<T>,
+) -> Result<T> {
+    let created = slot.is_none();
+    let value = slot.get_or_insert_with(|| Value::String(Vec::new()));
+    let string = value
+        .as_string_mut()
+        .ok_or(Error::ExpectedOtherType("string"))?;
+
+    let result = op(string);
+    if created && string.is_empty() {
+        *slot = None;
+    }
+    result
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FieldOperation {
+    Get(FieldType, usize),
+    Set(FieldType, usize, i64),
+    IncrBy(FieldType, usize, i64),
+    Overflow(Overflow),
+}
+
+impl FieldOperation {
+    fn parse_all(
+        mut args: impl Iterator<Item = String>,
+        read_only: bool,
+    ) -> Result<Vec<FieldOperation>> {
+        let mut operations = Vec::new();
+
+        while let Some(arg) = args.next() {
+            let subcommand = arg.to_ascii_uppercase();
+            if subcommand == "OVERFLOW" {
+                let mode = args.next().ok_or(Error::SyntaxError)?;
+                let mode = mode
+                    .parse()
+                    .map_err(|_| Error::InvalidArgument("Invalid OVERFLOW type specified"))?;
+                operations.push(Self::Overflow(mode));
+                continue;
+            }
+
+            let ty = args.next().ok_or(Error::SyntaxError)?;
+            let offset = args.next().ok_or(Error::SyntaxError)?;
+            let ty: FieldType = ty.parse().map_err(|_| {
+                Error::InvalidArgument(
+                    "Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.",
+                )
+            })?;
+            let offset = Self::parse_offset(&offset, ty)?;
+
+            let operation = match subcommand.as_str() {
+                "GET" => Self::Get(ty, offset),
+                "SET" | "INCRBY" if read_only => {
+                    return Err(Error::InvalidArgument(
+                        "BITFIELD_RO only supports the GET subcommand",
+                    ))
+                }
+                "SET" => {
+                    let value = args.next().ok_or(Error::SyntaxError)?;
+                    Self::Set(ty, offset, parse_integer(&value)?)
+                }
+                "INCRBY" => {
+                    let increment = args.next().ok_or(Error::SyntaxError)?;
+                    Self::IncrBy(ty, offset, parse_integer(&increment)?)
+                }
+                _ => return Err(Error::SyntaxError),
+            };
+            operations.push(operation);
+        }
+
+        Ok(operations)
+    }
+
+    /// Offsets prefixed with `#` are multiplied by the width of the type.
+    fn parse_offset(value: &str, ty: FieldType) -> Result<usize> {
+        match value.strip_prefix('#') {
+            Some(index) => index
+                .parse::<u64>()
+                .ok()
+                .and_then(|index| index.checked_mul(ty.bits as u64)),
+            None => value.parse::<u64>().ok(),
+        }
+        .filter(|offset| offset.saturating_add(ty.bits as u64 - 1) <= MAX_BIT_OFFSET)
+        .map(|offset| offset as usize)
+        .ok_or(Error::InvalidArgument(
+            "bit offset is not an integer or out of range",
+        ))
+    }
+
+    fn is_write(&self) -> bool {
+        matches!(self, Self::Set(..) | Self::IncrBy(..))
+    }
+}
+
+fn run_field_operations(data: &mut Vec<u8>, operations: &[FieldOperation]) -> Vec<Type> {
+    let mut overflow = Overflow::default();
+    let mut replies = Vec::new();
+
+    for operation in operations {
+        let reply = match *operation {
+            FieldOperation::Overflow(mode) => {
+                overflow = mode;
+                continue;
+            }
+            FieldOperation::Get(ty, offset) => Type::Integer(bitmap::get_field(data, ty, offset)),
+            FieldOperation::Set(ty, offset, value) => {
+                let previous = bitmap::get_field(data, ty, offset);
+                // Like Redis, negative values given for unsigned fields overflow upwards
+                let value = if ty.signed {
+                    value as i128
+                } else {
+                    value as u64 as i128
+                };
+                match overflow.apply(ty, value) {
+                    Some(value) => {
+                        bitmap::set_field(data, ty, offset, value);
+                        Type::Integer(previous)
+                    }
+                    None => Type::NullString,
+                }
+            }
+            FieldOperation::IncrBy(ty, offset, increment) => {
+                let previous = bitmap::get_field(data, ty, offset);
+                match overflow.apply(ty, previous as i128 + increment as i128) {
+                    Some(value) => {
+                        bitmap::set_field(data, ty, offset, value);
+                        Type::Integer(value)
+                    }
+                    None => Type::NullString,
+                }
+            }
+        };
+        replies.push(reply);
+    }
+
+    replies
+}
+
+impl Client {
+    pub(super) async fn handle_setbit(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args.next().ok_or(Error::MissingArgument("setbit", "key"))?;
+        let offset = args
+            .next()
+            .ok_or(Error::MissingArgument("setbit", "offset"))?;
+        let value = args
+            .next()
+            .ok_or(Error::MissingArgument("setbit", "value"))?;
+        let offset = parse_bit_offset(&offset)?;
+        let value = parse_bit(&value)?;
+
+        let previous = self
+            .store
+            .update(&key, |slot| {
+                with_string(slot, |string| Ok(bitmap::set_bit(string, offset, value)))
+            })
+            .await?;
+        self.store.notify(EventFlags::STRING, "setbit", &key);
+
+        Type::Integer(previous as i64).write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_getbit(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args.next().ok_or(Error::MissingArgument("getbit", "key"))?;
+        let offset = args
+            .next()
+            .ok_or(Error::MissingArgument("getbit", "offset"))?;
+        let offset = parse_bit_offset(&offset)?;
+
+        let bit = self
+            .read_string(&key, |string| bitmap::get_bit(string, offset))
+            .await?
+            .unwrap_or(false);
+
+        Type::Integer(bit as i64).write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_bitcount(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args
+            .next()
+            .ok_or(Error::MissingArgument("bitcount", "key"))?;
+        let range = match (args.next(), args.next()) {
+            (Some(start), Some(end)) => Some((
+                parse_integer(&start)?,
+                parse_integer(&end)?,
+                parse_range_unit(args.next())?,
+            )),
+            (None, None) => None,
+            _ => return Err(Error::SyntaxError),
+        };
+        if args.next().is_some() {
+            return Err(Error::SyntaxError);
+        }
+
+        let count = self
+            .read_string(&key, |string| {
+                let (start, end, unit) = range.unwrap_or((0, -1, RangeUnit::Byte));
+                bitmap::bit_range(string.len(), start, end, unit)
+                    .map_or(0, |(start, end)| bitmap::count_ones(string, start, end))
+            })
+            .await?
+            .unwrap_or(0);
+
+        Type::Integer(count as i64).write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_bitpos(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args.next().ok_or(Error::MissingArgument("bitpos", "key"))?;
+        let bit = args.next().ok_or(Error::MissingArgument("bitpos", "bit"))?;
+        let bit = parse_bit(&bit)
+            .map_err(|_| Error::InvalidArgument("The bit argument must be 1 or 0."))?;
+        let start = args.next().map(|v| parse_integer(&v)).transpose()?;
+        let end = args.next().map(|v| parse_integer(&v)).transpose()?;
+        let unit = parse_range_unit(args.next())?;
+        if args.next().is_some() {
+            return Err(Error::SyntaxError);
+        }
+
+        let position = self
+            .read_string(&key, |string| {
+                let range =
+                    bitmap::bit_range(string.len(), start.unwrap_or(0), end.unwrap_or(-1), unit);
+                let Some((start_bit, end_bit)) = range else {
+                    return -1;
+                };
+
+                match bitmap::position(string, bit, start_bit, end_bit) {
+                    Some(position) => position as i64,
+                    // Looking for a clear bit without an explicit end finds the padding
+                    None if !bit && end.is_none() => end_bit as i64 + 1,
+                    None => -1,
+                }
+            })
+            .await?
+            .unwrap_or(if bit { -1 } else { 0 });
+
+        Type::Integer(position).write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_bitop(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let operation = args
+            .next()
+            .ok_or(Error::MissingArgument("bitop", "operation"))?;
+        let operation: BitOperation = operation.parse().map_err(|_| Error::SyntaxError)?;
+        let destination = args
+            .next()
+            .ok_or(Error::MissingArgument("bitop", "destkey"))?;
+        let keys: Vec<_> = args.collect();
+        if keys.is_empty() {
+            return Err(Error::MissingArgument("bitop", "key"));
+        }
+        if operation == BitOperation::Not && keys.len() != 1 {
+            return Err(Error::InvalidArgument(
+                "BITOP NOT must be called with a single source key.",
+            ));
+        }
+
+        let len = self
+            .store
+            .with_keyspace(|keyspace| -> Result<_> {
+                let sources = keys
+                    .iter()
+                    .map(|key| match keyspace.get(key) {
+                        Some(value) => value.as_string().ok_or(Error::ExpectedOtherType("string")),
+                        None => Ok(&[][..]),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+
+                let result = bitmap::bit_operation(operation, &sources);
+                let len = result.len();
+                if result.is_empty() {
+                    if keyspace.remove(&destination).is_some() {
+                        keyspace.notify(EventFlags::GENERIC, "del", &destination);
+                    }
+                } else {
+                    keyspace.insert(destination.clone(), Value::String(result), None);
+                    keyspace.notify(EventFlags::STRING, "set", &destination);
+                }
+                Ok(len)
+            })
+            .await?;
+
+        Type::Integer(len as i64).write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_bitfield(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+        read_only: bool,
+    ) -> Result<()> {
+        let name = if read_only { "bitfield_ro" } else { "bitfield" };
+        let key = args.next().ok_or(Error::MissingArgument(name, "key"))?;
+        let operations = FieldOperation::parse_all(args, read_only)?;
+
+        let replies = if operations.iter().any(FieldOperation::is_write) {
+            let replies = self
+                .store
+                .update(&key, |slot| {
+                    with_string(slot, |string| Ok(run_field_operations(string, &operations)))
+                })
+                .await?;
+            self.store.notify(EventFlags::STRING, "setbit", &key);
+            replies
+        } else {
+            let get_fields = |string: &[u8]| {
+                operations
+                    .iter()
+                    .filter_map(|operation| match *operation {
+                        FieldOperation::Get(ty, offset) => {
+                            Some(Type::Integer(bitmap::get_field(string, ty, offset)))
+                        }
+                        _ => None,
+                    })
+                    .collect()
+            };
+            self.read_string(&key, get_fields)
+                .await?
+                .unwrap_or_else(|| get_fields(&[]))
+        };
+
+        Type::Array(replies).write(&mut self.stream).await
+    }
+
+    /// Runs `op` on the string stored under `key`, `None` if there is no such key.
+    pub(super) async fn read_string<T>(
+        &self,
+        key: &str,
+        op: impl FnOnce(&[u8]) -> T,
+    ) -> Result<Option<T>> {
+        self.store
+            .get_ref(key, |value| {
+                value
+                    .as_string()
+                    .map(op)
+                    .ok_or(Error::ExpectedOtherType("string"))
+            })
+            .await
+            .transpose()
+    }
+}
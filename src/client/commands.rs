@@ -0,0 +1,156 @@
+use crate::{error::Error, Result};
+
+/// Number of arguments a command takes including its name, like in the Redis command table:
+/// a positive arity is exact, a negative one is the minimum. `None` for unknown commands.
+fn arity(command: &str) -> Option<i32> {
+    let arity = match command {
+        "ping" => -1,
+        "echo" => 2,
+        "get" => 2,
+        "type" => 2,
+        "set" => -3,
+        "expire" | "pexpire" | "expireat" | "pexpireat" => -3,
+        "ttl" | "pttl" | "expiretime" | "pexpiretime" | "persist" => 2,
+        "xadd" => -5,
+        "xlen" => 2,
+        "xdel" => -3,
+        "xtrim" => -4,
+        "xrange" | "xrevrange" | "xread" | "xack" => -4,
+        "xgroup" => -2,
+        "xreadgroup" => -7,
+        "xpending" => -3,
+        "xclaim" => -6,
+        "xautoclaim" => -6,
+        "xsetid" => -3,
+        "xinfo" => -2,
+        "zadd" => -4,
+        "zincrby" => 4,
+        "zrem" | "zmscore" | "zrank" | "zrevrank" => -3,
+        "zcard" => 2,
+        "zscore" => 3,
+        "zcount" | "zlexcount" => 4,
+        "zrange" | "zrevrange" | "zrangebyscore" | "zrevrangebyscore" | "zrangebylex"
+        | "zrevrangebylex" => -4,
+        "zremrangebyrank" | "zremrangebyscore" | "zremrangebylex" => 4,
+        "zunion" | "zinter" | "zdiff" => -3,
+        "zunionstore" | "zinterstore" | "zdiffstore" => -4,
+        "zrangestore" => -5,
+        "zpopmin" | "zpopmax" => -2,
+        "bzpopmin" | "bzpopmax" => -3,
+        "zmpop" => -4,
+        "bzmpop" => -5,
+        "setbit" => 4,
+        "getbit" => 3,
+        "bitcount" | "bitfield" | "bitfield_ro" => -2,
+        "bitpos" => -3,
+        "bitop" => -4,
+        "geoadd" => -5,
+        "geopos" | "geohash" => -2,
+        "geodist" => -4,
+        "geosearch" => -7,
+        "geosearchstore" => -8,
+        "pfadd" | "pfcount" | "pfmerge" => -2,
+        "keys" => 2,
+        "scan" => -2,
+        "zscan" | "hscan" | "sscan" => -3,
+        "del" | "unlink" | "exists" | "touch" => -2,
+        "rename" | "renamenx" | "move" | "swapdb" => 3,
+        "copy" => -3,
+        "randomkey" | "dbsize" | "save" => 1,
+        "select" => 2,
+        "flushdb" | "flushall" | "bgsave" => -1,
+        "multi" | "exec" | "discard" | "unwatch" => 1,
+        "watch" => -2,
+        "subscribe" | "psubscribe" | "ssubscribe" | "pubsub" => -2,
+        "unsubscribe" | "punsubscribe" | "sunsubscribe" => -1,
+        "publish" | "spublish" => 3,
+        "config" | "debug" => -2,
+        "info" | "replconf" => -1,
+        "psync" => -3,
+        "client" => -2,
+        "hello" => -1,
+        _ => return None,
+    };
+    Some(arity)
+}
+
+/// Keys a read-only command reads, which clients with client-side caching then track. `cmd`
+/// includes the command name. Commands that write return no keys, as their replies aren't
+/// cached.
+pub(super) fn read_keys(cmd: &[String]) -> &[String] {
+    let Some((name, args)) = cmd.split_first() else {
+        return &[];
+    };
+
+    let keys = match name.to_ascii_lowercase().as_str() {
+        "get" | "type" | "ttl" | "pttl" | "expiretime" | "pexpiretime" | "xrange" | "xrevrange"
+        | "xlen" | "zcard" | "zscore" | "zmscore" | "zrank" | "zrevrank" | "zcount"
+        | "zlexcount" | "zrange" | "zrevrange" | "zrangebyscore" | "zrevrangebyscore"
+        | "zrangebylex" | "zrevrangebylex" | "getbit" | "bitcount" | "bitpos" | "bitfield_ro"
+        | "geopos" | "geohash" | "geodist" | "geosearch" | "zscan" | "hscan" | "sscan" => {
+            args.get(..1)
+        }
+        "exists" | "touch" | "pfcount" => Some(args),
+        // The number of keys comes first, like in `ZUNION numkeys key [key ...]`
+        "zunion" | "zinter" | "zdiff" => args
+            .first()
+            .and_then(|numkeys| numkeys.parse::<usize>().ok())
+            .and_then(|numkeys| args.get(1..numkeys.checked_add(1)?)),
+        // The keys are the first half of the arguments after STREAMS, the IDs the second
+        "xread" => args
+            .iter()
+            .position(|arg| arg.eq_ignore_ascii_case("streams"))
+            .map(|streams| &args[streams + 1..])
+            .map(|rest| &rest[..rest.len() / 2]),
+        _ => None,
+    };
+    keys.unwrap_or_default()
+}
+
+/// Rejects unknown commands, and commands called with `args` arguments, including their
+/// name, that don't fit their arity.
+pub(super) fn check_arity(command: &str, args: usize) -> Result<()> {
+    match arity(command) {
+        None => Err(Error::UnimplementedCommand(command.into())),
+        Some(arity) if !has_valid_arity(arity, args) => Err(Error::WrongArity(command.into())),
+        Some(_) => Ok(()),
+    }
+}
+
+fn has_valid_arity(arity: i32, args: usize) -> bool {
+    if arity >= 0 {
+        args == arity as usize
+    } else {
+        args >= arity.unsigned_abs() as usize
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_arity, read_keys};
+
+    fn keys(cmd: &str) -> Vec<String> {
+        let cmd: Vec<String> = cmd.split(' ').map(String::from).collect();
+        read_keys(&cmd).to_vec()
+    }
+
+    #[test]
+    fn keys_of_read_only_commands() {
+        assert_eq!(keys("GET a"), ["a"]);
+        assert_eq!(keys("zrange a 0 -1"), ["a"]);
+        assert_eq!(keys("EXISTS a b"), ["a", "b"]);
+        assert_eq!(keys("ZUNION 2 a b WITHSCORES"), ["a", "b"]);
+        assert_eq!(keys("ZUNION 3 a b"), Vec::<String>::new());
+        assert_eq!(keys("XREAD COUNT 2 STREAMS a b 0 0"), ["a", "b"]);
+        assert_eq!(keys("SET a 1"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn arity_of_commands() {
+        assert!(check_arity("get", 2).is_ok());
+        assert!(check_arity("get", 3).is_err());
+        assert!(check_arity("del", 4).is_ok());
+        assert!(check_arity("config", 1).is_err());
+        assert!(check_arity("nope", 1).is_err());
+    }
+}
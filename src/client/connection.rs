@@ -0,0 +1,279 @@
+use super::{commands, Client};
+use crate::{error::Error, resp::Type, store::TrackingOptions, Result};
+
+/// Version reported by HELLO, the Redis release whose behaviour the server follows.
+const VERSION: &str = "7.4.0";
+
+impl Client {
+    /// Wraps out-of-band data like published messages: a push frame for RESP3 clients, an array
+    /// for RESP2 ones.
+    pub(super) fn push(&self, items: Vec<Type>) -> Type {
+        if self.protocol == 3 {
+            Type::Push(items)
+        } else {
+            Type::Array(items)
+        }
+    }
+
+    /// `HELLO [protover]`, switching to RESP2 or RESP3 and describing the connection.
+    pub(super) async fn handle_hello(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        if let Some(version) = args.next() {
+            self.protocol = match version.parse::<i64>() {
+                Ok(2) => 2,
+                Ok(3) => 3,
+                Ok(_) => return Err(Error::NoProto),
+                Err(_) => {
+                    return Err(Error::InvalidArgument(
+                        "Protocol version is not an integer or out of range",
+                    ))
+                }
+            };
+        }
+        if args.next().is_some() {
+            return Err(Error::SyntaxError);
+        }
+
+        let mode = if self.store.get_config("cluster-enabled") == Some("yes") {
+            "cluster"
+        } else {
+            "standalone"
+        };
+        let role = self.store.info().await.role().to_string();
+        let fields = [
+            ("server", Type::BulkString("redis".into())),
+            ("version", Type::BulkString(VERSION.into())),
+            ("proto", Type::Integer(self.protocol.into())),
+            ("id", Type::Integer(self.id as i64)),
+            ("mode", Type::BulkString(mode.into())),
+            ("role", Type::BulkString(role)),
+            ("modules", Type::Array(Vec::new())),
+        ]
+        .map(|(name, value)| (Type::BulkString(name.into()), value));
+
+        let reply = if self.protocol == 3 {
+            Type::Map(fields.into())
+        } else {
+            Type::Array(fields.into_iter().flat_map(|(k, v)| [k, v]).collect())
+        };
+        reply.write(&mut self.stream).await
+    }
+
+    /// `CLIENT ID`, `CLIENT TRACKING`, `CLIENT CACHING` and `CLIENT GETREDIR`
+    pub(super) async fn handle_client(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let subcmd = args
+            .next()
+            .ok_or(Error::MissingArgument("client", "subcommand"))?
+            .to_ascii_lowercase();
+
+        let reply = match subcmd.as_str() {
+            "id" | "getredir" => {
+                if args.next().is_some() {
+                    return Err(Error::WrongArity(format!("client|{subcmd}")));
+                }
+                if subcmd == "id" {
+                    Type::Integer(self.id as i64)
+                } else {
+                    Type::Integer(self.store.tracking().redirect(self.id))
+                }
+            }
+            "tracking" => {
+                self.client_tracking(args)?;
+                Type::SimpleString("OK".into())
+            }
+            "caching" => {
+                self.client_caching(args)?;
+                Type::SimpleString("OK".into())
+            }
+            cmd => return Err(Error::UnimplementedCommand(format!("CLIENT {cmd}"))),
+        };
+
+        reply.write(&mut self.stream).await
+    }
+
+    /// `CLIENT TRACKING ON|OFF [REDIRECT id] [PREFIX prefix ...] [BCAST] [OPTIN] [OPTOUT]
+    /// [NOLOOP]`. Enabling tracking again adds prefixes to the ones already broadcast, but the
+    /// mode can only change after turning tracking off.
+    fn client_tracking(&mut self, mut args: impl Iterator<Item = String>) -> Result<()> {
+        let on = match args.next().map(|arg| arg.to_ascii_lowercase()).as_deref() {
+            Some("on") => true,
+            Some("off") => false,
+            _ => return Err(Error::SyntaxError),
+        };
+
+        let mut options = TrackingOptions::default();
+        while let Some(arg) = args.next() {
+            match arg.to_ascii_lowercase().as_str() {
+                "redirect" => {
+                    let id = args.next().ok_or(Error::SyntaxError)?;
+                    options.redirect = Some(id.parse().map_err(|_| Error::NotAnInteger)?);
+                }
+                "prefix" => options
+                    .prefixes
+                    .push(args.next().ok_or(Error::SyntaxError)?),
+                "bcast" => options.bcast = true,
+                "optin" => options.optin = true,
+                "optout" => options.optout = true,
+                "noloop" => options.noloop = true,
+                _ => return Err(Error::SyntaxError),
+            }
+        }
+
+        if !on {
+            self.store.tracking().disable(self.id);
+            self.tracking = None;
+            self.caching = None;
+            return Ok(());
+        }
+
+        if !options.bcast && !options.prefixes.is_empty() {
+            return Err(Error::InvalidArgument(
+                "PREFIX option requires BCAST mode to be enabled",
+            ));
+        }
+        if let Some(current) = &self.tracking {
+            if current.bcast != options.bcast {
+                return Err(Error::InvalidArgument("You can't switch BCAST mode on/off before disabling tracking for this client, and then re-enabling it with a different mode."));
+            }
+            if (current.optin, current.optout) != (options.optin, options.optout) {
+                return Err(Error::InvalidArgument("You can't switch OPTIN/OPTOUT mode before disabling tracking for this client, and then re-enabling it with a different mode."));
+            }
+        }
+        if options.bcast && (options.optin || options.optout) {
+            return Err(Error::InvalidArgument(
+                "OPTIN and OPTOUT are not compatible with BCAST",
+            ));
+        }
+        if options.optin && options.optout {
+            return Err(Error::InvalidArgument(
+                "You can't use both OPTIN and OPTOUT",
+            ));
+        }
+
+        let current = self
+            .tracking
+            .as_ref()
+            .map_or(&[][..], |current| &current.prefixes);
+        options.prefixes.retain(|prefix| !current.contains(prefix));
+        for (i, prefix) in options.prefixes.iter().enumerate() {
+            let others = current.iter().chain(&options.prefixes[i + 1..]);
+            for other in others {
+                if prefix.starts_with(other.as_str()) || other.starts_with(prefix.as_str()) {
+                    return Err(Error::OverlappingPrefixes(prefix.clone(), other.clone()));
+                }
+            }
+        }
+        options.prefixes.splice(0..0, current.iter().cloned());
+
+        if !self.store.tracking().enable(self.id, options.clone()) {
+            return Err(Error::InvalidArgument(
+                "The client ID you want redirect to does not exist",
+            ));
+        }
+        self.tracking = Some(options);
+        Ok(())
+    }
+
+    /// `CLIENT CACHING YES|NO`, deciding whether the keys the next command reads are tracked
+    /// in OPTIN or OPTOUT mode.
+    fn client_caching(&mut self, mut args: impl Iterator<Item = String>) -> Result<()> {
+        let yes = match args.next().map(|arg| arg.to_ascii_lowercase()).as_deref() {
+            Some("yes") => true,
+            Some("no") => false,
+            _ => return Err(Error::SyntaxError),
+        };
+        if args.next().is_some() {
+            return Err(Error::SyntaxError);
+        }
+
+        match &self.tracking {
+            Some(options) if options.optin || options.optout => {}
+            _ => return Err(Error::InvalidArgument("CLIENT CACHING can be called only when the client is in tracking mode with OPTIN or OPTOUT mode enabled")),
+        }
+        match &self.tracking {
+            Some(options) if yes && !options.optin => {
+                return Err(Error::InvalidArgument(
+                    "CLIENT CACHING YES is only valid when tracking is enabled in OPTIN mode.",
+                ))
+            }
+            Some(options) if !yes && !options.optout => {
+                return Err(Error::InvalidArgument(
+                    "CLIENT CACHING NO is only valid when tracking is enabled in OPTOUT mode.",
+                ))
+            }
+            _ => {}
+        }
+
+        self.caching = Some(yes);
+        Ok(())
+    }
+
+    /// Remembers the keys a read-only command is about to read, if the client tracks them.
+    /// BCAST clients are told about all keys matching their prefixes instead.
+    pub(super) fn track_reads(&self, cmd: &[Vec<u8>]) {
+        let Some(options) = &self.tracking else {
+            return;
+        };
+        let tracked = if options.optin {
+            self.caching == Some(true)
+        } else if options.optout {
+            self.caching != Some(false)
+        } else {
+            !options.bcast
+        };
+
+        // Keys are text, the values that may not be don't matter here
+        let cmd: Vec<String> = cmd
+            .iter()
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+        let keys = commands::read_keys(&cmd);
+        if tracked && !keys.is_empty() {
+            self.store
+                .tracking()
+                .remember(self.id, keys.iter().cloned());
+        }
+    }
+
+    /// Tells the client that cached keys changed, `None` meaning all of them. RESP2 clients
+    /// can only receive invalidations through Pub/Sub, as messages of the `__redis__:invalidate`
+    /// channel, so they're dropped for clients that aren't subscribed.
+    pub(super) async fn write_invalidation(&mut self, keys: Option<Vec<String>>) -> Result<()> {
+        let keys = match keys {
+            Some(keys) => Type::Array(keys.into_iter().map(Type::BulkString).collect()),
+            None if self.protocol == 3 => Type::Null,
+            None => Type::NullString,
+        };
+
+        let reply = if self.protocol == 3 {
+            Type::Push(vec![Type::BulkString("invalidate".into()), keys])
+        } else if self.is_subscribed() {
+            Type::Array(vec![
+                Type::BulkString("message".into()),
+                Type::BulkString("__redis__:invalidate".into()),
+                keys,
+            ])
+        } else {
+            return Ok(());
+        };
+        reply.write(&mut self.stream).await
+    }
+
+    /// Tells a RESP3 client that the client its invalidations were redirected to is gone.
+    pub(super) async fn write_redirect_broken(&mut self, redirect: u64) -> Result<()> {
+        if self.protocol != 3 {
+            return Ok(());
+        }
+        Type::Push(vec![
+            Type::BulkString("tracking-redir-broken".into()),
+            Type::Integer(redirect as i64),
+        ])
+        .write(&mut self.stream)
+        .await
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use super::{parse_integer, Client};
+use crate::{clock::ManualClock, error::Error, resp::Type, Result};
+
+impl Client {
+    /// The DEBUG hooks tests use to control time: SET-ACTIVE-EXPIRE and CLOCK. Other subcommands,
+    /// like the JMAP one of Redis, are not implemented.
+    pub(super) async fn handle_debug(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let subcmd = args.next().map(|s| s.to_ascii_lowercase());
+        match subcmd.as_deref() {
+            Some("set-active-expire") => {
+                let enabled = args
+                    .next()
+                    .ok_or(Error::MissingArgument("debug set-active-expire", "flag"))?;
+                self.store.set_active_expire(parse_integer(&enabled)? != 0);
+                Type::SimpleString("OK".into())
+                    .write(&mut self.stream)
+                    .await
+            }
+            Some("clock") => self.handle_debug_clock(args).await,
+            Some(cmd) => Err(Error::UnimplementedCommand(format!("DEBUG {cmd}"))),
+            None => Err(Error::MissingArgument("debug", "subcommand")),
+        }
+    }
+
+    /// `DEBUG CLOCK GET|SET unix-time-ms|ADVANCE ms`, moving time for keys, stream IDs and the
+    /// timeouts of blocked clients when the server was started with `--clock manual`.
+    async fn handle_debug_clock(&mut self, mut args: impl Iterator<Item = String>) -> Result<()> {
+        let subcmd = args
+            .next()
+            .ok_or(Error::MissingArgument("debug clock", "subcommand"))?
+            .to_ascii_lowercase();
+
+        if subcmd == "get" {
+            let now = self.store.now().duration_since(UNIX_EPOCH)?.as_millis();
+            return Type::Integer(now as i64).write(&mut self.stream).await;
+        }
+
+        let value = args
+            .next()
+            .ok_or(Error::MissingArgument("debug clock", "milliseconds"))?;
+        let value = parse_integer(&value)?;
+        let value = u64::try_from(value).map_err(|_| Error::NotAnInteger)?;
+        let clock: &ManualClock = self
+            .store
+            .clock()
+            .as_manual()
+            .ok_or(Error::InvalidArgument(
+                "the clock can only be changed when the server runs with --clock manual",
+            ))?;
+
+        match subcmd.as_str() {
+            "set" => clock.set(UNIX_EPOCH + Duration::from_millis(value)),
+            "advance" => clock.advance(Duration::from_millis(value)),
+            _ => return Err(Error::SyntaxError),
+        }
+        Type::SimpleString("OK".into())
+            .write(&mut self.stream)
+            .await
+    }
+}
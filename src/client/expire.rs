@@ -0,0 +1,203 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{parse_integer, Client};
+use crate::{error::Error, resp::Type, store::EventFlags, Result};
+
+/// How the time argument of the EXPIRE family is interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ExpireTime {
+    Seconds,
+    Milliseconds,
+    UnixSeconds,
+    UnixMilliseconds,
+}
+
+impl ExpireTime {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Seconds => "expire",
+            Self::Milliseconds => "pexpire",
+            Self::UnixSeconds => "expireat",
+            Self::UnixMilliseconds => "pexpireat",
+        }
+    }
+
+    /// Absolute expiration time in milliseconds since the epoch, which may be negative.
+    fn to_unix_millis(self, value: i64, now: SystemTime) -> Result<i64> {
+        let overflow = || Error::InvalidExpireTime(self.name());
+        let value = match self {
+            Self::Seconds | Self::UnixSeconds => value.checked_mul(1000).ok_or_else(overflow)?,
+            Self::Milliseconds | Self::UnixMilliseconds => value,
+        };
+
+        match self {
+            Self::Seconds | Self::Milliseconds => {
+                value.checked_add(unix_millis(now)).ok_or_else(overflow)
+            }
+            Self::UnixSeconds | Self::UnixMilliseconds => Ok(value),
+        }
+    }
+}
+
+/// The NX, XX, GT and LT flags of the EXPIRE family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct ExpireCondition {
+    nx: bool,
+    xx: bool,
+    gt: bool,
+    lt: bool,
+}
+
+impl ExpireCondition {
+    fn parse(args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut condition = Self::default();
+        for arg in args {
+            match arg.to_ascii_uppercase().as_str() {
+                "NX" => condition.nx = true,
+                "XX" => condition.xx = true,
+                "GT" => condition.gt = true,
+                "LT" => condition.lt = true,
+                _ => return Err(Error::SyntaxError),
+            }
+        }
+
+        if condition.nx && (condition.xx || condition.gt || condition.lt) {
+            return Err(Error::InvalidArgument(
+                "NX and XX, GT or LT options at the same time are not compatible",
+            ));
+        }
+        if condition.gt && condition.lt {
+            return Err(Error::InvalidArgument(
+                "GT and LT options at the same time are not compatible",
+            ));
+        }
+        Ok(condition)
+    }
+
+    /// Keys without a TTL are treated as having an infinite one by GT and LT.
+    fn allows(&self, current: Option<i64>, new: i64) -> bool {
+        !(self.nx && current.is_some()
+            || self.xx && current.is_none()
+            || self.gt && current.is_none_or(|current| new <= current)
+            || self.lt && current.is_some_and(|current| new >= current))
+    }
+}
+
+pub(super) fn unix_millis(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_millis() as i64,
+        Err(err) => -(err.duration().as_millis() as i64),
+    }
+}
+
+fn from_unix_millis(millis: i64) -> SystemTime {
+    if millis >= 0 {
+        UNIX_EPOCH + Duration::from_millis(millis as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_millis(millis.unsigned_abs())
+    }
+}
+
+/// Which value TTL and its variants reply with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum TtlOutput {
+    Remaining,
+    Absolute,
+}
+
+impl Client {
+    pub(super) async fn handle_expire(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+        time: ExpireTime,
+    ) -> Result<()> {
+        let key = args
+            .next()
+            .ok_or(Error::MissingArgument(time.name(), "key"))?;
+        let value = args
+            .next()
+            .ok_or(Error::MissingArgument(time.name(), "time"))?;
+        let value = parse_integer(&value)?;
+        let condition = ExpireCondition::parse(args)?;
+
+        let now = self.store.now();
+        let expires_at = time.to_unix_millis(value, now)?;
+
+        let updated = self
+            .store
+            .with_keyspace(|keyspace| {
+                let Some(current) = keyspace.expires_at(&key) else {
+                    return false;
+                };
+                if !condition.allows(current.map(unix_millis), expires_at) {
+                    return false;
+                }
+                keyspace.set_expires_at(&key, Some(from_unix_millis(expires_at)));
+                // An expiration time in the past deletes the key right away
+                if keyspace.contains(&key) {
+                    keyspace.notify(EventFlags::GENERIC, "expire", &key);
+                } else {
+                    keyspace.notify(EventFlags::GENERIC, "del", &key);
+                }
+                true
+            })
+            .await;
+
+        Type::Integer(updated as i64).write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_ttl(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+        output: TtlOutput,
+        millis: bool,
+    ) -> Result<()> {
+        let key = args.next().ok_or(Error::MissingArgument("ttl", "key"))?;
+
+        let now = self.store.now();
+        let reply = match self
+            .store
+            .with_keyspace(|keyspace| keyspace.expires_at(&key))
+            .await
+        {
+            None => -2,
+            Some(None) => -1,
+            Some(Some(expires_at)) => {
+                let value = match output {
+                    TtlOutput::Remaining => (unix_millis(expires_at) - unix_millis(now)).max(0),
+                    TtlOutput::Absolute => unix_millis(expires_at),
+                };
+                if millis {
+                    value
+                } else {
+                    (value + 500) / 1000
+                }
+            }
+        };
+
+        Type::Integer(reply).write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_persist(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args
+            .next()
+            .ok_or(Error::MissingArgument("persist", "key"))?;
+
+        let removed = self
+            .store
+            .with_keyspace(|keyspace| match keyspace.expires_at(&key) {
+                Some(Some(_)) => {
+                    keyspace.set_expires_at(&key, None);
+                    keyspace.notify(EventFlags::GENERIC, "persist", &key);
+                    true
+                }
+                _ => false,
+            })
+            .await;
+
+        Type::Integer(removed as i64).write(&mut self.stream).await
+    }
+}
@@ -0,0 +1,492 @@
+use std::ops::Bound;
+
+use super::{
+    parse_double, parse_integer,
+    sorted_set::{add_to_set, store_sorted_set, with_sorted_set, AddFlags},
+    Client,
+};
+use crate::{
+    error::Error,
+    geo::{self, Shape, Unit},
+    resp::Type,
+    sorted_set::SortedSet,
+    store::EventFlags,
+    Result,
+};
+
+fn parse_unit(value: Option<String>) -> Result<Unit> {
+    value
+        .and_then(|unit| unit.parse().ok())
+        .ok_or(Error::InvalidArgument(
+            "unsupported unit provided. please use M, KM, FT, MI",
+        ))
+}
+
+fn parse_coordinates(longitude: &str, latitude: &str) -> Result<(f64, f64)> {
+    let (longitude, latitude) = (parse_double(longitude)?, parse_double(latitude)?);
+    if !geo::is_valid(longitude, latitude) {
+        return Err(Error::InvalidCoordinates(longitude, latitude));
+    }
+    Ok((longitude, latitude))
+}
+
+/// Coordinates are printed with 17 decimals and trailing zeroes removed, like Redis does.
+fn format_coordinate(value: f64) -> String {
+    let formatted = format!("{value:.17}");
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_owned()
+}
+
+fn format_distance(meters: f64, unit: Unit) -> String {
+    format!("{:.4}", unit.from_meters(meters))
+}
+
+fn coordinates_reply((longitude, latitude): (f64, f64)) -> Type {
+    Type::Array(vec![
+        Type::BulkString(format_coordinate(longitude)),
+        Type::BulkString(format_coordinate(latitude)),
+    ])
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Origin {
+    Member(String),
+    Coordinates(f64, f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Order {
+    Unsorted,
+    Ascending,
+    Descending,
+}
+
+/// The options shared by GEOSEARCH and GEOSEARCHSTORE.
+#[derive(Debug, Clone)]
+struct SearchQuery {
+    origin: Origin,
+    shape: Shape,
+    unit: Unit,
+    order: Order,
+    count: Option<usize>,
+    any: bool,
+    with_coordinates: bool,
+    with_distance: bool,
+    with_hash: bool,
+    store_distance: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Found {
+    member: String,
+    hash: u64,
+    distance: f64,
+    coordinates: (f64, f64),
+}
+
+impl SearchQuery {
+    fn parse(mut args: impl Iterator<Item = String>, store: bool) -> Result<Self> {
+        let mut origin = None;
+        let mut shape = None;
+        let mut unit = Unit::Meters;
+        let mut order = Order::Unsorted;
+        let mut count = None;
+        let mut any = false;
+        let (mut with_coordinates, mut with_distance, mut with_hash) = (false, false, false);
+        let mut store_distance = false;
+
+        let multiple_origins = Error::InvalidArgument(if store {
+            "exactly one of FROMMEMBER or FROMLONLAT can be specified for 'geosearchstore' command"
+        } else {
+            "exactly one of FROMMEMBER or FROMLONLAT can be specified for 'geosearch' command"
+        });
+        let multiple_shapes = Error::InvalidArgument(if store {
+            "exactly one of BYRADIUS and BYBOX can be specified for 'geosearchstore' command"
+        } else {
+            "exactly one of BYRADIUS and BYBOX can be specified for 'geosearch' command"
+        });
+
+        while let Some(arg) = args.next() {
+            let mut next = || args.next().ok_or(Error::SyntaxError);
+            match arg.to_ascii_uppercase().as_str() {
+                "FROMMEMBER" => {
+                    if origin.is_some() {
+                        return Err(multiple_origins);
+                    }
+                    origin = Some(Origin::Member(next()?));
+                }
+                "FROMLONLAT" => {
+                    if origin.is_some() {
+                        return Err(multiple_origins);
+                    }
+                    let (longitude, latitude) = parse_coordinates(&next()?, &next()?)?;
+                    origin = Some(Origin::Coordinates(longitude, latitude));
+                }
+                "BYRADIUS" => {
+                    if shape.is_some() {
+                        return Err(multiple_shapes);
+                    }
+                    let radius = parse_double(&next()?)?;
+                    if radius < 0.0 {
+                        return Err(Error::InvalidArgument("radius cannot be negative"));
+                    }
+                    unit = parse_unit(next().ok())?;
+                    shape = Some(Shape::Radius(unit.to_meters(radius)));
+                }
+                "BYBOX" => {
+                    if shape.is_some() {
+                        return Err(multiple_shapes);
+                    }
+                    let (width, height) = (parse_double(&next()?)?, parse_double(&next()?)?);
+                    if width < 0.0 || height < 0.0 {
+                        return Err(Error::InvalidArgument("height or width cannot be negative"));
+                    }
+                    unit = parse_unit(next().ok())?;
+                    shape = Some(Shape::Box {
+                        width: unit.to_meters(width),
+                        height: unit.to_meters(height),
+                    });
+                }
+                "ASC" => order = Order::Ascending,
+                "DESC" => order = Order::Descending,
+                "COUNT" => {
+                    let value = parse_integer(&next()?)?;
+                    if value <= 0 {
+                        return Err(Error::InvalidArgument("COUNT must be > 0"));
+                    }
+                    count = Some(value as usize);
+                }
+                "ANY" => any = true,
+                "WITHCOORD" if !store => with_coordinates = true,
+                "WITHDIST" if !store => with_distance = true,
+                "WITHHASH" if !store => with_hash = true,
+                "STOREDIST" if store => store_distance = true,
+                _ => return Err(Error::SyntaxError),
+            }
+        }
+
+        let origin = origin.ok_or(Error::InvalidArgument(if store {
+            "exactly one of FROMMEMBER or FROMLONLAT must be provided for 'geosearchstore' command"
+        } else {
+            "exactly one of FROMMEMBER or FROMLONLAT must be provided for 'geosearch' command"
+        }))?;
+        let shape = shape.ok_or(Error::InvalidArgument(if store {
+            "exactly one of BYRADIUS and BYBOX must be provided for 'geosearchstore' command"
+        } else {
+            "exactly one of BYRADIUS and BYBOX must be provided for 'geosearch' command"
+        }))?;
+        if any && count.is_none() {
+            return Err(Error::InvalidArgument(
+                "the ANY argument requires COUNT argument",
+            ));
+        }
+        // The closest entries can only be found by sorting, unless any of them will do
+        if count.is_some() && !any && order == Order::Unsorted {
+            order = Order::Ascending;
+        }
+
+        Ok(Self {
+            origin,
+            shape,
+            unit,
+            order,
+            count,
+            any,
+            with_coordinates,
+            with_distance,
+            with_hash,
+            store_distance,
+        })
+    }
+
+    fn run(&self, set: &SortedSet) -> Result<Vec<Found>> {
+        let center = match &self.origin {
+            Origin::Coordinates(longitude, latitude) => (*longitude, *latitude),
+            Origin::Member(member) => set
+                .score(member)
+                .map(|score| geo::decode(score as u64))
+                .ok_or(Error::InvalidArgument(
+                    "could not decode requested zset member",
+                ))?,
+        };
+
+        let limit = self.count.filter(|_| self.any).unwrap_or(usize::MAX);
+        let mut found = Vec::new();
+        'ranges: for (min, max) in geo::search_ranges(center, self.shape) {
+            let Some((start, end)) =
+                set.ranks_by_score(Bound::Included(min as f64), Bound::Excluded(max as f64))
+            else {
+                continue;
+            };
+
+            for (member, score) in set.range_by_rank(start, end, false) {
+                let coordinates = geo::decode(score as u64);
+                if let Some(distance) = self.shape.distance_if_within(center, coordinates) {
+                    found.push(Found {
+                        member: member.to_owned(),
+                        hash: score as u64,
+                        distance,
+                        coordinates,
+                    });
+                    if found.len() >= limit {
+                        break 'ranges;
+                    }
+                }
+            }
+        }
+
+        match self.order {
+            Order::Unsorted => {}
+            Order::Ascending => found.sort_by(|a, b| a.distance.total_cmp(&b.distance)),
+            Order::Descending => found.sort_by(|a, b| b.distance.total_cmp(&a.distance)),
+        }
+        if let Some(count) = self.count {
+            found.truncate(count);
+        }
+        Ok(found)
+    }
+
+    fn reply(&self, found: Vec<Found>) -> Type {
+        let detailed = self.with_distance || self.with_hash || self.with_coordinates;
+        Type::Array(
+            found
+                .into_iter()
+                .map(|found| {
+                    let member = Type::BulkString(found.member);
+                    if !detailed {
+                        return member;
+                    }
+
+                    let mut reply = vec![member];
+                    if self.with_distance {
+                        reply.push(Type::BulkString(format_distance(found.distance, self.unit)));
+                    }
+                    if self.with_hash {
+                        reply.push(Type::Integer(found.hash as i64));
+                    }
+                    if self.with_coordinates {
+                        reply.push(coordinates_reply(found.coordinates));
+                    }
+                    Type::Array(reply)
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Client {
+    pub(super) async fn handle_geoadd(&mut self, args: impl Iterator<Item = String>) -> Result<()> {
+        let mut args = args.peekable();
+        let key = args.next().ok_or(Error::MissingArgument("geoadd", "key"))?;
+
+        let mut flags = AddFlags::default();
+        while let Some(arg) = args.peek() {
+            match arg.to_ascii_uppercase().as_str() {
+                "NX" => flags.nx = true,
+                "XX" => flags.xx = true,
+                "CH" => flags.ch = true,
+                _ => break,
+            }
+            args.next();
+        }
+
+        let rest: Vec<_> = args.collect();
+        if rest.is_empty() || rest.len() % 3 != 0 {
+            return Err(Error::InvalidArgument(
+                "syntax error. Try GEOADD key [x1] [y1] [name1] [x2] [y2] [name2] ... ",
+            ));
+        }
+        flags.validate(rest.len() / 3)?;
+
+        let pairs = rest
+            .chunks(3)
+            .map(|triple| {
+                let (longitude, latitude) = parse_coordinates(&triple[0], &triple[1])?;
+                let hash = geo::encode(longitude, latitude)
+                    .ok_or(Error::InvalidCoordinates(longitude, latitude))?;
+                Ok((hash as f64, triple[2].clone()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let create = !flags.xx;
+        let outcome = self
+            .store
+            .update(&key, |slot| {
+                with_sorted_set(slot, create, |set| add_to_set(set, &flags, pairs))
+            })
+            .await?
+            .unwrap_or_default();
+        if outcome.added + outcome.changed > 0 {
+            self.store.notify(EventFlags::ZSET, "zadd", &key);
+        }
+
+        let reply = if flags.ch {
+            outcome.added + outcome.changed
+        } else {
+            outcome.added
+        };
+        Type::Integer(reply).write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_geopos(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args.next().ok_or(Error::MissingArgument("geopos", "key"))?;
+        let members: Vec<_> = args.collect();
+
+        let positions = self
+            .read_sorted_set(&key, |set| {
+                members
+                    .iter()
+                    .map(|member| set.score(member).map(|score| geo::decode(score as u64)))
+                    .collect::<Vec<_>>()
+            })
+            .await?
+            .unwrap_or_else(|| vec![None; members.len()]);
+
+        Type::Array(
+            positions
+                .into_iter()
+                .map(|position| position.map_or(Type::NullArray, coordinates_reply))
+                .collect(),
+        )
+        .write(&mut self.stream)
+        .await
+    }
+
+    pub(super) async fn handle_geodist(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args
+            .next()
+            .ok_or(Error::MissingArgument("geodist", "key"))?;
+        let from = args
+            .next()
+            .ok_or(Error::MissingArgument("geodist", "member1"))?;
+        let to = args
+            .next()
+            .ok_or(Error::MissingArgument("geodist", "member2"))?;
+        let unit = match args.next() {
+            Some(unit) => parse_unit(Some(unit))?,
+            None => Unit::Meters,
+        };
+        if args.next().is_some() {
+            return Err(Error::SyntaxError);
+        }
+
+        let distance = self
+            .read_sorted_set(&key, |set| {
+                let from = geo::decode(set.score(&from)? as u64);
+                let to = geo::decode(set.score(&to)? as u64);
+                Some(geo::distance(from, to))
+            })
+            .await?
+            .flatten();
+
+        distance
+            .map_or(Type::NullString, |distance| {
+                Type::BulkString(format_distance(distance, unit))
+            })
+            .write(&mut self.stream)
+            .await
+    }
+
+    pub(super) async fn handle_geohash(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args
+            .next()
+            .ok_or(Error::MissingArgument("geohash", "key"))?;
+        let members: Vec<_> = args.collect();
+
+        let hashes = self
+            .read_sorted_set(&key, |set| {
+                members
+                    .iter()
+                    .map(|member| {
+                        set.score(member)
+                            .map(|score| geo::hash_string(score as u64))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .await?
+            .unwrap_or_else(|| vec![None; members.len()]);
+
+        Type::Array(
+            hashes
+                .into_iter()
+                .map(|hash| hash.map_or(Type::NullString, Type::BulkString))
+                .collect(),
+        )
+        .write(&mut self.stream)
+        .await
+    }
+
+    pub(super) async fn handle_geosearch(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args
+            .next()
+            .ok_or(Error::MissingArgument("geosearch", "key"))?;
+        let query = SearchQuery::parse(args, false)?;
+
+        let found = self
+            .read_sorted_set(&key, |set| query.run(set))
+            .await?
+            .transpose()?
+            .unwrap_or_default();
+
+        query.reply(found).write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_geosearchstore(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let destination = args
+            .next()
+            .ok_or(Error::MissingArgument("geosearchstore", "destination"))?;
+        let source = args
+            .next()
+            .ok_or(Error::MissingArgument("geosearchstore", "source"))?;
+        let query = SearchQuery::parse(args, true)?;
+
+        let len = self
+            .store
+            .with_keyspace(|keyspace| {
+                let found = match keyspace.get(&source) {
+                    Some(value) => query.run(
+                        value
+                            .as_sorted_set()
+                            .ok_or(Error::ExpectedOtherType("zset"))?,
+                    )?,
+                    None => Vec::new(),
+                };
+
+                let mut set = SortedSet::new();
+                for found in found {
+                    let score = if query.store_distance {
+                        query.unit.from_meters(found.distance)
+                    } else {
+                        found.hash as f64
+                    };
+                    set.insert(found.member, score);
+                }
+                Ok::<_, Error>(store_sorted_set(
+                    keyspace,
+                    destination,
+                    set,
+                    "geosearchstore",
+                ))
+            })
+            .await?;
+
+        Type::Integer(len as i64).write(&mut self.stream).await
+    }
+}
@@ -0,0 +1,136 @@
+use super::{utf8_arg, Client};
+use crate::{
+    error::Error,
+    hyperloglog::{self, DEFAULT_SPARSE_MAX_BYTES, REGISTERS},
+    resp::Type,
+    store::{EventFlags, Keyspace, Value},
+    Result,
+};
+
+/// Merges the registers of every existing key in `keys`. Returns whether any of them was dense.
+fn merge_keys<'a>(
+    keyspace: &Keyspace,
+    keys: impl IntoIterator<Item = &'a String>,
+    registers: &mut [u8],
+) -> Result<bool> {
+    let mut dense = false;
+    for key in keys {
+        let Some(value) = keyspace.get(key) else {
+            continue;
+        };
+        let data = value
+            .as_string()
+            .ok_or(Error::ExpectedOtherType("string"))?;
+        hyperloglog::merge_registers(registers, data)?;
+        dense |= hyperloglog::is_dense(data);
+    }
+    Ok(dense)
+}
+
+fn as_hyperloglog(value: &mut Value) -> Result<&mut Vec<u8>> {
+    value
+        .as_string_mut()
+        .ok_or(Error::ExpectedOtherType("string"))
+}
+
+impl Client {
+    fn sparse_max_bytes(&self) -> usize {
+        self.store
+            .get_config("hll-sparse-max-bytes")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_SPARSE_MAX_BYTES)
+    }
+
+    pub(super) async fn handle_pfadd(
+        &mut self,
+        mut args: impl Iterator<Item = Vec<u8>>,
+    ) -> Result<()> {
+        let key = utf8_arg(args.next().ok_or(Error::MissingArgument("pfadd", "key"))?)?;
+        let sparse_max_bytes = self.sparse_max_bytes();
+
+        let updated = self
+            .store
+            .update(&key, |slot| -> Result<bool> {
+                let mut updated = slot.is_none();
+                let data =
+                    as_hyperloglog(slot.get_or_insert_with(|| Value::String(hyperloglog::new())))?;
+                for element in args {
+                    updated |= hyperloglog::add(data, &element, sparse_max_bytes)?;
+                }
+                Ok(updated)
+            })
+            .await?;
+        if updated {
+            self.store.notify(EventFlags::STRING, "pfadd", &key);
+        }
+
+        Type::Integer(updated as i64).write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_pfcount(
+        &mut self,
+        args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let keys: Vec<String> = args.collect();
+
+        let count = match keys.as_slice() {
+            [] => return Err(Error::MissingArgument("pfcount", "key")),
+            // A single key has its cardinality cached in the value itself
+            [key] => {
+                self.store
+                    .update(key, |slot| match slot {
+                        Some(value) => Ok(hyperloglog::count(as_hyperloglog(value)?)?),
+                        None => Ok::<_, Error>(0),
+                    })
+                    .await?
+            }
+            keys => {
+                self.store
+                    .with_keyspace(|keyspace| {
+                        let mut registers = vec![0; REGISTERS];
+                        merge_keys(keyspace, keys, &mut registers)?;
+                        Ok::<_, Error>(hyperloglog::count_registers(&registers))
+                    })
+                    .await?
+            }
+        };
+
+        Type::Integer(count as i64).write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_pfmerge(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let destination = args
+            .next()
+            .ok_or(Error::MissingArgument("pfmerge", "destkey"))?;
+        let sources: Vec<String> = args.collect();
+        let sparse_max_bytes = self.sparse_max_bytes();
+
+        self.store
+            .with_keyspace(|keyspace| {
+                let mut registers = vec![0; REGISTERS];
+                let dense = merge_keys(
+                    keyspace,
+                    std::iter::once(&destination).chain(&sources),
+                    &mut registers,
+                )?;
+
+                keyspace.update(&destination, |slot| {
+                    let data = as_hyperloglog(
+                        slot.get_or_insert_with(|| Value::String(hyperloglog::new())),
+                    )?;
+                    hyperloglog::set_registers(data, &registers, dense, sparse_max_bytes)?;
+                    Ok::<_, Error>(())
+                })?;
+                keyspace.notify(EventFlags::STRING, "pfadd", &destination);
+                Ok::<_, Error>(())
+            })
+            .await?;
+
+        Type::SimpleString("OK".into())
+            .write(&mut self.stream)
+            .await
+    }
+}
@@ -0,0 +1,306 @@
+use super::{parse_integer, Client};
+use crate::{error::Error, resp::Type, store::EventFlags, Result};
+
+impl Client {
+    /// DEL and UNLINK. UNLINK removes the keys right away but drops their values on a blocking
+    /// task, so freeing a large value doesn't hold up the connection.
+    pub(super) async fn handle_del(
+        &mut self,
+        args: impl Iterator<Item = String>,
+        unlink: bool,
+    ) -> Result<()> {
+        let name = if unlink { "unlink" } else { "del" };
+        let keys: Vec<_> = args.collect();
+        if keys.is_empty() {
+            return Err(Error::MissingArgument(name, "key"));
+        }
+
+        let removed: Vec<_> = self
+            .store
+            .with_keyspace(|keyspace| {
+                keys.iter()
+                    .filter_map(|key| {
+                        let value = keyspace.remove(key)?;
+                        keyspace.notify(EventFlags::GENERIC, "del", key);
+                        Some(value)
+                    })
+                    .collect()
+            })
+            .await;
+        let count = removed.len();
+        if unlink {
+            tokio::task::spawn_blocking(move || drop(removed));
+        }
+
+        Type::Integer(count as i64).write(&mut self.stream).await
+    }
+
+    /// EXISTS and TOUCH, which count keys mentioned multiple times once per occurrence.
+    pub(super) async fn handle_exists(
+        &mut self,
+        args: impl Iterator<Item = String>,
+        name: &'static str,
+    ) -> Result<()> {
+        let keys: Vec<_> = args.collect();
+        if keys.is_empty() {
+            return Err(Error::MissingArgument(name, "key"));
+        }
+
+        let count = self
+            .store
+            .with_keyspace(|keyspace| keys.iter().filter(|key| keyspace.contains(key)).count())
+            .await;
+
+        Type::Integer(count as i64).write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_rename(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+        nx: bool,
+    ) -> Result<()> {
+        let name = if nx { "renamenx" } else { "rename" };
+        let from = args.next().ok_or(Error::MissingArgument(name, "key"))?;
+        let to = args.next().ok_or(Error::MissingArgument(name, "newkey"))?;
+
+        let renamed = self
+            .store
+            .with_keyspace(|keyspace| {
+                if !keyspace.contains(&from) {
+                    return Err(Error::InvalidArgument("no such key"));
+                }
+                if from == to {
+                    return Ok(!nx);
+                }
+                if nx && keyspace.contains(&to) {
+                    return Ok(false);
+                }
+                keyspace.rename(&from, &to);
+                keyspace.notify(EventFlags::GENERIC, "rename_from", &from);
+                keyspace.notify(EventFlags::GENERIC, "rename_to", &to);
+                Ok(true)
+            })
+            .await?;
+
+        if nx {
+            Type::Integer(renamed as i64).write(&mut self.stream).await
+        } else {
+            Type::SimpleString("OK".into())
+                .write(&mut self.stream)
+                .await
+        }
+    }
+
+    /// `COPY source destination [DB destination-db] [REPLACE]`
+    pub(super) async fn handle_copy(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let from = args
+            .next()
+            .ok_or(Error::MissingArgument("copy", "source"))?;
+        let to = args
+            .next()
+            .ok_or(Error::MissingArgument("copy", "destination"))?;
+
+        let mut db = self.store.db();
+        let mut replace = false;
+        while let Some(arg) = args.next() {
+            match arg.to_ascii_uppercase().as_str() {
+                "DB" => {
+                    let value = args.next().ok_or(Error::SyntaxError)?;
+                    db = parse_db_index(&value)?;
+                }
+                "REPLACE" => replace = true,
+                _ => return Err(Error::SyntaxError),
+            }
+        }
+
+        let source_db = self.store.db();
+        if source_db == db && from == to {
+            return Err(Error::InvalidArgument(
+                "source and destination objects are the same",
+            ));
+        }
+
+        let copied = self
+            .store
+            .with_databases(|dbs| {
+                let entry = dbs[source_db].get_entry(&from).cloned();
+                let destination = dbs
+                    .get_mut(db)
+                    .ok_or(Error::InvalidArgument("DB index is out of range"))?;
+                let Some(entry) = entry else {
+                    return Ok(false);
+                };
+                if !destination.insert_entry(to.clone(), entry, replace) {
+                    return Ok(false);
+                }
+                destination.notify(EventFlags::GENERIC, "copy_to", &to);
+                Ok::<_, Error>(true)
+            })
+            .await?;
+
+        Type::Integer(copied as i64).write(&mut self.stream).await
+    }
+
+    /// `MOVE key db`, which keeps the expiration time and fails if the key exists in `db`.
+    pub(super) async fn handle_move(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args.next().ok_or(Error::MissingArgument("move", "key"))?;
+        let db = args.next().ok_or(Error::MissingArgument("move", "db"))?;
+        let db = parse_db_index(&db)?;
+
+        let source_db = self.store.db();
+        if source_db == db {
+            return Err(Error::InvalidArgument(
+                "source and destination objects are the same",
+            ));
+        }
+
+        let moved = self
+            .store
+            .with_databases(|dbs| {
+                if db >= dbs.len() {
+                    return Err(Error::InvalidArgument("DB index is out of range"));
+                }
+                if dbs[db].contains(&key) {
+                    return Ok(false);
+                }
+                let Some(entry) = dbs[source_db].remove_entry(&key) else {
+                    return Ok(false);
+                };
+                dbs[db].insert_entry(key.clone(), entry, false);
+                dbs[source_db].notify(EventFlags::GENERIC, "move_from", &key);
+                dbs[db].notify(EventFlags::GENERIC, "move_to", &key);
+                Ok(true)
+            })
+            .await?;
+
+        Type::Integer(moved as i64).write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_select(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let db = args
+            .next()
+            .ok_or(Error::MissingArgument("select", "index"))?;
+        self.store.select(parse_db_index(&db)?).await?;
+        Type::SimpleString("OK".into())
+            .write(&mut self.stream)
+            .await
+    }
+
+    /// `SWAPDB index1 index2`, which affects all clients using either database right away.
+    pub(super) async fn handle_swapdb(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let first = args
+            .next()
+            .ok_or(Error::MissingArgument("swapdb", "index1"))?;
+        let second = args
+            .next()
+            .ok_or(Error::MissingArgument("swapdb", "index2"))?;
+        let first =
+            parse_db_index(&first).map_err(|_| Error::InvalidArgument("invalid first DB index"))?;
+        let second = parse_db_index(&second)
+            .map_err(|_| Error::InvalidArgument("invalid second DB index"))?;
+
+        self.store
+            .with_databases(|dbs| {
+                if first >= dbs.len() || second >= dbs.len() {
+                    return Err(Error::InvalidArgument("DB index is out of range"));
+                }
+                if first != second {
+                    let (low, high) = dbs.split_at_mut(first.max(second));
+                    low[first.min(second)].swap_keys(&mut high[0]);
+                }
+                Ok(())
+            })
+            .await?;
+        // Tracked keys aren't bound to a database, so every cached key may have changed
+        self.store.tracking().invalidate_all();
+
+        Type::SimpleString("OK".into())
+            .write(&mut self.stream)
+            .await
+    }
+
+    /// FLUSHDB and FLUSHALL. With ASYNC the values are dropped on a blocking task, like UNLINK.
+    pub(super) async fn handle_flush(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+        all: bool,
+    ) -> Result<()> {
+        let lazy = match args.next().map(|arg| arg.to_ascii_uppercase()).as_deref() {
+            None | Some("SYNC") => false,
+            Some("ASYNC") => true,
+            Some(_) => return Err(Error::SyntaxError),
+        };
+        if args.next().is_some() {
+            return Err(Error::SyntaxError);
+        }
+
+        let db = self.store.db();
+        let flushed: Vec<_> = self
+            .store
+            .with_databases(|dbs| {
+                if all {
+                    dbs.iter_mut().map(|keyspace| keyspace.clear()).collect()
+                } else {
+                    vec![dbs[db].clear()]
+                }
+            })
+            .await;
+        self.store.tracking().invalidate_all();
+        if lazy {
+            tokio::task::spawn_blocking(move || drop(flushed));
+        }
+
+        Type::SimpleString("OK".into())
+            .write(&mut self.stream)
+            .await
+    }
+
+    pub(super) async fn handle_save(&mut self) -> Result<()> {
+        self.store.save().await?;
+        Type::SimpleString("OK".into())
+            .write(&mut self.stream)
+            .await
+    }
+
+    pub(super) async fn handle_bgsave(&mut self) -> Result<()> {
+        self.store.background_save().await;
+        Type::SimpleString("Background saving started".into())
+            .write(&mut self.stream)
+            .await
+    }
+
+    pub(super) async fn handle_randomkey(&mut self) -> Result<()> {
+        match self
+            .store
+            .with_keyspace(|keyspace| keyspace.random_key())
+            .await
+        {
+            Some(key) => Type::BulkString(key).write(&mut self.stream).await,
+            None => Type::NullString.write(&mut self.stream).await,
+        }
+    }
+
+    pub(super) async fn handle_dbsize(&mut self) -> Result<()> {
+        let len = self.store.with_keyspace(|keyspace| keyspace.len()).await;
+        Type::Integer(len as i64).write(&mut self.stream).await
+    }
+}
+
+/// Parses a database index. Indexes past the configured number of databases are rejected
+/// where the databases are accessed.
+fn parse_db_index(value: &str) -> Result<usize> {
+    usize::try_from(parse_integer(value)?)
+        .map_err(|_| Error::InvalidArgument("DB index is out of range"))
+}
@@ -0,0 +1,342 @@
+use std::collections::HashSet;
+
+use super::Client;
+use crate::{cluster, error::Error, resp::Type, store::Message, Result};
+
+/// Commands a RESP2 client may send while it has subscriptions, as all other replies would be
+/// mixed up with published messages. RESP3 tells them apart, as messages are push frames.
+const SUBSCRIBED_COMMANDS: [&str; 7] = [
+    "subscribe",
+    "unsubscribe",
+    "psubscribe",
+    "punsubscribe",
+    "ssubscribe",
+    "sunsubscribe",
+    "ping",
+];
+
+/// What a client subscribes to, each with its own commands and namespace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Subscription {
+    Channel,
+    Pattern,
+    ShardChannel,
+}
+
+impl Subscription {
+    fn subscribe_command(self) -> &'static str {
+        match self {
+            Self::Channel => "subscribe",
+            Self::Pattern => "psubscribe",
+            Self::ShardChannel => "ssubscribe",
+        }
+    }
+
+    fn unsubscribe_command(self) -> &'static str {
+        match self {
+            Self::Channel => "unsubscribe",
+            Self::Pattern => "punsubscribe",
+            Self::ShardChannel => "sunsubscribe",
+        }
+    }
+}
+
+impl Client {
+    /// Whether the client has any subscriptions, which limits the commands it can send.
+    pub(super) fn is_subscribed(&self) -> bool {
+        !self.channels.is_empty() || !self.patterns.is_empty() || !self.shard_channels.is_empty()
+    }
+
+    /// Whether the client is limited to the subscription commands, which only RESP2 clients
+    /// with subscriptions are.
+    pub(super) fn in_subscribed_mode(&self) -> bool {
+        self.protocol == 2 && self.is_subscribed()
+    }
+
+    pub(super) fn check_subscribed_command(&self, command: &str) -> Result<()> {
+        if self.in_subscribed_mode() && !SUBSCRIBED_COMMANDS.contains(&command) {
+            return Err(Error::NotAllowedWhileSubscribed(command.into()));
+        }
+        Ok(())
+    }
+
+    fn subscriptions(&mut self, kind: Subscription) -> &mut HashSet<String> {
+        match kind {
+            Subscription::Channel => &mut self.channels,
+            Subscription::Pattern => &mut self.patterns,
+            Subscription::ShardChannel => &mut self.shard_channels,
+        }
+    }
+
+    /// The count in (un)subscribe replies. Like in Redis, shard channels are counted on their
+    /// own.
+    fn subscription_count(&self, kind: Subscription) -> i64 {
+        let count = match kind {
+            Subscription::Channel | Subscription::Pattern => {
+                self.channels.len() + self.patterns.len()
+            }
+            Subscription::ShardChannel => self.shard_channels.len(),
+        };
+        count as i64
+    }
+
+    /// In cluster mode, all shard channels of one command must belong to the same hash slot,
+    /// as they could be served by different nodes otherwise.
+    ///
+    /// Slot ownership is not implemented: this node acts as if it served every slot, so it
+    /// never redirects with MOVED, as there is no cluster topology to take the owner from.
+    fn check_same_slot(&self, channels: &[String]) -> Result<()> {
+        if self.store.get_config("cluster-enabled") != Some("yes") {
+            return Ok(());
+        }
+
+        let mut slots = channels
+            .iter()
+            .map(|channel| cluster::key_slot(channel.as_bytes()));
+        let first = slots.next();
+        if slots.any(|slot| Some(slot) != first) {
+            return Err(Error::CrossSlot);
+        }
+        Ok(())
+    }
+
+    /// SUBSCRIBE, PSUBSCRIBE and SSUBSCRIBE, confirming each name with its own reply.
+    pub(super) async fn handle_subscribe(
+        &mut self,
+        args: impl Iterator<Item = String>,
+        kind: Subscription,
+    ) -> Result<()> {
+        let command = kind.subscribe_command();
+        let names: Vec<_> = args.collect();
+        if names.is_empty() {
+            return Err(Error::MissingArgument(command, "channel"));
+        }
+        if kind == Subscription::ShardChannel {
+            self.check_same_slot(&names)?;
+        }
+
+        for name in names {
+            let (id, subscriber) = (self.id, self.subscriber.clone());
+            self.store.with_pubsub(|pubsub| match kind {
+                Subscription::Channel => pubsub.subscribe(&name, id, &subscriber),
+                Subscription::Pattern => pubsub.psubscribe(&name, id, &subscriber),
+                Subscription::ShardChannel => pubsub.ssubscribe(&name, id, &subscriber),
+            });
+            self.subscriptions(kind).insert(name.clone());
+
+            self.push(subscription_reply(
+                command,
+                Some(name),
+                self.subscription_count(kind),
+            ))
+            .write(&mut self.stream)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// UNSUBSCRIBE, PUNSUBSCRIBE and SUNSUBSCRIBE, which without arguments remove all
+    /// subscriptions of the kind.
+    pub(super) async fn handle_unsubscribe(
+        &mut self,
+        args: impl Iterator<Item = String>,
+        kind: Subscription,
+    ) -> Result<()> {
+        let command = kind.unsubscribe_command();
+        let mut names: Vec<_> = args.collect();
+        if names.is_empty() {
+            names = self.subscriptions(kind).iter().cloned().collect();
+            if names.is_empty() {
+                return self
+                    .push(subscription_reply(
+                        command,
+                        None,
+                        self.subscription_count(kind),
+                    ))
+                    .write(&mut self.stream)
+                    .await;
+            }
+        } else if kind == Subscription::ShardChannel {
+            self.check_same_slot(&names)?;
+        }
+
+        for name in names {
+            let id = self.id;
+            self.store.with_pubsub(|pubsub| match kind {
+                Subscription::Channel => pubsub.unsubscribe(&name, id),
+                Subscription::Pattern => pubsub.punsubscribe(&name, id),
+                Subscription::ShardChannel => pubsub.sunsubscribe(&name, id),
+            });
+            self.subscriptions(kind).remove(&name);
+
+            self.push(subscription_reply(
+                command,
+                Some(name),
+                self.subscription_count(kind),
+            ))
+            .write(&mut self.stream)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Removes all subscriptions of a client that disconnected.
+    pub(super) fn unsubscribe_all(&mut self) {
+        let channels = std::mem::take(&mut self.channels);
+        let patterns = std::mem::take(&mut self.patterns);
+        let shard_channels = std::mem::take(&mut self.shard_channels);
+        let id = self.id;
+        self.store.with_pubsub(|pubsub| {
+            for channel in &channels {
+                pubsub.unsubscribe(channel, id);
+            }
+            for pattern in &patterns {
+                pubsub.punsubscribe(pattern, id);
+            }
+            for channel in &shard_channels {
+                pubsub.sunsubscribe(channel, id);
+            }
+        });
+    }
+
+    /// PUBLISH and SPUBLISH. SPUBLISH takes a single shard channel, so there is no slot to
+    /// check while this node serves every slot, see [`Self::check_same_slot`].
+    pub(super) async fn handle_publish(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+        shard: bool,
+    ) -> Result<()> {
+        let name = if shard { "spublish" } else { "publish" };
+        let channel = args.next().ok_or(Error::MissingArgument(name, "channel"))?;
+        let message = args.next().ok_or(Error::MissingArgument(name, "message"))?;
+
+        let received = self.store.with_pubsub(|pubsub| {
+            if shard {
+                pubsub.spublish(&channel, &message)
+            } else {
+                pubsub.publish(&channel, &message)
+            }
+        });
+
+        Type::Integer(received as i64).write(&mut self.stream).await
+    }
+
+    /// `PUBSUB CHANNELS|SHARDCHANNELS [pattern]`, `PUBSUB NUMSUB|SHARDNUMSUB [channel ...]` and
+    /// `PUBSUB NUMPAT`
+    pub(super) async fn handle_pubsub(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let subcmd = args
+            .next()
+            .ok_or(Error::MissingArgument("pubsub", "subcommand"))?
+            .to_ascii_lowercase();
+
+        let reply = match subcmd.as_str() {
+            "channels" | "shardchannels" => {
+                let pattern = args.next();
+                if args.next().is_some() {
+                    return Err(Error::WrongArity(format!("pubsub|{subcmd}")));
+                }
+                let channels = self.store.with_pubsub(|pubsub| {
+                    if subcmd == "channels" {
+                        pubsub.channels(pattern.as_deref())
+                    } else {
+                        pubsub.shard_channels(pattern.as_deref())
+                    }
+                });
+                Type::Array(channels.into_iter().map(Type::BulkString).collect())
+            }
+            "numsub" | "shardnumsub" => {
+                let channels: Vec<_> = args.collect();
+                let counts = self.store.with_pubsub(|pubsub| {
+                    channels
+                        .iter()
+                        .map(|channel| {
+                            if subcmd == "numsub" {
+                                pubsub.subscribers(channel)
+                            } else {
+                                pubsub.shard_subscribers(channel)
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                });
+                Type::Array(
+                    channels
+                        .into_iter()
+                        .zip(counts)
+                        .flat_map(|(channel, count)| {
+                            [Type::BulkString(channel), Type::Integer(count as i64)]
+                        })
+                        .collect(),
+                )
+            }
+            "numpat" => {
+                if args.next().is_some() {
+                    return Err(Error::WrongArity("pubsub|numpat".into()));
+                }
+                let patterns = self.store.with_pubsub(|pubsub| pubsub.patterns());
+                Type::Integer(patterns as i64)
+            }
+            cmd => return Err(Error::UnimplementedCommand(format!("PUBSUB {cmd}"))),
+        };
+
+        reply.write(&mut self.stream).await
+    }
+
+    /// PING while subscribed, which replies with an array so that it can't be mistaken for a
+    /// published message.
+    pub(super) async fn handle_subscribed_ping(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let message = args.next().unwrap_or_default();
+        Type::Array(vec![
+            Type::BulkString("pong".into()),
+            Type::BulkString(message),
+        ])
+        .write(&mut self.stream)
+        .await
+    }
+
+    /// Forwards a message published to one of the client's subscriptions, or an invalidation
+    /// of keys it cached.
+    pub(super) async fn write_message(&mut self, message: Message) -> Result<()> {
+        let reply = match message {
+            Message::Channel { channel, payload } => vec![
+                Type::BulkString("message".into()),
+                Type::BulkString(channel),
+                Type::BulkString(payload),
+            ],
+            Message::Pattern {
+                pattern,
+                channel,
+                payload,
+            } => vec![
+                Type::BulkString("pmessage".into()),
+                Type::BulkString(pattern),
+                Type::BulkString(channel),
+                Type::BulkString(payload),
+            ],
+            Message::Shard { channel, payload } => vec![
+                Type::BulkString("smessage".into()),
+                Type::BulkString(channel),
+                Type::BulkString(payload),
+            ],
+            Message::Invalidate(keys) => return self.write_invalidation(keys).await,
+            Message::TrackingRedirectBroken(redirect) => {
+                return self.write_redirect_broken(redirect).await
+            }
+        };
+        self.push(reply).write(&mut self.stream).await
+    }
+}
+
+/// The reply confirming a (un)subscription, with the number of subscriptions left afterwards.
+fn subscription_reply(kind: &str, name: Option<String>, count: i64) -> Vec<Type> {
+    vec![
+        Type::BulkString(kind.into()),
+        name.map_or(Type::NullString, Type::BulkString),
+        Type::Integer(count),
+    ]
+}
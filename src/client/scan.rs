@@ -0,0 +1,171 @@
+use super::{parse_integer, sorted_set::scored_members_reply, Client};
+use crate::{error::Error, glob, resp::Type, Result};
+
+const DEFAULT_COUNT: usize = 10;
+const TYPE_NAMES: [&str; 6] = ["string", "list", "set", "zset", "hash", "stream"];
+
+/// The cursor and options shared by SCAN and its variants for a single key.
+struct ScanArgs {
+    cursor: u64,
+    pattern: Option<String>,
+    count: usize,
+    kind: Option<String>,
+    /// NOSCORES of ZSCAN and NOVALUES of HSCAN.
+    no_values: bool,
+}
+
+impl ScanArgs {
+    fn parse(
+        mut args: impl Iterator<Item = String>,
+        name: &'static str,
+        flags: &[&str],
+    ) -> Result<Self> {
+        let cursor = args.next().ok_or(Error::MissingArgument(name, "cursor"))?;
+        let cursor = cursor
+            .parse()
+            .map_err(|_| Error::InvalidArgument("invalid cursor"))?;
+
+        let mut parsed = Self {
+            cursor,
+            pattern: None,
+            count: DEFAULT_COUNT,
+            kind: None,
+            no_values: false,
+        };
+        while let Some(arg) = args.next() {
+            let arg = arg.to_ascii_uppercase();
+            match arg.as_str() {
+                "MATCH" => parsed.pattern = Some(args.next().ok_or(Error::SyntaxError)?),
+                "COUNT" => {
+                    let count = parse_integer(&args.next().ok_or(Error::SyntaxError)?)?;
+                    parsed.count = usize::try_from(count)
+                        .ok()
+                        .filter(|count| *count > 0)
+                        .ok_or(Error::SyntaxError)?;
+                }
+                "TYPE" if name == "scan" => {
+                    let kind = args.next().ok_or(Error::SyntaxError)?.to_ascii_lowercase();
+                    if !TYPE_NAMES.contains(&kind.as_str()) {
+                        return Err(Error::InvalidArgument("unknown type name"));
+                    }
+                    parsed.kind = Some(kind);
+                }
+                flag if flags.contains(&flag) => parsed.no_values = true,
+                _ => return Err(Error::SyntaxError),
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn matches(&self, member: &str) -> bool {
+        self.pattern
+            .as_ref()
+            .is_none_or(|pattern| glob::matches(pattern.as_bytes(), member.as_bytes()))
+    }
+}
+
+fn scan_reply(cursor: u64, items: Type) -> Type {
+    Type::Array(vec![Type::BulkString(cursor.to_string()), items])
+}
+
+impl Client {
+    /// `KEYS pattern`
+    pub(super) async fn handle_keys(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let pattern = args
+            .next()
+            .ok_or(Error::MissingArgument("keys", "pattern"))?;
+
+        let keys = self
+            .store
+            .with_keyspace(|keyspace| {
+                keyspace
+                    .keys()
+                    .filter(|key| glob::matches(pattern.as_bytes(), key.as_bytes()))
+                    .map(|key| Type::BulkString(key.clone()))
+                    .collect()
+            })
+            .await;
+
+        Type::Array(keys).write(&mut self.stream).await
+    }
+
+    /// `SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]`
+    pub(super) async fn handle_scan(&mut self, args: impl Iterator<Item = String>) -> Result<()> {
+        let scan = ScanArgs::parse(args, "scan", &[])?;
+
+        let (cursor, keys) = self
+            .store
+            .with_keyspace(|keyspace| {
+                keyspace.scan(scan.cursor, scan.count, |key, value| {
+                    scan.kind.as_ref().is_none_or(|kind| value.kind() == kind) && scan.matches(key)
+                })
+            })
+            .await;
+
+        let keys = keys.into_iter().map(Type::BulkString).collect();
+        scan_reply(cursor, Type::Array(keys))
+            .write(&mut self.stream)
+            .await
+    }
+
+    /// `ZSCAN key cursor [MATCH pattern] [COUNT count] [NOSCORES]`
+    pub(super) async fn handle_zscan(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args.next().ok_or(Error::MissingArgument("zscan", "key"))?;
+        let scan = ScanArgs::parse(args, "zscan", &["NOSCORES"])?;
+
+        let (cursor, members) = self
+            .store
+            .with_keyspace(|keyspace| {
+                let Some(value) = keyspace.get(&key) else {
+                    return Ok((0, Vec::new()));
+                };
+                let set = value
+                    .as_sorted_set()
+                    .ok_or(Error::ExpectedOtherType("zset"))?;
+
+                let (cursor, members) = set.scan(scan.cursor, scan.count);
+                let members: Vec<_> = members
+                    .into_iter()
+                    .filter(|(member, _)| scan.matches(member))
+                    .map(|(member, score)| (member.to_owned(), score))
+                    .collect();
+                Ok::<_, Error>((cursor, members))
+            })
+            .await?;
+
+        scan_reply(cursor, scored_members_reply(members, !scan.no_values))
+            .write(&mut self.stream)
+            .await
+    }
+
+    /// HSCAN and SSCAN. There are no hash or set values yet, so these only tell missing keys
+    /// apart from keys of another type.
+    pub(super) async fn handle_collection_scan(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+        name: &'static str,
+        kind: &'static str,
+    ) -> Result<()> {
+        let key = args.next().ok_or(Error::MissingArgument(name, "key"))?;
+        let flags: &[&str] = if name == "hscan" { &["NOVALUES"] } else { &[] };
+        ScanArgs::parse(args, name, flags)?;
+
+        let exists = self
+            .store
+            .with_keyspace(|keyspace| keyspace.contains(&key))
+            .await;
+        if exists {
+            return Err(Error::ExpectedOtherType(kind));
+        }
+
+        scan_reply(0, Type::Array(Vec::new()))
+            .write(&mut self.stream)
+            .await
+    }
+}
@@ -0,0 +1,1217 @@
+use std::{collections::HashMap, ops::Bound, time::Duration};
+
+use tokio::sync::mpsc;
+
+use super::{format_double, parse_double, parse_integer, Client};
+use crate::{
+    error::Error,
+    resp::Type,
+    sorted_set::SortedSet,
+    store::{EventFlags, Keyspace, Value},
+    Result,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RangeBy {
+    Rank,
+    Score,
+    Lex,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum LexBound {
+    Min,
+    Max,
+    Included(String),
+    Excluded(String),
+}
+
+impl LexBound {
+    fn parse(value: &str) -> Result<Self> {
+        match value {
+            "-" => Ok(Self::Min),
+            "+" => Ok(Self::Max),
+            _ => match value.split_at_checked(1) {
+                Some(("[", rest)) => Ok(Self::Included(rest.into())),
+                Some(("(", rest)) => Ok(Self::Excluded(rest.into())),
+                _ => Err(Error::InvalidArgument(
+                    "min or max not valid string range item",
+                )),
+            },
+        }
+    }
+
+    /// Bound usable as the lower end of a range, `None` when nothing can be above it.
+    fn as_min(&self) -> Option<Bound<&str>> {
+        match self {
+            Self::Min => Some(Bound::Unbounded),
+            Self::Max => None,
+            Self::Included(value) => Some(Bound::Included(value)),
+            Self::Excluded(value) => Some(Bound::Excluded(value)),
+        }
+    }
+
+    /// Bound usable as the upper end of a range, `None` when nothing can be below it.
+    fn as_max(&self) -> Option<Bound<&str>> {
+        match self {
+            Self::Min => None,
+            Self::Max => Some(Bound::Unbounded),
+            Self::Included(value) => Some(Bound::Included(value)),
+            Self::Excluded(value) => Some(Bound::Excluded(value)),
+        }
+    }
+}
+
+fn parse_score_bound(value: &str) -> Result<Bound<f64>> {
+    let (exclusive, score) = match value.strip_prefix('(') {
+        Some(rest) => (true, rest),
+        None => (false, value),
+    };
+    let score =
+        parse_double(score).map_err(|_| Error::InvalidArgument("min or max is not a float"))?;
+
+    Ok(if exclusive {
+        Bound::Excluded(score)
+    } else {
+        Bound::Included(score)
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum RangeSpec {
+    Rank(i64, i64),
+    Score(Bound<f64>, Bound<f64>),
+    Lex(LexBound, LexBound),
+}
+
+/// A parsed ZRANGE-style query, covering all of the range commands.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct RangeQuery {
+    spec: RangeSpec,
+    reverse: bool,
+    limit: Option<(i64, i64)>,
+}
+
+impl RangeQuery {
+    /// `start` and `stop` are taken in the order they appear on the command line, which for
+    /// reversed score and lex ranges means the maximum comes first.
+    fn new(by: RangeBy, start: &str, stop: &str, reverse: bool) -> Result<Self> {
+        let (low, high) = if reverse && by != RangeBy::Rank {
+            (stop, start)
+        } else {
+            (start, stop)
+        };
+
+        let spec = match by {
+            RangeBy::Rank => RangeSpec::Rank(parse_integer(start)?, parse_integer(stop)?),
+            RangeBy::Score => RangeSpec::Score(parse_score_bound(low)?, parse_score_bound(high)?),
+            RangeBy::Lex => RangeSpec::Lex(LexBound::parse(low)?, LexBound::parse(high)?),
+        };
+
+        Ok(Self {
+            spec,
+            reverse,
+            limit: None,
+        })
+    }
+
+    /// Parses the options following `start` and `stop` in ZRANGE and its legacy variants,
+    /// returning the query and whether WITHSCORES was given.
+    fn parse(
+        start: &str,
+        stop: &str,
+        mut args: impl Iterator<Item = String>,
+        legacy: Option<(&'static str, bool)>,
+    ) -> Result<(Self, bool)> {
+        let (mut by, mut reverse) = match legacy {
+            Some(("score", reverse)) => (RangeBy::Score, reverse),
+            Some(("lex", reverse)) => (RangeBy::Lex, reverse),
+            Some((_, reverse)) => (RangeBy::Rank, reverse),
+            None => (RangeBy::Rank, false),
+        };
+        let mut limit = None;
+        let mut with_scores = false;
+
+        while let Some(arg) = args.next() {
+            match arg.to_ascii_uppercase().as_str() {
+                "BYSCORE" if legacy.is_none() => by = RangeBy::Score,
+                "BYLEX" if legacy.is_none() => by = RangeBy::Lex,
+                "REV" if legacy.is_none() => reverse = true,
+                "WITHSCORES" => with_scores = true,
+                "LIMIT" => {
+                    let offset = args.next().ok_or(Error::SyntaxError)?;
+                    let count = args.next().ok_or(Error::SyntaxError)?;
+                    limit = Some((parse_integer(&offset)?, parse_integer(&count)?));
+                }
+                _ => return Err(Error::SyntaxError),
+            }
+        }
+
+        if limit.is_some() && by == RangeBy::Rank {
+            return Err(Error::InvalidArgument(
+                "syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX",
+            ));
+        }
+        if with_scores && by == RangeBy::Lex {
+            return Err(Error::InvalidArgument(
+                "syntax error, WITHSCORES not supported in combination with BYLEX",
+            ));
+        }
+
+        let mut query = Self::new(by, start, stop, reverse)?;
+        query.limit = limit;
+        Ok((query, with_scores))
+    }
+
+    /// Resolves the query into an inclusive interval of ascending ranks.
+    fn ranks(&self, set: &SortedSet) -> Option<(usize, usize)> {
+        let (mut start, mut end) = match &self.spec {
+            RangeSpec::Rank(start, stop) => {
+                let len = set.len() as i64;
+                let start = if *start < 0 { len + start } else { *start }.max(0);
+                let stop = if *stop < 0 { len + stop } else { *stop }.min(len - 1);
+                if start > stop || start >= len {
+                    return None;
+                }
+
+                let (start, stop) = (start as usize, stop as usize);
+                if self.reverse {
+                    let last = set.len() - 1;
+                    (last - stop, last - start)
+                } else {
+                    (start, stop)
+                }
+            }
+            RangeSpec::Score(min, max) => set.ranks_by_score(*min, *max)?,
+            RangeSpec::Lex(min, max) => set.ranks_by_lex(min.as_min()?, max.as_max()?)?,
+        };
+
+        if let Some((offset, count)) = self.limit {
+            let offset = usize::try_from(offset).ok()?;
+            if offset > end - start || count == 0 {
+                return None;
+            }
+
+            // A negative count means everything after the offset
+            let count = usize::try_from(count).unwrap_or(usize::MAX);
+            if self.reverse {
+                end -= offset;
+                start = start.max((end + 1).saturating_sub(count));
+            } else {
+                start += offset;
+                end = end.min(start.saturating_add(count - 1));
+            }
+        }
+
+        Some((start, end))
+    }
+
+    pub(super) fn collect(&self, set: &SortedSet) -> Vec<(String, f64)> {
+        match self.ranks(set) {
+            Some((start, end)) => set
+                .range_by_rank(start, end, self.reverse)
+                .map(|(member, score)| (member.to_owned(), score))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn count(&self, set: &SortedSet) -> usize {
+        self.ranks(set).map_or(0, |(start, end)| end - start + 1)
+    }
+}
+
+/// Runs `op` on the sorted set in `slot`, creating it first if `create` is set.
+///
+/// Returns `None` without calling `op` when there is no set and none should be created. Sets
+/// left empty by `op` are deleted, like Redis does.
+pub(super) fn with_sorted_set<T>(
+    slot: &mut Option<Value>,
+    create: bool,
+    op: impl FnOnce(&mut SortedSet) -> Result<T>,
+) -> Result<Option<T>> {
+    match slot {
+        None if !create => return Ok(None),
+        None => *slot = Some(Value::SortedSet(SortedSet::new())),
+        Some(_) => {}
+    }
+
+    let set = slot
+        .as_mut()
+        .and_then(Value::as_sorted_set_mut)
+        .ok_or(Error::ExpectedOtherType("zset"))?;
+    let result = op(set);
+    if set.is_empty() {
+        *slot = None;
+    }
+
+    result.map(Some)
+}
+
+pub(super) fn scored_members_reply(members: Vec<(String, f64)>, with_scores: bool) -> Type {
+    Type::Array(
+        members
+            .into_iter()
+            .flat_map(|(member, score)| {
+                let score = with_scores.then(|| Type::BulkString(format_double(score)));
+                std::iter::once(Type::BulkString(member)).chain(score)
+            })
+            .collect(),
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum SetOperation {
+    Union,
+    Inter,
+    Diff,
+}
+
+impl SetOperation {
+    fn name(self, store: bool) -> &'static str {
+        match (self, store) {
+            (Self::Union, false) => "zunion",
+            (Self::Union, true) => "zunionstore",
+            (Self::Inter, false) => "zinter",
+            (Self::Inter, true) => "zinterstore",
+            (Self::Diff, false) => "zdiff",
+            (Self::Diff, true) => "zdiffstore",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            // inf + -inf is the only way to get NaN here, Redis treats it as 0
+            Self::Sum => match a + b {
+                sum if sum.is_nan() => 0.0,
+                sum => sum,
+            },
+            Self::Min => a.min(b),
+            Self::Max => a.max(b),
+        }
+    }
+}
+
+/// Arguments shared by ZUNION, ZINTER, ZDIFF and their STORE variants.
+#[derive(Debug, Clone, PartialEq)]
+struct Combination {
+    operation: SetOperation,
+    keys: Vec<String>,
+    weights: Vec<f64>,
+    aggregate: Aggregate,
+}
+
+impl Combination {
+    /// Parses everything starting at `numkeys`, returning whether WITHSCORES was given.
+    fn parse(
+        operation: SetOperation,
+        store: bool,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<(Self, bool)> {
+        let name = operation.name(store);
+        let numkeys = args.next().ok_or(Error::MissingArgument(name, "numkeys"))?;
+        let numkeys = parse_integer(&numkeys)?;
+        if numkeys <= 0 {
+            return Err(Error::InvalidArgument("at least 1 input key is needed"));
+        }
+
+        let keys: Vec<_> = args.by_ref().take(numkeys as usize).collect();
+        if keys.len() != numkeys as usize {
+            return Err(Error::SyntaxError);
+        }
+
+        let mut combination = Self {
+            operation,
+            weights: vec![1.0; keys.len()],
+            keys,
+            aggregate: Aggregate::Sum,
+        };
+        let mut with_scores = false;
+
+        while let Some(arg) = args.next() {
+            match arg.to_ascii_uppercase().as_str() {
+                "WEIGHTS" if operation != SetOperation::Diff => {
+                    for weight in combination.weights.iter_mut() {
+                        let value = args.next().ok_or(Error::SyntaxError)?;
+                        *weight = parse_double(&value)
+                            .map_err(|_| Error::InvalidArgument("weight value is not a float"))?;
+                    }
+                }
+                "AGGREGATE" if operation != SetOperation::Diff => {
+                    let value = args.next().ok_or(Error::SyntaxError)?;
+                    combination.aggregate = match value.to_ascii_uppercase().as_str() {
+                        "SUM" => Aggregate::Sum,
+                        "MIN" => Aggregate::Min,
+                        "MAX" => Aggregate::Max,
+                        _ => return Err(Error::SyntaxError),
+                    };
+                }
+                "WITHSCORES" if !store => with_scores = true,
+                _ => return Err(Error::SyntaxError),
+            }
+        }
+
+        Ok((combination, with_scores))
+    }
+
+    fn compute(&self, keyspace: &Keyspace) -> Result<SortedSet> {
+        let sets = self
+            .keys
+            .iter()
+            .map(|key| match keyspace.get(key) {
+                Some(value) => value
+                    .as_sorted_set()
+                    .map(Some)
+                    .ok_or(Error::ExpectedOtherType("zset")),
+                None => Ok(None),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // 0 * inf is NaN, which Redis also turns into 0
+        let weighted = |score: f64, weight: f64| match score * weight {
+            score if score.is_nan() => 0.0,
+            score => score,
+        };
+
+        let mut scores: HashMap<&str, f64> = HashMap::new();
+        match self.operation {
+            SetOperation::Union => {
+                for (set, weight) in sets.iter().zip(&self.weights) {
+                    for (member, score) in set.iter().flat_map(|set| set.iter()) {
+                        let score = weighted(score, *weight);
+                        scores
+                            .entry(member)
+                            .and_modify(|current| *current = self.aggregate.apply(*current, score))
+                            .or_insert(score);
+                    }
+                }
+            }
+            SetOperation::Inter => {
+                if let Some(sets) = sets.iter().copied().collect::<Option<Vec<_>>>() {
+                    let (smallest, _) = sets
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, set)| set.len())
+                        .expect("There is at least one key");
+
+                    'members: for (member, _) in sets[smallest].iter() {
+                        let mut result = None;
+                        for (set, weight) in sets.iter().zip(&self.weights) {
+                            let Some(score) = set.score(member) else {
+                                continue 'members;
+                            };
+                            let score = weighted(score, *weight);
+                            result = Some(match result {
+                                Some(current) => self.aggregate.apply(current, score),
+                                None => score,
+                            });
+                        }
+                        scores.insert(member, result.expect("There is at least one key"));
+                    }
+                }
+            }
+            SetOperation::Diff => {
+                if let Some((Some(first), rest)) = sets.split_first() {
+                    for (member, score) in first.iter() {
+                        if !rest.iter().flatten().any(|set| set.score(member).is_some()) {
+                            scores.insert(member, score);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut result = SortedSet::new();
+        for (member, score) in scores {
+            result.insert(member.to_owned(), score);
+        }
+        Ok(result)
+    }
+}
+
+/// Replaces `key` with `set`, or deletes it when `set` is empty, and publishes `event` or `del`
+/// accordingly. Returns the new length.
+pub(super) fn store_sorted_set(
+    keyspace: &mut Keyspace,
+    key: String,
+    set: SortedSet,
+    event: &str,
+) -> usize {
+    let len = set.len();
+    if set.is_empty() {
+        if keyspace.remove(&key).is_some() {
+            keyspace.notify(EventFlags::GENERIC, "del", &key);
+        }
+    } else {
+        keyspace.insert(key.clone(), Value::SortedSet(set), None);
+        keyspace.notify(EventFlags::ZSET, event, &key);
+    }
+    len
+}
+
+/// Publishes `event` after members were removed from the sorted set under `key`, followed by
+/// `del` if that left the set empty and so deleted it.
+fn notify_removal(keyspace: &Keyspace, key: &str, event: &str) {
+    keyspace.notify(EventFlags::ZSET, event, key);
+    if !keyspace.contains(key) {
+        keyspace.notify(EventFlags::GENERIC, "del", key);
+    }
+}
+
+fn pop_members(set: &mut SortedSet, max: bool, count: usize) -> Vec<(String, f64)> {
+    std::iter::from_fn(|| if max { set.pop_last() } else { set.pop_first() })
+        .take(count)
+        .collect()
+}
+
+fn pop_from_key(
+    keyspace: &mut Keyspace,
+    key: &str,
+    max: bool,
+    count: usize,
+) -> Result<Option<Vec<(String, f64)>>> {
+    let popped = keyspace.update(key, |slot| {
+        with_sorted_set(slot, false, |set| Ok(pop_members(set, max, count)))
+    })?;
+    if popped.as_ref().is_some_and(|popped| !popped.is_empty()) {
+        notify_removal(keyspace, key, if max { "zpopmax" } else { "zpopmin" });
+    }
+    Ok(popped)
+}
+
+fn parse_timeout(value: &str) -> Result<Option<Duration>> {
+    let timeout = value
+        .parse::<f64>()
+        .ok()
+        .filter(|timeout| timeout.is_finite())
+        .ok_or(Error::InvalidArgument(
+            "timeout is not a float or out of range",
+        ))?;
+    if timeout < 0.0 {
+        return Err(Error::InvalidArgument("timeout is negative"));
+    }
+
+    Ok((timeout > 0.0).then(|| Duration::from_secs_f64(timeout)))
+}
+
+/// Parses `numkeys key [key ...] MIN|MAX [COUNT count]` of ZMPOP and BZMPOP.
+fn parse_mpop(
+    name: &'static str,
+    mut args: impl Iterator<Item = String>,
+) -> Result<(Vec<String>, bool, usize)> {
+    let numkeys = args.next().ok_or(Error::MissingArgument(name, "numkeys"))?;
+    let numkeys = parse_integer(&numkeys)
+        .ok()
+        .filter(|numkeys| *numkeys > 0)
+        .ok_or(Error::InvalidArgument("numkeys should be greater than 0"))?;
+
+    let keys: Vec<_> = args.by_ref().take(numkeys as usize).collect();
+    if keys.len() != numkeys as usize {
+        return Err(Error::SyntaxError);
+    }
+
+    let max = match args.next().map(|arg| arg.to_ascii_uppercase()).as_deref() {
+        Some("MIN") => false,
+        Some("MAX") => true,
+        _ => return Err(Error::SyntaxError),
+    };
+
+    let count = match args.next() {
+        Some(arg) if arg.eq_ignore_ascii_case("COUNT") => {
+            let count = args.next().ok_or(Error::SyntaxError)?;
+            parse_integer(&count)
+                .ok()
+                .filter(|count| *count > 0)
+                .ok_or(Error::InvalidArgument("count should be greater than 0"))?
+                as usize
+        }
+        Some(_) => return Err(Error::SyntaxError),
+        None => 1,
+    };
+    if args.next().is_some() {
+        return Err(Error::SyntaxError);
+    }
+
+    Ok((keys, max, count))
+}
+
+fn mpop_reply(popped: Option<(String, Vec<(String, f64)>)>) -> Type {
+    match popped {
+        Some((key, members)) => Type::Array(vec![
+            Type::BulkString(key),
+            Type::Array(
+                members
+                    .into_iter()
+                    .map(|(member, score)| {
+                        Type::Array(vec![
+                            Type::BulkString(member),
+                            Type::BulkString(format_double(score)),
+                        ])
+                    })
+                    .collect(),
+            ),
+        ]),
+        None => Type::NullArray,
+    }
+}
+
+#[derive(Debug, Default)]
+pub(super) struct AddFlags {
+    pub(super) nx: bool,
+    pub(super) xx: bool,
+    pub(super) gt: bool,
+    pub(super) lt: bool,
+    pub(super) ch: bool,
+    pub(super) incr: bool,
+}
+
+impl AddFlags {
+    pub(super) fn validate(&self, pairs: usize) -> Result<()> {
+        if self.nx && self.xx {
+            return Err(Error::InvalidArgument(
+                "XX and NX options at the same time are not compatible",
+            ));
+        }
+        if (self.gt && self.lt) || ((self.gt || self.lt) && self.nx) {
+            return Err(Error::InvalidArgument(
+                "GT, LT, and/or NX options at the same time are not compatible",
+            ));
+        }
+        if self.incr && pairs > 1 {
+            return Err(Error::InvalidArgument(
+                "INCR option supports a single increment-element pair",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub(super) struct AddOutcome {
+    pub(super) added: i64,
+    pub(super) changed: i64,
+    pub(super) score: Option<f64>,
+}
+
+pub(super) fn add_to_set(
+    set: &mut SortedSet,
+    flags: &AddFlags,
+    pairs: Vec<(f64, String)>,
+) -> Result<AddOutcome> {
+    let mut outcome = AddOutcome::default();
+
+    for (score, member) in pairs {
+        outcome.score = None;
+        match set.score(&member) {
+            Some(current) => {
+                if flags.nx {
+                    continue;
+                }
+
+                let new = if flags.incr { current + score } else { score };
+                if new.is_nan() {
+                    return Err(Error::InvalidArgument(
+                        "resulting score is not a number (NaN)",
+                    ));
+                }
+                if (flags.lt && new >= current) || (flags.gt && new <= current) {
+                    continue;
+                }
+
+                if new != current {
+                    set.insert(member, new);
+                    outcome.changed += 1;
+                }
+                outcome.score = Some(new);
+            }
+            None => {
+                if flags.xx {
+                    continue;
+                }
+
+                set.insert(member, score);
+                outcome.added += 1;
+                outcome.score = Some(score);
+            }
+        }
+    }
+
+    Ok(outcome)
+}
+
+impl Client {
+    pub(super) async fn handle_zadd(&mut self, args: impl Iterator<Item = String>) -> Result<()> {
+        let mut args = args.peekable();
+        let key = args.next().ok_or(Error::MissingArgument("zadd", "key"))?;
+
+        let mut flags = AddFlags::default();
+        while let Some(arg) = args.peek() {
+            match arg.to_ascii_uppercase().as_str() {
+                "NX" => flags.nx = true,
+                "XX" => flags.xx = true,
+                "GT" => flags.gt = true,
+                "LT" => flags.lt = true,
+                "CH" => flags.ch = true,
+                "INCR" => flags.incr = true,
+                _ => break,
+            }
+            args.next();
+        }
+
+        let rest: Vec<_> = args.collect();
+        if rest.is_empty() {
+            return Err(Error::MissingArgument("zadd", "score"));
+        }
+        if rest.len() % 2 != 0 {
+            return Err(Error::SyntaxError);
+        }
+        flags.validate(rest.len() / 2)?;
+
+        let pairs = rest
+            .chunks(2)
+            .map(|pair| Ok((parse_double(&pair[0])?, pair[1].clone())))
+            .collect::<Result<Vec<_>>>()?;
+
+        let create = !flags.xx;
+        let outcome = self
+            .store
+            .update(&key, |slot| {
+                with_sorted_set(slot, create, |set| add_to_set(set, &flags, pairs))
+            })
+            .await?
+            .unwrap_or_default();
+        if outcome.added + outcome.changed > 0 {
+            let event = if flags.incr { "zincr" } else { "zadd" };
+            self.store.notify(EventFlags::ZSET, event, &key);
+        }
+
+        let reply = if flags.incr {
+            outcome.score.map_or(Type::NullString, |score| {
+                Type::BulkString(format_double(score))
+            })
+        } else if flags.ch {
+            Type::Integer(outcome.added + outcome.changed)
+        } else {
+            Type::Integer(outcome.added)
+        };
+
+        reply.write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_zincrby(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args
+            .next()
+            .ok_or(Error::MissingArgument("zincrby", "key"))?;
+        let increment = args
+            .next()
+            .ok_or(Error::MissingArgument("zincrby", "increment"))?;
+        let member = args
+            .next()
+            .ok_or(Error::MissingArgument("zincrby", "member"))?;
+        let increment = parse_double(&increment)?;
+
+        let flags = AddFlags {
+            incr: true,
+            ..Default::default()
+        };
+        let outcome = self
+            .store
+            .update(&key, |slot| {
+                with_sorted_set(slot, true, |set| {
+                    add_to_set(set, &flags, vec![(increment, member)])
+                })
+            })
+            .await?
+            .unwrap_or_default();
+        if outcome.added + outcome.changed > 0 {
+            self.store.notify(EventFlags::ZSET, "zincr", &key);
+        }
+
+        outcome
+            .score
+            .map_or(Type::NullString, |score| {
+                Type::BulkString(format_double(score))
+            })
+            .write(&mut self.stream)
+            .await
+    }
+
+    pub(super) async fn handle_zrem(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args.next().ok_or(Error::MissingArgument("zrem", "key"))?;
+        let members: Vec<_> = args.collect();
+        if members.is_empty() {
+            return Err(Error::MissingArgument("zrem", "member"));
+        }
+
+        let removed = self
+            .store
+            .with_keyspace(|keyspace| -> Result<_> {
+                let removed = keyspace
+                    .update(&key, |slot| {
+                        with_sorted_set(slot, false, |set| {
+                            Ok(members.iter().filter(|m| set.remove(m).is_some()).count())
+                        })
+                    })?
+                    .unwrap_or(0);
+                if removed > 0 {
+                    notify_removal(keyspace, &key, "zrem");
+                }
+                Ok(removed)
+            })
+            .await?;
+
+        Type::Integer(removed as i64).write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_zcard(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args.next().ok_or(Error::MissingArgument("zcard", "key"))?;
+
+        let len = self
+            .read_sorted_set(&key, |set| set.len())
+            .await?
+            .unwrap_or(0);
+
+        Type::Integer(len as i64).write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_zscore(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args.next().ok_or(Error::MissingArgument("zscore", "key"))?;
+        let member = args
+            .next()
+            .ok_or(Error::MissingArgument("zscore", "member"))?;
+
+        self.read_sorted_set(&key, |set| set.score(&member))
+            .await?
+            .flatten()
+            .map_or(Type::NullString, |score| {
+                Type::BulkString(format_double(score))
+            })
+            .write(&mut self.stream)
+            .await
+    }
+
+    pub(super) async fn handle_zmscore(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args
+            .next()
+            .ok_or(Error::MissingArgument("zmscore", "key"))?;
+        let members: Vec<_> = args.collect();
+        if members.is_empty() {
+            return Err(Error::MissingArgument("zmscore", "member"));
+        }
+
+        let scores = self
+            .read_sorted_set(&key, |set| {
+                members.iter().map(|m| set.score(m)).collect::<Vec<_>>()
+            })
+            .await?
+            .unwrap_or_else(|| vec![None; members.len()]);
+
+        Type::Array(
+            scores
+                .into_iter()
+                .map(|score| score.map_or(Type::NullString, |s| Type::BulkString(format_double(s))))
+                .collect(),
+        )
+        .write(&mut self.stream)
+        .await
+    }
+
+    pub(super) async fn handle_zrank(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+        reverse: bool,
+    ) -> Result<()> {
+        let name = if reverse { "zrevrank" } else { "zrank" };
+        let key = args.next().ok_or(Error::MissingArgument(name, "key"))?;
+        let member = args.next().ok_or(Error::MissingArgument(name, "member"))?;
+        let with_score = match args.next() {
+            Some(arg) if arg.eq_ignore_ascii_case("WITHSCORE") => true,
+            Some(_) => return Err(Error::SyntaxError),
+            None => false,
+        };
+        if args.next().is_some() {
+            return Err(Error::SyntaxError);
+        }
+
+        let found = self
+            .read_sorted_set(&key, |set| {
+                let rank = set.rank(&member)?;
+                let rank = if reverse { set.len() - 1 - rank } else { rank };
+                Some((rank, set.score(&member)?))
+            })
+            .await?
+            .flatten();
+
+        let reply = match (found, with_score) {
+            (Some((rank, _)), false) => Type::Integer(rank as i64),
+            (Some((rank, score)), true) => Type::Array(vec![
+                Type::Integer(rank as i64),
+                Type::BulkString(format_double(score)),
+            ]),
+            (None, false) => Type::NullString,
+            (None, true) => Type::NullArray,
+        };
+
+        reply.write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_zcount(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+        by: &'static str,
+    ) -> Result<()> {
+        let (name, by) = match by {
+            "lex" => ("zlexcount", RangeBy::Lex),
+            _ => ("zcount", RangeBy::Score),
+        };
+        let key = args.next().ok_or(Error::MissingArgument(name, "key"))?;
+        let min = args.next().ok_or(Error::MissingArgument(name, "min"))?;
+        let max = args.next().ok_or(Error::MissingArgument(name, "max"))?;
+        let query = RangeQuery::new(by, &min, &max, false)?;
+
+        let count = self
+            .read_sorted_set(&key, |set| query.count(set))
+            .await?
+            .unwrap_or(0);
+
+        Type::Integer(count as i64).write(&mut self.stream).await
+    }
+
+    /// Handles ZRANGE and all of its legacy variants. `by` and `reverse` are implied by the
+    /// legacy commands, for which ZRANGE-only options are not accepted.
+    pub(super) async fn handle_zrange(
+        &mut self,
+        name: &'static str,
+        mut args: impl Iterator<Item = String>,
+        legacy: Option<(&'static str, bool)>,
+    ) -> Result<()> {
+        let key = args.next().ok_or(Error::MissingArgument(name, "key"))?;
+        let start = args.next().ok_or(Error::MissingArgument(name, "start"))?;
+        let stop = args.next().ok_or(Error::MissingArgument(name, "stop"))?;
+
+        let (query, with_scores) = RangeQuery::parse(&start, &stop, args, legacy)?;
+
+        let members = self
+            .read_sorted_set(&key, |set| query.collect(set))
+            .await?
+            .unwrap_or_default();
+
+        scored_members_reply(members, with_scores)
+            .write(&mut self.stream)
+            .await
+    }
+
+    pub(super) async fn handle_zremrange(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+        by: &'static str,
+    ) -> Result<()> {
+        let (name, by) = match by {
+            "score" => ("zremrangebyscore", RangeBy::Score),
+            "lex" => ("zremrangebylex", RangeBy::Lex),
+            _ => ("zremrangebyrank", RangeBy::Rank),
+        };
+        let key = args.next().ok_or(Error::MissingArgument(name, "key"))?;
+        let start = args.next().ok_or(Error::MissingArgument(name, "start"))?;
+        let stop = args.next().ok_or(Error::MissingArgument(name, "stop"))?;
+        let query = RangeQuery::new(by, &start, &stop, false)?;
+
+        let removed = self
+            .store
+            .with_keyspace(|keyspace| -> Result<_> {
+                let removed = keyspace
+                    .update(&key, |slot| {
+                        with_sorted_set(slot, false, |set| {
+                            Ok(query
+                                .ranks(set)
+                                .map_or(0, |(start, end)| set.remove_range_by_rank(start, end)))
+                        })
+                    })?
+                    .unwrap_or(0);
+                if removed > 0 {
+                    notify_removal(keyspace, &key, name);
+                }
+                Ok(removed)
+            })
+            .await?;
+
+        Type::Integer(removed as i64).write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_zcombine(
+        &mut self,
+        args: impl Iterator<Item = String>,
+        operation: SetOperation,
+    ) -> Result<()> {
+        let (combination, with_scores) = Combination::parse(operation, false, args)?;
+
+        let result = self
+            .store
+            .with_keyspace(|keyspace| combination.compute(keyspace))
+            .await?;
+
+        scored_members_reply(
+            result
+                .iter()
+                .map(|(member, score)| (member.to_owned(), score))
+                .collect(),
+            with_scores,
+        )
+        .write(&mut self.stream)
+        .await
+    }
+
+    pub(super) async fn handle_zcombinestore(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+        operation: SetOperation,
+    ) -> Result<()> {
+        let destination = args
+            .next()
+            .ok_or(Error::MissingArgument(operation.name(true), "destination"))?;
+        let (combination, _) = Combination::parse(operation, true, args)?;
+
+        let len = self
+            .store
+            .with_keyspace(|keyspace| -> Result<_> {
+                let result = combination.compute(keyspace)?;
+                Ok(store_sorted_set(
+                    keyspace,
+                    destination,
+                    result,
+                    operation.name(true),
+                ))
+            })
+            .await?;
+
+        Type::Integer(len as i64).write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_zrangestore(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let destination = args
+            .next()
+            .ok_or(Error::MissingArgument("zrangestore", "destination"))?;
+        let source = args
+            .next()
+            .ok_or(Error::MissingArgument("zrangestore", "source"))?;
+        let start = args
+            .next()
+            .ok_or(Error::MissingArgument("zrangestore", "start"))?;
+        let stop = args
+            .next()
+            .ok_or(Error::MissingArgument("zrangestore", "stop"))?;
+        let (query, with_scores) = RangeQuery::parse(&start, &stop, args, None)?;
+        if with_scores {
+            return Err(Error::SyntaxError);
+        }
+
+        let len = self
+            .store
+            .with_keyspace(|keyspace| -> Result<_> {
+                let members = match keyspace.get(&source) {
+                    Some(value) => value
+                        .as_sorted_set()
+                        .map(|set| query.collect(set))
+                        .ok_or(Error::ExpectedOtherType("zset"))?,
+                    None => Vec::new(),
+                };
+
+                let mut result = SortedSet::new();
+                for (member, score) in members {
+                    result.insert(member, score);
+                }
+                Ok(store_sorted_set(
+                    keyspace,
+                    destination,
+                    result,
+                    "zrangestore",
+                ))
+            })
+            .await?;
+
+        Type::Integer(len as i64).write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_zpop(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+        max: bool,
+    ) -> Result<()> {
+        let name = if max { "zpopmax" } else { "zpopmin" };
+        let key = args.next().ok_or(Error::MissingArgument(name, "key"))?;
+        let count = match args.next() {
+            Some(count) => usize::try_from(parse_integer(&count)?)
+                .map_err(|_| Error::InvalidArgument("value is out of range, must be positive"))?,
+            None => 1,
+        };
+        if args.next().is_some() {
+            return Err(Error::SyntaxError);
+        }
+
+        let popped = self
+            .store
+            .with_keyspace(|keyspace| pop_from_key(keyspace, &key, max, count))
+            .await?
+            .unwrap_or_default();
+
+        scored_members_reply(popped, true)
+            .write(&mut self.stream)
+            .await
+    }
+
+    pub(super) async fn handle_bzpop(
+        &mut self,
+        args: impl Iterator<Item = String>,
+        max: bool,
+    ) -> Result<()> {
+        let name = if max { "bzpopmax" } else { "bzpopmin" };
+        let mut keys: Vec<_> = args.collect();
+        let timeout = keys.pop().ok_or(Error::MissingArgument(name, "timeout"))?;
+        let timeout = parse_timeout(&timeout)?;
+        if keys.is_empty() {
+            return Err(Error::MissingArgument(name, "key"));
+        }
+
+        let popped = self
+            .blocking_pop(&keys, timeout, |keyspace, key| {
+                Ok(pop_from_key(keyspace, key, max, 1)?
+                    .and_then(|mut popped| popped.pop())
+                    .map(|popped| (key.to_owned(), popped)))
+            })
+            .await?;
+
+        match popped {
+            Some((key, (member, score))) => Type::Array(vec![
+                Type::BulkString(key),
+                Type::BulkString(member),
+                Type::BulkString(format_double(score)),
+            ]),
+            None => Type::NullArray,
+        }
+        .write(&mut self.stream)
+        .await
+    }
+
+    pub(super) async fn handle_zmpop(&mut self, args: impl Iterator<Item = String>) -> Result<()> {
+        let (keys, max, count) = parse_mpop("zmpop", args)?;
+
+        let popped = self
+            .store
+            .with_keyspace(|keyspace| -> Result<_> {
+                for key in &keys {
+                    if let Some(popped) = pop_from_key(keyspace, key, max, count)? {
+                        return Ok(Some((key.clone(), popped)));
+                    }
+                }
+                Ok(None)
+            })
+            .await?;
+
+        mpop_reply(popped).write(&mut self.stream).await
+    }
+
+    pub(super) async fn handle_bzmpop(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let timeout = args
+            .next()
+            .ok_or(Error::MissingArgument("bzmpop", "timeout"))?;
+        let timeout = parse_timeout(&timeout)?;
+        let (keys, max, count) = parse_mpop("bzmpop", args)?;
+
+        let popped = self
+            .blocking_pop(&keys, timeout, |keyspace, key| {
+                Ok(pop_from_key(keyspace, key, max, count)?.map(|popped| (key.to_owned(), popped)))
+            })
+            .await?;
+
+        mpop_reply(popped).write(&mut self.stream).await
+    }
+
+    /// Tries `pop` on each of `keys` in order, and if none of them yields a value, waits until
+    /// one of them is written to and tries again. Gives up with `None` after `timeout`.
+    async fn blocking_pop<T>(
+        &self,
+        keys: &[String],
+        timeout: Option<Duration>,
+        mut pop: impl FnMut(&mut Keyspace, &str) -> Result<Option<T>>,
+    ) -> Result<Option<T>> {
+        let deadline = timeout.map(|timeout| self.store.now() + timeout);
+
+        loop {
+            let (tx, rx) = mpsc::channel(1);
+            let popped = self
+                .store
+                .with_keyspace(|keyspace| -> Result<_> {
+                    for key in keys {
+                        if let Some(popped) = pop(keyspace, key)? {
+                            return Ok(Some(popped));
+                        }
+                    }
+
+                    // Transactions can't wait, so they behave as if the timeout passed right away
+                    if self.in_exec {
+                        return Ok(None);
+                    }
+                    // Registering under the same lock guarantees no write is missed
+                    for key in keys {
+                        keyspace.notify_on_ready(key, tx.clone());
+                    }
+                    Ok(None)
+                })
+                .await?;
+
+            if popped.is_some() || self.in_exec {
+                return Ok(popped);
+            }
+            if !self.wait_for_keys(keys, (tx, rx), deadline).await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Runs `op` on the sorted set stored under `key`, `None` if there is no such key.
+    pub(super) async fn read_sorted_set<T>(
+        &self,
+        key: &str,
+        op: impl FnOnce(&SortedSet) -> T,
+    ) -> Result<Option<T>> {
+        self.store
+            .get_ref(key, |value| {
+                value
+                    .as_sorted_set()
+                    .map(op)
+                    .ok_or(Error::ExpectedOtherType("zset"))
+            })
+            .await
+            .transpose()
+    }
+}
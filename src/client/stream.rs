@@ -0,0 +1,1403 @@
+use std::{
+    ops::Bound,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::sync::mpsc;
+
+use super::{expire::unix_millis, parse_integer, Client};
+use crate::{
+    error::Error,
+    resp::Type,
+    store::{EventFlags, KeyListener, Keyspace, Value},
+    stream::{
+        is_empty_range, ClaimOptions, ConsumerGroup, GroupRead, Item, ItemData, ItemId, Stream,
+        Trim, TrimThreshold, DEFAULT_TRIM_LIMIT,
+    },
+    Result,
+};
+
+/// Reply of XGROUP subcommands on a key that doesn't exist.
+const XGROUP_NO_KEY: &str = "The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.";
+
+/// Parses an item ID given as argument, where the sequence number may be left out like in
+/// `1526919030474`, meaning `1526919030474-0`.
+fn parse_id(id: &str) -> Result<ItemId> {
+    let invalid =
+        || Error::InvalidArgument("Invalid stream ID specified as stream command argument");
+    if id.contains('-') {
+        ItemId::try_from(id).map_err(|_| invalid())
+    } else {
+        Ok(ItemId::new(id.parse().map_err(|_| invalid())?, 0))
+    }
+}
+
+/// Parses the bound of an ID range: `-` and `+` are the lowest and highest IDs, a `(` prefix
+/// excludes the ID, and an ID without sequence number covers all items of that millisecond.
+fn parse_range_bound(bound: &str, start: bool) -> Result<Bound<ItemId>> {
+    match bound {
+        "-" if start => return Ok(Bound::Unbounded),
+        "+" if !start => return Ok(Bound::Unbounded),
+        "-" => return Ok(Bound::Included(ItemId::new(0, 0))),
+        "+" => return Ok(Bound::Included(ItemId::new(u64::MAX, u64::MAX))),
+        _ => {}
+    }
+
+    let (id, exclusive) = match bound.strip_prefix('(') {
+        Some(id) => (id, true),
+        None => (bound, false),
+    };
+    let mut parsed = parse_id(id)?;
+    if !start && !id.contains('-') {
+        parsed = ItemId::new(parsed.timestamp(), u64::MAX);
+    }
+
+    Ok(if exclusive {
+        Bound::Excluded(parsed)
+    } else {
+        Bound::Included(parsed)
+    })
+}
+
+/// The reply entry of a stream item, with a null value if the item was deleted meanwhile.
+fn item_reply(id: ItemId, data: Option<ItemData>) -> Type {
+    match data {
+        Some(data) => Item::new(id, data).into(),
+        None => Type::Array(vec![Type::BulkString(id.to_string()), Type::NullArray]),
+    }
+}
+
+/// Trimming options of XTRIM and XADD, which may come in any order.
+#[derive(Debug, Default)]
+struct TrimArgs {
+    threshold: Option<TrimThreshold>,
+    approximate: bool,
+    limit: Option<usize>,
+}
+
+impl TrimArgs {
+    /// Parses `arg` along with its values if it's `MAXLEN|MINID [=|~] threshold` or
+    /// `LIMIT count`, returning whether it was.
+    fn parse(&mut self, arg: &str, args: &mut impl Iterator<Item = String>) -> Result<bool> {
+        let option = arg.to_ascii_lowercase();
+        if option == "limit" {
+            let limit = parse_integer(&args.next().ok_or(Error::SyntaxError)?)?;
+            let limit = usize::try_from(limit)
+                .map_err(|_| Error::InvalidArgument("The LIMIT argument must be >= 0."))?;
+            self.limit = Some(limit);
+            return Ok(true);
+        }
+        if option != "maxlen" && option != "minid" {
+            return Ok(false);
+        }
+
+        let mut value = args.next().ok_or(Error::SyntaxError)?;
+        self.approximate = value == "~";
+        if value == "~" || value == "=" {
+            value = args.next().ok_or(Error::SyntaxError)?;
+        }
+        let threshold = if option == "maxlen" {
+            let len = usize::try_from(parse_integer(&value)?)
+                .map_err(|_| Error::InvalidArgument("The MAXLEN argument must be >= 0."))?;
+            TrimThreshold::MaxLen(len)
+        } else {
+            TrimThreshold::MinId(parse_id(&value)?)
+        };
+
+        match (self.threshold, threshold) {
+            (Some(TrimThreshold::MaxLen(_)), TrimThreshold::MinId(_))
+            | (Some(TrimThreshold::MinId(_)), TrimThreshold::MaxLen(_)) => {
+                Err(Error::InvalidArgument(
+                    "syntax error, MAXLEN and MINID options at the same time are not compatible",
+                ))
+            }
+            _ => {
+                self.threshold = Some(threshold);
+                Ok(true)
+            }
+        }
+    }
+
+    /// The trimming to do, if a threshold was given. Approximate trimming removes at most
+    /// [`DEFAULT_TRIM_LIMIT`] items unless told otherwise, `LIMIT 0` meaning no limit.
+    fn build(self) -> Result<Option<Trim>> {
+        if self.limit.is_some() && !self.approximate {
+            return Err(Error::InvalidArgument(
+                "syntax error, LIMIT cannot be used without the special ~ option",
+            ));
+        }
+
+        let limit = match (self.approximate, self.limit) {
+            (false, _) | (true, Some(0)) => None,
+            (true, None) => Some(DEFAULT_TRIM_LIMIT),
+            (true, limit) => limit,
+        };
+        Ok(self.threshold.map(|threshold| Trim { threshold, limit }))
+    }
+}
+
+/// Parses the minimum idle time of XCLAIM and XAUTOCLAIM, where negative times mean 0.
+fn parse_min_idle(value: &str, command: &str) -> Result<Duration> {
+    let min_idle = value.parse::<i64>().map_err(|_| {
+        Error::InvalidArgument(if command == "xclaim" {
+            "Invalid min-idle-time argument for XCLAIM"
+        } else {
+            "Invalid min-idle-time argument for XAUTOCLAIM"
+        })
+    })?;
+    Ok(Duration::from_millis(min_idle.max(0) as u64))
+}
+
+/// Reply entries of claimed items, or only their IDs.
+fn claimed_reply(stream: &Stream, claimed: Vec<ItemId>, justid: bool) -> Type {
+    let claimed = claimed.into_iter().map(|id| {
+        if justid {
+            Type::BulkString(id.to_string())
+        } else {
+            item_reply(id, stream.get(id))
+        }
+    });
+    Type::Array(claimed.collect())
+}
+
+/// Runs `op` on the stream under `key`, with `None` if the key doesn't exist.
+fn with_stream<T>(
+    keyspace: &mut Keyspace,
+    key: &str,
+    op: impl FnOnce(Option<&mut Stream>) -> Result<T>,
+) -> Result<T> {
+    keyspace.update(key, |value| match value {
+        Some(value) => op(Some(
+            value
+                .as_stream_mut()
+                .ok_or(Error::ExpectedOtherType("stream"))?,
+        )),
+        None => op(None),
+    })
+}
+
+/// A reply of XINFO: a map for RESP3 clients, a flat array of fields and values for others.
+fn info_reply(fields: Vec<(&str, Type)>, protocol: u8) -> Type {
+    let fields = fields
+        .into_iter()
+        .map(|(name, value)| (Type::BulkString(name.into()), value));
+    if protocol == 3 {
+        Type::Map(fields.collect())
+    } else {
+        Type::Array(fields.flat_map(|(name, value)| [name, value]).collect())
+    }
+}
+
+/// Fields XINFO STREAM replies with both with and without FULL.
+fn stream_counters(stream: &Stream) -> Vec<(&'static str, Type)> {
+    // Nodes are indexed by a map rather than a radix tree, so each node counts as one of both
+    let nodes = stream.node_count() as i64;
+    vec![
+        ("length", Type::Integer(stream.len() as i64)),
+        ("radix-tree-keys", Type::Integer(nodes)),
+        ("radix-tree-nodes", Type::Integer(nodes)),
+        (
+            "last-generated-id",
+            Type::BulkString(stream.last_id().to_string()),
+        ),
+        (
+            "max-deleted-entry-id",
+            Type::BulkString(stream.max_deleted_id().to_string()),
+        ),
+        (
+            "entries-added",
+            Type::Integer(stream.entries_added() as i64),
+        ),
+        (
+            "recorded-first-entry-id",
+            Type::BulkString(stream.first_id().to_string()),
+        ),
+    ]
+}
+
+/// `entries-read` and `lag` of a group in XINFO, which are nil if they aren't known.
+fn group_progress(stream: &Stream, group: &ConsumerGroup) -> [(&'static str, Type); 2] {
+    let count =
+        |value: Option<u64>| value.map_or(Type::NullString, |value| Type::Integer(value as i64));
+    [
+        ("entries-read", count(group.entries_read())),
+        ("lag", count(stream.lag(group))),
+    ]
+}
+
+fn stream_info(stream: &Stream, protocol: u8) -> Type {
+    let entry = |item: Option<Item>| item.map_or(Type::NullString, Type::from);
+    let first = entry(stream.range(Bound::Unbounded, Bound::Unbounded).next());
+    let last = entry(stream.range(Bound::Unbounded, Bound::Unbounded).next_back());
+
+    let mut fields = stream_counters(stream);
+    fields.extend([
+        ("groups", Type::Integer(stream.groups().count() as i64)),
+        ("first-entry", first),
+        ("last-entry", last),
+    ]);
+    info_reply(fields, protocol)
+}
+
+/// XINFO STREAM FULL, with up to `count` items, and pending entries of each group and consumer.
+fn stream_full_info(stream: &Stream, count: usize, protocol: u8) -> Type {
+    let entries = stream
+        .range(Bound::Unbounded, Bound::Unbounded)
+        .take(count)
+        .map(Type::from);
+
+    let groups = stream.groups().map(|(name, group)| {
+        let pending = group.pending().iter().take(count).map(|(id, entry)| {
+            Type::Array(vec![
+                Type::BulkString(id.to_string()),
+                Type::BulkString(entry.consumer.clone()),
+                Type::Integer(unix_millis(entry.delivered_at)),
+                Type::Integer(entry.delivery_count as i64),
+            ])
+        });
+        let consumers = group.consumers().map(|(name, consumer)| {
+            let pending = consumer.pending().take(count).map(|id| {
+                let entry = &group.pending()[id];
+                Type::Array(vec![
+                    Type::BulkString(id.to_string()),
+                    Type::Integer(unix_millis(entry.delivered_at)),
+                    Type::Integer(entry.delivery_count as i64),
+                ])
+            });
+            let fields = vec![
+                ("name", Type::BulkString(name.clone())),
+                ("seen-time", Type::Integer(unix_millis(consumer.seen_at))),
+                (
+                    "active-time",
+                    Type::Integer(consumer.active_at.map_or(-1, unix_millis)),
+                ),
+                ("pel-count", Type::Integer(consumer.pending_count() as i64)),
+                ("pending", Type::Array(pending.collect())),
+            ];
+            info_reply(fields, protocol)
+        });
+
+        let mut fields = vec![
+            ("name", Type::BulkString(name.clone())),
+            (
+                "last-delivered-id",
+                Type::BulkString(group.last_delivered().to_string()),
+            ),
+        ];
+        fields.extend(group_progress(stream, group));
+        fields.extend([
+            ("pel-count", Type::Integer(group.pending().len() as i64)),
+            ("pending", Type::Array(pending.collect())),
+            ("consumers", Type::Array(consumers.collect())),
+        ]);
+        info_reply(fields, protocol)
+    });
+
+    let mut fields = stream_counters(stream);
+    fields.extend([
+        ("entries", Type::Array(entries.collect())),
+        ("groups", Type::Array(groups.collect())),
+    ]);
+    info_reply(fields, protocol)
+}
+
+fn groups_info(stream: &Stream, protocol: u8) -> Type {
+    let groups = stream.groups().map(|(name, group)| {
+        let mut fields = vec![
+            ("name", Type::BulkString(name.clone())),
+            ("consumers", Type::Integer(group.consumers().count() as i64)),
+            ("pending", Type::Integer(group.pending().len() as i64)),
+            (
+                "last-delivered-id",
+                Type::BulkString(group.last_delivered().to_string()),
+            ),
+        ];
+        fields.extend(group_progress(stream, group));
+        info_reply(fields, protocol)
+    });
+    Type::Array(groups.collect())
+}
+
+/// XINFO CONSUMERS, where `idle` is the time since a consumer last tried to read or claim
+/// items, and `inactive` since it last got any, -1 if it never did.
+fn consumers_info(group: &ConsumerGroup, now: SystemTime, protocol: u8) -> Type {
+    let millis_since =
+        |time: SystemTime| now.duration_since(time).unwrap_or_default().as_millis() as i64;
+    let consumers = group.consumers().map(|(name, consumer)| {
+        let fields = vec![
+            ("name", Type::BulkString(name.clone())),
+            ("pending", Type::Integer(consumer.pending_count() as i64)),
+            ("idle", Type::Integer(millis_since(consumer.seen_at))),
+            (
+                "inactive",
+                Type::Integer(consumer.active_at.map_or(-1, millis_since)),
+            ),
+        ];
+        info_reply(fields, protocol)
+    });
+    Type::Array(consumers.collect())
+}
+
+/// Where XREAD starts reading a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReadStart {
+    /// `$`: items added after the command started.
+    New,
+    /// `+`: the last item.
+    Last,
+    /// Items after the ID.
+    After(ItemId),
+}
+
+impl ReadStart {
+    /// The range of items to read from `stream`, `None` if there's no stream yet.
+    fn resolve(self, stream: Option<&Stream>) -> Bound<ItemId> {
+        let last_item = || {
+            stream?
+                .range(Bound::Unbounded, Bound::Unbounded)
+                .next_back()
+        };
+        match self {
+            Self::New => Bound::Excluded(stream.map_or(ItemId::new(0, 0), Stream::last_id)),
+            Self::Last => last_item().map_or(Bound::Excluded(ItemId::new(0, 0)), |item| {
+                Bound::Included(item.id)
+            }),
+            Self::After(id) => Bound::Excluded(id),
+        }
+    }
+}
+
+/// Reads up to `count` items from each stream starting at its bound, leaving out streams
+/// without any.
+fn read_streams(
+    keyspace: &Keyspace,
+    keys: &[String],
+    bounds: &[Bound<ItemId>],
+    count: usize,
+) -> Result<Vec<Type>> {
+    let mut reply = Vec::new();
+    for (key, start) in keys.iter().zip(bounds) {
+        let Some(value) = keyspace.get(key) else {
+            continue;
+        };
+        let stream = value
+            .as_stream()
+            .ok_or(Error::ExpectedOtherType("stream"))?;
+
+        let items: Vec<_> = stream
+            .range(*start, Bound::Unbounded)
+            .take(count)
+            .map(Type::from)
+            .collect();
+        if !items.is_empty() {
+            reply.push(Type::Array(vec![
+                Type::BulkString(key.clone()),
+                Type::Array(items),
+            ]));
+        }
+    }
+    Ok(reply)
+}
+
+/// Options of XREADGROUP, with the streams to read and which of their items.
+struct ReadGroup {
+    group: String,
+    consumer: String,
+    count: Option<usize>,
+    noack: bool,
+    streams: Vec<(String, GroupRead)>,
+}
+
+impl ReadGroup {
+    /// Reads from all streams, leaving out those without new items. If nothing was read,
+    /// `listener` is registered on the streams to wait for writes. Fails without reading
+    /// anything unless every stream has the group.
+    fn read(&self, keyspace: &mut Keyspace, listener: Option<&KeyListener>) -> Result<Vec<Type>> {
+        for (key, _) in &self.streams {
+            let stream = keyspace
+                .get(key)
+                .map(|value| value.as_stream().ok_or(Error::ExpectedOtherType("stream")));
+            if stream
+                .transpose()?
+                .and_then(|s| s.group(&self.group))
+                .is_none()
+            {
+                return Err(Error::NoGroup(key.clone(), self.group.clone()));
+            }
+        }
+
+        let now = keyspace.now();
+        let mut reply = Vec::new();
+        for (key, read) in &self.streams {
+            let (created, items) = with_stream(keyspace, key, |stream| {
+                let stream = stream.expect("Checked above");
+                let created = !stream
+                    .group(&self.group)
+                    .is_some_and(|group| group.has_consumer(&self.consumer));
+                let items = stream
+                    .read_group(
+                        &self.group,
+                        &self.consumer,
+                        *read,
+                        self.count,
+                        self.noack,
+                        now,
+                    )
+                    .expect("Checked above");
+                Ok((created, items))
+            })?;
+            if created {
+                keyspace.notify(EventFlags::STREAM, "xgroup-createconsumer", key);
+            }
+
+            // Pending entries are always replied with, so that the consumer sees it has none
+            if items.is_empty() && *read == GroupRead::New {
+                continue;
+            }
+            let items = items.into_iter().map(|(id, data)| item_reply(id, data));
+            reply.push(Type::Array(vec![
+                Type::BulkString(key.clone()),
+                Type::Array(items.collect()),
+            ]));
+        }
+
+        if let (true, Some(listener)) = (reply.is_empty(), listener) {
+            for (key, _) in &self.streams {
+                keyspace.notify_on_ready(key, listener.clone());
+            }
+        }
+        Ok(reply)
+    }
+}
+
+impl Client {
+    /// `XGROUP CREATE|SETID|DESTROY|CREATECONSUMER|DELCONSUMER`, where CREATE and SETID take
+    /// the number of items the group read as `ENTRIESREAD entries-read`.
+    pub(super) async fn handle_xgroup(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let subcmd = args
+            .next()
+            .ok_or(Error::MissingArgument("xgroup", "subcommand"))?
+            .to_ascii_lowercase();
+        let key = args.next().ok_or(Error::MissingArgument("xgroup", "key"))?;
+        let group = args
+            .next()
+            .ok_or(Error::MissingArgument("xgroup", "group"))?;
+        let now = self.store.now();
+
+        let (reply, event) = match subcmd.as_str() {
+            "create" | "setid" => {
+                let id = args.next().ok_or(Error::MissingArgument("xgroup", "id"))?;
+                // `$` stands for the last item of the stream
+                let id = match id.as_str() {
+                    "$" => None,
+                    id => Some(parse_id(id)?),
+                };
+                let mut mkstream = false;
+                let mut entries_read = None;
+                while let Some(arg) = args.next() {
+                    match arg.to_ascii_lowercase().as_str() {
+                        "mkstream" if subcmd == "create" => mkstream = true,
+                        // -1 stands for an unknown number of entries read
+                        "entriesread" => {
+                            let value = parse_integer(&args.next().ok_or(Error::SyntaxError)?)?;
+                            entries_read = match value {
+                                -1 => None,
+                                value => Some(u64::try_from(value).map_err(|_| {
+                                    Error::InvalidArgument(
+                                        "value for ENTRIESREAD must be positive or -1",
+                                    )
+                                })?),
+                            };
+                        }
+                        _ => return Err(Error::SyntaxError),
+                    }
+                }
+
+                self.store
+                    .update(&key, |value| -> Result<()> {
+                        if value.is_none() && mkstream {
+                            *value = Some(Value::Stream(Stream::new()));
+                        }
+                        let stream = value
+                            .as_mut()
+                            .ok_or(Error::InvalidArgument(XGROUP_NO_KEY))?
+                            .as_stream_mut()
+                            .ok_or(Error::ExpectedOtherType("stream"))?;
+                        let id = id.unwrap_or_else(|| stream.last_id());
+
+                        if subcmd == "create" {
+                            if !stream.create_group(&group, id, entries_read) {
+                                return Err(Error::BusyGroup);
+                            }
+                        } else {
+                            stream
+                                .group_mut(&group)
+                                .ok_or_else(|| Error::NoGroupForKey(key.clone(), group.clone()))?
+                                .set_last_delivered(id, entries_read);
+                        }
+                        Ok(())
+                    })
+                    .await?;
+                let event = if subcmd == "create" {
+                    "xgroup-create"
+                } else {
+                    "xgroup-setid"
+                };
+                (Type::SimpleString("OK".into()), Some(event))
+            }
+            "destroy" => {
+                if args.next().is_some() {
+                    return Err(Error::WrongArity("xgroup|destroy".into()));
+                }
+                let destroyed = self
+                    .store
+                    .with_keyspace(|keyspace| {
+                        with_stream(keyspace, &key, |stream| {
+                            let stream = stream.ok_or(Error::InvalidArgument(XGROUP_NO_KEY))?;
+                            Ok(stream.destroy_group(&group))
+                        })
+                    })
+                    .await?;
+                (
+                    Type::Integer(destroyed as i64),
+                    destroyed.then_some("xgroup-destroy"),
+                )
+            }
+            "createconsumer" | "delconsumer" => {
+                let consumer = args
+                    .next()
+                    .ok_or(Error::MissingArgument("xgroup", "consumer"))?;
+                if args.next().is_some() {
+                    return Err(Error::WrongArity(format!("xgroup|{subcmd}")));
+                }
+
+                let (count, changed) = self
+                    .store
+                    .with_keyspace(|keyspace| {
+                        with_stream(keyspace, &key, |stream| {
+                            let group = stream
+                                .ok_or(Error::InvalidArgument(XGROUP_NO_KEY))?
+                                .group_mut(&group)
+                                .ok_or_else(|| Error::NoGroupForKey(key.clone(), group.clone()))?;
+                            if subcmd == "createconsumer" {
+                                let created = group.create_consumer(&consumer, now);
+                                Ok((created as usize, created))
+                            } else {
+                                let existed = group.has_consumer(&consumer);
+                                Ok((group.delete_consumer(&consumer), existed))
+                            }
+                        })
+                    })
+                    .await?;
+                let event = if subcmd == "createconsumer" {
+                    "xgroup-createconsumer"
+                } else {
+                    "xgroup-delconsumer"
+                };
+                (Type::Integer(count as i64), changed.then_some(event))
+            }
+            cmd => return Err(Error::UnimplementedCommand(format!("XGROUP {cmd}"))),
+        };
+
+        if let Some(event) = event {
+            self.store.notify(EventFlags::STREAM, event, &key);
+        }
+        reply.write(&mut self.stream).await
+    }
+
+    /// `XREADGROUP GROUP group consumer [COUNT count] [BLOCK ms] [NOACK] STREAMS key [key ...]
+    /// id [id ...]`, where the ID `>` reads new items and other IDs re-read the consumer's
+    /// pending entries. Blocking only waits for new items.
+    pub(super) async fn handle_xreadgroup(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let mut group = None;
+        let mut count = None;
+        let mut block = None;
+        let mut noack = false;
+        let mut streams = Vec::new();
+
+        while let Some(arg) = args.next() {
+            match arg.to_ascii_lowercase().as_str() {
+                "group" => {
+                    let name = args.next().ok_or(Error::SyntaxError)?;
+                    let consumer = args.next().ok_or(Error::SyntaxError)?;
+                    group = Some((name, consumer));
+                }
+                "count" => {
+                    let value = parse_integer(&args.next().ok_or(Error::SyntaxError)?)?;
+                    // Like in Redis, a count of 0 or less means no limit
+                    count = usize::try_from(value).ok().filter(|count| *count > 0);
+                }
+                "block" => {
+                    let timeout = parse_integer(&args.next().ok_or(Error::SyntaxError)?)?;
+                    let timeout = u64::try_from(timeout)
+                        .map_err(|_| Error::InvalidArgument("timeout is negative"))?;
+                    block = Some(timeout);
+                }
+                "noack" => noack = true,
+                "streams" => {
+                    let rest: Vec<_> = args.by_ref().collect();
+                    if rest.is_empty() || rest.len() % 2 != 0 {
+                        return Err(Error::InvalidArgument("Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' must be specified."));
+                    }
+                    let (keys, ids) = rest.split_at(rest.len() / 2);
+                    for (key, id) in keys.iter().zip(ids) {
+                        let read = if id == ">" {
+                            GroupRead::New
+                        } else {
+                            GroupRead::Pending(parse_id(id)?)
+                        };
+                        streams.push((key.clone(), read));
+                    }
+                }
+                _ => return Err(Error::SyntaxError),
+            }
+        }
+        let Some((group, consumer)) = group else {
+            return Err(Error::InvalidArgument(
+                "Missing GROUP option for XREADGROUP",
+            ));
+        };
+        if streams.is_empty() {
+            return Err(Error::SyntaxError);
+        }
+
+        let read = ReadGroup {
+            group,
+            consumer,
+            count,
+            noack,
+            streams,
+        };
+        // Inside a transaction XREADGROUP doesn't wait, like with an empty result after the
+        // timeout
+        let block = block.filter(|_| !self.in_exec);
+        let deadline = block
+            .filter(|block| *block > 0)
+            .map(|block| self.store.now() + Duration::from_millis(block));
+
+        let keys: Vec<_> = read.streams.iter().map(|(key, _)| key.clone()).collect();
+        let reply = loop {
+            let (tx, rx) = mpsc::channel(1);
+            let listener = block.is_some().then_some(&tx);
+            let reply = self
+                .store
+                .with_keyspace(|keyspace| read.read(keyspace, listener))
+                .await?;
+            if !reply.is_empty() || block.is_none() {
+                break reply;
+            }
+
+            // Another consumer may get the new items first, then this one waits again
+            if !self.wait_for_keys(&keys, (tx, rx), deadline).await? {
+                break reply;
+            }
+        };
+
+        if reply.is_empty() {
+            Type::NullArray.write(&mut self.stream).await
+        } else {
+            Type::Array(reply).write(&mut self.stream).await
+        }
+    }
+
+    /// `XACK key group id [id ...]`, replying with the number of entries that were pending.
+    pub(super) async fn handle_xack(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args.next().ok_or(Error::MissingArgument("xack", "key"))?;
+        let group = args.next().ok_or(Error::MissingArgument("xack", "group"))?;
+        let ids = args.map(|id| parse_id(&id)).collect::<Result<Vec<_>>>()?;
+        if ids.is_empty() {
+            return Err(Error::MissingArgument("xack", "id"));
+        }
+
+        let acked = self
+            .store
+            .with_keyspace(|keyspace| {
+                with_stream(keyspace, &key, |stream| {
+                    let Some(group) = stream.and_then(|stream| stream.group_mut(&group)) else {
+                        return Ok(0);
+                    };
+                    Ok(ids.into_iter().filter(|id| group.ack(*id)).count())
+                })
+            })
+            .await?;
+
+        Type::Integer(acked as i64).write(&mut self.stream).await
+    }
+
+    /// `XPENDING key group [[IDLE min-idle-time] start end count [consumer]]`: without a range
+    /// a summary of the pending entries, otherwise the entries themselves.
+    pub(super) async fn handle_xpending(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args
+            .next()
+            .ok_or(Error::MissingArgument("xpending", "key"))?;
+        let group = args
+            .next()
+            .ok_or(Error::MissingArgument("xpending", "group"))?;
+
+        let mut rest: Vec<_> = args.collect();
+        let mut min_idle = None;
+        if rest
+            .first()
+            .is_some_and(|arg| arg.eq_ignore_ascii_case("idle"))
+        {
+            let idle = rest.get(1).ok_or(Error::SyntaxError)?;
+            let idle = u64::try_from(parse_integer(idle)?).unwrap_or(0);
+            min_idle = Some(Duration::from_millis(idle));
+            rest.drain(..2);
+        }
+        let range = match rest.as_slice() {
+            [] if min_idle.is_none() => None,
+            [start, end, count] | [start, end, count, _] => {
+                let start = parse_range_bound(start, true)?;
+                let end = parse_range_bound(end, false)?;
+                let count = usize::try_from(parse_integer(count)?).unwrap_or(0);
+                Some((start, end, count, rest.get(3).cloned()))
+            }
+            _ => return Err(Error::SyntaxError),
+        };
+
+        let now = self.store.now();
+        let reply = self
+            .store
+            .get_ref(&key, |value| -> Result<Type> {
+                let stream = value
+                    .as_stream()
+                    .ok_or(Error::ExpectedOtherType("stream"))?;
+                let group = stream
+                    .group(&group)
+                    .ok_or_else(|| Error::NoGroup(key.clone(), group.clone()))?;
+                let pending = group.pending();
+
+                let Some((start, end, count, consumer)) = range else {
+                    let (Some(first), Some(last)) = (pending.keys().next(), pending.keys().last())
+                    else {
+                        return Ok(Type::Array(vec![
+                            Type::Integer(0),
+                            Type::NullString,
+                            Type::NullString,
+                            Type::NullArray,
+                        ]));
+                    };
+                    let consumers = group
+                        .consumers()
+                        .filter(|(_, consumer)| consumer.pending_count() > 0)
+                        .map(|(name, consumer)| {
+                            Type::Array(vec![
+                                Type::BulkString(name.clone()),
+                                Type::BulkString(consumer.pending_count().to_string()),
+                            ])
+                        });
+                    return Ok(Type::Array(vec![
+                        Type::Integer(pending.len() as i64),
+                        Type::BulkString(first.to_string()),
+                        Type::BulkString(last.to_string()),
+                        Type::Array(consumers.collect()),
+                    ]));
+                };
+
+                if is_empty_range(start, end) {
+                    return Ok(Type::Array(Vec::new()));
+                }
+
+                let entries = pending
+                    .range((start, end))
+                    .filter(|(_, entry)| {
+                        consumer
+                            .as_ref()
+                            .is_none_or(|consumer| entry.consumer == *consumer)
+                    })
+                    .map(|(id, entry)| {
+                        let idle = now.duration_since(entry.delivered_at).unwrap_or_default();
+                        (id, entry, idle)
+                    })
+                    .filter(|(_, _, idle)| min_idle.is_none_or(|min_idle| *idle >= min_idle))
+                    .take(count)
+                    .map(|(id, entry, idle)| {
+                        Type::Array(vec![
+                            Type::BulkString(id.to_string()),
+                            Type::BulkString(entry.consumer.clone()),
+                            Type::Integer(idle.as_millis() as i64),
+                            Type::Integer(entry.delivery_count as i64),
+                        ])
+                    });
+                Ok(Type::Array(entries.collect()))
+            })
+            .await
+            .unwrap_or_else(|| Err(Error::NoGroup(key.clone(), group.clone())))?;
+
+        reply.write(&mut self.stream).await
+    }
+
+    /// `XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms] [TIME unix-time-ms]
+    /// [RETRYCOUNT count] [FORCE] [JUSTID] [LASTID id]`
+    pub(super) async fn handle_xclaim(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args.next().ok_or(Error::MissingArgument("xclaim", "key"))?;
+        let group = args
+            .next()
+            .ok_or(Error::MissingArgument("xclaim", "group"))?;
+        let consumer = args
+            .next()
+            .ok_or(Error::MissingArgument("xclaim", "consumer"))?;
+        let min_idle = args
+            .next()
+            .ok_or(Error::MissingArgument("xclaim", "min-idle-time"))?;
+        let min_idle = parse_min_idle(&min_idle, "xclaim")?;
+
+        // IDs go on until the first argument that isn't one, which starts the options
+        let mut args = args.peekable();
+        let mut ids = Vec::new();
+        while let Some(id) = args.next_if(|arg| parse_id(arg).is_ok()) {
+            ids.push(parse_id(&id)?);
+        }
+        if ids.is_empty() {
+            return Err(Error::InvalidArgument(
+                "Invalid stream ID specified as stream command argument",
+            ));
+        }
+
+        let now = self.store.now();
+        let mut options = ClaimOptions {
+            min_idle,
+            delivered_at: now,
+            retry_count: None,
+            deliver: true,
+            force: false,
+        };
+        let mut last_id = None;
+        while let Some(arg) = args.next() {
+            let mut value =
+                || -> Result<i64> { parse_integer(&args.next().ok_or(Error::SyntaxError)?) };
+            match arg.to_ascii_lowercase().as_str() {
+                "idle" => {
+                    let idle = Duration::from_millis(value()?.max(0) as u64);
+                    options.delivered_at = now.checked_sub(idle).unwrap_or(UNIX_EPOCH);
+                }
+                "time" => {
+                    let time = UNIX_EPOCH + Duration::from_millis(value()?.max(0) as u64);
+                    options.delivered_at = time.min(now);
+                }
+                "retrycount" => options.retry_count = Some(value()?.max(0) as u64),
+                "force" => options.force = true,
+                "justid" => options.deliver = false,
+                "lastid" => {
+                    let id = args.next().ok_or(Error::SyntaxError)?;
+                    last_id = Some(parse_id(&id)?);
+                }
+                _ => {
+                    return Err(Error::UnrecognizedOption(arg, "XCLAIM"));
+                }
+            }
+        }
+        let justid = !options.deliver;
+
+        let (reply, created) = self
+            .store
+            .with_keyspace(|keyspace| {
+                with_stream(keyspace, &key, |stream| {
+                    let no_group = || Error::NoGroup(key.clone(), group.clone());
+                    let stream = stream.ok_or_else(no_group)?;
+                    let existing = stream.group(&group).ok_or_else(no_group)?;
+                    let created = !existing.has_consumer(&consumer);
+                    if let Some(last_id) = last_id {
+                        let group = stream.group_mut(&group).ok_or_else(no_group)?;
+                        if last_id > group.last_delivered() {
+                            group.set_last_delivered(last_id, group.entries_read());
+                        }
+                    }
+
+                    let claimed = stream
+                        .claim(&group, &consumer, &ids, &options, now)
+                        .ok_or_else(no_group)?;
+                    let created = created && !claimed.is_empty();
+                    Ok((claimed_reply(stream, claimed, justid), created))
+                })
+            })
+            .await?;
+        if created {
+            self.store
+                .notify(EventFlags::STREAM, "xgroup-createconsumer", &key);
+        }
+
+        reply.write(&mut self.stream).await
+    }
+
+    /// `XAUTOCLAIM key group consumer min-idle-time start [COUNT count] [JUSTID]`, replying
+    /// with the cursor to continue from, the claimed items and the IDs of deleted items that
+    /// were removed from the pending entries.
+    pub(super) async fn handle_xautoclaim(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args
+            .next()
+            .ok_or(Error::MissingArgument("xautoclaim", "key"))?;
+        let group = args
+            .next()
+            .ok_or(Error::MissingArgument("xautoclaim", "group"))?;
+        let consumer = args
+            .next()
+            .ok_or(Error::MissingArgument("xautoclaim", "consumer"))?;
+        let min_idle = args
+            .next()
+            .ok_or(Error::MissingArgument("xautoclaim", "min-idle-time"))?;
+        let min_idle = parse_min_idle(&min_idle, "xautoclaim")?;
+        let start = args
+            .next()
+            .ok_or(Error::MissingArgument("xautoclaim", "start"))?;
+        let start = parse_range_bound(&start, true)?;
+
+        let mut count = 100;
+        let mut justid = false;
+        while let Some(arg) = args.next() {
+            match arg.to_ascii_lowercase().as_str() {
+                "count" => {
+                    let value = parse_integer(&args.next().ok_or(Error::SyntaxError)?)?;
+                    count = usize::try_from(value)
+                        .ok()
+                        .filter(|count| *count > 0)
+                        .ok_or(Error::InvalidArgument("COUNT must be > 0"))?;
+                }
+                "justid" => justid = true,
+                _ => return Err(Error::SyntaxError),
+            }
+        }
+
+        let now = self.store.now();
+        let options = ClaimOptions {
+            min_idle,
+            delivered_at: now,
+            retry_count: None,
+            deliver: !justid,
+            force: false,
+        };
+        let (reply, created) = self
+            .store
+            .with_keyspace(|keyspace| {
+                with_stream(keyspace, &key, |stream| {
+                    let no_group = || Error::NoGroup(key.clone(), group.clone());
+                    let stream = stream.ok_or_else(no_group)?;
+                    let existing = stream.group(&group).ok_or_else(no_group)?;
+                    let created = !existing.has_consumer(&consumer);
+
+                    let claim = stream
+                        .auto_claim(&group, &consumer, start, count, &options, now)
+                        .ok_or_else(no_group)?;
+                    let created = created && !claim.claimed.is_empty();
+                    let deleted = claim
+                        .deleted
+                        .iter()
+                        .map(|id| Type::BulkString(id.to_string()))
+                        .collect();
+                    let reply = Type::Array(vec![
+                        Type::BulkString(claim.next.to_string()),
+                        claimed_reply(stream, claim.claimed, justid),
+                        Type::Array(deleted),
+                    ]);
+                    Ok((reply, created))
+                })
+            })
+            .await?;
+        if created {
+            self.store
+                .notify(EventFlags::STREAM, "xgroup-createconsumer", &key);
+        }
+
+        reply.write(&mut self.stream).await
+    }
+
+    /// `XADD key [NOMKSTREAM] [MAXLEN|MINID [=|~] threshold [LIMIT count]] <*|id> field value
+    /// [field value ...]`
+    pub(super) async fn handle_xadd(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args.next().ok_or(Error::MissingArgument("xadd", "key"))?;
+        let mut nomkstream = false;
+        let mut trim = TrimArgs::default();
+        let id = loop {
+            let arg = args.next().ok_or(Error::MissingArgument("xadd", "id"))?;
+            if arg.eq_ignore_ascii_case("nomkstream") {
+                nomkstream = true;
+            } else if !trim.parse(&arg, &mut args)? {
+                break arg;
+            }
+        };
+        let trim = trim.build()?;
+
+        let mut items = ItemData::new();
+        while let Some(key) = args.next() {
+            let value = args.next().ok_or(Error::MissingArgument("xadd", "value"))?;
+            items.push((key, value));
+        }
+        if items.is_empty() {
+            return Err(Error::WrongArity("xadd".into()));
+        }
+
+        let id = self
+            .store
+            .insert_stream_item(key, id.as_str().try_into()?, items, trim, nomkstream)
+            .await?;
+        match id {
+            Some(id) => Type::BulkString(id.to_string()),
+            None => Type::NullString,
+        }
+        .write(&mut self.stream)
+        .await
+    }
+
+    /// `XLEN key`
+    pub(super) async fn handle_xlen(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args.next().ok_or(Error::MissingArgument("xlen", "key"))?;
+        if args.next().is_some() {
+            return Err(Error::WrongArity("xlen".into()));
+        }
+
+        let len = self
+            .store
+            .with_keyspace(|keyspace| match keyspace.get(&key) {
+                Some(value) => value
+                    .as_stream()
+                    .map(Stream::len)
+                    .ok_or(Error::ExpectedOtherType("stream")),
+                None => Ok(0),
+            })
+            .await?;
+        Type::Integer(len as i64).write(&mut self.stream).await
+    }
+
+    /// `XDEL key id [id ...]`, replying with the number of items deleted.
+    pub(super) async fn handle_xdel(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args.next().ok_or(Error::MissingArgument("xdel", "key"))?;
+        let ids = args.map(|id| parse_id(&id)).collect::<Result<Vec<_>>>()?;
+
+        let deleted = self
+            .store
+            .with_keyspace(|keyspace| {
+                with_stream(keyspace, &key, |stream| {
+                    Ok(stream.map_or(0, |stream| stream.delete(&ids)))
+                })
+            })
+            .await?;
+        if deleted > 0 {
+            self.store.notify(EventFlags::STREAM, "xdel", &key);
+        }
+
+        Type::Integer(deleted as i64).write(&mut self.stream).await
+    }
+
+    /// `XTRIM key MAXLEN|MINID [=|~] threshold [LIMIT count]`, replying with the number of
+    /// items removed.
+    pub(super) async fn handle_xtrim(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args.next().ok_or(Error::MissingArgument("xtrim", "key"))?;
+        let mut trim = TrimArgs::default();
+        while let Some(arg) = args.next() {
+            if !trim.parse(&arg, &mut args)? {
+                return Err(Error::SyntaxError);
+            }
+        }
+        let trim = trim.build()?.ok_or(Error::SyntaxError)?;
+
+        let trimmed = self
+            .store
+            .with_keyspace(|keyspace| {
+                with_stream(keyspace, &key, |stream| {
+                    Ok(stream.map_or(0, |stream| stream.trim(trim)))
+                })
+            })
+            .await?;
+        if trimmed > 0 {
+            self.store.notify(EventFlags::STREAM, "xtrim", &key);
+        }
+
+        Type::Integer(trimmed as i64).write(&mut self.stream).await
+    }
+
+    /// `XSETID key last-id [ENTRIESADDED entries-added] [MAXDELETEDID max-deleted-id]`, to
+    /// migrate a stream together with the counters XINFO reports.
+    pub(super) async fn handle_xsetid(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let key = args.next().ok_or(Error::MissingArgument("xsetid", "key"))?;
+        let id = args
+            .next()
+            .ok_or(Error::MissingArgument("xsetid", "last-id"))?;
+        let id = parse_id(&id)?;
+        let (mut entries_added, mut max_deleted_id) = (None, None);
+        while let Some(arg) = args.next() {
+            let value = args.next().ok_or(Error::SyntaxError)?;
+            match arg.to_ascii_lowercase().as_str() {
+                "entriesadded" => {
+                    let value = u64::try_from(parse_integer(&value)?)
+                        .map_err(|_| Error::InvalidArgument("entries_added must be positive"))?;
+                    entries_added = Some(value);
+                }
+                "maxdeletedid" => max_deleted_id = Some(parse_id(&value)?),
+                _ => return Err(Error::SyntaxError),
+            }
+        }
+
+        self.store
+            .with_keyspace(|keyspace| {
+                with_stream(keyspace, &key, |stream| {
+                    let stream = stream.ok_or(Error::InvalidArgument("no such key"))?;
+                    Ok(stream.set_last_id(id, entries_added, max_deleted_id)?)
+                })
+            })
+            .await?;
+        self.store.notify(EventFlags::STREAM, "xsetid", &key);
+
+        Type::SimpleString("OK".into())
+            .write(&mut self.stream)
+            .await
+    }
+
+    /// `XINFO STREAM key [FULL [COUNT count]]`, `XINFO GROUPS key` and
+    /// `XINFO CONSUMERS key group`. FULL lists up to `count` items and pending entries, 10 by
+    /// default and all of them with 0.
+    pub(super) async fn handle_xinfo(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let subcmd = args
+            .next()
+            .ok_or(Error::MissingArgument("xinfo", "subcommand"))?
+            .to_ascii_lowercase();
+        if !matches!(subcmd.as_str(), "stream" | "groups" | "consumers") {
+            return Err(Error::UnimplementedCommand(format!("XINFO {subcmd}")));
+        }
+        let arity = || Error::WrongArity(format!("xinfo|{subcmd}"));
+        let key = args.next().ok_or_else(arity)?;
+        let group = match subcmd.as_str() {
+            "consumers" => Some(args.next().ok_or_else(arity)?),
+            _ => None,
+        };
+
+        let mut full = None;
+        if subcmd == "stream" {
+            if let Some(arg) = args.next() {
+                if !arg.eq_ignore_ascii_case("full") {
+                    return Err(Error::SyntaxError);
+                }
+                let mut count = 10;
+                match (args.next(), args.next()) {
+                    (None, _) => {}
+                    (Some(arg), Some(value)) if arg.eq_ignore_ascii_case("count") => {
+                        count = usize::try_from(parse_integer(&value)?).unwrap_or(0);
+                    }
+                    _ => return Err(Error::SyntaxError),
+                }
+                full = Some(if count == 0 { usize::MAX } else { count });
+            }
+        }
+        if args.next().is_some() {
+            return Err(if subcmd == "stream" {
+                Error::SyntaxError
+            } else {
+                arity()
+            });
+        }
+
+        let now = self.store.now();
+        let protocol = self.protocol;
+        let reply = self
+            .store
+            .get_ref(&key, |value| -> Result<Type> {
+                let stream = value
+                    .as_stream()
+                    .ok_or(Error::ExpectedOtherType("stream"))?;
+                Ok(match (&group, full) {
+                    (Some(group), _) => {
+                        let group = stream
+                            .group(group)
+                            .ok_or_else(|| Error::NoGroupForKey(key.clone(), group.clone()))?;
+                        consumers_info(group, now, protocol)
+                    }
+                    (None, _) if subcmd == "groups" => groups_info(stream, protocol),
+                    (None, Some(count)) => stream_full_info(stream, count, protocol),
+                    (None, None) => stream_info(stream, protocol),
+                })
+            })
+            .await
+            .unwrap_or(Err(Error::InvalidArgument("no such key")))?;
+
+        reply.write(&mut self.stream).await
+    }
+
+    /// `XRANGE key start end [COUNT count]`, or `XREVRANGE key end start [COUNT count]` with
+    /// `rev` to reply with the newest items first.
+    pub(super) async fn handle_xrange(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+        rev: bool,
+    ) -> Result<()> {
+        let command = if rev { "xrevrange" } else { "xrange" };
+        let key = args.next().ok_or(Error::MissingArgument(command, "key"))?;
+        let mut start = args
+            .next()
+            .ok_or(Error::MissingArgument(command, "start"))?;
+        let mut end = args.next().ok_or(Error::MissingArgument(command, "end"))?;
+        if rev {
+            std::mem::swap(&mut start, &mut end);
+        }
+        let start = parse_range_bound(&start, true)?;
+        let end = parse_range_bound(&end, false)?;
+
+        let mut count = None;
+        while let Some(arg) = args.next() {
+            match arg.to_ascii_lowercase().as_str() {
+                // Negative counts reply with no items, like COUNT 0
+                "count" => {
+                    let value = parse_integer(&args.next().ok_or(Error::SyntaxError)?)?;
+                    count = Some(usize::try_from(value).unwrap_or(0));
+                }
+                _ => return Err(Error::SyntaxError),
+            }
+        }
+        let count = count.unwrap_or(usize::MAX);
+
+        self.store
+            .get_ref(&key, move |value| -> Result<_> {
+                let stream = value
+                    .as_stream()
+                    .ok_or(Error::ExpectedOtherType("stream"))?;
+
+                let range = stream.range(start, end);
+                let items = if rev {
+                    range.rev().take(count).map(|v| v.into()).collect()
+                } else {
+                    range.take(count).map(|v| v.into()).collect()
+                };
+                Ok(Type::Array(items))
+            })
+            .await
+            .unwrap_or(Ok(Type::Array(Vec::new())))?
+            .write(&mut self.stream)
+            .await
+    }
+
+    /// `XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]`, reading
+    /// the items after each ID. With BLOCK it waits until any of the streams has such items,
+    /// then replies with those of every stream.
+    pub(super) async fn handle_xread(
+        &mut self,
+        mut args: impl Iterator<Item = String>,
+    ) -> Result<()> {
+        let mut count = usize::MAX;
+        let mut block = None;
+        let mut streams = Vec::new();
+        while let Some(arg) = args.next() {
+            match arg.to_ascii_uppercase().as_str() {
+                "STREAMS" => {
+                    let args: Vec<_> = args.collect();
+                    if args.is_empty() || args.len() % 2 != 0 {
+                        return Err(Error::InvalidArgument("Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified."));
+                    }
+
+                    let (keys, ids) = args.split_at(args.len() / 2);
+                    for (key, id) in keys.iter().zip(ids) {
+                        let start = match id.as_str() {
+                            "$" => ReadStart::New,
+                            "+" => ReadStart::Last,
+                            id => ReadStart::After(parse_id(id)?),
+                        };
+                        streams.push((key.clone(), start));
+                    }
+                    break;
+                }
+                "BLOCK" => {
+                    let duration = args
+                        .next()
+                        .ok_or(Error::MissingArgument("xread", "block duration"))?;
+                    block = Some(duration.parse::<u64>()?);
+                }
+                // Counts below 1 read all items
+                "COUNT" => {
+                    let value = parse_integer(&args.next().ok_or(Error::SyntaxError)?)?;
+                    count = usize::try_from(value)
+                        .ok()
+                        .filter(|count| *count > 0)
+                        .unwrap_or(usize::MAX);
+                }
+                _ => {
+                    return Err(Error::UnexpectedArgument(arg));
+                }
+            }
+        }
+        if streams.is_empty() {
+            return Err(Error::SyntaxError);
+        }
+
+        // Inside a transaction XREAD doesn't wait, like with an empty result after the timeout
+        let block = block.filter(|_| !self.in_exec);
+        let deadline = block
+            .filter(|block| *block > 0)
+            .map(|block| self.store.now() + Duration::from_millis(block));
+        let keys: Vec<_> = streams.iter().map(|(key, _)| key.clone()).collect();
+
+        // `$` and `+` are resolved on the first read, so that waiting doesn't change them
+        let mut bounds = None;
+        let reply = loop {
+            let (tx, rx) = mpsc::channel(1);
+            let reply = self
+                .store
+                .with_keyspace(|keyspace| -> Result<_> {
+                    let bounds = bounds.get_or_insert_with(|| {
+                        streams
+                            .iter()
+                            .map(|(key, start)| {
+                                let stream = keyspace.get(key).and_then(Value::as_stream);
+                                start.resolve(stream)
+                            })
+                            .collect::<Vec<_>>()
+                    });
+                    let reply = read_streams(keyspace, &keys, bounds, count)?;
+                    if reply.is_empty() && block.is_some() {
+                        for key in &keys {
+                            keyspace.notify_on_ready(key, tx.clone());
+                        }
+                    }
+                    Ok(reply)
+                })
+                .await?;
+            if !reply.is_empty() || block.is_none() {
+                break reply;
+            }
+
+            // Writes other than new items wake the client up too, then it waits again
+            if !self.wait_for_keys(&keys, (tx, rx), deadline).await? {
+                break reply;
+            }
+        };
+
+        if reply.is_empty() {
+            Type::NullString.write(&mut self.stream).await
+        } else {
+            Type::Array(reply).write(&mut self.stream).await
+        }
+    }
+}
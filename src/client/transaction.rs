@@ -0,0 +1,147 @@
+use super::Client;
+use crate::{error::Error, resp::Type, Result};
+
+/// A key watched by WATCH, with the version it had at the time.
+#[derive(Debug)]
+pub(super) struct WatchedKey {
+    db: usize,
+    key: String,
+    version: Option<u64>,
+}
+
+/// Commands queued after MULTI, waiting for EXEC.
+#[derive(Debug, Default)]
+pub(super) struct Transaction {
+    commands: Vec<Vec<Vec<u8>>>,
+    /// Set when a command could not be queued, which makes EXEC fail.
+    aborted: bool,
+}
+
+impl Client {
+    /// Whether `command` should be queued instead of run right away.
+    pub(super) fn queues(&self, command: &str) -> bool {
+        self.transaction.is_some() && !matches!(command, "multi" | "exec" | "discard" | "watch")
+    }
+
+    /// Makes the current transaction, if any, fail on EXEC because one of its commands was
+    /// rejected.
+    pub(super) fn abort_transaction(&mut self) {
+        if let Some(transaction) = &mut self.transaction {
+            transaction.aborted = true;
+        }
+    }
+
+    /// Queues a command of the current transaction.
+    pub(super) async fn queue_command(&mut self, cmd: Vec<Vec<u8>>) -> Result<()> {
+        self.transaction
+            .as_mut()
+            .expect("commands are only queued during a transaction")
+            .commands
+            .push(cmd);
+        Type::SimpleString("QUEUED".into())
+            .write(&mut self.stream)
+            .await
+    }
+
+    pub(super) async fn handle_multi(&mut self) -> Result<()> {
+        if self.transaction.is_some() {
+            return Err(Error::InvalidArgument("MULTI calls can not be nested"));
+        }
+
+        self.transaction = Some(Transaction::default());
+        Type::SimpleString("OK".into())
+            .write(&mut self.stream)
+            .await
+    }
+
+    pub(super) async fn handle_discard(&mut self) -> Result<()> {
+        if self.transaction.take().is_none() {
+            return Err(Error::InvalidArgument("DISCARD without MULTI"));
+        }
+        self.watched.clear();
+
+        Type::SimpleString("OK".into())
+            .write(&mut self.stream)
+            .await
+    }
+
+    /// `WATCH key [key ...]`, making the next EXEC fail if any of the keys is written first.
+    pub(super) async fn handle_watch(&mut self, args: impl Iterator<Item = String>) -> Result<()> {
+        if self.transaction.is_some() {
+            return Err(Error::InvalidArgument("WATCH inside MULTI is not allowed"));
+        }
+
+        let db = self.store.db();
+        let keys: Vec<_> = args.collect();
+        if keys.is_empty() {
+            return Err(Error::MissingArgument("watch", "key"));
+        }
+        let watched: Vec<_> = self
+            .store
+            .with_keyspace(|keyspace| {
+                keys.into_iter()
+                    .map(|key| WatchedKey {
+                        db,
+                        version: keyspace.version(&key),
+                        key,
+                    })
+                    .collect()
+            })
+            .await;
+        self.watched.extend(watched);
+
+        Type::SimpleString("OK".into())
+            .write(&mut self.stream)
+            .await
+    }
+
+    pub(super) async fn handle_unwatch(&mut self) -> Result<()> {
+        self.watched.clear();
+        Type::SimpleString("OK".into())
+            .write(&mut self.stream)
+            .await
+    }
+
+    /// Runs the queued commands while holding the lock on the databases, replying with an
+    /// array of their replies. Errors of single commands become elements of that array, while
+    /// a write to a watched key makes the reply a null array without running anything.
+    pub(super) async fn handle_exec(&mut self) -> Result<()> {
+        let transaction = self
+            .transaction
+            .take()
+            .ok_or(Error::InvalidArgument("EXEC without MULTI"))?;
+        let watched = std::mem::take(&mut self.watched);
+        if transaction.aborted {
+            return Err(Error::ExecAbort);
+        }
+
+        self.store.hold_lock().await;
+        let modified = self
+            .store
+            .with_databases(|dbs| {
+                watched
+                    .iter()
+                    .any(|watched| dbs[watched.db].version(&watched.key) != watched.version)
+            })
+            .await;
+        if modified {
+            self.store.release_lock();
+            return Type::NullArray.write(&mut self.stream).await;
+        }
+
+        Type::write_array_header(transaction.commands.len(), &mut self.stream).await?;
+        self.in_exec = true;
+        let mut result = Ok(());
+        for cmd in transaction.commands {
+            // EXEC itself is never queued, but the compiler can't know the recursion ends
+            result = Box::pin(self.run_command_with_reply(cmd)).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        self.in_exec = false;
+        self.store.release_lock();
+
+        result
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    fmt::Debug,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::sync::{futures::Notified, Notify};
+
+/// Source of the current time for key expiry and stream IDs.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> SystemTime;
+
+    /// Returns the clock if it can be moved by hand, like from DEBUG CLOCK.
+    fn as_manual(&self) -> Option<&ManualClock> {
+        None
+    }
+}
+
+/// Wall clock time that never goes backwards, so that adjusting the system clock can't bring
+/// back keys that have already expired.
+#[derive(Debug, Default)]
+pub struct SystemClock {
+    latest: AtomicU64,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        let now = to_nanos(SystemTime::now());
+        let latest = self.latest.fetch_max(now, Ordering::Relaxed);
+        from_nanos(latest.max(now))
+    }
+}
+
+/// A clock that only moves when told to, for deterministic tests of expiry and timeouts.
+#[derive(Debug)]
+pub struct ManualClock {
+    now: AtomicU64,
+    moved: Notify,
+}
+
+impl ManualClock {
+    pub fn new(start: SystemTime) -> Self {
+        Self {
+            now: AtomicU64::new(to_nanos(start)),
+            moved: Notify::new(),
+        }
+    }
+
+    pub fn set(&self, now: SystemTime) {
+        self.now.store(to_nanos(now), Ordering::Relaxed);
+        self.moved.notify_waiters();
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.fetch_add(by.as_nanos() as u64, Ordering::Relaxed);
+        self.moved.notify_waiters();
+    }
+
+    /// Completes the next time the clock is set or advanced after this was called, which is when
+    /// clients blocked until some deadline have to check it again.
+    pub fn moved(&self) -> Notified<'_> {
+        self.moved.notified()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        from_nanos(self.now.load(Ordering::Relaxed))
+    }
+
+    fn as_manual(&self) -> Option<&ManualClock> {
+        Some(self)
+    }
+}
+
+fn to_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos() as u64)
+}
+
+fn from_nanos(nanos: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(nanos)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::{Clock, ManualClock, SystemClock};
+
+    #[test]
+    fn system_clock_does_not_go_backwards() {
+        let clock = SystemClock::new();
+        let first = clock.now();
+        assert!(clock.now() >= first);
+
+        let future = SystemTime::now() + Duration::from_secs(3600);
+        clock.latest.store(
+            future.duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64,
+            std::sync::atomic::Ordering::Relaxed,
+        );
+        assert_eq!(clock.now(), future);
+    }
+
+    #[test]
+    fn manual_clock() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(10));
+        assert_eq!(clock.now(), UNIX_EPOCH + Duration::from_secs(10));
+
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(clock.now(), UNIX_EPOCH + Duration::from_millis(11500));
+
+        clock.set(UNIX_EPOCH);
+        assert_eq!(clock.now(), UNIX_EPOCH);
+        assert!(clock.as_manual().is_some());
+        assert!(SystemClock::new().as_manual().is_none());
+    }
+}
@@ -0,0 +1,59 @@
+//! Hash slots of cluster mode. There is a single node, which serves every slot: slot
+//! ownership and MOVED redirections are not implemented.
+
+/// Number of hash slots keys and shard channels are distributed over in a cluster.
+pub const SLOTS: u16 = 16384;
+
+/// The hash slot of a key or shard channel. If it contains a non-empty hash tag like in
+/// `{user1000}.following`, only the tag is hashed, so that related keys share a slot.
+pub fn key_slot(key: &[u8]) -> u16 {
+    let tag = key
+        .iter()
+        .position(|&b| b == b'{')
+        .and_then(|start| {
+            let rest = &key[start + 1..];
+            let end = rest.iter().position(|&b| b == b'}')?;
+            Some(&rest[..end])
+        })
+        .filter(|tag| !tag.is_empty());
+
+    crc16(tag.unwrap_or(key)) % SLOTS
+}
+
+/// The CRC-16/XMODEM checksum Redis Cluster uses for hash slots.
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ (u16::from(byte) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{crc16, key_slot};
+
+    #[test]
+    fn checksum() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+    }
+
+    #[test]
+    fn slots_and_hash_tags() {
+        assert_eq!(key_slot(b"foo"), 12182);
+        assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+        assert_eq!(
+            key_slot(b"{user1000}.following"),
+            key_slot(b"{user1000}.followers")
+        );
+        // Empty tags and unterminated braces hash the whole key
+        assert_eq!(key_slot(b"{}foo"), crc16(b"{}foo") % 16384);
+        assert_eq!(key_slot(b"{foo"), crc16(b"{foo") % 16384);
+        // Only the first tag counts
+        assert_eq!(key_slot(b"{a}{b}"), key_slot(b"a"));
+    }
+}
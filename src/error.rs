@@ -8,8 +8,9 @@ use std::{
 use thiserror::{self, Error};
 
 use crate::{
+    hyperloglog::HyperLogLogError,
     resp::Type,
-    stream::{InsertionError, ItemIdParseError},
+    stream::{InsertionError, ItemIdParseError, SetIdError},
 };
 
 #[derive(Debug, Error)]
@@ -43,8 +44,44 @@ pub enum Error {
 
     #[error("Missing argument {1} in {0} command")]
     MissingArgument(&'static str, &'static str),
+    #[error("wrong number of arguments for '{0}' command")]
+    WrongArity(String),
+    #[error("Transaction discarded because of previous errors.")]
+    ExecAbort,
+    #[error("Can't execute '{0}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context")]
+    NotAllowedWhileSubscribed(String),
+    #[error("Keys in request don't hash to the same slot")]
+    CrossSlot,
+    #[error("Unknown option or number of arguments for CONFIG SET - '{0}'")]
+    UnknownConfigOption(String),
+    #[error("CONFIG SET failed (possibly related to argument '{0}') - {1}")]
+    InvalidConfigValue(String, &'static str),
+    #[error("unsupported protocol version")]
+    NoProto,
+    #[error("Prefix '{0}' overlaps with an existing prefix '{1}'. Prefixes for a single client must not overlap.")]
+    OverlappingPrefixes(String, String),
+    #[error("No such key '{0}' or consumer group '{1}'")]
+    NoGroup(String, String),
+    #[error("No such consumer group '{1}' for key name '{0}'")]
+    NoGroupForKey(String, String),
+    #[error("Unrecognized {1} option '{0}'")]
+    UnrecognizedOption(String, &'static str),
+    #[error("Consumer Group name already exists")]
+    BusyGroup,
     #[error("Unexpected argument '{0}'")]
     UnexpectedArgument(String),
+    #[error("syntax error")]
+    SyntaxError,
+    #[error("value is not an integer or out of range")]
+    NotAnInteger,
+    #[error("value is not a valid float")]
+    NotAFloat,
+    #[error("{0}")]
+    InvalidArgument(&'static str),
+    #[error("invalid expire time in '{0}' command")]
+    InvalidExpireTime(&'static str),
+    #[error("invalid longitude,latitude pair {0:.6},{1:.6}")]
+    InvalidCoordinates(f64, f64),
 
     #[error("Parse error {0:?}")]
     ParseError(nom::Err<nom::error::Error<Vec<u8>>>),
@@ -56,6 +93,11 @@ pub enum Error {
 
     #[error("Failed to insert into stream: {0}")]
     StreamInsertError(#[from] InsertionError),
+    #[error("Failed to set the last ID of a stream: {0}")]
+    StreamSetIdError(#[from] SetIdError),
+
+    #[error("Invalid HyperLogLog: {0}")]
+    HyperLogLogError(#[from] HyperLogLogError),
 
     #[error("Failed to parse item id: {0}")]
     ItemIdParseError(#[from] ItemIdParseError),
@@ -70,12 +112,26 @@ pub enum Error {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ErrorKind {
     Generic,
+    WrongType,
+    InvalidObject,
+    ExecAbort,
+    CrossSlot,
+    NoProto,
+    NoGroup,
+    BusyGroup,
 }
 
 impl Display for ErrorKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Generic => write!(f, "ERR"),
+            Self::WrongType => write!(f, "WRONGTYPE"),
+            Self::InvalidObject => write!(f, "INVALIDOBJ"),
+            Self::ExecAbort => write!(f, "EXECABORT"),
+            Self::CrossSlot => write!(f, "CROSSSLOT"),
+            Self::NoProto => write!(f, "NOPROTO"),
+            Self::NoGroup => write!(f, "NOGROUP"),
+            Self::BusyGroup => write!(f, "BUSYGROUP"),
         }
     }
 }
@@ -86,7 +142,17 @@ impl Error {
     }
 
     pub fn kind(&self) -> ErrorKind {
-        ErrorKind::Generic
+        match self {
+            Self::ExpectedOtherType(_)
+            | Self::HyperLogLogError(HyperLogLogError::NotAHyperLogLog) => ErrorKind::WrongType,
+            Self::HyperLogLogError(_) => ErrorKind::InvalidObject,
+            Self::ExecAbort => ErrorKind::ExecAbort,
+            Self::CrossSlot => ErrorKind::CrossSlot,
+            Self::NoProto => ErrorKind::NoProto,
+            Self::NoGroup(_, _) | Self::NoGroupForKey(_, _) => ErrorKind::NoGroup,
+            Self::BusyGroup => ErrorKind::BusyGroup,
+            _ => ErrorKind::Generic,
+        }
     }
 
     pub fn redis_error_message(&self, cmd: &str) -> String {
@@ -97,15 +163,38 @@ impl Error {
             Self::StreamInsertError(InsertionError::IdTooLow) => {
                 format!("The ID specified in {cmd} must be greater than 0-0")
             }
+            Self::ExpectedOtherType(_) => {
+                "Operation against a key holding the wrong kind of value".into()
+            }
+            Self::HyperLogLogError(err) => err.to_string(),
+            Self::StreamSetIdError(err) => err.to_string(),
+            Self::SyntaxError
+            | Self::NotAnInteger
+            | Self::NotAFloat
+            | Self::InvalidArgument(_)
+            | Self::InvalidExpireTime(_)
+            | Self::InvalidCoordinates(_, _)
+            | Self::WrongArity(_)
+            | Self::ExecAbort
+            | Self::NotAllowedWhileSubscribed(_)
+            | Self::CrossSlot
+            | Self::UnknownConfigOption(_)
+            | Self::InvalidConfigValue(_, _)
+            | Self::NoProto
+            | Self::OverlappingPrefixes(_, _)
+            | Self::NoGroupForKey(_, _)
+            | Self::BusyGroup
+            | Self::UnrecognizedOption(_, _) => self.to_string(),
+            Self::NoGroup(_, _) if cmd == "XREADGROUP" => {
+                format!("{self} in XREADGROUP with GROUP option")
+            }
+            Self::NoGroup(_, _) => self.to_string(),
             other => format!("Internal Error in {cmd}: {other}"),
         }
     }
 
     pub fn is_fatal(&self) -> bool {
-        match self {
-            Self::AsyncIoError(_) | Self::Context(_, _) => true,
-            _ => false,
-        }
+        matches!(self, Self::AsyncIoError(_) | Self::Context(_, _))
     }
 }
 
@@ -168,13 +257,9 @@ impl Display for PrintTrace<'_> {
         if let Some(cause) = std::error::Error::source(self.0) {
             f.write_str("\nCaused by:\n")?;
             let mut cause = Some(cause);
-            loop {
-                if let Some(err) = cause {
-                    f.write_fmt(format_args!("  {err}"))?;
-                    cause = err.source();
-                } else {
-                    break;
-                }
+            while let Some(err) = cause {
+                f.write_fmt(format_args!("  {err}"))?;
+                cause = err.source();
             }
         }
         Ok(())
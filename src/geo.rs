@@ -0,0 +1,431 @@
+use std::str::FromStr;
+
+pub const LONGITUDE_MIN: f64 = -180.0;
+pub const LONGITUDE_MAX: f64 = 180.0;
+/// Latitudes are limited to what EPSG:900913 / Web Mercator can represent.
+pub const LATITUDE_MIN: f64 = -85.05112878;
+pub const LATITUDE_MAX: f64 = 85.05112878;
+
+/// Precision of the geohashes stored as sorted set scores, 26 bits for each coordinate.
+const STEP_MAX: u32 = 26;
+const EARTH_RADIUS_IN_METERS: f64 = 6372797.560856;
+const MERCATOR_MAX: f64 = 20037726.37;
+
+const ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Range {
+    min: f64,
+    max: f64,
+}
+
+const LONGITUDE_RANGE: Range = Range {
+    min: LONGITUDE_MIN,
+    max: LONGITUDE_MAX,
+};
+const LATITUDE_RANGE: Range = Range {
+    min: LATITUDE_MIN,
+    max: LATITUDE_MAX,
+};
+const STANDARD_LATITUDE_RANGE: Range = Range {
+    min: -90.0,
+    max: 90.0,
+};
+
+/// Distance unit accepted by the geo commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Meters,
+    Kilometers,
+    Miles,
+    Feet,
+}
+
+impl FromStr for Unit {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "m" => Ok(Self::Meters),
+            "km" => Ok(Self::Kilometers),
+            "mi" => Ok(Self::Miles),
+            "ft" => Ok(Self::Feet),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Unit {
+    pub fn to_meters(&self, value: f64) -> f64 {
+        value * self.factor()
+    }
+
+    pub fn from_meters(&self, value: f64) -> f64 {
+        value / self.factor()
+    }
+
+    fn factor(&self) -> f64 {
+        match self {
+            Self::Meters => 1.0,
+            Self::Kilometers => 1000.0,
+            Self::Miles => 1609.34,
+            Self::Feet => 0.3048,
+        }
+    }
+}
+
+/// Area searched by GEOSEARCH around its center, in meters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+impl Shape {
+    /// Returns the distance from `center` to `point` if the point lies within the shape.
+    pub fn distance_if_within(&self, center: (f64, f64), point: (f64, f64)) -> Option<f64> {
+        let (longitude, latitude) = center;
+        match *self {
+            Self::Radius(radius) => {
+                let distance = distance(center, point);
+                (distance <= radius).then_some(distance)
+            }
+            Self::Box { width, height } => {
+                // The latitude distance is cheaper, so it's checked first
+                let latitude_distance =
+                    EARTH_RADIUS_IN_METERS * (point.1.to_radians() - latitude.to_radians()).abs();
+                if latitude_distance > height / 2.0 {
+                    return None;
+                }
+                if distance((longitude, point.1), point) > width / 2.0 {
+                    return None;
+                }
+                Some(distance(center, point))
+            }
+        }
+    }
+
+    fn half_size(&self) -> (f64, f64) {
+        match *self {
+            Self::Radius(radius) => (radius, radius),
+            Self::Box { width, height } => (width / 2.0, height / 2.0),
+        }
+    }
+}
+
+pub fn is_valid(longitude: f64, latitude: f64) -> bool {
+    (LONGITUDE_MIN..=LONGITUDE_MAX).contains(&longitude)
+        && (LATITUDE_MIN..=LATITUDE_MAX).contains(&latitude)
+}
+
+/// The 52 bit geohash used as the sorted set score, or `None` for invalid coordinates.
+pub fn encode(longitude: f64, latitude: f64) -> Option<u64> {
+    is_valid(longitude, latitude).then(|| {
+        encode_with(
+            LONGITUDE_RANGE,
+            LATITUDE_RANGE,
+            longitude,
+            latitude,
+            STEP_MAX,
+        )
+    })
+}
+
+/// Center of the area described by a 52 bit geohash, as `(longitude, latitude)`.
+pub fn decode(hash: u64) -> (f64, f64) {
+    let (latitude, longitude) = deinterleave(hash);
+    let area = cell_area(STEP_MAX, latitude, longitude);
+
+    let longitude = ((area.0.min + area.0.max) / 2.0).clamp(LONGITUDE_MIN, LONGITUDE_MAX);
+    let latitude = ((area.1.min + area.1.max) / 2.0).clamp(LATITUDE_MIN, LATITUDE_MAX);
+    (longitude, latitude)
+}
+
+/// Great-circle distance in meters between two `(longitude, latitude)` points.
+pub fn distance(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lon1, lat1) = (from.0.to_radians(), from.1.to_radians());
+    let (lon2, lat2) = (to.0.to_radians(), to.1.to_radians());
+
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let v = ((lon2 - lon1) / 2.0).sin();
+    2.0 * EARTH_RADIUS_IN_METERS * (u * u + lat1.cos() * lat2.cos() * v * v).sqrt().asin()
+}
+
+/// The standard 11 character base32 geohash, as returned by GEOHASH.
+///
+/// Scores use a latitude range limited to +/-85 degrees, so the position is re-encoded with the
+/// standard +/-90 degree range first.
+pub fn hash_string(hash: u64) -> String {
+    let (longitude, latitude) = decode(hash);
+    let hash = encode_with(
+        LONGITUDE_RANGE,
+        STANDARD_LATITUDE_RANGE,
+        longitude,
+        latitude,
+        STEP_MAX,
+    );
+
+    (0..11)
+        .map(|i| {
+            // Only 52 bits are available, the last character is always zero
+            let index = if i == 10 {
+                0
+            } else {
+                (hash >> (52 - (i + 1) * 5)) & 0x1F
+            };
+            ALPHABET[index as usize] as char
+        })
+        .collect()
+}
+
+/// Score ranges `[min, max)` that together cover every point of `shape` around `center`.
+///
+/// Like Redis, this picks a geohash precision where a cell is about as large as the shape and
+/// returns the cell containing the center along with its 8 neighbours.
+pub fn search_ranges(center: (f64, f64), shape: Shape) -> Vec<(u64, u64)> {
+    let (longitude, latitude) = center;
+    let (half_width, half_height) = shape.half_size();
+    let radius = match shape {
+        Shape::Radius(radius) => radius,
+        Shape::Box { .. } => half_width.hypot(half_height),
+    };
+    let bounds = bounding_box(center, half_width, half_height);
+
+    let mut step = estimate_step(radius, latitude);
+    let cell = |step| {
+        deinterleave(encode_with(
+            LONGITUDE_RANGE,
+            LATITUDE_RANGE,
+            longitude,
+            latitude,
+            step,
+        ))
+    };
+
+    let (mut lat_index, mut lon_index) = cell(step);
+    if step >= 2 {
+        let mask = (1u64 << step) - 1;
+        let north = cell_area(step, (lat_index + 1) & mask, lon_index);
+        let south = cell_area(step, lat_index.wrapping_sub(1) & mask, lon_index);
+        let east = cell_area(step, lat_index, (lon_index + 1) & mask);
+        let west = cell_area(step, lat_index, lon_index.wrapping_sub(1) & mask);
+
+        // The neighbours don't cover the whole shape, use larger cells
+        if north.1.max < bounds.3
+            || south.1.min > bounds.1
+            || east.0.max < bounds.2
+            || west.0.min > bounds.0
+        {
+            step -= 1;
+            (lat_index, lon_index) = cell(step);
+        }
+    }
+
+    let mask = (1u64 << step) - 1;
+    let shift = 2 * STEP_MAX - 2 * step;
+    let mut ranges = Vec::with_capacity(9);
+    for lat_delta in [0, 1, u64::MAX] {
+        for lon_delta in [0, 1, u64::MAX] {
+            let hash = interleave(
+                lat_index.wrapping_add(lat_delta) & mask,
+                lon_index.wrapping_add(lon_delta) & mask,
+            );
+            let range = (hash << shift, (hash + 1) << shift);
+            if !ranges.contains(&range) {
+                ranges.push(range);
+            }
+        }
+    }
+    ranges
+}
+
+fn encode_with(
+    longitude_range: Range,
+    latitude_range: Range,
+    longitude: f64,
+    latitude: f64,
+    step: u32,
+) -> u64 {
+    let offset = |value: f64, range: Range| {
+        ((value - range.min) / (range.max - range.min) * (1u64 << step) as f64) as u32 as u64
+    };
+    interleave(
+        offset(latitude, latitude_range),
+        offset(longitude, longitude_range),
+    )
+}
+
+/// Longitude and latitude ranges of the cell with the given indexes at precision `step`.
+fn cell_area(step: u32, lat_index: u64, lon_index: u64) -> (Range, Range) {
+    let cells = (1u64 << step) as f64;
+    let area = |index: u64, range: Range| {
+        let scale = range.max - range.min;
+        Range {
+            min: range.min + (index as f64 / cells) * scale,
+            max: range.min + ((index + 1) as f64 / cells) * scale,
+        }
+    };
+    (
+        area(lon_index, LONGITUDE_RANGE),
+        area(lat_index, LATITUDE_RANGE),
+    )
+}
+
+/// `(min longitude, min latitude, max longitude, max latitude)` of a box around `center`.
+fn bounding_box(center: (f64, f64), half_width: f64, half_height: f64) -> (f64, f64, f64, f64) {
+    let (longitude, latitude) = center;
+    let lat_delta = (half_height / EARTH_RADIUS_IN_METERS).to_degrees();
+    let lon_delta_top =
+        (half_width / EARTH_RADIUS_IN_METERS / (latitude + lat_delta).to_radians().cos())
+            .to_degrees();
+    let lon_delta_bottom =
+        (half_width / EARTH_RADIUS_IN_METERS / (latitude - lat_delta).to_radians().cos())
+            .to_degrees();
+
+    // The box is widest on the side closer to the equator
+    let lon_delta = if latitude < 0.0 {
+        lon_delta_bottom
+    } else {
+        lon_delta_top
+    };
+    (
+        longitude - lon_delta,
+        latitude - lat_delta,
+        longitude + lon_delta,
+        latitude + lat_delta,
+    )
+}
+
+fn estimate_step(mut radius: f64, latitude: f64) -> u32 {
+    if radius == 0.0 {
+        return STEP_MAX;
+    }
+
+    let mut step: i32 = 1;
+    while radius < MERCATOR_MAX {
+        radius *= 2.0;
+        step += 1;
+    }
+    // Make sure the range is included in most of the base cases
+    step -= 2;
+
+    // Cells get narrower towards the poles
+    if latitude.abs() > 66.0 {
+        step -= 1;
+        if latitude.abs() > 80.0 {
+            step -= 1;
+        }
+    }
+
+    step.clamp(1, STEP_MAX as i32) as u32
+}
+
+/// Interleaves the low 32 bits of `x` and `y`, with `x` in the even positions.
+fn interleave(x: u64, y: u64) -> u64 {
+    spread(x) | (spread(y) << 1)
+}
+
+/// Inverse of [`interleave`], returning `(x, y)`.
+fn deinterleave(value: u64) -> (u64, u64) {
+    (squash(value), squash(value >> 1))
+}
+
+fn spread(value: u64) -> u64 {
+    let mut value = value & 0xFFFF_FFFF;
+    value = (value | (value << 16)) & 0x0000_FFFF_0000_FFFF;
+    value = (value | (value << 8)) & 0x00FF_00FF_00FF_00FF;
+    value = (value | (value << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+    value = (value | (value << 2)) & 0x3333_3333_3333_3333;
+    (value | (value << 1)) & 0x5555_5555_5555_5555
+}
+
+fn squash(value: u64) -> u64 {
+    let mut value = value & 0x5555_5555_5555_5555;
+    value = (value | (value >> 1)) & 0x3333_3333_3333_3333;
+    value = (value | (value >> 2)) & 0x0F0F_0F0F_0F0F_0F0F;
+    value = (value | (value >> 4)) & 0x00FF_00FF_00FF_00FF;
+    value = (value | (value >> 8)) & 0x0000_FFFF_0000_FFFF;
+    (value | (value >> 16)) & 0x0000_0000_FFFF_FFFF
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PALERMO: (f64, f64) = (13.361389, 38.115556);
+    const CATANIA: (f64, f64) = (15.087269, 37.502669);
+
+    #[test]
+    fn encoding() {
+        assert_eq!(encode(PALERMO.0, PALERMO.1), Some(3479099956230698));
+        assert_eq!(encode(CATANIA.0, CATANIA.1), Some(3479447370796909));
+        assert_eq!(encode(181.0, 0.0), None);
+        assert_eq!(encode(0.0, 86.0), None);
+
+        let (longitude, latitude) = decode(3479099956230698);
+        assert!((longitude - PALERMO.0).abs() < 1e-5);
+        assert!((latitude - PALERMO.1).abs() < 1e-5);
+    }
+
+    #[test]
+    fn interleaving() {
+        assert_eq!(interleave(0b11, 0b00), 0b0101);
+        assert_eq!(interleave(0b00, 0b11), 0b1010);
+        assert_eq!(deinterleave(0b1001), (0b01, 0b10));
+    }
+
+    #[test]
+    fn hashes() {
+        assert_eq!(hash_string(3479099956230698), "sqc8b49rny0");
+        assert_eq!(hash_string(3479447370796909), "sqdtr74hyu0");
+    }
+
+    #[test]
+    fn distances() {
+        let palermo = decode(3479099956230698);
+        let catania = decode(3479447370796909);
+        assert_eq!(format!("{:.4}", distance(palermo, catania)), "166274.1516");
+        assert_eq!(
+            format!(
+                "{:.4}",
+                Unit::Kilometers.from_meters(distance(palermo, catania))
+            ),
+            "166.2742"
+        );
+    }
+
+    #[test]
+    fn shapes() {
+        let palermo = decode(3479099956230698);
+        let catania = decode(3479447370796909);
+
+        assert!(Shape::Radius(200_000.0)
+            .distance_if_within((15.0, 37.0), palermo)
+            .is_some());
+        assert!(Shape::Radius(100_000.0)
+            .distance_if_within((15.0, 37.0), palermo)
+            .is_none());
+
+        let area = Shape::Box {
+            width: 400_000.0,
+            height: 400_000.0,
+        };
+        assert!(area.distance_if_within((15.0, 37.0), catania).is_some());
+        let narrow = Shape::Box {
+            width: 10_000.0,
+            height: 400_000.0,
+        };
+        assert!(narrow.distance_if_within((15.0, 37.0), palermo).is_none());
+    }
+
+    #[test]
+    fn search_ranges_cover_the_shape() {
+        let center = (15.0, 37.0);
+        let shape = Shape::Radius(200_000.0);
+        let ranges = search_ranges(center, shape);
+
+        for hash in [3479099956230698u64, 3479447370796909] {
+            assert!(ranges.iter().any(|(min, max)| (*min..*max).contains(&hash)));
+        }
+    }
+}
@@ -0,0 +1,153 @@
+/// Patterns nested deeper than this, like many `*` in a row, never match instead of recursing
+/// without bound.
+const MAX_NESTING: usize = 1000;
+
+/// Matches `string` against a glob-style `pattern`, with the same rules as Redis uses for
+/// KEYS and the MATCH option of SCAN:
+///
+/// - `?` matches any single byte and `*` any sequence of bytes, including an empty one
+/// - `[abc]` matches one of the listed bytes, `[^abc]` any other byte, and `[a-z]` a range
+/// - `\` escapes the following byte, both inside and outside of brackets
+pub fn matches(pattern: &[u8], string: &[u8]) -> bool {
+    let mut skip_longer_matches = false;
+    match_from(pattern, string, &mut skip_longer_matches, 0)
+}
+
+/// Once a `*` fails to match with every possible suffix, a `*` earlier in the pattern can't
+/// match by consuming more either, so `skip_longer_matches` stops those retries.
+fn match_from(
+    mut pattern: &[u8],
+    mut string: &[u8],
+    skip_longer_matches: &mut bool,
+    nesting: usize,
+) -> bool {
+    if nesting > MAX_NESTING {
+        return false;
+    }
+
+    while let (Some(&p), Some(&c)) = (pattern.first(), string.first()) {
+        match p {
+            b'*' => {
+                while pattern.get(1) == Some(&b'*') {
+                    pattern = &pattern[1..];
+                }
+                if pattern.len() == 1 {
+                    return true;
+                }
+                while !string.is_empty() {
+                    if match_from(&pattern[1..], string, skip_longer_matches, nesting + 1) {
+                        return true;
+                    }
+                    if *skip_longer_matches {
+                        return false;
+                    }
+                    string = &string[1..];
+                }
+                *skip_longer_matches = true;
+                return false;
+            }
+            b'?' => string = &string[1..],
+            b'[' => {
+                pattern = &pattern[1..];
+                let negate = pattern.first() == Some(&b'^');
+                if negate {
+                    pattern = &pattern[1..];
+                }
+
+                let mut matched = false;
+                loop {
+                    match pattern {
+                        // An unterminated class ends with the pattern
+                        [] => break,
+                        [b'\\', escaped, ..] => {
+                            matched |= *escaped == c;
+                            pattern = &pattern[1..];
+                        }
+                        [b']', ..] => break,
+                        [start, b'-', end, ..] => {
+                            let (start, end) = (*start.min(end), *start.max(end));
+                            matched |= (start..=end).contains(&c);
+                            pattern = &pattern[2..];
+                        }
+                        [other, ..] => matched |= *other == c,
+                    }
+                    pattern = &pattern[1..];
+                }
+
+                if matched == negate {
+                    return false;
+                }
+                string = &string[1..];
+            }
+            _ => {
+                if p == b'\\' && pattern.len() >= 2 {
+                    pattern = &pattern[1..];
+                }
+                if pattern[0] != c {
+                    return false;
+                }
+                string = &string[1..];
+            }
+        }
+
+        pattern = pattern.get(1..).unwrap_or_default();
+    }
+
+    // Trailing stars match the empty rest of the string
+    if string.is_empty() {
+        while pattern.first() == Some(&b'*') {
+            pattern = &pattern[1..];
+        }
+    }
+    pattern.is_empty() && string.is_empty()
+}
+
+#[cfg(test)]
+mod test {
+    use super::matches;
+
+    #[test]
+    fn wildcards() {
+        assert!(matches(b"*", b""));
+        assert!(matches(b"*", b"anything"));
+        assert!(matches(b"h?llo", b"hello"));
+        assert!(!matches(b"h?llo", b"hllo"));
+        assert!(matches(b"h*llo", b"hllo"));
+        assert!(matches(b"h*llo", b"heeeello"));
+        assert!(matches(b"user:*:name", b"user:42:name"));
+        assert!(!matches(b"user:*:name", b"user:42:email"));
+        assert!(matches(b"a**b", b"ab"));
+        assert!(!matches(b"a", b""));
+        assert!(!matches(b"", b"a"));
+        assert!(matches(b"", b""));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches(b"h[ae]llo", b"hello"));
+        assert!(matches(b"h[ae]llo", b"hallo"));
+        assert!(!matches(b"h[ae]llo", b"hillo"));
+        assert!(matches(b"h[^e]llo", b"hallo"));
+        assert!(!matches(b"h[^e]llo", b"hello"));
+        assert!(matches(b"h[a-b]llo", b"hbllo"));
+        assert!(matches(b"h[b-a]llo", b"hallo"));
+        assert!(!matches(b"h[a-b]llo", b"hcllo"));
+        assert!(matches(b"[\\]]", b"]"));
+        assert!(matches(b"[a-", b"a"));
+        assert!(!matches(b"[abc", b"d"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches(b"h\\*llo", b"h*llo"));
+        assert!(!matches(b"h\\*llo", b"hello"));
+        assert!(matches(b"\\?", b"?"));
+        assert!(matches(b"a\\", b"a\\"));
+    }
+
+    #[test]
+    fn pathological_patterns_finish() {
+        let pattern = b"a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*b";
+        assert!(!matches(pattern, &[b'a'; 100]));
+    }
+}
@@ -0,0 +1,592 @@
+use thiserror::Error;
+
+const P: u32 = 14;
+const Q: u32 = 64 - P;
+pub const REGISTERS: usize = 1 << P;
+const P_MASK: u64 = REGISTERS as u64 - 1;
+const BITS: usize = 6;
+const REGISTER_MAX: u32 = (1 << BITS) - 1;
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+const HEADER_SIZE: usize = 16;
+const DENSE_SIZE: usize = HEADER_SIZE + (REGISTERS * BITS).div_ceil(8);
+const MAGIC: &[u8; 4] = b"HYLL";
+const ENCODING: usize = 4;
+const CARD: usize = 8;
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+
+const SPARSE_VAL_MAX_VALUE: u8 = 32;
+const SPARSE_VAL_MAX_LEN: usize = 4;
+const SPARSE_ZERO_MAX_LEN: usize = 64;
+const SPARSE_XZERO_MAX_LEN: usize = 16384;
+
+/// Default for the `hll-sparse-max-bytes` config, after which sparse values turn dense.
+pub const DEFAULT_SPARSE_MAX_BYTES: usize = 3000;
+
+#[derive(Debug, Clone, Error, PartialEq)]
+#[non_exhaustive]
+pub enum HyperLogLogError {
+    #[error("Key is not a valid HyperLogLog string value.")]
+    NotAHyperLogLog,
+    #[error("Corrupted HLL object detected")]
+    Corrupted,
+}
+
+type Result<T> = std::result::Result<T, HyperLogLogError>;
+
+/// A single opcode of the sparse representation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Opcode {
+    /// `00xxxxxx`, up to 64 registers set to 0
+    Zero(usize),
+    /// `01xxxxxx yyyyyyyy`, up to 16384 registers set to 0
+    XZero(usize),
+    /// `1vvvvvxx`, up to 4 registers set to a value between 1 and 32
+    Val(u8, usize),
+}
+
+impl Opcode {
+    fn decode(data: &[u8], at: usize) -> Result<Self> {
+        let byte = data[at];
+        Ok(match byte & 0xC0 {
+            0x00 => Self::Zero((byte & 0x3F) as usize + 1),
+            0x40 => {
+                let low = *data.get(at + 1).ok_or(HyperLogLogError::Corrupted)?;
+                Self::XZero(((((byte & 0x3F) as usize) << 8) | low as usize) + 1)
+            }
+            _ => Self::Val(((byte >> 2) & 0x1F) + 1, (byte & 0x03) as usize + 1),
+        })
+    }
+
+    /// Zero runs are encoded with the shortest opcode that fits.
+    fn zeroes(len: usize) -> Self {
+        if len > SPARSE_ZERO_MAX_LEN {
+            Self::XZero(len)
+        } else {
+            Self::Zero(len)
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::XZero(_) => 2,
+            _ => 1,
+        }
+    }
+
+    fn span(&self) -> usize {
+        match *self {
+            Self::Zero(len) | Self::XZero(len) | Self::Val(_, len) => len,
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            Self::Zero(len) => out.push((len - 1) as u8),
+            Self::XZero(len) => {
+                let len = len - 1;
+                out.push(0x40 | (len >> 8) as u8);
+                out.push((len & 0xFF) as u8);
+            }
+            Self::Val(value, len) => out.push(0x80 | ((value - 1) << 2) | (len - 1) as u8),
+        }
+    }
+}
+
+/// Creates an empty HyperLogLog, sparse encoded with a single run of zeroes.
+pub fn new() -> Vec<u8> {
+    let mut data = Vec::with_capacity(HEADER_SIZE + 2);
+    data.extend_from_slice(MAGIC);
+    data.push(SPARSE);
+    data.resize(HEADER_SIZE, 0);
+
+    let mut remaining = REGISTERS;
+    while remaining > 0 {
+        let len = remaining.min(SPARSE_XZERO_MAX_LEN);
+        Opcode::XZero(len).encode(&mut data);
+        remaining -= len;
+    }
+    data
+}
+
+/// Checks that `data` looks like a HyperLogLog, the same way Redis does before using a string.
+pub fn validate(data: &[u8]) -> Result<()> {
+    if data.len() < HEADER_SIZE
+        || &data[..4] != MAGIC
+        || data[ENCODING] > SPARSE
+        || (data[ENCODING] == DENSE && data.len() != DENSE_SIZE)
+    {
+        return Err(HyperLogLogError::NotAHyperLogLog);
+    }
+    Ok(())
+}
+
+/// Adds `element`, returning whether any register changed.
+pub fn add(data: &mut Vec<u8>, element: &[u8], sparse_max_bytes: usize) -> Result<bool> {
+    validate(data)?;
+
+    let (index, count) = pattern_len(element);
+    let updated = match data[ENCODING] {
+        DENSE => dense_set(&mut data[HEADER_SIZE..], index, count),
+        _ => sparse_set(data, index, count, sparse_max_bytes)?,
+    };
+
+    if updated {
+        invalidate_cache(data);
+    }
+    Ok(updated)
+}
+
+/// Estimated cardinality, served from and stored into the cache in the header.
+pub fn count(data: &mut [u8]) -> Result<u64> {
+    validate(data)?;
+
+    if data[CARD + 7] & 0x80 == 0 {
+        let card: [u8; 8] = data[CARD..CARD + 8].try_into().expect("Header is 16 bytes");
+        return Ok(u64::from_le_bytes(card));
+    }
+
+    let card = estimate(&histogram(data)?);
+    data[CARD..CARD + 8].copy_from_slice(&card.to_le_bytes());
+    Ok(card)
+}
+
+/// Raises every register in `max` to at least the value it has in `data`.
+pub fn merge_registers(max: &mut [u8], data: &[u8]) -> Result<()> {
+    validate(data)?;
+
+    if data[ENCODING] == DENSE {
+        for (i, register) in max.iter_mut().enumerate() {
+            *register = (*register).max(dense_get(&data[HEADER_SIZE..], i));
+        }
+        return Ok(());
+    }
+
+    let mut index = 0;
+    for_each_opcode(data, |opcode| {
+        if index + opcode.span() > REGISTERS {
+            return Err(HyperLogLogError::Corrupted);
+        }
+        if let Opcode::Val(value, len) = opcode {
+            for register in &mut max[index..index + len] {
+                *register = (*register).max(value);
+            }
+        }
+        index += opcode.span();
+        Ok(())
+    })?;
+
+    if index != REGISTERS {
+        return Err(HyperLogLogError::Corrupted);
+    }
+    Ok(())
+}
+
+/// Estimated cardinality of a set of plain, unpacked registers.
+pub fn count_registers(registers: &[u8]) -> u64 {
+    let mut histogram = [0u32; 64];
+    for register in registers {
+        histogram[*register as usize] += 1;
+    }
+    estimate(&histogram)
+}
+
+/// Stores `registers` into `data` where they are larger than the current ones, the way
+/// PFMERGE does. Turns `data` dense first if `dense` is set.
+pub fn set_registers(
+    data: &mut Vec<u8>,
+    registers: &[u8],
+    dense: bool,
+    sparse_max_bytes: usize,
+) -> Result<()> {
+    validate(data)?;
+    if dense && data[ENCODING] == SPARSE {
+        sparse_to_dense(data)?;
+    }
+
+    for (index, count) in registers.iter().enumerate() {
+        if *count == 0 {
+            continue;
+        }
+        match data[ENCODING] {
+            DENSE => {
+                dense_set(&mut data[HEADER_SIZE..], index, *count);
+            }
+            _ => {
+                sparse_set(data, index, *count, sparse_max_bytes)?;
+            }
+        }
+    }
+
+    invalidate_cache(data);
+    Ok(())
+}
+
+pub fn is_dense(data: &[u8]) -> bool {
+    data.get(ENCODING) == Some(&DENSE)
+}
+
+fn invalidate_cache(data: &mut [u8]) {
+    data[CARD + 7] |= 0x80;
+}
+
+/// MurmurHash2, 64-bit version, reading the input as little endian words like Redis does.
+fn murmurhash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().expect("Chunks are 8 bytes"));
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            h ^= (*byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+/// Register index for `element` and the length of the run of zeroes in the rest of its hash.
+fn pattern_len(element: &[u8]) -> (usize, u8) {
+    let hash = murmurhash64a(element, 0xadc8_3b19);
+    let index = (hash & P_MASK) as usize;
+    // Setting bit Q makes sure the count is at most Q + 1
+    let hash = (hash >> P) | (1 << Q);
+    (index, hash.trailing_zeros() as u8 + 1)
+}
+
+fn dense_get(registers: &[u8], index: usize) -> u8 {
+    let byte = index * BITS / 8;
+    let fb = (index * BITS) & 7;
+    let b0 = registers[byte] as u32;
+    let b1 = registers.get(byte + 1).copied().unwrap_or(0) as u32;
+    (((b0 >> fb) | (b1 << (8 - fb))) & REGISTER_MAX) as u8
+}
+
+fn dense_set(registers: &mut [u8], index: usize, count: u8) -> bool {
+    if dense_get(registers, index) >= count {
+        return false;
+    }
+
+    let byte = index * BITS / 8;
+    let fb = (index * BITS) & 7;
+    let value = count as u32;
+    registers[byte] &= !((REGISTER_MAX << fb) as u8);
+    registers[byte] |= (value << fb) as u8;
+    if let Some(next) = registers.get_mut(byte + 1) {
+        *next &= !((REGISTER_MAX >> (8 - fb)) as u8);
+        *next |= (value >> (8 - fb)) as u8;
+    }
+    true
+}
+
+fn for_each_opcode(data: &[u8], mut op: impl FnMut(Opcode) -> Result<()>) -> Result<()> {
+    let mut at = HEADER_SIZE;
+    while at < data.len() {
+        let opcode = Opcode::decode(data, at)?;
+        op(opcode)?;
+        at += opcode.len();
+    }
+    Ok(())
+}
+
+fn histogram(data: &[u8]) -> Result<[u32; 64]> {
+    let mut histogram = [0u32; 64];
+
+    if data[ENCODING] == DENSE {
+        for i in 0..REGISTERS {
+            histogram[dense_get(&data[HEADER_SIZE..], i) as usize] += 1;
+        }
+        return Ok(histogram);
+    }
+
+    let mut index = 0;
+    for_each_opcode(data, |opcode| {
+        match opcode {
+            Opcode::Zero(len) | Opcode::XZero(len) => histogram[0] += len as u32,
+            Opcode::Val(value, len) => histogram[value as usize] += len as u32,
+        }
+        index += opcode.span();
+        Ok(())
+    })?;
+
+    if index != REGISTERS {
+        return Err(HyperLogLogError::Corrupted);
+    }
+    Ok(histogram)
+}
+
+/// The cardinality estimator by Otmar Ertl, same as the one used by Redis since version 5.
+fn estimate(histogram: &[u32; 64]) -> u64 {
+    let m = REGISTERS as f64;
+    let mut z = m * tau((m - histogram[Q as usize + 1] as f64) / m);
+    for j in (1..=Q as usize).rev() {
+        z += histogram[j] as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+
+    (ALPHA_INF * m * m / z).round() as u64
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if previous == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if previous == z {
+            return z / 3.0;
+        }
+    }
+}
+
+fn sparse_to_dense(data: &mut Vec<u8>) -> Result<()> {
+    let mut registers = vec![0u8; REGISTERS];
+    merge_registers(&mut registers, data)?;
+
+    let mut dense = Vec::with_capacity(DENSE_SIZE);
+    dense.extend_from_slice(&data[..HEADER_SIZE]);
+    dense[ENCODING] = DENSE;
+    dense.resize(DENSE_SIZE, 0);
+    for (i, count) in registers.into_iter().enumerate() {
+        if count > 0 {
+            dense_set(&mut dense[HEADER_SIZE..], i, count);
+        }
+    }
+
+    *data = dense;
+    Ok(())
+}
+
+fn promote(data: &mut Vec<u8>, index: usize, count: u8) -> Result<bool> {
+    sparse_to_dense(data)?;
+    Ok(dense_set(&mut data[HEADER_SIZE..], index, count))
+}
+
+/// Sets register `index` to `count` if it's larger, splitting the covering opcode like Redis'
+/// `hllSparseSet` does, so the resulting bytes are identical.
+fn sparse_set(data: &mut Vec<u8>, index: usize, count: u8, max_bytes: usize) -> Result<bool> {
+    if count > SPARSE_VAL_MAX_VALUE {
+        return promote(data, index, count);
+    }
+
+    // Find the opcode covering the register
+    let mut at = HEADER_SIZE;
+    let mut first = 0;
+    let mut previous = None;
+    let opcode = loop {
+        if at >= data.len() {
+            return Err(HyperLogLogError::Corrupted);
+        }
+        let opcode = Opcode::decode(data, at)?;
+        if index < first + opcode.span() {
+            break opcode;
+        }
+        previous = Some(at);
+        at += opcode.len();
+        first += opcode.span();
+    };
+
+    let replacement = match opcode {
+        Opcode::Val(value, _) if value >= count => return Ok(false),
+        Opcode::Val(_, 1) | Opcode::Zero(1) => vec![Opcode::Val(count, 1)],
+        _ => {
+            let last = first + opcode.span() - 1;
+            let (before, after) = match opcode {
+                Opcode::Val(value, _) => (
+                    Opcode::Val(value, index.saturating_sub(first)),
+                    Opcode::Val(value, last - index),
+                ),
+                _ => (
+                    Opcode::zeroes(index.saturating_sub(first)),
+                    Opcode::zeroes(last - index),
+                ),
+            };
+
+            let mut sequence = Vec::with_capacity(3);
+            if index != first {
+                sequence.push(before);
+            }
+            sequence.push(Opcode::Val(count, 1));
+            if index != last {
+                sequence.push(after);
+            }
+            sequence
+        }
+    };
+
+    let mut encoded = Vec::with_capacity(5);
+    for opcode in &replacement {
+        opcode.encode(&mut encoded);
+    }
+    if encoded.len() > opcode.len() && data.len() + encoded.len() - opcode.len() > max_bytes {
+        return promote(data, index, count);
+    }
+    data.splice(at..at + opcode.len(), encoded);
+
+    merge_adjacent_values(data, previous.unwrap_or(HEADER_SIZE))?;
+    Ok(true)
+}
+
+/// Joins neighbouring VAL opcodes with the same value, looking at up to 5 opcodes from `at`.
+fn merge_adjacent_values(data: &mut Vec<u8>, mut at: usize) -> Result<()> {
+    let mut remaining = 5;
+    while at < data.len() && remaining > 0 {
+        remaining -= 1;
+
+        let opcode = Opcode::decode(data, at)?;
+        if let (Opcode::Val(value, len), Some(next)) = (opcode, data.get(at + 1)) {
+            if let Ok(Opcode::Val(next_value, next_len)) = Opcode::decode(&[*next], 0) {
+                if value == next_value && len + next_len <= SPARSE_VAL_MAX_LEN {
+                    let mut merged = Vec::with_capacity(1);
+                    Opcode::Val(value, len + next_len).encode(&mut merged);
+                    data[at + 1] = merged[0];
+                    data.remove(at);
+                    continue;
+                }
+            }
+        }
+
+        at += opcode.len();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn empty_encoding() {
+        let data = new();
+        assert_eq!(
+            data,
+            vec![b'H', b'Y', b'L', b'L', 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x7F, 0xFF]
+        );
+        assert_eq!(count(&mut new()), Ok(0));
+    }
+
+    #[test]
+    fn murmurhash() {
+        assert_eq!(murmurhash64a(b"", 0), 0);
+        assert_ne!(
+            murmurhash64a(b"a", 0xadc83b19),
+            murmurhash64a(b"b", 0xadc83b19)
+        );
+    }
+
+    #[test]
+    fn small_counts_are_exact() {
+        let mut data = new();
+        for element in ["a", "b", "c", "d", "e", "f", "g"] {
+            assert_eq!(add(&mut data, element.as_bytes(), 3000), Ok(true));
+        }
+        assert_eq!(add(&mut data, b"a", 3000), Ok(false));
+        assert_eq!(count(&mut data), Ok(7));
+        assert!(!is_dense(&data));
+    }
+
+    #[test]
+    fn cache_is_invalidated() {
+        let mut data = new();
+        add(&mut data, b"a", 3000).unwrap();
+        assert_eq!(count(&mut data), Ok(1));
+        assert_eq!(data[CARD + 7] & 0x80, 0);
+        add(&mut data, b"b", 3000).unwrap();
+        assert_ne!(data[CARD + 7] & 0x80, 0);
+        assert_eq!(count(&mut data), Ok(2));
+    }
+
+    #[test]
+    fn sparse_and_dense_agree() {
+        let mut sparse = new();
+        let mut dense = new();
+        sparse_to_dense(&mut dense).unwrap();
+
+        for i in 0..2000 {
+            let element = format!("element:{i}");
+            add(&mut sparse, element.as_bytes(), usize::MAX).unwrap();
+            add(&mut dense, element.as_bytes(), usize::MAX).unwrap();
+        }
+
+        assert!(!is_dense(&sparse));
+        assert!(is_dense(&dense));
+        assert_eq!(dense.len(), DENSE_SIZE);
+
+        let mut from_sparse = vec![0; REGISTERS];
+        let mut from_dense = vec![0; REGISTERS];
+        merge_registers(&mut from_sparse, &sparse).unwrap();
+        merge_registers(&mut from_dense, &dense).unwrap();
+        assert_eq!(from_sparse, from_dense);
+        assert_eq!(count(&mut sparse), count(&mut dense));
+    }
+
+    #[test]
+    fn promotes_to_dense_and_estimates() {
+        let mut data = new();
+        for i in 0..100_000 {
+            add(&mut data, format!("{i}").as_bytes(), 3000).unwrap();
+        }
+
+        assert!(is_dense(&data));
+        let estimate = count(&mut data).unwrap() as f64;
+        assert!((estimate - 100_000.0).abs() / 100_000.0 < 0.02);
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        assert_eq!(validate(b"HYLL"), Err(HyperLogLogError::NotAHyperLogLog));
+        assert_eq!(
+            validate(b"HYLX\x01\0\0\0\0\0\0\0\0\0\0\0\x7f\xff"),
+            Err(HyperLogLogError::NotAHyperLogLog)
+        );
+
+        let mut truncated = new();
+        truncated.pop();
+        truncated[HEADER_SIZE] = 0x00;
+        invalidate_cache(&mut truncated);
+        assert_eq!(count(&mut truncated), Err(HyperLogLogError::Corrupted));
+    }
+}
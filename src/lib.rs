@@ -1,7 +1,15 @@
+pub mod bitmap;
 pub mod client;
+pub mod clock;
+pub mod cluster;
 pub mod error;
+pub mod geo;
+pub mod glob;
+pub mod hyperloglog;
+pub mod listpack;
 pub mod rdb;
 pub mod resp;
+pub mod sorted_set;
 pub mod store;
 pub mod stream;
 
@@ -0,0 +1,398 @@
+//! Listpacks, the compact lists Redis stores small collections in, like the nodes of streams
+//! in memory and in RDB files.
+
+const HEADER_SIZE: usize = 6;
+const END: u8 = 0xFF;
+
+/// An element of a listpack, which stores integers and strings differently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Element {
+    Integer(i64),
+    String(Vec<u8>),
+}
+
+impl Element {
+    /// The value of an integer element, or of a string holding one.
+    pub fn integer(&self) -> Option<i64> {
+        match self {
+            Self::Integer(value) => Some(*value),
+            Self::String(value) => std::str::from_utf8(value).ok()?.parse().ok(),
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Self::Integer(value) => value.to_string().into_bytes(),
+            Self::String(value) => value,
+        }
+    }
+}
+
+/// Builds a listpack by appending elements to it.
+#[derive(Debug)]
+pub struct Writer {
+    buf: Vec<u8>,
+    len: usize,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self {
+            buf: vec![0; HEADER_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Continues appending to a listpack `finish` returned.
+    pub fn reopen(mut listpack: Vec<u8>) -> Self {
+        let len = match u16::from_le_bytes([listpack[4], listpack[5]]) {
+            // The header only holds the number of elements up to this
+            u16::MAX => parse(&listpack).map_or(0, |elements| elements.len()),
+            len => len.into(),
+        };
+        listpack.pop();
+        Self { buf: listpack, len }
+    }
+
+    pub fn push_integer(&mut self, value: i64) {
+        let start = self.buf.len();
+        match value {
+            0..=127 => self.buf.push(value as u8),
+            -4096..=4095 => {
+                let value = value as u16 & 0x1FFF;
+                self.buf
+                    .extend_from_slice(&[0xC0 | (value >> 8) as u8, value as u8]);
+            }
+            _ => {
+                let (tag, size) = if i16::try_from(value).is_ok() {
+                    (0xF1, 2)
+                } else if (-(1 << 23)..1 << 23).contains(&value) {
+                    (0xF2, 3)
+                } else if i32::try_from(value).is_ok() {
+                    (0xF3, 4)
+                } else {
+                    (0xF4, 8)
+                };
+                self.buf.push(tag);
+                self.buf.extend_from_slice(&value.to_le_bytes()[..size]);
+            }
+        }
+        self.end_element(start);
+    }
+
+    pub fn push_string(&mut self, value: &[u8]) {
+        let start = self.buf.len();
+        match value.len() {
+            len @ 0..64 => self.buf.push(0x80 | len as u8),
+            len @ 64..4096 => self
+                .buf
+                .extend_from_slice(&[0xE0 | (len >> 8) as u8, len as u8]),
+            len => {
+                let len = u32::try_from(len).expect("elements over 4 GiB are not supported");
+                self.buf.push(0xF0);
+                self.buf.extend_from_slice(&len.to_le_bytes());
+            }
+        }
+        self.buf.extend_from_slice(value);
+        self.end_element(start);
+    }
+
+    /// Appends a string, stored as an integer if it's one written the canonical way like Redis
+    /// does, so that it reads back the same.
+    pub fn push(&mut self, value: &[u8]) {
+        let integer = std::str::from_utf8(value)
+            .ok()
+            .and_then(|value| value.parse::<i64>().ok())
+            .filter(|integer| integer.to_string().as_bytes() == value);
+        match integer {
+            Some(integer) => self.push_integer(integer),
+            None => self.push_string(value),
+        }
+    }
+
+    /// Appends the length of the element starting at `start`, which lets Redis traverse the
+    /// listpack backwards.
+    fn end_element(&mut self, start: usize) {
+        let len = self.buf.len() - start;
+        let size = backlen_size(len);
+        for i in (0..size).rev() {
+            let group = (len >> (7 * i)) as u8 & 0x7F;
+            self.buf
+                .push(if i == size - 1 { group } else { group | 0x80 });
+        }
+        self.len += 1;
+    }
+
+    /// Ends the listpack, filling in its total size and number of elements.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.buf.push(END);
+        let size = u32::try_from(self.buf.len()).expect("listpacks over 4 GiB are not supported");
+        let len = u16::try_from(self.len).unwrap_or(u16::MAX);
+        self.buf[..4].copy_from_slice(&size.to_le_bytes());
+        self.buf[4..HEADER_SIZE].copy_from_slice(&len.to_le_bytes());
+        self.buf
+    }
+}
+
+impl Default for Writer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Number of bytes the length of an element takes after it, with the same thresholds as Redis.
+fn backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..16383 => 2,
+        16383..2097151 => 3,
+        2097151..268435455 => 4,
+        _ => 5,
+    }
+}
+
+/// The elements of a listpack, `None` if it's malformed.
+pub fn parse(data: &[u8]) -> Option<Vec<Element>> {
+    let size = u32::from_le_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    let mut data = data.get(HEADER_SIZE..size)?;
+
+    let mut elements = Vec::new();
+    while *data.first()? != END {
+        let (element, len) = decode(data)?;
+        elements.push(element);
+        data = data.get(len + backlen_size(len)..)?;
+    }
+    Some(elements)
+}
+
+/// Reads the elements of a listpack one at a time, for when only some of them are needed. It
+/// stops at the end of the listpack or at the first malformed element.
+#[derive(Debug, Clone)]
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// `None` if the size in the header doesn't match the data.
+    pub fn new(listpack: &'a [u8]) -> Option<Self> {
+        let size = u32::from_le_bytes(listpack.get(..4)?.try_into().ok()?) as usize;
+        Some(Self {
+            data: listpack.get(HEADER_SIZE..size)?,
+        })
+    }
+
+    /// Moves past `count` elements without copying them, `None` if there aren't as many.
+    pub fn advance(&mut self, count: usize) -> Option<()> {
+        for _ in 0..count {
+            let (_, len) = decode_borrowed(self.data)?;
+            self.data = self.data.get(len + backlen_size(len)..)?;
+        }
+        Some(())
+    }
+}
+
+impl Iterator for Reader<'_> {
+    type Item = Element;
+
+    fn next(&mut self) -> Option<Element> {
+        let (element, len) = decode(self.data)?;
+        self.data = self.data.get(len + backlen_size(len)..)?;
+        Some(element)
+    }
+}
+
+/// Replaces the element at `index` with an integer, like Redis' `lpReplaceInteger`. Returns
+/// `None` if the listpack has no such element.
+pub fn replace_integer(listpack: &mut Vec<u8>, index: usize, value: i64) -> Option<()> {
+    let mut start = HEADER_SIZE;
+    for _ in 0..index {
+        let (_, len) = decode(listpack.get(start..)?)?;
+        start += len + backlen_size(len);
+    }
+    let (_, len) = decode(listpack.get(start..)?)?;
+
+    let mut writer = Writer::new();
+    writer.push_integer(value);
+    listpack.splice(
+        start..start + len + backlen_size(len),
+        writer.buf.drain(HEADER_SIZE..),
+    );
+    let size = u32::try_from(listpack.len()).expect("listpacks over 4 GiB are not supported");
+    listpack[..4].copy_from_slice(&size.to_le_bytes());
+    Some(())
+}
+
+/// Decodes the element `data` starts with, returning it with its size without the length that
+/// follows it. `None` at the end of the listpack or if the element is malformed.
+fn decode(data: &[u8]) -> Option<(Element, usize)> {
+    let (element, len) = decode_borrowed(data)?;
+    let element = match element {
+        Ok(value) => Element::Integer(value),
+        Err(value) => Element::String(value.to_vec()),
+    };
+    Some((element, len))
+}
+
+/// Like [`decode`], but without copying strings, which come as `Err`.
+fn decode_borrowed(data: &[u8]) -> Option<(Result<i64, &[u8]>, usize)> {
+    let (&tag, rest) = data.split_first()?;
+    let take = |len: usize| rest.get(..len);
+    let integer = |len: usize| {
+        let mut bytes = [0; 8];
+        bytes[..len].copy_from_slice(take(len)?);
+        // Sign-extends the value from its `len` bytes
+        let shift = 64 - 8 * len as u32;
+        Some((i64::from_le_bytes(bytes) << shift) >> shift)
+    };
+
+    let decoded = match tag {
+        0x00..=0x7F => (Ok(tag.into()), 1),
+        0x80..=0xBF => {
+            let len = (tag & 0x3F) as usize;
+            (Err(take(len)?), 1 + len)
+        }
+        0xC0..=0xDF => {
+            let value = (((tag & 0x1F) as i64) << 8) | *take(1)?.first()? as i64;
+            (Ok((value << 51) >> 51), 2)
+        }
+        0xE0..=0xEF => {
+            let len = (((tag & 0x0F) as usize) << 8) | *take(1)?.first()? as usize;
+            (Err(&take(len + 1)?[1..]), 2 + len)
+        }
+        0xF0 => {
+            let len = u32::from_le_bytes(take(4)?.try_into().ok()?) as usize;
+            (Err(&take(len + 4)?[4..]), 5 + len)
+        }
+        0xF1 => (Ok(integer(2)?), 3),
+        0xF2 => (Ok(integer(3)?), 4),
+        0xF3 => (Ok(integer(4)?), 5),
+        0xF4 => (Ok(integer(8)?), 9),
+        _ => return None,
+    };
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, replace_integer, Element, Reader, Writer};
+
+    #[test]
+    fn round_trip() {
+        let integers = [
+            0,
+            127,
+            128,
+            -1,
+            4095,
+            -4096,
+            4096,
+            -32768,
+            1 << 20,
+            -(1 << 30),
+            i64::MIN,
+        ];
+        let strings = [&b""[..], b"field", &[b'x'; 100], &[b'y'; 5000]];
+
+        let mut writer = Writer::new();
+        for value in integers {
+            writer.push_integer(value);
+        }
+        for value in strings {
+            writer.push_string(value);
+        }
+        let data = writer.finish();
+        assert_eq!(data[4..6], [15, 0]);
+
+        let expected = integers
+            .map(Element::Integer)
+            .into_iter()
+            .chain(strings.map(|value| Element::String(value.to_vec())));
+        assert_eq!(parse(&data), Some(expected.collect()));
+        assert_eq!(parse(&data[..data.len() - 1]), None);
+    }
+
+    #[test]
+    fn reopening_and_integer_strings() {
+        let mut writer = Writer::new();
+        writer.push(b"12");
+        let mut writer = Writer::reopen(writer.finish());
+        writer.push(b"012");
+        writer.push(b"-7");
+        writer.push(b"1e3");
+        let data = writer.finish();
+
+        assert_eq!(data[4..6], [4, 0]);
+        let elements = parse(&data).unwrap();
+        assert_eq!(
+            elements,
+            [
+                Element::Integer(12),
+                Element::String(b"012".to_vec()),
+                Element::Integer(-7),
+                Element::String(b"1e3".to_vec()),
+            ]
+        );
+        let strings: Vec<_> = elements.into_iter().map(Element::into_bytes).collect();
+        assert_eq!(strings, [&b"12"[..], b"012", b"-7", b"1e3"]);
+    }
+
+    #[test]
+    fn parses_redis_encoding() {
+        // ["a", 1024, "bb"] as written by Redis
+        let data = [
+            17, 0, 0, 0, 3, 0, 0x81, b'a', 2, 0xC4, 0x00, 2, 0x82, b'b', b'b', 3, 0xFF,
+        ];
+        assert_eq!(
+            parse(&data),
+            Some(vec![
+                Element::String(b"a".to_vec()),
+                Element::Integer(1024),
+                Element::String(b"bb".to_vec()),
+            ])
+        );
+    }
+
+    #[test]
+    fn replacing_integers() {
+        let mut writer = Writer::new();
+        writer.push_integer(5);
+        writer.push_string(b"x");
+        writer.push_integer(-1);
+        let mut data = writer.finish();
+
+        // Changes the size of the first element, then keeps it
+        replace_integer(&mut data, 0, 1 << 20).unwrap();
+        replace_integer(&mut data, 2, 7).unwrap();
+        assert_eq!(
+            data.len(),
+            u32::from_le_bytes(data[..4].try_into().unwrap()) as usize
+        );
+        assert_eq!(
+            parse(&data),
+            Some(vec![
+                Element::Integer(1 << 20),
+                Element::String(b"x".to_vec()),
+                Element::Integer(7),
+            ])
+        );
+        assert_eq!(replace_integer(&mut data, 3, 0), None);
+    }
+
+    #[test]
+    fn reading_some_elements() {
+        let mut writer = Writer::new();
+        writer.push_integer(1);
+        writer.push_string(&[b'x'; 200]);
+        writer.push_integer(-300);
+        writer.push_string(b"last");
+        let data = writer.finish();
+
+        let mut reader = Reader::new(&data).unwrap();
+        assert_eq!(reader.next(), Some(Element::Integer(1)));
+        reader.advance(2).unwrap();
+        assert_eq!(reader.next(), Some(Element::String(b"last".to_vec())));
+        assert_eq!(reader.next(), None);
+        assert_eq!(Reader::new(&data).unwrap().advance(5), None);
+        assert!(Reader::new(&data[..data.len() - 1]).is_none());
+    }
+}
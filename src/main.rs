@@ -1,10 +1,11 @@
-use anyhow;
 use redis_starter_rust::client::Client;
+use redis_starter_rust::clock::{Clock, ManualClock, SystemClock};
 use redis_starter_rust::store::{DataStore, Role};
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
 
 use std::env;
-use tokio;
 use tokio::net::TcpListener;
 
 #[tokio::main]
@@ -54,10 +55,18 @@ async fn main() -> anyhow::Result<()> {
         Role::Master
     };
 
-    let mut store = DataStore::new(config, role);
+    // A manual clock only moves through DEBUG CLOCK, which tests use to control expiry
+    let clock: Arc<dyn Clock> = match config.get("clock").map(String::as_str) {
+        Some("manual") => Arc::new(ManualClock::new(SystemTime::now())),
+        Some("system") | None => Arc::new(SystemClock::new()),
+        Some(other) => anyhow::bail!("unknown clock '{other}', expected 'system' or 'manual'"),
+    };
+
+    let mut store = DataStore::with_clock(config, role, clock);
     if let Err(err) = store.init().await {
         eprintln!("Initialization failed: {}", err.with_trace());
     }
+    store.spawn_active_expiry();
 
     loop {
         let (stream, addr) = listener.accept().await?;
@@ -1,68 +1,84 @@
 use core::str;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::ops::Add;
 use std::time::{self, Duration, SystemTime};
 
 use crate::error::{Error, WithContext};
+use crate::stream::{self, ItemId};
 use crate::Result;
 use nom::bits::complete as bits;
 use nom::branch;
 use nom::bytes::complete as bytes;
 use nom::combinator;
-use nom::error::FromExternalError;
+use nom::error::{FromExternalError, ParseError};
 use nom::multi;
 
+const VERSION: &[u8; 4] = b"0011";
+const TYPE_STRING: u8 = 0;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZE_DB: u8 = 0xFB;
+const OPCODE_EXPIRE_TIME_MS: u8 = 0xFC;
+const OPCODE_SELECT_DB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
 type NomError<T> = nom::error::VerboseError<T>;
 pub(crate) type ParseResult<'a, T> = nom::IResult<&'a [u8], T, NomError<&'a [u8]>>;
 pub(crate) type BitParseResult<'a, T> =
     nom::IResult<(&'a [u8], usize), T, NomError<(&'a [u8], usize)>>;
 
+/// Contents of an RDB file, with the keys of every database stored in it.
 pub struct Database {
     aux: HashMap<String, String>,
+    dbs: BTreeMap<usize, DbData>,
+}
+
+/// Keys of a single numbered database in an RDB file.
+#[derive(Debug, Default)]
+pub struct DbData {
     keys: HashMap<String, OwnedValue>,
     expiring: HashMap<String, (OwnedValue, time::SystemTime)>,
 }
 
+impl DbData {
+    pub fn keys(&self) -> &HashMap<String, OwnedValue> {
+        &self.keys
+    }
+
+    pub fn expiring(&self) -> &HashMap<String, (OwnedValue, time::SystemTime)> {
+        &self.expiring
+    }
+}
+
 impl Database {
     pub fn parse(data: &[u8]) -> Result<Self> {
         let sections = Self::parse_sections(data).context("Parsing RDB file")?;
         eprintln!("Parsed sections: {sections:?} in file {data:?}");
 
         let mut aux = HashMap::new();
-        let mut keys = HashMap::new();
-        let mut expiring = HashMap::new();
-
-        let size_hint = sections.iter().find_map(|s| {
-            if let Section::ResizeDb {
-                hash_table_size,
-                expire_table_size,
-            } = s
-            {
-                Some((hash_table_size, expire_table_size))
-            } else {
-                None
-            }
-        });
+        let mut dbs = BTreeMap::new();
+        // Keys before the first SelectDb section belong to database 0
+        let mut db: &mut DbData = dbs.entry(0).or_default();
 
-        if let Some((hash_table_size, expire_table_size)) = size_hint {
-            keys.reserve(*hash_table_size);
-            expiring.reserve(*expire_table_size);
-        }
-
-        let mut got_db = false;
         for sec in sections {
             match sec {
-                Section::SelectDb(_) if !got_db => got_db = true,
-                Section::SelectDb(_) if got_db => {
-                    return Err(Error::Unimplemented)
-                        .context("Multiple databases are not supported")
+                Section::SelectDb(index) => db = dbs.entry(index).or_default(),
+                Section::ResizeDb {
+                    hash_table_size,
+                    expire_table_size,
+                } => {
+                    db.keys.reserve(hash_table_size);
+                    db.expiring.reserve(expire_table_size);
                 }
                 Section::Value(key, value) => {
-                    keys.insert(key.into_owned(), value.to_owned());
+                    db.keys.insert(key.into_owned(), value.to_owned());
                 }
                 Section::ExpireTime { time, key, value } => {
-                    expiring.insert(
+                    db.expiring.insert(
                         key.into_owned(),
                         (
                             value.to_owned(),
@@ -71,7 +87,7 @@ impl Database {
                     );
                 }
                 Section::ExpireTimeMs { time, key, value } => {
-                    expiring.insert(
+                    db.expiring.insert(
                         key.into_owned(),
                         (
                             value.to_owned(),
@@ -82,18 +98,14 @@ impl Database {
                 Section::Aux(key, value) => {
                     aux.insert(key.into_owned(), value.into_owned());
                 }
-                _ => {}
+                Section::EndOfFile => {}
             }
         }
 
-        Ok(Self {
-            aux,
-            keys,
-            expiring,
-        })
+        Ok(Self { aux, dbs })
     }
 
-    fn parse_sections(data: &[u8]) -> Result<Vec<Section>> {
+    fn parse_sections(data: &[u8]) -> Result<Vec<Section<'_>>> {
         let (data, _) = bytes::tag::<_, _, NomError<_>>(b"REDIS")(data)?;
         let (data, version) = bytes::take::<_, _, NomError<_>>(4usize)(data)?;
         let version = str::from_utf8(version).map_err(Error::Utf8Error)?;
@@ -113,30 +125,209 @@ impl Database {
         &self.aux
     }
 
-    pub fn keys(&self) -> &HashMap<String, OwnedValue> {
-        &self.keys
+    /// The databases in the file by their index. Database 0 is always present, even if empty.
+    pub fn dbs(&self) -> &BTreeMap<usize, DbData> {
+        &self.dbs
     }
+}
 
-    pub fn expiring(&self) -> &HashMap<String, (OwnedValue, time::SystemTime)> {
-        &self.expiring
+/// Writes the contents of databases in the RDB format, in the order they are added.
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-fn parse_length_6bit(data: (&[u8], usize)) -> BitParseResult<usize> {
+impl Encoder {
+    pub fn new() -> Self {
+        let mut encoder = Self {
+            buf: b"REDIS".to_vec(),
+        };
+        encoder.buf.extend_from_slice(VERSION);
+        encoder.aux("redis-ver", "7.2.0");
+        encoder.aux("redis-bits", "64");
+        encoder
+    }
+
+    fn aux(&mut self, key: &str, value: &str) {
+        self.buf.push(OPCODE_AUX);
+        self.string(key.as_bytes());
+        self.string(value.as_bytes());
+    }
+
+    /// Starts a database, which holds the keys added until the next one is started.
+    pub fn select_db(&mut self, index: usize, keys: usize, expiring: usize) {
+        self.buf.push(OPCODE_SELECT_DB);
+        self.length(index);
+        self.buf.push(OPCODE_RESIZE_DB);
+        self.length(keys);
+        self.length(expiring);
+    }
+
+    pub fn add_string(&mut self, key: &str, value: &[u8], expires_at: Option<SystemTime>) {
+        self.key(TYPE_STRING, key, expires_at);
+        self.string(value);
+    }
+
+    pub fn add_sorted_set<'a>(
+        &mut self,
+        key: &str,
+        members: impl ExactSizeIterator<Item = (&'a str, f64)>,
+        expires_at: Option<SystemTime>,
+    ) {
+        self.key(TYPE_ZSET_2, key, expires_at);
+        self.length(members.len());
+        for (member, score) in members {
+            self.string(member.as_bytes());
+            self.buf.extend_from_slice(&score.to_le_bytes());
+        }
+    }
+
+    /// Adds a stream, with its nodes as they are.
+    pub fn add_stream(&mut self, key: &str, stream: &StreamValue, expires_at: Option<SystemTime>) {
+        self.key(TYPE_STREAM_LISTPACKS_3, key, expires_at);
+        self.length(stream.nodes.len());
+        for (master_id, listpack) in &stream.nodes {
+            self.string(&[master_id.0.to_be_bytes(), master_id.1.to_be_bytes()].concat());
+            self.string(listpack);
+        }
+
+        self.length(stream.length as usize);
+        self.stream_id(stream.last_id);
+        self.stream_id(stream.first_id);
+        self.stream_id(stream.max_deleted_id);
+        self.length(stream.entries_added as usize);
+
+        self.length(stream.groups.len());
+        for group in &stream.groups {
+            self.string(&group.name);
+            self.stream_id(group.last_delivered);
+            // Redis stores an unknown number of entries read as -1
+            self.length(group.entries_read.unwrap_or(u64::MAX) as usize);
+
+            self.length(group.pending.len());
+            for pending in &group.pending {
+                self.raw_stream_id(pending.id);
+                self.millisecond_time(Some(pending.delivered_at));
+                self.length(pending.delivery_count as usize);
+            }
+
+            self.length(group.consumers.len());
+            for consumer in &group.consumers {
+                self.string(&consumer.name);
+                self.millisecond_time(Some(consumer.seen_at));
+                self.millisecond_time(consumer.active_at);
+                let pending = group
+                    .pending
+                    .iter()
+                    .filter(|pending| pending.consumer == consumer.name);
+                self.length(pending.clone().count());
+                for pending in pending {
+                    self.raw_stream_id(pending.id);
+                }
+            }
+        }
+    }
+
+    /// Ends the file with its checksum.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.buf.push(OPCODE_EOF);
+        let checksum = crc64(&self.buf);
+        self.buf.extend_from_slice(&checksum.to_le_bytes());
+        self.buf
+    }
+
+    fn key(&mut self, kind: u8, key: &str, expires_at: Option<SystemTime>) {
+        if let Some(expires_at) = expires_at {
+            let millis = expires_at
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_millis() as u64);
+            self.buf.push(OPCODE_EXPIRE_TIME_MS);
+            self.buf.extend_from_slice(&millis.to_le_bytes());
+        }
+        self.buf.push(kind);
+        self.string(key.as_bytes());
+    }
+
+    fn string(&mut self, value: &[u8]) {
+        self.length(value.len());
+        self.buf.extend_from_slice(value);
+    }
+
+    fn stream_id(&mut self, id: StreamId) {
+        self.length(id.0 as usize);
+        self.length(id.1 as usize);
+    }
+
+    fn raw_stream_id(&mut self, id: StreamId) {
+        self.buf.extend_from_slice(&id.0.to_be_bytes());
+        self.buf.extend_from_slice(&id.1.to_be_bytes());
+    }
+
+    /// Writes a time in milliseconds, `None` being written as -1.
+    fn millisecond_time(&mut self, time: Option<SystemTime>) {
+        let millis = time.map_or(-1, |time| {
+            time.duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_millis() as i64)
+        });
+        self.buf.extend_from_slice(&millis.to_le_bytes());
+    }
+
+    fn length(&mut self, length: usize) {
+        match length {
+            0..0x40 => self.buf.push(length as u8),
+            0x40..0x4000 => self
+                .buf
+                .extend_from_slice(&(0x4000 | length as u16).to_be_bytes()),
+            _ => match u32::try_from(length) {
+                Ok(length) => {
+                    self.buf.push(0x80);
+                    self.buf.extend_from_slice(&length.to_be_bytes());
+                }
+                Err(_) => {
+                    self.buf.push(0x81);
+                    self.buf.extend_from_slice(&(length as u64).to_be_bytes());
+                }
+            },
+        }
+    }
+}
+
+/// The CRC-64 variant with the Jones polynomial that Redis uses as the RDB checksum.
+fn crc64(data: &[u8]) -> u64 {
+    // The polynomial 0xad93d23594c935a9, bit reversed since the input is reflected
+    const POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+
+    data.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u64, |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+fn parse_length_6bit(data: (&[u8], usize)) -> BitParseResult<'_, usize> {
     let (data, _) = bits::tag(0usize, 2usize)(data)?;
     bits::take(6usize)(data)
 }
 
-fn parse_length_14bit(data: (&[u8], usize)) -> BitParseResult<usize> {
+fn parse_length_14bit(data: (&[u8], usize)) -> BitParseResult<'_, usize> {
     let (data, _) = bits::tag(1usize, 2usize)(data)?;
     bits::take(14usize)(data)
 }
 
-fn parse_length_32bit(data: (&[u8], usize)) -> BitParseResult<usize> {
+fn parse_length_32bit(data: (&[u8], usize)) -> BitParseResult<'_, usize> {
     let (data, _) = bits::tag(2usize, 2usize)(data)?;
     let (data, value_slice) = nom::bytes(bytes::take::<_, _, NomError<_>>(4usize))(data)?;
 
-    let value = u32::from_le_bytes(
+    let value = u32::from_be_bytes(
         value_slice
             .try_into()
             .expect("We took 4 bytes, so this should succeed"),
@@ -145,45 +336,197 @@ fn parse_length_32bit(data: (&[u8], usize)) -> BitParseResult<usize> {
     Ok((data, value as usize))
 }
 
-fn parse_length(data: &[u8]) -> ParseResult<usize> {
-    nom::bits(branch::alt((
-        parse_length_6bit,
-        parse_length_14bit,
-        parse_length_32bit,
-    )))(data)
+fn parse_length_64bit(data: &[u8]) -> ParseResult<'_, usize> {
+    let (data, _) = bytes::tag([0x81u8])(data)?;
+    let (data, value_slice) = bytes::take(8usize)(data)?;
+
+    let value = u64::from_be_bytes(
+        value_slice
+            .try_into()
+            .expect("We took 8 bytes, so this should succeed"),
+    );
+
+    Ok((data, value as usize))
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+fn parse_length(data: &[u8]) -> ParseResult<'_, usize> {
+    branch::alt((
+        parse_length_64bit,
+        nom::bits(branch::alt((
+            parse_length_6bit,
+            parse_length_14bit,
+            parse_length_32bit,
+        ))),
+    ))(data)
+}
+
+/// A stream ID stored as two lengths.
+fn parse_stream_id(data: &[u8]) -> ParseResult<'_, StreamId> {
+    let (data, ms) = parse_length(data)?;
+    let (data, seq) = parse_length(data)?;
+    Ok((data, (ms as u64, seq as u64)))
+}
+
+/// A stream ID stored as 16 big-endian bytes, which must have been checked to be 16 bytes long.
+fn parse_raw_stream_id(data: &[u8]) -> StreamId {
+    let (ms, seq) = data.split_at(8);
+    (
+        u64::from_be_bytes(ms.try_into().expect("IDs have 16 bytes")),
+        u64::from_be_bytes(seq.try_into().expect("IDs have 16 bytes")),
+    )
+}
+
+fn parse_millisecond_time(data: &[u8]) -> ParseResult<'_, SystemTime> {
+    let (data, time) = bytes::take(8usize)(data)?;
+    let time = u64::from_le_bytes(time.try_into().expect("We took 8 bytes"));
+    Ok((
+        data,
+        SystemTime::UNIX_EPOCH.add(Duration::from_millis(time)),
+    ))
+}
+
+/// Decompresses LZF data, which Redis uses for longer strings. Returns `None` if the input is
+/// malformed or doesn't decompress to exactly `length` bytes.
+fn lzf_decompress(input: &[u8], length: usize) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(length);
+    let mut input = input.iter().copied();
+
+    while let Some(ctrl) = input.next() {
+        let ctrl = ctrl as usize;
+        if ctrl < 32 {
+            // Literal run of ctrl + 1 bytes
+            for _ in 0..=ctrl {
+                output.push(input.next()?);
+            }
+        } else {
+            // Back reference into the already decompressed output
+            let mut len = ctrl >> 5;
+            if len == 7 {
+                len += input.next()? as usize;
+            }
+            let distance = ((ctrl & 0x1F) << 8) + input.next()? as usize + 1;
+            let start = output.len().checked_sub(distance)?;
+            for i in 0..len + 2 {
+                output.push(output[start + i]);
+            }
+        }
+    }
+
+    (output.len() == length).then_some(output)
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum OwnedValue {
-    String(String),
+    String(Vec<u8>),
     Integer(i32),
+    SortedSet(Vec<(Vec<u8>, f64)>),
+    Stream(StreamValue),
+}
+
+/// ID of a stream entry, as its milliseconds and sequence number.
+pub type StreamId = (u64, u64);
+
+/// A stream with its consumer groups.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamValue {
+    /// Listpacks of entries by the ID of their first entry, which only streams decode.
+    pub nodes: Vec<(StreamId, Vec<u8>)>,
+    pub length: u64,
+    pub last_id: StreamId,
+    pub first_id: StreamId,
+    pub max_deleted_id: StreamId,
+    pub entries_added: u64,
+    pub groups: Vec<StreamGroup>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamGroup {
+    pub name: Vec<u8>,
+    pub last_delivered: StreamId,
+    /// Number of entries the group read, `None` if it isn't known.
+    pub entries_read: Option<u64>,
+    pub pending: Vec<StreamPending>,
+    pub consumers: Vec<StreamConsumer>,
+}
+
+/// An entry delivered to the consumer of a group and not acknowledged yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamPending {
+    pub id: StreamId,
+    pub consumer: Vec<u8>,
+    pub delivered_at: SystemTime,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamConsumer {
+    pub name: Vec<u8>,
+    pub seen_at: SystemTime,
+    pub active_at: Option<SystemTime>,
 }
 
 #[derive(Debug)]
 enum Value<'a> {
-    String(&'a str),
+    String(Cow<'a, [u8]>),
     Integer(i32),
+    SortedSet(Vec<(Cow<'a, [u8]>, f64)>),
+    Stream(StreamValue),
 }
 
 impl<'a> Value<'a> {
     fn to_owned(&self) -> OwnedValue {
         match self {
-            Value::String(v) => OwnedValue::String(v.to_string()),
+            Value::String(v) => OwnedValue::String(v.to_vec()),
             Value::Integer(v) => OwnedValue::Integer(*v),
+            Value::SortedSet(members) => OwnedValue::SortedSet(
+                members
+                    .iter()
+                    .map(|(member, score)| (member.to_vec(), *score))
+                    .collect(),
+            ),
+            Value::Stream(stream) => OwnedValue::Stream(stream.clone()),
+        }
+    }
+
+    /// The bytes of a string, whichever way it was encoded.
+    fn into_bytes(self) -> Cow<'a, [u8]> {
+        match self {
+            Value::String(v) => v,
+            Value::Integer(v) => Cow::Owned(v.to_string().into_bytes()),
+            Value::SortedSet(_) | Value::Stream(_) => {
+                unreachable!("only strings are parsed as members")
+            }
         }
     }
 
     fn parse_key_value(data: &'a [u8]) -> ParseResult<'a, (Cow<'a, str>, Value<'a>)> {
-        branch::alt((Self::parse_kv_string,))(data)
+        branch::alt((
+            Self::parse_kv_string,
+            Self::parse_kv_sorted_set,
+            Self::parse_kv_stream,
+        ))(data)
     }
 
     fn parse_kv_key(data: &'a [u8]) -> ParseResult<'a, Cow<'a, str>> {
-        let (data, key) = Self::parse_string(data)?;
+        let (rest, key) = Self::parse_string(data)?;
+        let to_error = |e| {
+            nom::Err::Error(NomError::from_external_error(
+                data,
+                nom::error::ErrorKind::Verify,
+                e,
+            ))
+        };
         let key = match key {
-            Value::String(v) => Cow::Borrowed(v),
+            Value::String(Cow::Borrowed(v)) => {
+                Cow::Borrowed(std::str::from_utf8(v).map_err(to_error)?)
+            }
+            Value::String(Cow::Owned(v)) => {
+                Cow::Owned(String::from_utf8(v).map_err(|e| to_error(e.utf8_error()))?)
+            }
             Value::Integer(v) => Cow::Owned(v.to_string()),
+            Value::SortedSet(_) | Value::Stream(_) => unreachable!("keys are parsed as strings"),
         };
-        Ok((data, key))
+        Ok((rest, key))
     }
 
     fn parse_kv_string(data: &'a [u8]) -> ParseResult<'a, (Cow<'a, str>, Value<'a>)> {
@@ -194,30 +537,217 @@ impl<'a> Value<'a> {
         Ok((data, (key, value)))
     }
 
+    /// A sorted set with binary scores, `RDB_TYPE_ZSET_2` in Redis.
+    fn parse_kv_sorted_set(data: &'a [u8]) -> ParseResult<'a, (Cow<'a, str>, Value<'a>)> {
+        let (data, _) = bytes::tag([TYPE_ZSET_2])(data)?;
+        let (data, key) = Self::parse_kv_key(data)?;
+        let (data, length) = parse_length(data)?;
+        let (data, members) = multi::count(Self::parse_scored_member, length)(data)?;
+
+        Ok((data, (key, Self::SortedSet(members))))
+    }
+
+    /// A stream stored as listpacks, `RDB_TYPE_STREAM_LISTPACKS` in Redis. Its later versions
+    /// add the deletion and consumer activity tracking of Redis 7.
+    fn parse_kv_stream(data: &'a [u8]) -> ParseResult<'a, (Cow<'a, str>, Value<'a>)> {
+        let (data, kind) = branch::alt((
+            bytes::tag([TYPE_STREAM_LISTPACKS]),
+            bytes::tag([TYPE_STREAM_LISTPACKS_2]),
+            bytes::tag([TYPE_STREAM_LISTPACKS_3]),
+        ))(data)?;
+        let kind = kind[0];
+        let (data, key) = Self::parse_kv_key(data)?;
+
+        let (data, nodes) = parse_length(data)?;
+        let (data, nodes) = multi::count(Self::parse_stream_node, nodes)(data)?;
+
+        let (data, length) = parse_length(data)?;
+        let (mut data, last_id) = parse_stream_id(data)?;
+        let mut stream = StreamValue {
+            nodes,
+            length: length as u64,
+            last_id,
+            entries_added: length as u64,
+            ..Default::default()
+        };
+        if kind >= TYPE_STREAM_LISTPACKS_2 {
+            let (rest, first_id) = parse_stream_id(data)?;
+            let (rest, max_deleted_id) = parse_stream_id(rest)?;
+            let (rest, entries_added) = parse_length(rest)?;
+            stream.first_id = first_id;
+            stream.max_deleted_id = max_deleted_id;
+            stream.entries_added = entries_added as u64;
+            data = rest;
+        }
+
+        let (data, groups) = parse_length(data)?;
+        let (data, groups) =
+            multi::count(|data| Self::parse_stream_group(data, kind), groups)(data)?;
+        stream.groups = groups;
+
+        Ok((data, (key, Self::Stream(stream))))
+    }
+
+    /// A node of a stream with the ID of its first entry, a listpack of entries in the layout
+    /// streams keep them in, which is checked but not decoded.
+    fn parse_stream_node(data: &'a [u8]) -> ParseResult<'a, (StreamId, Vec<u8>)> {
+        let input = data;
+        let (data, master_id) = Self::parse_string(data)?;
+        let (data, listpack) = Self::parse_string(data)?;
+
+        let master_id = master_id.into_bytes();
+        let listpack = listpack.into_bytes();
+        let node = (master_id.len() == 16)
+            .then(|| parse_raw_stream_id(&master_id))
+            .filter(|(ms, seq)| stream::is_valid_node(ItemId::new(*ms, *seq), &listpack))
+            .map(|master_id| (master_id, listpack.into_owned()));
+        let node = node.ok_or_else(|| {
+            nom::Err::Error(NomError::from_error_kind(
+                input,
+                nom::error::ErrorKind::Verify,
+            ))
+        })?;
+        Ok((data, node))
+    }
+
+    fn parse_stream_group(data: &'a [u8], kind: u8) -> ParseResult<'a, StreamGroup> {
+        let input = data;
+        let (data, name) = Self::parse_string(data)?;
+        let (mut data, last_delivered) = parse_stream_id(data)?;
+        let mut entries_read = None;
+        if kind >= TYPE_STREAM_LISTPACKS_2 {
+            let (rest, read) = parse_length(data)?;
+            entries_read = (read as u64 != u64::MAX).then_some(read as u64);
+            data = rest;
+        }
+
+        let (data, pending) = parse_length(data)?;
+        let (data, pending) = multi::count(
+            nom::sequence::tuple((bytes::take(16usize), parse_millisecond_time, parse_length)),
+            pending,
+        )(data)?;
+
+        let (data, consumers) = parse_length(data)?;
+        let (data, consumers) =
+            multi::count(|data| Self::parse_stream_consumer(data, kind), consumers)(data)?;
+
+        // The group's pending entries are assigned to consumers by ID
+        let owners: HashMap<_, _> = consumers
+            .iter()
+            .flat_map(|(consumer, ids)| ids.iter().map(move |id| (*id, &consumer.name)))
+            .collect();
+        let pending = pending
+            .into_iter()
+            .map(|(id, delivered_at, delivery_count)| {
+                let id = parse_raw_stream_id(id);
+                Some(StreamPending {
+                    id,
+                    consumer: owners.get(&id)?.to_vec(),
+                    delivered_at,
+                    delivery_count: delivery_count as u64,
+                })
+            })
+            .collect::<Option<_>>()
+            .ok_or_else(|| {
+                nom::Err::Error(NomError::from_error_kind(
+                    input,
+                    nom::error::ErrorKind::Verify,
+                ))
+            })?;
+
+        let group = StreamGroup {
+            name: name.into_bytes().into_owned(),
+            last_delivered,
+            entries_read,
+            pending,
+            consumers: consumers
+                .into_iter()
+                .map(|(consumer, _)| consumer)
+                .collect(),
+        };
+        Ok((data, group))
+    }
+
+    /// A consumer with the IDs of its pending entries.
+    fn parse_stream_consumer(
+        data: &'a [u8],
+        kind: u8,
+    ) -> ParseResult<'a, (StreamConsumer, Vec<StreamId>)> {
+        let (data, name) = Self::parse_string(data)?;
+        let (mut data, seen_at) = parse_millisecond_time(data)?;
+        let mut active_at = Some(seen_at);
+        if kind >= TYPE_STREAM_LISTPACKS_3 {
+            let (rest, time) = bytes::take(8usize)(data)?;
+            let time = i64::from_le_bytes(time.try_into().expect("We took 8 bytes"));
+            // Consumers that never got entries have an active time of -1
+            active_at = u64::try_from(time)
+                .ok()
+                .map(|time| SystemTime::UNIX_EPOCH.add(Duration::from_millis(time)));
+            data = rest;
+        }
+
+        let (data, pending) = parse_length(data)?;
+        let (data, pending) = multi::count(bytes::take(16usize), pending)(data)?;
+
+        let consumer = StreamConsumer {
+            name: name.into_bytes().into_owned(),
+            seen_at,
+            active_at,
+        };
+        Ok((
+            data,
+            (
+                consumer,
+                pending.into_iter().map(parse_raw_stream_id).collect(),
+            ),
+        ))
+    }
+
+    fn parse_scored_member(data: &'a [u8]) -> ParseResult<'a, (Cow<'a, [u8]>, f64)> {
+        let (data, member) = Self::parse_string(data)?;
+        let (data, score) = bytes::take(8usize)(data)?;
+        let score = f64::from_le_bytes(
+            score
+                .try_into()
+                .expect("We took 8 bytes, so this should be OK"),
+        );
+        Ok((data, (member.into_bytes(), score)))
+    }
+
     fn parse_string(data: &'a [u8]) -> ParseResult<'a, Value<'a>> {
         branch::alt((
             Self::parse_length_prefixed_string,
             Self::parse_int_8bit,
             Self::parse_int_16bit,
             Self::parse_int_32bit,
+            Self::parse_lzf_string,
         ))(data)
     }
 
     fn parse_length_prefixed_string(data: &'a [u8]) -> ParseResult<'a, Self> {
         let (data, length) = parse_length(data)?;
-        let (data, value_slice) = bytes::take(length)(data)?;
-        let value = std::str::from_utf8(value_slice).map_err(|e| {
-            nom::Err::Error(NomError::from_external_error(
-                value_slice,
+        let (data, value) = bytes::take(length)(data)?;
+
+        Ok((data, Self::String(Cow::Borrowed(value))))
+    }
+
+    fn parse_lzf_string(data: &'a [u8]) -> ParseResult<'a, Self> {
+        let input = data;
+        let (data, _) = bytes::tag([0b11000011u8])(data)?;
+        let (data, compressed_length) = parse_length(data)?;
+        let (data, length) = parse_length(data)?;
+        let (data, compressed) = bytes::take(compressed_length)(data)?;
+
+        let value = lzf_decompress(compressed, length).ok_or_else(|| {
+            nom::Err::Error(NomError::from_error_kind(
+                input,
                 nom::error::ErrorKind::Verify,
-                e,
             ))
         })?;
-
-        Ok((data, Self::String(value)))
+        Ok((data, Self::String(Cow::Owned(value))))
     }
 
-    fn parse_int_8bit(data: &[u8]) -> ParseResult<Self> {
+    fn parse_int_8bit(data: &[u8]) -> ParseResult<'_, Self> {
         let (data, _) = bytes::tag([0b11000000u8])(data)?;
         let (data, value_slice) = bytes::take(1usize)(data)?;
         let value = i8::from_le_bytes(
@@ -228,8 +758,8 @@ impl<'a> Value<'a> {
         Ok((data, Self::Integer(value as i32)))
     }
 
-    fn parse_int_16bit(data: &[u8]) -> ParseResult<Self> {
-        let (data, _) = bytes::tag([0b11000000u8])(data)?;
+    fn parse_int_16bit(data: &[u8]) -> ParseResult<'_, Self> {
+        let (data, _) = bytes::tag([0b11000001u8])(data)?;
         let (data, value_slice) = bytes::take(2usize)(data)?;
         let value = i16::from_le_bytes(
             value_slice
@@ -239,8 +769,8 @@ impl<'a> Value<'a> {
         Ok((data, Self::Integer(value as i32)))
     }
 
-    fn parse_int_32bit(data: &[u8]) -> ParseResult<Self> {
-        let (data, _) = bytes::tag([0b11000000u8])(data)?;
+    fn parse_int_32bit(data: &[u8]) -> ParseResult<'_, Self> {
+        let (data, _) = bytes::tag([0b11000010u8])(data)?;
         let (data, value_slice) = bytes::take(4usize)(data)?;
         let value = i32::from_le_bytes(
             value_slice
@@ -251,8 +781,6 @@ impl<'a> Value<'a> {
     }
 }
 
-// Temporarily silence warnings
-#[allow(dead_code)]
 #[derive(Debug)]
 enum Section<'a> {
     EndOfFile,
@@ -365,9 +893,20 @@ mod test {
         time::{Duration, SystemTime},
     };
 
-    use crate::rdb::OwnedValue;
+    use crate::rdb::{OwnedValue, StreamConsumer, StreamGroup, StreamPending, StreamValue};
+    use crate::stream::{ItemId, Stream};
 
-    use super::Database;
+    use super::{crc64, lzf_decompress, Database, Encoder};
+
+    #[test]
+    fn test_lzf() {
+        assert_eq!(
+            lzf_decompress(&[0x02, b'a', b'b', b'c', 0x20, 0x02], 6),
+            Some(b"abcabc".to_vec())
+        );
+        assert_eq!(lzf_decompress(&[0x20, 0x00], 2), None);
+        assert_eq!(lzf_decompress(&[0x01, b'a'], 2), None);
+    }
 
     #[test]
     fn test_simple_parse() {
@@ -380,7 +919,7 @@ mod test {
 
         let parsed = Database::parse(&data).expect("data is valid, parsing should succeed");
         assert_eq!(
-            parsed.keys().get("apple"),
+            parsed.dbs()[&0].keys().get("apple"),
             Some(&OwnedValue::String("grape".into()))
         );
     }
@@ -396,7 +935,7 @@ mod test {
 
         let parsed = Database::parse(&data).expect("data is valid, parsing should succeed");
         assert_eq!(
-            parsed.keys().get("raspberry"),
+            parsed.dbs()[&0].keys().get("raspberry"),
             Some(&OwnedValue::String("blueberry".into()))
         );
     }
@@ -414,19 +953,19 @@ mod test {
 
         let parsed = Database::parse(&data).expect("data is valid, parsing should succeed");
         assert_eq!(
-            parsed.keys().get("banana"),
+            parsed.dbs()[&0].keys().get("banana"),
             Some(&OwnedValue::String("grape".into()))
         );
         assert_eq!(
-            parsed.keys().get("raspberry"),
+            parsed.dbs()[&0].keys().get("raspberry"),
             Some(&OwnedValue::String("raspberry".into()))
         );
         assert_eq!(
-            parsed.keys().get("mango"),
+            parsed.dbs()[&0].keys().get("mango"),
             Some(&OwnedValue::String("orange".into()))
         );
         assert_eq!(
-            parsed.keys().get("orange"),
+            parsed.dbs()[&0].keys().get("orange"),
             Some(&OwnedValue::String("banana".into()))
         );
     }
@@ -441,7 +980,7 @@ mod test {
         ];
         let parsed = Database::parse(&data).expect("data is valid, parsing should succeed");
         assert_eq!(
-            parsed.keys().get("blueberry"),
+            parsed.dbs()[&0].keys().get("blueberry"),
             Some(&OwnedValue::String("grape".into()))
         );
     }
@@ -459,11 +998,164 @@ mod test {
         ];
         let parsed = Database::parse(&data).expect("data is valid, parsing should succeed");
         assert_eq!(
-            parsed.expiring().get("blueberry"),
+            parsed.dbs()[&0].expiring().get("blueberry"),
             Some(&(
                 OwnedValue::String("strawberry".into()),
                 SystemTime::UNIX_EPOCH.add(Duration::from_millis(1640995200000))
             ))
         );
     }
+
+    #[test]
+    fn test_encoded_strings() {
+        let mut data = b"REDIS0011".to_vec();
+        data.extend_from_slice(&[0xFE, 0x00]);
+        // Key 12345 as a 16-bit integer, value compressed with LZF
+        data.extend_from_slice(&[
+            0x00, 0xC1, 0x39, 0x30, 0xC3, 5, 10, 0x00, b'a', 0xE0, 0x00, 0x00,
+        ]);
+        // Key "-2" as an 8-bit integer, value 100000 as a 32-bit integer
+        data.extend_from_slice(&[0x00, 0xC0, 0xFE, 0xC2, 0xA0, 0x86, 0x01, 0x00]);
+        data.extend_from_slice(&[0xFF, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        let parsed = Database::parse(&data).expect("data is valid, parsing should succeed");
+        assert_eq!(
+            parsed.dbs()[&0].keys().get("12345"),
+            Some(&OwnedValue::String("aaaaaaaaaa".into()))
+        );
+        assert_eq!(
+            parsed.dbs()[&0].keys().get("-2"),
+            Some(&OwnedValue::Integer(100000))
+        );
+    }
+
+    #[test]
+    fn test_crc64() {
+        assert_eq!(crc64(b"123456789"), 0xe9c6d914c4b8d9ca);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let expires_at = SystemTime::UNIX_EPOCH.add(Duration::from_millis(1640995200000));
+        let long_value = vec![b'x'; 20000];
+
+        let mut encoder = Encoder::new();
+        encoder.select_db(0, 2, 1);
+        encoder.add_string("plain", b"value", None);
+        encoder.add_string("long", &long_value, Some(expires_at));
+        encoder.select_db(3, 1, 0);
+        encoder.add_sorted_set("set", [("a", 1.5), ("b", -2.0)].into_iter(), None);
+        let data = encoder.finish();
+
+        let parsed = Database::parse(&data).expect("encoded data should parse");
+        assert_eq!(
+            parsed.aux().get("redis-ver").map(String::as_str),
+            Some("7.2.0")
+        );
+        assert_eq!(parsed.dbs().keys().copied().collect::<Vec<_>>(), vec![0, 3]);
+        assert_eq!(
+            parsed.dbs()[&0].keys().get("plain"),
+            Some(&OwnedValue::String("value".into()))
+        );
+        assert_eq!(
+            parsed.dbs()[&0].expiring().get("long"),
+            Some(&(OwnedValue::String(long_value), expires_at))
+        );
+        assert_eq!(
+            parsed.dbs()[&3].keys().get("set"),
+            Some(&OwnedValue::SortedSet(vec![
+                (b"a".to_vec(), 1.5),
+                (b"b".to_vec(), -2.0)
+            ]))
+        );
+
+        let checksum = u64::from_le_bytes(data[data.len() - 8..].try_into().unwrap());
+        assert_eq!(checksum, crc64(&data[..data.len() - 8]));
+    }
+
+    #[test]
+    fn test_stream_round_trip() {
+        let time = SystemTime::UNIX_EPOCH.add(Duration::from_millis(1700000000000));
+        let fields = |values: &[&str]| {
+            values
+                .chunks(2)
+                .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+                .collect()
+        };
+        // Enough entries for two nodes, most with the fields of the first one
+        let mut entries = Stream::new();
+        for i in 0..150 {
+            let data = match i {
+                20 => fields(&["a", "x", "a", "y", "b", "z"]),
+                120 => fields(&["temperature", "-21.5"]),
+                _ => fields(&["b", "1", "a", &i.to_string()]),
+            };
+            let id = ItemId::new(1000 + i / 3, i % 3);
+            entries.insert(id.into(), data, time).unwrap();
+        }
+        entries.delete(&[ItemId::new(1000, 0), ItemId::new(1010, 1)]);
+        let entries = entries.to_rdb();
+        assert_eq!(entries.nodes.len(), 2);
+
+        let stream = StreamValue {
+            nodes: entries.nodes,
+            length: entries.length,
+            first_id: entries.first_id,
+            last_id: (2000, 5),
+            max_deleted_id: (1200, 0),
+            entries_added: 180,
+            groups: vec![StreamGroup {
+                name: b"workers".to_vec(),
+                last_delivered: (1010, 1),
+                entries_read: Some(12),
+                pending: vec![
+                    StreamPending {
+                        id: (1000, 1),
+                        consumer: b"alice".to_vec(),
+                        delivered_at: time,
+                        delivery_count: 3,
+                    },
+                    StreamPending {
+                        id: (1002, 0),
+                        consumer: b"bob".to_vec(),
+                        delivered_at: time,
+                        delivery_count: 1,
+                    },
+                ],
+                consumers: vec![
+                    StreamConsumer {
+                        name: b"alice".to_vec(),
+                        seen_at: time,
+                        active_at: Some(time),
+                    },
+                    StreamConsumer {
+                        name: b"bob".to_vec(),
+                        seen_at: time,
+                        active_at: Some(time),
+                    },
+                    StreamConsumer {
+                        name: b"idle".to_vec(),
+                        seen_at: time,
+                        active_at: None,
+                    },
+                ],
+            }],
+        };
+
+        let mut encoder = Encoder::new();
+        encoder.select_db(0, 2, 0);
+        encoder.add_stream("events", &stream, None);
+        encoder.add_stream("empty", &StreamValue::default(), None);
+        let data = encoder.finish();
+
+        let parsed = Database::parse(&data).expect("encoded data should parse");
+        assert_eq!(
+            parsed.dbs()[&0].keys().get("events"),
+            Some(&OwnedValue::Stream(stream))
+        );
+        assert_eq!(
+            parsed.dbs()[&0].keys().get("empty"),
+            Some(&OwnedValue::Stream(StreamValue::default()))
+        );
+    }
 }
@@ -12,10 +12,16 @@ pub enum Type {
     SimpleError(ErrorKind, String),
     SimpleString(String),
     BulkString(String),
+    BulkBytes(Vec<u8>),
+    Integer(i64),
     NullString,
     Array(Vec<Type>),
     NullArray,
     Null,
+    /// RESP3 map, which RESP2 clients expect as an array alternating keys and values.
+    Map(Vec<(Type, Type)>),
+    /// RESP3 out-of-band data like invalidation messages, sent outside of replies.
+    Push(Vec<Type>),
 }
 
 type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
@@ -43,6 +49,7 @@ impl Type {
         match char::from(ident) {
             '+' => Self::parse_simple_string(stream).await,
             '$' => Self::parse_bulk_string(stream).await,
+            ':' => Self::parse_integer(stream).await,
             '*' => Self::parse_array(stream).await,
             '_' => Self::parse_null(stream).await,
             _ => Err(Error::UnknownTypeSpecifier(ident)),
@@ -81,18 +88,26 @@ impl Type {
     async fn parse_bulk_string(stream: &mut PinnedRead<'_>) -> Result<Type> {
         let len = Self::parse_isize(stream).await?;
         if let Ok(len) = usize::try_from(len) {
-            let mut buffer = Vec::with_capacity(len);
-            buffer.resize(len, 0);
+            let mut buffer = vec![0; len];
             stream.read_exact(&mut buffer).await?;
 
             Self::expect_crlf(stream).await?;
 
-            Ok(Type::BulkString(str::from_utf8(&buffer)?.into()))
+            match String::from_utf8(buffer) {
+                Ok(value) => Ok(Type::BulkString(value)),
+                Err(err) => Ok(Type::BulkBytes(err.into_bytes())),
+            }
         } else {
             Ok(Type::NullString)
         }
     }
 
+    async fn parse_integer(stream: &mut PinnedRead<'_>) -> Result<Type> {
+        let buffer = Self::read_until_crlf(stream).await?;
+
+        Ok(Type::Integer(str::from_utf8(&buffer)?.parse()?))
+    }
+
     async fn parse_isize(stream: &mut PinnedRead<'_>) -> Result<isize> {
         let len = Self::read_until_crlf(stream).await?;
         let len: isize = str::from_utf8(&len)?.parse()?;
@@ -135,14 +150,31 @@ impl Type {
         self.write_impl(&mut Pin::new(stream)).await
     }
 
+    /// Starts an array of `len` elements, which the caller writes one by one afterwards.
+    pub async fn write_array_header(
+        len: usize,
+        stream: &mut (dyn AsyncWrite + Unpin + Send),
+    ) -> Result<()> {
+        let mut stream = Pin::new(stream);
+        stream.write_u8(b'*').await?;
+        stream.write_all(len.to_string().as_bytes()).await?;
+        stream.write_all(b"\r\n").await?;
+
+        Ok(())
+    }
+
     async fn write_impl(&self, stream: &mut PinnedWrite<'_>) -> Result<()> {
         match self {
             Type::SimpleError(kind, message) => {
-                Self::write_simple_error(stream, kind, &message).await
+                Self::write_simple_error(stream, kind, message).await
             }
-            Type::SimpleString(str) => Self::write_simple_string(stream, &str).await,
-            Type::BulkString(str) => Self::write_bulk_string(stream, str).await,
-            Type::Array(items) => Self::write_array(stream, items).await,
+            Type::SimpleString(str) => Self::write_simple_string(stream, str).await,
+            Type::BulkString(str) => Self::write_bulk_string(stream, str.as_bytes()).await,
+            Type::BulkBytes(bytes) => Self::write_bulk_string(stream, bytes).await,
+            Type::Array(items) => Self::write_aggregate(stream, b'*', items).await,
+            Type::Push(items) => Self::write_aggregate(stream, b'>', items).await,
+            Type::Map(entries) => Self::write_map(stream, entries).await,
+            Type::Integer(value) => Self::write_integer(stream, *value).await,
             Type::NullString => Ok(stream.write_all(b"$-1\r\n").await?),
             Type::NullArray => Ok(stream.write_all(b"*-1\r\n").await?),
             Type::Null => Ok(stream.write_all(b"_\r\n").await?),
@@ -173,22 +205,32 @@ impl Type {
         Ok(())
     }
 
-    async fn write_bulk_string(stream: &mut PinnedWrite<'_>, value: &str) -> Result<()> {
+    async fn write_bulk_string(stream: &mut PinnedWrite<'_>, value: &[u8]) -> Result<()> {
         stream.write_u8(b'$').await?;
         stream.write_all(value.len().to_string().as_bytes()).await?;
         stream.write_all(b"\r\n").await?;
-        stream.write_all(value.as_bytes()).await?;
+        stream.write_all(value).await?;
+        stream.write_all(b"\r\n").await?;
+
+        Ok(())
+    }
+
+    async fn write_integer(stream: &mut PinnedWrite<'_>, value: i64) -> Result<()> {
+        stream.write_u8(b':').await?;
+        stream.write_all(value.to_string().as_bytes()).await?;
         stream.write_all(b"\r\n").await?;
 
         Ok(())
     }
 
-    fn write_array<'a>(
+    /// Writes an array or push frame, which only differ in their first byte.
+    fn write_aggregate<'a>(
         stream: &'a mut PinnedWrite<'_>,
+        prefix: u8,
         value: &'a [Type],
     ) -> BoxFuture<'a, Result<()>> {
         async move {
-            stream.write_u8(b'*').await?;
+            stream.write_u8(prefix).await?;
             stream.write_all(value.len().to_string().as_bytes()).await?;
             stream.write_all(b"\r\n").await?;
 
@@ -199,19 +241,34 @@ impl Type {
         }
         .boxed()
     }
+
+    fn write_map<'a>(
+        stream: &'a mut PinnedWrite<'_>,
+        entries: &'a [(Type, Type)],
+    ) -> BoxFuture<'a, Result<()>> {
+        async move {
+            stream.write_u8(b'%').await?;
+            stream
+                .write_all(entries.len().to_string().as_bytes())
+                .await?;
+            stream.write_all(b"\r\n").await?;
+
+            for (key, value) in entries {
+                key.write(stream).await?;
+                value.write(stream).await?;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
 }
 
-impl From<stream::Item<'_>> for Type {
+impl From<stream::Item> for Type {
     fn from(value: stream::Item) -> Self {
         let fields_array = value
             .elements
-            .iter()
-            .flat_map(|(key, value)| {
-                [
-                    Self::BulkString(key.clone()),
-                    Self::BulkString(value.clone()),
-                ]
-            })
+            .into_iter()
+            .flat_map(|(key, value)| [Self::BulkString(key), Self::BulkString(value)])
             .collect();
 
         Self::Array(vec![
@@ -263,6 +320,24 @@ mod test {
         assert_eq!(buffer, b"$11\r\nTest string\r\n");
     }
 
+    #[tokio::test]
+    async fn parse_binary_bulk_string() {
+        let input = b"$3\r\n\xff\x00a\r\n";
+        let mut input = &input[..];
+        let parsed = Type::parse(&mut input).await.expect("");
+        assert_eq!(parsed, Type::BulkBytes(vec![0xff, 0x00, b'a']));
+    }
+
+    #[tokio::test]
+    async fn write_binary_bulk_string() {
+        let mut buffer = Vec::<u8>::new();
+        Type::BulkBytes(vec![0x80, b'\r'])
+            .write(&mut buffer)
+            .await
+            .expect("Write should succeed");
+        assert_eq!(buffer, b"$2\r\n\x80\r\r\n");
+    }
+
     #[tokio::test]
     async fn parse_null_string() {
         let input = b"$-1\r\n";
@@ -281,6 +356,24 @@ mod test {
         assert_eq!(buffer, b"$-1\r\n");
     }
 
+    #[tokio::test]
+    async fn parse_integer() {
+        let input = b":-42\r\n";
+        let mut input = &input[..];
+        let parsed = Type::parse(&mut input).await.expect("");
+        assert_eq!(parsed, Type::Integer(-42));
+    }
+
+    #[tokio::test]
+    async fn write_integer() {
+        let mut buffer = Vec::<u8>::new();
+        Type::Integer(1000)
+            .write(&mut buffer)
+            .await
+            .expect("Write should succeed");
+        assert_eq!(buffer, b":1000\r\n");
+    }
+
     #[tokio::test]
     async fn parse_array() {
         let input = b"*3\r\n+OK1\r\n+OK2\r\n+OK3\r\n";
@@ -299,9 +392,10 @@ mod test {
     #[tokio::test]
     async fn write_array() {
         let mut buffer = Vec::<u8>::new();
-        let mut items = Vec::new();
-        items.push(Type::SimpleString("Test1".into()));
-        items.push(Type::BulkString("Test2\n".into()));
+        let items = vec![
+            Type::SimpleString("Test1".into()),
+            Type::BulkString("Test2\n".into()),
+        ];
         Type::Array(items)
             .write(&mut buffer)
             .await
@@ -344,4 +438,24 @@ mod test {
             .expect("Write should succeed");
         assert_eq!(buffer, b"_\r\n");
     }
+
+    #[tokio::test]
+    async fn write_map() {
+        let mut buffer = Vec::<u8>::new();
+        Type::Map(vec![(Type::BulkString("proto".into()), Type::Integer(3))])
+            .write(&mut buffer)
+            .await
+            .expect("Write should succeed");
+        assert_eq!(buffer, b"%1\r\n$5\r\nproto\r\n:3\r\n");
+    }
+
+    #[tokio::test]
+    async fn write_push() {
+        let mut buffer = Vec::<u8>::new();
+        Type::Push(vec![Type::BulkString("invalidate".into()), Type::Null])
+            .write(&mut buffer)
+            .await
+            .expect("Write should succeed");
+        assert_eq!(buffer, b">2\r\n$10\r\ninvalidate\r\n_\r\n");
+    }
 }
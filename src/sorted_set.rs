@@ -0,0 +1,630 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    hash::{DefaultHasher, Hash, Hasher},
+    ops::Bound,
+};
+
+const MAX_LEVEL: usize = 32;
+const NIL: usize = usize::MAX;
+const HEAD: usize = 0;
+
+#[derive(Debug, Clone)]
+struct Level {
+    forward: usize,
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    member: String,
+    score: f64,
+    backward: usize,
+    levels: Vec<Level>,
+}
+
+impl Node {
+    fn precedes(&self, score: f64, member: &str) -> bool {
+        self.score < score || (self.score == score && self.member.as_str() < member)
+    }
+}
+
+/// Ordered collection of unique members, each with a floating point score.
+///
+/// Members are ordered by score and then lexicographically. Lookups by member go through a
+/// hash map, while everything that depends on ordering goes through a skiplist whose links
+/// carry spans, so ranks can be computed in O(log n).
+#[derive(Debug, Clone)]
+pub struct SortedSet {
+    scores: HashMap<String, f64>,
+    /// Every member ordered by its hash, which gives stable ZSCAN cursors.
+    ordered: BTreeSet<(u64, String)>,
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    tail: usize,
+    level: usize,
+    rng: u64,
+}
+
+impl Default for SortedSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        let head = Node {
+            member: String::new(),
+            score: 0.0,
+            backward: NIL,
+            levels: vec![
+                Level {
+                    forward: NIL,
+                    span: 0
+                };
+                MAX_LEVEL
+            ],
+        };
+
+        Self {
+            scores: HashMap::new(),
+            ordered: BTreeSet::new(),
+            nodes: vec![head],
+            free: Vec::new(),
+            tail: NIL,
+            level: 1,
+            rng: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &str) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Adds `member` or moves it to a new score, returning the previous score if it was present.
+    pub fn insert(&mut self, member: String, score: f64) -> Option<f64> {
+        debug_assert!(!score.is_nan());
+
+        let previous = self.scores.get(&member).copied();
+        match previous {
+            Some(old) if old == score => {}
+            Some(old) => {
+                self.list_delete(old, &member);
+                self.list_insert(member.clone(), score);
+                self.scores.insert(member, score);
+            }
+            None => {
+                self.list_insert(member.clone(), score);
+                self.ordered.insert((member_hash(&member), member.clone()));
+                self.scores.insert(member, score);
+            }
+        }
+
+        previous
+    }
+
+    pub fn remove(&mut self, member: &str) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.ordered
+            .remove(&(member_hash(member), member.to_owned()));
+        self.list_delete(score, member);
+        Some(score)
+    }
+
+    pub fn pop_first(&mut self) -> Option<(String, f64)> {
+        let first = self.nodes[HEAD].levels[0].forward;
+        self.pop_node(first)
+    }
+
+    pub fn pop_last(&mut self) -> Option<(String, f64)> {
+        self.pop_node(self.tail)
+    }
+
+    fn pop_node(&mut self, node: usize) -> Option<(String, f64)> {
+        if node == NIL {
+            return None;
+        }
+
+        let member = self.nodes[node].member.clone();
+        let score = self.remove(&member)?;
+        Some((member, score))
+    }
+
+    /// Zero-based position of `member` in ascending order.
+    pub fn rank(&self, member: &str) -> Option<usize> {
+        let score = self.score(member)?;
+        Some(self.count_before(|node| node.precedes(score, member)))
+    }
+
+    pub fn get_by_rank(&self, rank: usize) -> Option<(&str, f64)> {
+        let node = self.node_by_rank(rank);
+        (node != NIL).then(|| {
+            let node = &self.nodes[node];
+            (node.member.as_str(), node.score)
+        })
+    }
+
+    /// Iterates over the members with ranks in `start..=end`, from `end` down when `reverse`.
+    pub fn range_by_rank(&self, start: usize, end: usize, reverse: bool) -> Iter<'_> {
+        let end = end.min(self.len().saturating_sub(1));
+        if start > end || self.is_empty() {
+            return Iter::empty(self);
+        }
+
+        let first = self.node_by_rank(if reverse { end } else { start });
+        Iter {
+            set: self,
+            node: first,
+            remaining: end - start + 1,
+            reverse,
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            set: self,
+            node: self.nodes[HEAD].levels[0].forward,
+            remaining: self.len(),
+            reverse: false,
+        }
+    }
+
+    /// Up to `count` members starting at `cursor` in the hash order of the members, with the
+    /// cursor to continue from, or 0 once every member was returned. Like SCAN, members that
+    /// stay in the set during the whole iteration are returned at least once.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&str, f64)>) {
+        let mut visited = self.ordered.range((cursor, String::new())..);
+
+        let members = visited
+            .by_ref()
+            .take(count)
+            .map(|(_, member)| (member.as_str(), self.scores[member]))
+            .collect();
+        (visited.next().map_or(0, |(hash, _)| *hash), members)
+    }
+
+    /// Inclusive rank interval of the members whose score lies between `min` and `max`.
+    pub fn ranks_by_score(&self, min: Bound<f64>, max: Bound<f64>) -> Option<(usize, usize)> {
+        let start = self.count_before(|node| match min {
+            Bound::Included(min) => node.score < min,
+            Bound::Excluded(min) => node.score <= min,
+            Bound::Unbounded => false,
+        });
+        let end = self.count_before(|node| match max {
+            Bound::Included(max) => node.score <= max,
+            Bound::Excluded(max) => node.score < max,
+            Bound::Unbounded => true,
+        });
+
+        (end > start).then(|| (start, end - 1))
+    }
+
+    /// Inclusive rank interval of the members between `min` and `max` in lexicographical order.
+    ///
+    /// Like in Redis, the result is only meaningful when all members share the same score.
+    pub fn ranks_by_lex(&self, min: Bound<&str>, max: Bound<&str>) -> Option<(usize, usize)> {
+        let start = self.count_before(|node| match min {
+            Bound::Included(min) => node.member.as_str() < min,
+            Bound::Excluded(min) => node.member.as_str() <= min,
+            Bound::Unbounded => false,
+        });
+        let end = self.count_before(|node| match max {
+            Bound::Included(max) => node.member.as_str() <= max,
+            Bound::Excluded(max) => node.member.as_str() < max,
+            Bound::Unbounded => true,
+        });
+
+        (end > start).then(|| (start, end - 1))
+    }
+
+    /// Removes the members with ranks in `start..=end`, returning how many were removed.
+    pub fn remove_range_by_rank(&mut self, start: usize, end: usize) -> usize {
+        let members: Vec<_> = self
+            .range_by_rank(start, end, false)
+            .map(|(member, _)| member.to_owned())
+            .collect();
+
+        for member in &members {
+            self.remove(member);
+        }
+
+        members.len()
+    }
+
+    fn random_level(&mut self) -> usize {
+        let mut level = 1;
+        // xorshift64, a 1/4 chance of promoting a node to the next level
+        loop {
+            self.rng ^= self.rng << 13;
+            self.rng ^= self.rng >> 7;
+            self.rng ^= self.rng << 17;
+            if level < MAX_LEVEL && self.rng & 0b11 == 0 {
+                level += 1;
+            } else {
+                return level;
+            }
+        }
+    }
+
+    /// Number of leading nodes for which `pred` holds. `pred` has to be monotonic in list order.
+    fn count_before(&self, pred: impl Fn(&Node) -> bool) -> usize {
+        let mut node = HEAD;
+        let mut traversed = 0;
+
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.nodes[node].levels[i].forward;
+                if next != NIL && pred(&self.nodes[next]) {
+                    traversed += self.nodes[node].levels[i].span;
+                    node = next;
+                } else {
+                    break;
+                }
+            }
+        }
+
+        traversed
+    }
+
+    fn node_by_rank(&self, rank: usize) -> usize {
+        let target = rank + 1;
+        let mut node = HEAD;
+        let mut traversed = 0;
+
+        for i in (0..self.level).rev() {
+            loop {
+                let level = &self.nodes[node].levels[i];
+                if level.forward != NIL && traversed + level.span <= target {
+                    traversed += level.span;
+                    node = level.forward;
+                } else {
+                    break;
+                }
+            }
+
+            if traversed == target {
+                return node;
+            }
+        }
+
+        NIL
+    }
+
+    fn list_insert(&mut self, member: String, score: f64) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0usize; MAX_LEVEL];
+        let mut node = HEAD;
+
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+            loop {
+                let next = self.nodes[node].levels[i].forward;
+                if next != NIL && self.nodes[next].precedes(score, &member) {
+                    rank[i] += self.nodes[node].levels[i].span;
+                    node = next;
+                } else {
+                    break;
+                }
+            }
+            update[i] = node;
+        }
+
+        let length = self.len();
+        let level = self.random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.nodes[HEAD].levels[i].span = length;
+            }
+            self.level = level;
+        }
+
+        let new = Node {
+            member,
+            score,
+            backward: NIL,
+            levels: vec![
+                Level {
+                    forward: NIL,
+                    span: 0
+                };
+                level
+            ],
+        };
+        let new = if let Some(index) = self.free.pop() {
+            self.nodes[index] = new;
+            index
+        } else {
+            self.nodes.push(new);
+            self.nodes.len() - 1
+        };
+
+        for i in 0..level {
+            let prev = update[i];
+            let distance = rank[0] - rank[i];
+            self.nodes[new].levels[i] = Level {
+                forward: self.nodes[prev].levels[i].forward,
+                span: self.nodes[prev].levels[i].span - distance,
+            };
+            self.nodes[prev].levels[i] = Level {
+                forward: new,
+                span: distance + 1,
+            };
+        }
+
+        for (i, prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[*prev].levels[i].span += 1;
+        }
+
+        self.nodes[new].backward = if update[0] == HEAD { NIL } else { update[0] };
+        match self.nodes[new].levels[0].forward {
+            NIL => self.tail = new,
+            next => self.nodes[next].backward = new,
+        }
+    }
+
+    fn list_delete(&mut self, score: f64, member: &str) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut node = HEAD;
+
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.nodes[node].levels[i].forward;
+                if next != NIL && self.nodes[next].precedes(score, member) {
+                    node = next;
+                } else {
+                    break;
+                }
+            }
+            update[i] = node;
+        }
+
+        let node = self.nodes[node].levels[0].forward;
+        if node == NIL || self.nodes[node].score != score || self.nodes[node].member != member {
+            return;
+        }
+
+        for (i, prev) in update.iter().enumerate().take(self.level) {
+            if self.nodes[*prev].levels[i].forward == node {
+                let removed = self.nodes[node].levels[i].clone();
+                let level = &mut self.nodes[*prev].levels[i];
+                level.span += removed.span;
+                level.span -= 1;
+                level.forward = removed.forward;
+            } else {
+                self.nodes[*prev].levels[i].span -= 1;
+            }
+        }
+
+        let backward = self.nodes[node].backward;
+        match self.nodes[node].levels[0].forward {
+            NIL => self.tail = backward,
+            next => self.nodes[next].backward = backward,
+        }
+
+        while self.level > 1 && self.nodes[HEAD].levels[self.level - 1].forward == NIL {
+            self.level -= 1;
+        }
+
+        self.nodes[node].member = String::new();
+        self.nodes[node].levels = Vec::new();
+        self.free.push(node);
+    }
+}
+
+/// Hash of a member, which is never 0 since a cursor of 0 ends a scan.
+fn member_hash(member: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    member.hash(&mut hasher);
+    hasher.finish().max(1)
+}
+
+pub struct Iter<'a> {
+    set: &'a SortedSet,
+    node: usize,
+    remaining: usize,
+    reverse: bool,
+}
+
+impl<'a> Iter<'a> {
+    fn empty(set: &'a SortedSet) -> Self {
+        Self {
+            set,
+            node: NIL,
+            remaining: 0,
+            reverse: false,
+        }
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a str, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 || self.node == NIL {
+            return None;
+        }
+
+        let node = &self.set.nodes[self.node];
+        self.remaining -= 1;
+        self.node = if self.reverse {
+            node.backward
+        } else {
+            node.levels[0].forward
+        };
+
+        Some((node.member.as_str(), node.score))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn members(iter: Iter<'_>) -> Vec<&str> {
+        iter.map(|(member, _)| member).collect()
+    }
+
+    #[test]
+    fn orders_by_score_then_member() {
+        let mut sut = SortedSet::new();
+        sut.insert("c".into(), 1.0);
+        sut.insert("b".into(), 2.0);
+        sut.insert("a".into(), 1.0);
+
+        assert_eq!(members(sut.iter()), vec!["a", "c", "b"]);
+        assert_eq!(sut.rank("a"), Some(0));
+        assert_eq!(sut.rank("c"), Some(1));
+        assert_eq!(sut.rank("b"), Some(2));
+        assert_eq!(sut.rank("d"), None);
+    }
+
+    #[test]
+    fn update_moves_member() {
+        let mut sut = SortedSet::new();
+        sut.insert("a".into(), 1.0);
+        sut.insert("b".into(), 2.0);
+
+        assert_eq!(sut.insert("a".into(), 3.0), Some(1.0));
+        assert_eq!(members(sut.iter()), vec!["b", "a"]);
+        assert_eq!(sut.len(), 2);
+        assert_eq!(sut.score("a"), Some(3.0));
+    }
+
+    #[test]
+    fn remove_and_reuse_nodes() {
+        let mut sut = SortedSet::new();
+        for i in 0..100 {
+            sut.insert(format!("m{i:03}"), i as f64);
+        }
+        for i in (0..100).step_by(2) {
+            assert_eq!(sut.remove(&format!("m{i:03}")), Some(i as f64));
+        }
+        for i in 100..150 {
+            sut.insert(format!("m{i:03}"), i as f64);
+        }
+
+        assert_eq!(sut.len(), 100);
+        for (rank, (member, score)) in sut.iter().enumerate() {
+            assert_eq!(sut.rank(member), Some(rank));
+            assert_eq!(sut.get_by_rank(rank), Some((member, score)));
+        }
+        assert_eq!(sut.remove("m000"), None);
+    }
+
+    #[test]
+    fn score_ranges() {
+        let mut sut = SortedSet::new();
+        for i in 1..=5 {
+            sut.insert(i.to_string(), i as f64);
+        }
+
+        assert_eq!(
+            sut.ranks_by_score(Bound::Included(2.0), Bound::Included(4.0)),
+            Some((1, 3))
+        );
+        assert_eq!(
+            sut.ranks_by_score(Bound::Excluded(2.0), Bound::Excluded(4.0)),
+            Some((2, 2))
+        );
+        assert_eq!(
+            sut.ranks_by_score(Bound::Unbounded, Bound::Excluded(1.0)),
+            None
+        );
+        assert_eq!(
+            sut.ranks_by_score(Bound::Included(f64::NEG_INFINITY), Bound::Unbounded),
+            Some((0, 4))
+        );
+    }
+
+    #[test]
+    fn lex_ranges() {
+        let mut sut = SortedSet::new();
+        for member in ["a", "b", "c", "d"] {
+            sut.insert(member.into(), 0.0);
+        }
+
+        assert_eq!(
+            sut.ranks_by_lex(Bound::Excluded("a"), Bound::Included("c")),
+            Some((1, 2))
+        );
+        assert_eq!(
+            sut.ranks_by_lex(Bound::Unbounded, Bound::Unbounded),
+            Some((0, 3))
+        );
+        assert_eq!(
+            sut.ranks_by_lex(Bound::Included("e"), Bound::Unbounded),
+            None
+        );
+    }
+
+    #[test]
+    fn pop_from_both_ends() {
+        let mut sut = SortedSet::new();
+        for i in 0..5 {
+            sut.insert(i.to_string(), i as f64);
+        }
+
+        assert_eq!(sut.pop_first(), Some(("0".into(), 0.0)));
+        assert_eq!(sut.pop_last(), Some(("4".into(), 4.0)));
+        assert_eq!(sut.pop_last(), Some(("3".into(), 3.0)));
+        assert_eq!(members(sut.iter()), vec!["1", "2"]);
+
+        sut.pop_first();
+        sut.pop_first();
+        assert_eq!(sut.pop_first(), None);
+        assert_eq!(sut.pop_last(), None);
+    }
+
+    #[test]
+    fn reverse_rank_range() {
+        let mut sut = SortedSet::new();
+        for i in 0..10 {
+            sut.insert(i.to_string(), i as f64);
+        }
+
+        assert_eq!(members(sut.range_by_rank(2, 4, true)), vec!["4", "3", "2"]);
+        assert_eq!(members(sut.range_by_rank(8, 20, false)), vec!["8", "9"]);
+        assert_eq!(sut.remove_range_by_rank(0, 4), 5);
+        assert_eq!(members(sut.iter()), vec!["5", "6", "7", "8", "9"]);
+    }
+
+    #[test]
+    fn scan_in_batches() {
+        let mut sut = SortedSet::new();
+        for i in 0..25 {
+            sut.insert(i.to_string(), i as f64);
+        }
+
+        let mut seen = Vec::new();
+        let mut cursor = 0;
+        loop {
+            let (next, batch) = sut.scan(cursor, 10);
+            assert!(batch.len() <= 10);
+            seen.extend(batch.into_iter().map(|(member, _)| member.to_owned()));
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+
+        seen.sort_by_key(|member| member.parse::<u32>().unwrap());
+        let expected: Vec<_> = (0..25).map(|i| i.to_string()).collect();
+        assert_eq!(seen, expected);
+    }
+}
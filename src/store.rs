@@ -1,22 +1,41 @@
 use std::path::{Path, PathBuf};
 use std::time::UNIX_EPOCH;
-use std::{collections::HashMap, sync::Arc, time::SystemTime};
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
 
 use tokio::fs;
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, MutexGuard, OwnedMutexGuard};
 
+use crate::clock::{Clock, SystemClock};
 use crate::error::{Error, WithContext};
-use crate::stream::{InsertListener, ItemData, ItemId, ProvidedItemId, Stream};
+use crate::sorted_set::SortedSet;
+use crate::stream::{ItemData, ItemId, ProvidedItemId, Stream, Trim};
 use crate::{rdb, Result};
+pub use keyspace::{ExpiryStats, KeyListener, Keyspace};
 use master_connection::MasterConnection;
+pub use notify::{EventFlags, Notifier};
+pub use pubsub::{Message, PubSub, Subscriber};
+pub use tracking::{Tracking, TrackingOptions};
 
+mod keyspace;
 mod master_connection;
+mod notify;
+mod pubsub;
+mod tracking;
 
 #[derive(Debug, Clone)]
 pub enum Value {
-    String(String),
+    String(Vec<u8>),
     Stream(Stream),
+    SortedSet(SortedSet),
 }
 
 impl Value {
@@ -24,6 +43,21 @@ impl Value {
         match self {
             Value::String(_) => "string",
             Value::Stream(_) => "stream",
+            Value::SortedSet(_) => "zset",
+        }
+    }
+
+    pub fn as_string(&self) -> Option<&[u8]> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_string_mut(&mut self) -> Option<&mut Vec<u8>> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
         }
     }
 
@@ -40,12 +74,125 @@ impl Value {
             _ => None,
         }
     }
+
+    pub fn as_sorted_set(&self) -> Option<&SortedSet> {
+        match self {
+            Value::SortedSet(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_sorted_set_mut(&mut self) -> Option<&mut SortedSet> {
+        match self {
+            Value::SortedSet(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl Value {
+    fn from_rdb(value: &rdb::OwnedValue) -> Self {
+        match value {
+            rdb::OwnedValue::String(s) => Value::String(s.clone()),
+            rdb::OwnedValue::Integer(v) => Value::String(v.to_string().into_bytes()),
+            rdb::OwnedValue::SortedSet(members) => {
+                let mut set = SortedSet::new();
+                for (member, score) in members {
+                    set.insert(String::from_utf8_lossy(member).into_owned(), *score);
+                }
+                Value::SortedSet(set)
+            }
+            rdb::OwnedValue::Stream(stream) => Value::Stream(Stream::from_rdb(stream)),
+        }
+    }
+}
+
+/// Encodes the databases of a snapshot, leaving out empty ones.
+fn encode_rdb(dbs: &[Snapshot]) -> Vec<u8> {
+    let mut encoder = rdb::Encoder::new();
+    for (index, entries) in dbs.iter().enumerate() {
+        if entries.is_empty() {
+            continue;
+        }
+
+        let expiring = entries
+            .iter()
+            .filter(|(_, _, expires_at)| expires_at.is_some());
+        encoder.select_db(index, entries.len(), expiring.count());
+        for (key, value, expires_at) in entries {
+            match value {
+                Value::String(value) => encoder.add_string(key, value, *expires_at),
+                Value::SortedSet(set) => encoder.add_sorted_set(key, set.iter(), *expires_at),
+                Value::Stream(stream) => encoder.add_stream(key, &stream.to_rdb(), *expires_at),
+            }
+        }
+    }
+    encoder.finish()
+}
+
+/// Access to the databases, through either the shared lock or the one a transaction holds.
+enum DatabasesGuard<'a> {
+    Shared(MutexGuard<'a, Vec<Keyspace>>),
+    Held(MutexGuard<'a, OwnedMutexGuard<Vec<Keyspace>>>),
+}
+
+impl Deref for DatabasesGuard<'_> {
+    type Target = Vec<Keyspace>;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Shared(guard) => guard,
+            Self::Held(guard) => guard,
+        }
+    }
+}
+
+impl DerefMut for DatabasesGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            Self::Shared(guard) => guard,
+            Self::Held(guard) => guard,
+        }
+    }
+}
+
+/// Access to the selected database.
+struct KeyspaceGuard<'a> {
+    dbs: DatabasesGuard<'a>,
+    db: usize,
+}
+
+impl Deref for KeyspaceGuard<'_> {
+    type Target = Keyspace;
+
+    fn deref(&self) -> &Self::Target {
+        &self.dbs[self.db]
+    }
+}
+
+impl DerefMut for KeyspaceGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.dbs[self.db]
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct DataValue {
     value: Value,
     expires_at: Option<SystemTime>,
+    /// Changes whenever the value is written, which is how WATCH notices modifications.
+    version: u64,
+}
+
+impl DataValue {
+    /// A value that gets its version when stored in a keyspace.
+    fn new(value: Value, expires_at: Option<SystemTime>) -> Self {
+        Self {
+            value,
+            expires_at,
+            version: 0,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -115,20 +262,178 @@ impl Info {
     }
 }
 
+/// Number of databases unless configured otherwise with `databases`.
+const DEFAULT_DATABASES: usize = 16;
+
+/// Values of all keys of one database, with their expiration times, copied for saving.
+type Snapshot = Vec<(String, Value, Option<SystemTime>)>;
+
+/// Handle to the shared databases. Every client has its own handle, which remembers the
+/// database the client selected.
 #[derive(Debug, Clone)]
 pub struct DataStore {
-    data: Arc<Mutex<HashMap<String, DataValue>>>,
+    data: Arc<Mutex<Vec<Keyspace>>>,
+    /// The lock on `data` while this handle runs a transaction, which all accesses go through.
+    held: Option<Arc<Mutex<OwnedMutexGuard<Vec<Keyspace>>>>>,
+    db: usize,
+    /// Publishes keyspace events and holds the Pub/Sub subscriptions.
+    notifier: Notifier,
+    tracking: Tracking,
+    /// ID of the client using this handle, which writes are attributed to for tracking.
+    client: Option<u64>,
     config: Arc<HashMap<String, String>>,
     info: Arc<Mutex<Info>>,
+    clock: Arc<dyn Clock>,
+    active_expire: Arc<AtomicBool>,
 }
 
 impl DataStore {
     pub fn new(config: HashMap<String, String>, role: Role) -> Self {
+        Self::with_clock(config, role, Arc::new(SystemClock::new()))
+    }
+
+    pub fn with_clock(config: HashMap<String, String>, role: Role, clock: Arc<dyn Clock>) -> Self {
+        let databases = config
+            .get("databases")
+            .and_then(|databases| databases.parse::<usize>().ok())
+            .filter(|databases| *databases > 0)
+            .unwrap_or(DEFAULT_DATABASES);
+        let events = config
+            .get("notify-keyspace-events")
+            .map_or("", String::as_str);
+        let events = EventFlags::parse(events).unwrap_or_else(|| {
+            eprintln!("Invalid notify-keyspace-events '{events}', keyspace events are disabled");
+            EventFlags::NONE
+        });
+        let notifier = Notifier::new(events);
+        let max_keys = config
+            .get("tracking-table-max-keys")
+            .and_then(|max_keys| max_keys.parse::<usize>().ok())
+            .unwrap_or(tracking::DEFAULT_MAX_KEYS);
+        let tracking = Tracking::new(max_keys);
+        let data = (0..databases)
+            .map(|db| {
+                Keyspace::new(clock.clone())
+                    .with_notifier(db, notifier.clone())
+                    .with_tracking(tracking.clone())
+            })
+            .collect();
+
         Self {
-            data: Arc::new(Mutex::new(HashMap::new())),
+            data: Arc::new(Mutex::new(data)),
+            held: None,
+            db: 0,
+            notifier,
+            tracking,
+            client: None,
             config: Arc::new(config),
             info: Arc::new(Mutex::new(Info::new(role))),
+            clock,
+            active_expire: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// Current time according to the clock used for expiry and stream IDs.
+    pub fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    /// Index of the database this handle works on.
+    pub fn db(&self) -> usize {
+        self.db
+    }
+
+    pub async fn databases(&self) -> usize {
+        self.lock().await.len()
+    }
+
+    /// Switches this handle to another database, like SELECT.
+    pub async fn select(&mut self, db: usize) -> Result<()> {
+        if db >= self.databases().await {
+            return Err(Error::InvalidArgument("DB index is out of range"));
         }
+        self.db = db;
+        Ok(())
+    }
+
+    /// Keeps the databases locked until [`DataStore::release_lock`], so that the commands of a
+    /// transaction run without commands of other clients in between. Other handles wait for
+    /// the lock as usual.
+    pub async fn hold_lock(&mut self) {
+        if self.held.is_none() {
+            let guard = self.data.clone().lock_owned().await;
+            self.held = Some(Arc::new(Mutex::new(guard)));
+        }
+    }
+
+    pub fn release_lock(&mut self) {
+        self.held = None;
+    }
+
+    async fn lock(&self) -> DatabasesGuard<'_> {
+        let mut dbs = match &self.held {
+            Some(held) => DatabasesGuard::Held(held.lock().await),
+            None => DatabasesGuard::Shared(self.data.lock().await),
+        };
+        for keyspace in dbs.iter_mut() {
+            keyspace.set_writer(self.client);
+        }
+        dbs
+    }
+
+    async fn keyspace(&self) -> KeyspaceGuard<'_> {
+        KeyspaceGuard {
+            dbs: self.lock().await,
+            db: self.db,
+        }
+    }
+
+    /// Pauses or resumes the background expiry of keys, keys are still expired lazily.
+    pub fn set_active_expire(&self, enabled: bool) {
+        self.active_expire.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Starts the background task removing expired keys, running `hz` times per second.
+    pub fn spawn_active_expiry(&self) {
+        let hz = self
+            .get_config("hz")
+            .and_then(|hz| hz.parse::<u64>().ok())
+            .filter(|hz| (1..=500).contains(hz))
+            .unwrap_or(10);
+        let period = Duration::from_millis(1000 / hz);
+        // Like in Redis, a cycle may take up to 25% of the time between two of them
+        let time_limit = period / 4;
+
+        let data = self.data.clone();
+        let enabled = self.active_expire.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            // Databases share the time limit, so each cycle starts where the last one stopped
+            let mut next_db = 0;
+            loop {
+                interval.tick().await;
+                if !enabled.load(Ordering::Relaxed) {
+                    continue;
+                }
+
+                let start = Instant::now();
+                let mut dbs = data.lock().await;
+                for keyspace in dbs.iter_mut() {
+                    keyspace.set_writer(None);
+                }
+                for _ in 0..dbs.len() {
+                    let Some(remaining) = time_limit.checked_sub(start.elapsed()) else {
+                        break;
+                    };
+                    dbs[next_db].active_expire_cycle(remaining);
+                    next_db = (next_db + 1) % dbs.len();
+                }
+            }
+        });
     }
 
     pub async fn info(&self) -> Info {
@@ -169,41 +474,24 @@ impl DataStore {
 
                 let parsed = rdb::Database::parse(&data)?;
 
-                let mut data = self.data.lock().await;
-                for (key, value) in parsed.keys() {
-                    let value = match value {
-                        rdb::OwnedValue::String(s) => s.clone(),
-                        rdb::OwnedValue::Integer(v) => v.to_string(),
-                    };
-
-                    data.insert(
-                        key.clone(),
-                        DataValue {
-                            value: Value::String(value),
-                            expires_at: None,
-                        },
-                    );
-                }
-                let now = SystemTime::now();
-                for (key, (value, expires_at)) in parsed.expiring() {
-                    let expires_at = expires_at.clone();
-
-                    if expires_at < now {
+                let now = self.now();
+                let mut dbs = self.data.lock().await;
+                let databases = dbs.len();
+                for (index, db) in parsed.dbs() {
+                    let Some(keyspace) = dbs.get_mut(*index) else {
+                        eprintln!("Not loading database {index}, only {databases} are configured");
                         continue;
-                    }
-
-                    let value = match value {
-                        rdb::OwnedValue::String(s) => s.clone(),
-                        rdb::OwnedValue::Integer(v) => v.to_string(),
                     };
 
-                    data.insert(
-                        key.clone(),
-                        DataValue {
-                            value: Value::String(value),
-                            expires_at: Some(expires_at),
-                        },
-                    );
+                    for (key, value) in db.keys() {
+                        keyspace.insert(key.clone(), Value::from_rdb(value), None);
+                    }
+                    for (key, (value, expires_at)) in db.expiring() {
+                        if *expires_at < now {
+                            continue;
+                        }
+                        keyspace.insert(key.clone(), Value::from_rdb(value), Some(*expires_at));
+                    }
                 }
             }
             (Some(_), None) => eprintln!("Not loading database, `dbfilename` not provided"),
@@ -217,86 +505,160 @@ impl DataStore {
         Ok(fs::read(path).await?)
     }
 
+    /// Path of the RDB file, from `dir` and `dbfilename` with the same defaults as Redis.
+    fn rdb_path(&self) -> PathBuf {
+        let dir = self.get_config("dir").unwrap_or(".");
+        let file = self.get_config("dbfilename").unwrap_or("dump.rdb");
+        Path::new(dir).join(file)
+    }
+
+    /// Copies every database, so that saving doesn't need to hold the lock.
+    async fn snapshot(&self) -> Vec<Snapshot> {
+        self.lock()
+            .await
+            .iter()
+            .map(|keyspace| {
+                keyspace
+                    .entries()
+                    .map(|(key, value, expires_at)| (key.clone(), value.clone(), expires_at))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Writes all databases to the RDB file, like SAVE.
+    pub async fn save(&self) -> Result<()> {
+        let data = encode_rdb(&self.snapshot().await);
+        Ok(fs::write(self.rdb_path(), data).await?)
+    }
+
+    /// Writes all databases to the RDB file on a background task, like BGSAVE. Only copying
+    /// the values happens before this returns.
+    pub async fn background_save(&self) {
+        let snapshot = self.snapshot().await;
+        let path = self.rdb_path();
+        tokio::spawn(async move {
+            let data = tokio::task::spawn_blocking(move || encode_rdb(&snapshot)).await;
+            let result = match data {
+                Ok(data) => fs::write(&path, data).await,
+                Err(err) => Err(err.into()),
+            };
+            match result {
+                Ok(()) => eprintln!("Background saving to {path:?} finished"),
+                Err(err) => eprintln!("Background saving to {path:?} failed: {err}"),
+            }
+        });
+    }
+
     pub async fn set(
         &self,
         key: String,
         value: Value,
         expires_at: Option<SystemTime>,
     ) -> Option<Value> {
-        let now = SystemTime::now();
-
-        self.data
-            .clone()
-            .lock_owned()
-            .await
-            .insert(key, DataValue { value, expires_at })
-            .and_then(|v| match v.expires_at {
-                Some(expires_at) if expires_at <= now => None,
-                _ => Some(v.value),
-            })
+        self.keyspace().await.insert(key, value, expires_at)
     }
 
     pub async fn get_ref<T>(&self, key: &str, op: impl FnOnce(&Value) -> T) -> Option<T> {
-        let now = SystemTime::now();
-
-        self.data
-            .lock()
-            .await
-            .get(key)
-            .and_then(|v| match v.expires_at {
-                Some(expires_at) if expires_at <= now => None,
-                _ => Some(op(&v.value)),
-            })
+        self.keyspace().await.get(key).map(op)
     }
 
     pub async fn get(&self, key: &str) -> Option<Value> {
         self.get_ref(key, |v| v.clone()).await
     }
 
-    pub async fn insert_stream_item(
+    /// See [`Keyspace::update`].
+    pub async fn update<T, E>(
         &self,
-        key: String,
-        id: ProvidedItemId,
-        data: ItemData,
-    ) -> Result<ItemId> {
-        Ok(self
-            .data
-            .lock()
-            .await
-            .entry(key.clone())
-            .or_insert_with(|| DataValue {
-                value: Value::Stream(Stream::new(key)),
-                expires_at: None,
-            })
-            .value
-            .as_stream_mut()
-            .ok_or(Error::ExpectedOtherType("stream"))?
-            .insert(id, data)?)
+        key: &str,
+        op: impl FnOnce(&mut Option<Value>) -> std::result::Result<T, E>,
+    ) -> std::result::Result<T, E> {
+        self.keyspace().await.update(key, op)
+    }
+
+    /// Runs `op` with exclusive access to the whole keyspace of the selected database, for
+    /// commands that touch several keys at once.
+    pub async fn with_keyspace<T>(&self, op: impl FnOnce(&mut Keyspace) -> T) -> T {
+        op(&mut *self.keyspace().await)
+    }
+
+    /// Runs `op` with exclusive access to all databases, for commands that move keys between
+    /// them.
+    pub async fn with_databases<T>(&self, op: impl FnOnce(&mut [Keyspace]) -> T) -> T {
+        op(&mut self.lock().await)
+    }
+
+    /// Runs `op` on the Pub/Sub subscriptions, which are shared by all databases.
+    pub fn with_pubsub<T>(&self, op: impl FnOnce(&mut PubSub) -> T) -> T {
+        self.notifier.with_pubsub(op)
     }
 
-    pub async fn notify_on_stream_insert(
+    /// Attributes the writes through this handle to client `id`.
+    pub fn set_client(&mut self, id: u64) {
+        self.client = Some(id);
+    }
+
+    /// The table of keys cached by clients, which is shared by all databases.
+    pub fn tracking(&self) -> &Tracking {
+        &self.tracking
+    }
+
+    /// Publishes a keyspace event for `key` in the selected database. Commands that run
+    /// inside [`DataStore::with_keyspace`] use [`Keyspace::notify`] instead.
+    pub fn notify(&self, class: EventFlags, event: &str, key: &str) {
+        self.notifier.notify(class, event, self.db, key);
+    }
+
+    /// The `notify-keyspace-events` flags, which CONFIG SET can change at runtime.
+    pub fn keyspace_events(&self) -> EventFlags {
+        self.notifier.flags()
+    }
+
+    pub fn set_keyspace_events(&self, flags: EventFlags) {
+        self.notifier.set_flags(flags);
+    }
+
+    /// Adds an item to the stream under `key` and trims it. The stream isn't created with
+    /// `nomkstream`, returning `None` if it doesn't exist.
+    pub async fn insert_stream_item(
         &self,
         key: String,
-        listener: InsertListener,
-    ) -> Result<()> {
-        self.data
-            .lock()
-            .await
-            .entry(key.clone())
-            .or_insert_with(|| DataValue {
-                value: Value::Stream(Stream::new(key)),
-                expires_at: None,
-            })
-            .value
-            .as_stream_mut()
-            .ok_or(Error::ExpectedOtherType("stream"))?
-            .notify_on_insert(listener);
+        id: ProvidedItemId,
+        data: ItemData,
+        trim: Option<Trim>,
+        nomkstream: bool,
+    ) -> Result<Option<ItemId>> {
+        let now = self.now();
+        let mut keyspace = self.keyspace().await;
+        if nomkstream && !keyspace.contains(&key) {
+            return Ok(None);
+        }
 
-        Ok(())
+        // A new stream is only stored once the item was accepted
+        let (id, trimmed) = keyspace.update(&key, |slot| -> Result<_> {
+            let mut created = None;
+            let stream = match slot {
+                Some(value) => value
+                    .as_stream_mut()
+                    .ok_or(Error::ExpectedOtherType("stream"))?,
+                None => created.insert(Stream::new()),
+            };
+            let id = stream.insert(id, data, now)?;
+            let trimmed = trim.map_or(0, |trim| stream.trim(trim));
+            if let Some(stream) = created {
+                *slot = Some(Value::Stream(stream));
+            }
+            Ok((id, trimmed))
+        })?;
+        keyspace.notify(EventFlags::STREAM, "xadd", &key);
+        if trimmed > 0 {
+            keyspace.notify(EventFlags::STREAM, "xtrim", &key);
+        }
+        Ok(Some(id))
     }
 
     pub async fn keys(&self) -> Vec<String> {
-        self.data.lock().await.keys().map(|k| k.clone()).collect()
+        self.keyspace().await.keys().cloned().collect()
     }
 
     pub fn get_config(&self, key: &str) -> Option<&str> {
@@ -0,0 +1,745 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::sync::mpsc;
+
+use super::{DataValue, EventFlags, Notifier, Tracking, Value};
+use crate::clock::{Clock, SystemClock};
+
+/// Notified with the name of a key after a write left a value under it.
+pub type KeyListener = mpsc::Sender<String>;
+
+/// Number of keys with a TTL checked in one round of the active expiry cycle.
+const EXPIRE_KEYS_PER_LOOP: usize = 20;
+/// The cycle goes on with another round while more than this percentage of sampled keys expired.
+const EXPIRE_ACCEPTABLE_STALE: usize = 10;
+
+/// Counters of expired keys, reported in the stats section of INFO.
+#[derive(Debug, Clone, Default)]
+pub struct ExpiryStats {
+    pub expired_keys: u64,
+    /// Moving average of the percentage of sampled keys that were found expired.
+    pub expired_stale_perc: f64,
+    pub expired_time_cap_reached_count: u64,
+    pub expire_cycle_cpu_milliseconds: u64,
+    expire_cycle_time: Duration,
+}
+
+impl ExpiryStats {
+    /// Adds up the stats of another database, reporting the highest stale percentage of both.
+    pub fn merge(&mut self, other: &ExpiryStats) {
+        self.expired_keys += other.expired_keys;
+        self.expired_stale_perc = self.expired_stale_perc.max(other.expired_stale_perc);
+        self.expired_time_cap_reached_count += other.expired_time_cap_reached_count;
+        self.expire_cycle_cpu_milliseconds += other.expire_cycle_cpu_milliseconds;
+        self.expire_cycle_time += other.expire_cycle_time;
+    }
+}
+
+/// Keys that have a TTL, stored so that they can be sampled at random.
+#[derive(Debug, Default)]
+struct VolatileKeys {
+    keys: Vec<String>,
+    positions: HashMap<String, usize>,
+}
+
+impl VolatileKeys {
+    fn insert(&mut self, key: &str) {
+        if !self.positions.contains_key(key) {
+            self.positions.insert(key.to_owned(), self.keys.len());
+            self.keys.push(key.to_owned());
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(position) = self.positions.remove(key) {
+            self.keys.swap_remove(position);
+            if let Some(moved) = self.keys.get(position) {
+                self.positions.insert(moved.clone(), position);
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+}
+
+/// The set of keys and their values, guarded as a whole by the `DataStore` lock.
+#[derive(Debug)]
+pub struct Keyspace {
+    entries: HashMap<String, DataValue>,
+    /// Every key ordered by its hash, which gives random keys and stable SCAN cursors.
+    ordered: BTreeSet<(u64, String)>,
+    listeners: HashMap<String, Vec<KeyListener>>,
+    volatile: VolatileKeys,
+    stats: ExpiryStats,
+    rng: u64,
+    clock: Arc<dyn Clock>,
+    /// Index of this database in keyspace events.
+    db: usize,
+    notifier: Notifier,
+    /// Keys read by clients that use client-side caching, invalidated on writes.
+    tracking: Tracking,
+    /// Client whose command currently runs on the keyspace, which NOLOOP tracking clients
+    /// don't hear their own writes from. `None` for background work like active expiry.
+    writer: Option<u64>,
+}
+
+impl Default for Keyspace {
+    fn default() -> Self {
+        Self::new(Arc::new(SystemClock::new()))
+    }
+}
+
+impl Keyspace {
+    /// Creates an empty keyspace that expires keys according to `clock`.
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+
+        Self {
+            entries: HashMap::new(),
+            ordered: BTreeSet::new(),
+            listeners: HashMap::new(),
+            volatile: VolatileKeys::default(),
+            stats: ExpiryStats::default(),
+            rng: seed | 1,
+            clock,
+            db: 0,
+            notifier: Notifier::default(),
+            tracking: Tracking::default(),
+            writer: None,
+        }
+    }
+
+    /// Publishes the keyspace events of this keyspace through `notifier`, as database `db`.
+    pub fn with_notifier(mut self, db: usize, notifier: Notifier) -> Self {
+        self.db = db;
+        self.notifier = notifier;
+        self
+    }
+
+    /// Invalidates keys cached by clients through `tracking` when they're written.
+    pub fn with_tracking(mut self, tracking: Tracking) -> Self {
+        self.tracking = tracking;
+        self
+    }
+
+    pub(super) fn set_writer(&mut self, writer: Option<u64>) {
+        self.writer = writer;
+    }
+
+    /// Publishes a keyspace event for `key`, if `notify-keyspace-events` selects its class.
+    pub fn notify(&self, class: EventFlags, event: &str, key: &str) {
+        self.notifier.notify(class, event, self.db, key);
+    }
+
+    /// Current time according to the clock used for expiry.
+    pub fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        let value = self.get_entry(key).map(|entry| &entry.value);
+        if value.is_none() {
+            self.notify(EventFlags::KEY_MISS, "keymiss", key);
+        }
+        value
+    }
+
+    /// Stores `value` under `key`, returning the previous value unless it had already expired.
+    pub fn insert(
+        &mut self,
+        key: String,
+        value: Value,
+        expires_at: Option<SystemTime>,
+    ) -> Option<Value> {
+        self.notify_ready(&key);
+        let previous = self.take_entry(&key);
+        if previous.is_none() {
+            self.notify(EventFlags::NEW, "new", &key);
+        }
+        self.put_entry(key, DataValue::new(value, expires_at));
+        previous.map(|v| v.value)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        self.take_entry(key).map(|v| v.value)
+    }
+
+    /// Runs `op` on the value stored under `key`, which sees `None` if the key is missing or
+    /// expired. Setting the slot to `None` deletes the key, setting it to `Some` stores the value
+    /// while keeping the expiration time of the previous one. An `op` that fails is assumed to have
+    /// left the value alone, so it doesn't count as a write.
+    pub fn update<T, E>(
+        &mut self,
+        key: &str,
+        op: impl FnOnce(&mut Option<Value>) -> Result<T, E>,
+    ) -> Result<T, E> {
+        if self.is_expired(key) {
+            self.take_entry(key);
+        }
+
+        // The key stays indexed while `op` runs, it's only forgotten if it ends up deleted
+        let (mut value, expires_at, version) = match self.entries.remove(key) {
+            Some(DataValue {
+                value,
+                expires_at,
+                version,
+            }) => (Some(value), expires_at, version),
+            None => (None, None, 0),
+        };
+        let existed = value.is_some();
+
+        let result = op(&mut value);
+        match value {
+            Some(value) if existed && result.is_err() => {
+                let mut entry = DataValue::new(value, expires_at);
+                entry.version = version;
+                self.entries.insert(key.to_owned(), entry);
+            }
+            Some(value) if existed => {
+                let mut entry = DataValue::new(value, expires_at);
+                entry.version = next_version();
+                self.entries.insert(key.to_owned(), entry);
+                self.invalidate(key);
+                self.notify_ready(key);
+            }
+            Some(_) if result.is_err() => {}
+            Some(value) => {
+                self.notify(EventFlags::NEW, "new", key);
+                self.put_entry(key.to_owned(), DataValue::new(value, expires_at));
+                self.notify_ready(key);
+            }
+            None if existed => self.forget(key),
+            None => {}
+        }
+
+        result
+    }
+
+    /// Returns the value stored under `key`, first replacing a missing or expired one with `init`.
+    /// The value counts as written, since the caller may modify it.
+    pub fn get_or_insert_with(&mut self, key: &str, init: impl FnOnce() -> Value) -> &mut Value {
+        if self.is_expired(key) {
+            self.take_entry(key);
+        }
+
+        if !self.entries.contains_key(key) {
+            self.notify(EventFlags::NEW, "new", key);
+            self.put_entry(key.to_owned(), DataValue::new(init(), None));
+        }
+        self.tracking.invalidate(key, self.writer);
+        self.notify_ready(key);
+        let entry = self
+            .entries
+            .get_mut(key)
+            .expect("The entry was just inserted");
+        entry.version = next_version();
+        &mut entry.value
+    }
+
+    /// Identifies the current value under `key`, `None` if there is none. Any write to the key
+    /// changes it, including deleting the key or storing a new value under it.
+    pub fn version(&self, key: &str) -> Option<u64> {
+        self.get_entry(key).map(|entry| entry.version)
+    }
+
+    /// Whether `key` exists. Unlike [`Keyspace::get`], this doesn't count as a key miss.
+    pub fn contains(&self, key: &str) -> bool {
+        self.get_entry(key).is_some()
+    }
+
+    /// Moves the value and expiration time of `from` to `to`, replacing what was stored there.
+    /// Returns `false` if there is no `from` key.
+    pub fn rename(&mut self, from: &str, to: &str) -> bool {
+        let Some(entry) = self.take_entry(from) else {
+            return false;
+        };
+
+        if self.take_entry(to).is_none() {
+            self.notify(EventFlags::NEW, "new", to);
+        }
+        self.put_entry(to.to_owned(), entry);
+        self.notify_ready(to);
+        true
+    }
+
+    /// The value under `key` together with its expiration time, unless it expired.
+    pub fn get_entry(&self, key: &str) -> Option<&DataValue> {
+        let now = self.now();
+        self.entries
+            .get(key)
+            .filter(|entry| entry.expires_at.is_none_or(|expires_at| expires_at > now))
+    }
+
+    /// Removes `key`, returning its value together with its expiration time unless it expired.
+    pub fn remove_entry(&mut self, key: &str) -> Option<DataValue> {
+        self.take_entry(key)
+    }
+
+    /// Stores `entry` under `key` including its expiration time, which is how values move
+    /// between keys and databases. Returns `false` without storing anything if `key` exists
+    /// and `replace` isn't set.
+    pub fn insert_entry(&mut self, key: String, entry: DataValue, replace: bool) -> bool {
+        if !replace && self.contains(&key) {
+            return false;
+        }
+
+        if self.take_entry(&key).is_none() {
+            self.notify(EventFlags::NEW, "new", &key);
+        }
+        self.notify_ready(&key);
+        self.put_entry(key, entry);
+        true
+    }
+
+    /// Values with their expiration times, skipping expired ones.
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &Value, Option<SystemTime>)> {
+        let now = self.now();
+        self.entries
+            .iter()
+            .filter(move |(_, entry)| entry.expires_at.is_none_or(|expires_at| expires_at > now))
+            .map(|(key, entry)| (key, &entry.value, entry.expires_at))
+    }
+
+    /// Removes every key, returning the values so that the caller decides where they are
+    /// dropped. Clients blocked on keys stay blocked.
+    pub fn clear(&mut self) -> HashMap<String, DataValue> {
+        self.ordered.clear();
+        self.volatile = VolatileKeys::default();
+        std::mem::take(&mut self.entries)
+    }
+
+    /// Exchanges the keys of two keyspaces, waking clients blocked on keys that now exist.
+    /// Blocked clients and the expiry stats stay with their keyspace.
+    pub fn swap_keys(&mut self, other: &mut Keyspace) {
+        std::mem::swap(&mut self.entries, &mut other.entries);
+        std::mem::swap(&mut self.ordered, &mut other.ordered);
+        std::mem::swap(&mut self.volatile, &mut other.volatile);
+
+        for keyspace in [self, other] {
+            let ready: Vec<_> = keyspace
+                .listeners
+                .keys()
+                .filter(|key| keyspace.contains(key))
+                .cloned()
+                .collect();
+            for key in ready {
+                keyspace.notify_ready(&key);
+            }
+        }
+    }
+
+    /// A random key that hasn't expired, removing expired keys found along the way.
+    pub fn random_key(&mut self) -> Option<String> {
+        loop {
+            let start = (self.next_random(), String::new());
+            let (_, key) = self
+                .ordered
+                .range(start..)
+                .next()
+                .or_else(|| self.ordered.first())?
+                .clone();
+
+            if !self.is_expired(&key) {
+                return Some(key);
+            }
+            self.take_entry(&key);
+        }
+    }
+
+    /// Expiration time of `key`, or `None` if the key doesn't exist.
+    pub fn expires_at(&self, key: &str) -> Option<Option<SystemTime>> {
+        if self.is_expired(key) {
+            return None;
+        }
+        self.entries.get(key).map(|v| v.expires_at)
+    }
+
+    /// Changes the expiration time of an existing key, deleting it right away if the time has
+    /// already passed. Returns `false` if there is no such key.
+    pub fn set_expires_at(&mut self, key: &str, expires_at: Option<SystemTime>) -> bool {
+        let Some(mut entry) = self.take_entry(key) else {
+            return false;
+        };
+
+        if expires_at.is_none_or(|expires_at| expires_at > self.now()) {
+            entry.expires_at = expires_at;
+            self.put_entry(key.to_owned(), entry);
+        }
+        true
+    }
+
+    /// All keys that haven't expired, in no particular order.
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        let now = self.now();
+        self.entries
+            .iter()
+            .filter(move |(_, entry)| entry.expires_at.is_none_or(|expires_at| expires_at > now))
+            .map(|(key, _)| key)
+    }
+
+    /// Visits up to `count` keys starting at `cursor`, returning the cursor to continue from
+    /// (0 once every key was visited) and the visited keys accepted by `filter`.
+    ///
+    /// Cursors are positions in the hash order of the keys, so a key that exists during the
+    /// whole iteration is returned at least once, no matter what is added or removed meanwhile.
+    pub fn scan(
+        &self,
+        cursor: u64,
+        count: usize,
+        mut filter: impl FnMut(&str, &Value) -> bool,
+    ) -> (u64, Vec<String>) {
+        let now = self.now();
+        let mut visited = self.ordered.range((cursor, String::new())..);
+
+        let mut keys = Vec::new();
+        for (_, key) in visited.by_ref().take(count) {
+            let entry = &self.entries[key];
+            let expired = entry.expires_at.is_some_and(|expires_at| expires_at <= now);
+            if !expired && filter(key, &entry.value) {
+                keys.push(key.clone());
+            }
+        }
+
+        (visited.next().map_or(0, |(hash, _)| *hash), keys)
+    }
+
+    /// Number of keys, not counting expired ones that were not removed yet.
+    pub fn len(&self) -> usize {
+        let now = self.now();
+        let expired = self
+            .volatile
+            .keys
+            .iter()
+            .filter(|key| {
+                self.entries[*key]
+                    .expires_at
+                    .is_some_and(|expires_at| expires_at <= now)
+            })
+            .count();
+        self.entries.len() - expired
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of keys with a TTL.
+    pub fn volatile_len(&self) -> usize {
+        self.volatile.len()
+    }
+
+    pub fn expiry_stats(&self) -> &ExpiryStats {
+        &self.stats
+    }
+
+    /// Removes expired keys by repeatedly sampling keys with a TTL, like Redis' active expiry
+    /// cycle. Each round checks a few random keys and another round follows while many of them
+    /// turned out expired, until `time_limit` runs out. Returns the number of removed keys.
+    pub fn active_expire_cycle(&mut self, time_limit: Duration) -> usize {
+        let start = Instant::now();
+        let (mut total_sampled, mut total_expired) = (0, 0);
+
+        for iteration in 1.. {
+            let samples = self.volatile.len().min(EXPIRE_KEYS_PER_LOOP);
+            if samples == 0 {
+                break;
+            }
+
+            let now = self.now();
+            let mut expired = 0;
+            for _ in 0..samples {
+                let index = (self.next_random() % self.volatile.len() as u64) as usize;
+                let key = self.volatile.keys[index].clone();
+                if self.entries[&key]
+                    .expires_at
+                    .is_some_and(|expires_at| expires_at <= now)
+                {
+                    self.take_entry(&key);
+                    expired += 1;
+                }
+            }
+            total_sampled += samples;
+            total_expired += expired;
+
+            // Checking the time is relatively expensive, so it's only done every 16 rounds
+            if iteration % 16 == 0 && start.elapsed() > time_limit {
+                self.stats.expired_time_cap_reached_count += 1;
+                break;
+            }
+            if expired * 100 <= samples * EXPIRE_ACCEPTABLE_STALE {
+                break;
+            }
+        }
+
+        let current_perc = if total_sampled > 0 {
+            total_expired as f64 / total_sampled as f64
+        } else {
+            0.0
+        };
+        self.stats.expired_stale_perc = current_perc * 0.05 + self.stats.expired_stale_perc * 0.95;
+        self.stats.expire_cycle_time += start.elapsed();
+        self.stats.expire_cycle_cpu_milliseconds = self.stats.expire_cycle_time.as_millis() as u64;
+
+        total_expired
+    }
+
+    /// Registers `listener` to be notified the next time a write leaves a value under `key`.
+    pub fn notify_on_ready(&mut self, key: &str, listener: KeyListener) {
+        let listeners = self.listeners.entry(key.to_owned()).or_default();
+        listeners.retain(|listener| !listener.is_closed());
+        listeners.push(listener);
+    }
+
+    /// Unregisters `listener` from `key`, once its client stopped waiting.
+    pub fn stop_notifying(&mut self, key: &str, listener: &KeyListener) {
+        if let Some(listeners) = self.listeners.get_mut(key) {
+            listeners.retain(|other| !other.same_channel(listener) && !other.is_closed());
+            if listeners.is_empty() {
+                self.listeners.remove(key);
+            }
+        }
+    }
+
+    fn notify_ready(&mut self, key: &str) {
+        if let Some(listeners) = self.listeners.remove(key) {
+            for listener in listeners {
+                let _ = listener.try_send(key.to_owned());
+            }
+        }
+    }
+
+    fn is_expired(&self, key: &str) -> bool {
+        matches!(
+            self.entries.get(key),
+            Some(DataValue { expires_at: Some(expires_at), .. }) if *expires_at <= self.now()
+        )
+    }
+
+    /// Removes the entry under `key`, returning it unless it had already expired.
+    fn take_entry(&mut self, key: &str) -> Option<DataValue> {
+        let entry = self.entries.remove(key)?;
+        self.forget(key);
+        if entry
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= self.now())
+        {
+            self.stats.expired_keys += 1;
+            self.notify(EventFlags::EXPIRED, "expired", key);
+            None
+        } else {
+            Some(entry)
+        }
+    }
+
+    /// Stores an entry under a key that isn't in the keyspace.
+    fn put_entry(&mut self, key: String, mut entry: DataValue) {
+        entry.version = next_version();
+        if entry.expires_at.is_some() {
+            self.volatile.insert(&key);
+        }
+        self.ordered.insert((key_hash(&key), key.clone()));
+        self.invalidate(&key);
+        self.entries.insert(key, entry);
+    }
+
+    /// Drops `key` from the indexes, after its entry was removed.
+    fn forget(&mut self, key: &str) {
+        self.volatile.remove(key);
+        self.ordered.remove(&(key_hash(key), key.to_owned()));
+        self.invalidate(key);
+    }
+
+    /// Tells clients caching `key` that its value changed.
+    fn invalidate(&self, key: &str) {
+        self.tracking.invalidate(key, self.writer);
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+}
+
+/// Versions are unique across all keyspaces, so moving values between databases changes them.
+fn next_version() -> u64 {
+    static NEXT_VERSION: AtomicU64 = AtomicU64::new(1);
+    NEXT_VERSION.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Hash of a key, which is never 0 since a cursor of 0 ends a scan.
+fn key_hash(key: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish().max(1)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashSet,
+        sync::Arc,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use super::{Keyspace, Value};
+    use crate::clock::ManualClock;
+
+    fn string(value: &str) -> Value {
+        Value::String(value.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn tracks_volatile_keys() {
+        let mut keyspace = Keyspace::default();
+        let later = SystemTime::now() + Duration::from_secs(60);
+
+        keyspace.insert("a".into(), string("1"), Some(later));
+        keyspace.insert("b".into(), string("2"), Some(later));
+        keyspace.insert("c".into(), string("3"), None);
+        assert_eq!(keyspace.volatile_len(), 2);
+
+        keyspace.insert("a".into(), string("1"), None);
+        keyspace.remove("b");
+        assert_eq!(keyspace.volatile_len(), 0);
+        assert_eq!(keyspace.len(), 2);
+    }
+
+    #[test]
+    fn active_expiry_removes_expired_keys() {
+        let mut keyspace = Keyspace::default();
+        let earlier = SystemTime::now() - Duration::from_secs(1);
+        let later = SystemTime::now() + Duration::from_secs(60);
+
+        for i in 0..1000 {
+            keyspace.insert(format!("expired:{i}"), string("x"), Some(earlier));
+        }
+        for i in 0..10 {
+            keyspace.insert(format!("alive:{i}"), string("x"), Some(later));
+        }
+
+        // Sampling stops once few expired keys are found, so a handful may survive a cycle
+        let removed = keyspace.active_expire_cycle(Duration::from_secs(10));
+        assert!(removed > 900);
+        assert_eq!(keyspace.entries.len(), 1010 - removed);
+        assert_eq!(keyspace.len(), 10);
+        assert_eq!(keyspace.expiry_stats().expired_keys, removed as u64);
+        assert!(keyspace.keys().filter(|k| k.starts_with("alive")).count() == 10);
+    }
+
+    #[test]
+    fn rename_copy_and_random_key() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let mut keyspace = Keyspace::new(clock.clone());
+        let expires_at = Some(UNIX_EPOCH + Duration::from_secs(10));
+
+        keyspace.insert("a".into(), string("1"), expires_at);
+        assert!(keyspace.rename("a", "b"));
+        assert!(!keyspace.contains("a"));
+        assert_eq!(keyspace.expires_at("b"), Some(expires_at));
+        assert!(!keyspace.rename("a", "c"));
+
+        keyspace.insert("c".into(), string("2"), None);
+        let entry = keyspace.get_entry("b").cloned().unwrap();
+        assert!(!keyspace.insert_entry("c".into(), entry.clone(), false));
+        assert!(keyspace.insert_entry("c".into(), entry, true));
+        assert_eq!(keyspace.expires_at("c"), Some(expires_at));
+        assert_eq!(keyspace.volatile_len(), 2);
+        assert!(matches!(keyspace.random_key().as_deref(), Some("b" | "c")));
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(keyspace.len(), 0);
+        assert_eq!(keyspace.random_key(), None);
+        assert_eq!(keyspace.keys().count(), 0);
+    }
+
+    #[test]
+    fn scan_visits_every_key() {
+        let mut keyspace = Keyspace::default();
+        for i in 0..100 {
+            keyspace.insert(format!("key:{i}"), string("x"), None);
+        }
+
+        let mut seen = HashSet::new();
+        let mut cursor = 0;
+        loop {
+            let (next, keys) = keyspace.scan(cursor, 7, |key, _| !key.ends_with('0'));
+            seen.extend(keys);
+            // Keys added or removed during the scan don't affect the others
+            keyspace.insert(format!("new:{cursor}"), string("x"), None);
+            keyspace.remove("key:10");
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+
+        let expected: HashSet<_> = (0..100)
+            .filter(|i| i % 10 != 0)
+            .map(|i| format!("key:{i}"))
+            .collect();
+        assert!(seen.is_superset(&expected));
+        assert!(seen.iter().all(|key| !key.ends_with('0')));
+    }
+
+    #[test]
+    fn versions_change_on_writes() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let mut keyspace = Keyspace::new(clock.clone());
+        assert_eq!(keyspace.version("a"), None);
+
+        keyspace.insert("a".into(), string("1"), None);
+        let inserted = keyspace.version("a");
+        assert!(inserted.is_some());
+        keyspace.get("a");
+        assert_eq!(keyspace.version("a"), inserted);
+
+        let failed: Result<(), ()> = keyspace.update("a", |_| Err(()));
+        assert!(failed.is_err());
+        assert_eq!(keyspace.version("a"), inserted);
+
+        keyspace.update("a", |_| Ok::<_, ()>(())).unwrap();
+        let updated = keyspace.version("a");
+        assert_ne!(updated, inserted);
+
+        keyspace.remove("a");
+        keyspace.insert("a".into(), string("1"), None);
+        assert_ne!(keyspace.version("a"), updated);
+
+        keyspace.set_expires_at("a", Some(UNIX_EPOCH + Duration::from_secs(1)));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(keyspace.version("a"), None);
+    }
+
+    #[test]
+    fn expires_according_to_clock() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let mut keyspace = Keyspace::new(clock.clone());
+
+        keyspace.insert(
+            "a".into(),
+            string("1"),
+            Some(UNIX_EPOCH + Duration::from_secs(10)),
+        );
+        assert!(keyspace.get("a").is_some());
+
+        clock.advance(Duration::from_secs(10));
+        assert!(keyspace.get("a").is_none());
+        assert_eq!(keyspace.expires_at("a"), None);
+        assert_eq!(keyspace.active_expire_cycle(Duration::from_secs(1)), 1);
+        assert!(keyspace.is_empty());
+    }
+}
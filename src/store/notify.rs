@@ -0,0 +1,224 @@
+use std::{
+    fmt::Display,
+    ops::BitOr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use super::PubSub;
+
+/// Classes of keyspace events, selected with the `notify-keyspace-events` flags.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EventFlags(u32);
+
+impl EventFlags {
+    pub const NONE: Self = Self(0);
+    /// `K`: publish to `__keyspace@<db>__:<key>` with the event as message.
+    pub const KEYSPACE: Self = Self(1 << 0);
+    /// `E`: publish to `__keyevent@<db>__:<event>` with the key as message.
+    pub const KEYEVENT: Self = Self(1 << 1);
+    /// `g`: generic commands like DEL, EXPIRE and RENAME.
+    pub const GENERIC: Self = Self(1 << 2);
+    pub const STRING: Self = Self(1 << 3);
+    pub const LIST: Self = Self(1 << 4);
+    pub const SET: Self = Self(1 << 5);
+    pub const HASH: Self = Self(1 << 6);
+    pub const ZSET: Self = Self(1 << 7);
+    /// `x`: keys removed because their TTL passed.
+    pub const EXPIRED: Self = Self(1 << 8);
+    /// `e`: keys removed to free memory.
+    pub const EVICTED: Self = Self(1 << 9);
+    pub const STREAM: Self = Self(1 << 10);
+    /// `m`: reads of missing keys, which `A` leaves out.
+    pub const KEY_MISS: Self = Self(1 << 11);
+    /// `n`: keys being created, which `A` leaves out.
+    pub const NEW: Self = Self(1 << 12);
+
+    /// Every event class `A` stands for.
+    const ALL: Self = Self(
+        Self::GENERIC.0
+            | Self::STRING.0
+            | Self::LIST.0
+            | Self::SET.0
+            | Self::HASH.0
+            | Self::ZSET.0
+            | Self::EXPIRED.0
+            | Self::EVICTED.0
+            | Self::STREAM.0,
+    );
+
+    /// Flag characters of the event classes, in the order they're printed.
+    const CLASSES: [(char, Self); 9] = [
+        ('g', Self::GENERIC),
+        ('$', Self::STRING),
+        ('l', Self::LIST),
+        ('s', Self::SET),
+        ('h', Self::HASH),
+        ('z', Self::ZSET),
+        ('x', Self::EXPIRED),
+        ('e', Self::EVICTED),
+        ('t', Self::STREAM),
+    ];
+    const OTHERS: [(char, Self); 4] = [
+        ('K', Self::KEYSPACE),
+        ('E', Self::KEYEVENT),
+        ('m', Self::KEY_MISS),
+        ('n', Self::NEW),
+    ];
+
+    /// Parses a flag string like `KEA` or `Kx`, `None` if it contains an unknown character.
+    pub fn parse(flags: &str) -> Option<Self> {
+        flags.chars().try_fold(Self::NONE, |parsed, flag| {
+            let class = match flag {
+                'A' => Self::ALL,
+                _ => {
+                    Self::CLASSES
+                        .iter()
+                        .chain(&Self::OTHERS)
+                        .find(|(c, _)| *c == flag)?
+                        .1
+                }
+            };
+            Some(parsed | class)
+        })
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl BitOr for EventFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl Display for EventFlags {
+    /// Prints the flags like CONFIG GET does, using `A` when all event classes are selected.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.contains(Self::ALL) {
+            write!(f, "A")?;
+        } else {
+            for (flag, class) in Self::CLASSES {
+                if self.contains(class) {
+                    write!(f, "{flag}")?;
+                }
+            }
+        }
+        for (flag, class) in Self::OTHERS {
+            if self.contains(class) {
+                write!(f, "{flag}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Publishes keyspace events to the Pub/Sub channels, according to the configured flags. Clones
+/// share the subscriptions and the flags.
+#[derive(Debug, Clone, Default)]
+pub struct Notifier {
+    pubsub: Arc<Mutex<PubSub>>,
+    flags: Arc<AtomicU32>,
+}
+
+impl Notifier {
+    pub fn new(flags: EventFlags) -> Self {
+        Self {
+            pubsub: Arc::default(),
+            flags: Arc::new(AtomicU32::new(flags.0)),
+        }
+    }
+
+    pub fn flags(&self) -> EventFlags {
+        EventFlags(self.flags.load(Ordering::Relaxed))
+    }
+
+    pub fn set_flags(&self, flags: EventFlags) {
+        self.flags.store(flags.0, Ordering::Relaxed);
+    }
+
+    pub fn with_pubsub<T>(&self, op: impl FnOnce(&mut PubSub) -> T) -> T {
+        op(&mut self.pubsub.lock().expect("Pub/Sub lock poisoned"))
+    }
+
+    /// Publishes `event` of `class` that happened to `key` in database `db`, if the flags
+    /// select the class and at least one of the keyspace and keyevent channels.
+    pub fn notify(&self, class: EventFlags, event: &str, db: usize, key: &str) {
+        let flags = self.flags();
+        if !flags.intersects(class) {
+            return;
+        }
+
+        self.with_pubsub(|pubsub| {
+            if flags.contains(EventFlags::KEYSPACE) {
+                pubsub.publish(&format!("__keyspace@{db}__:{key}"), event);
+            }
+            if flags.contains(EventFlags::KEYEVENT) {
+                pubsub.publish(&format!("__keyevent@{db}__:{event}"), key);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::sync::mpsc;
+
+    use super::{EventFlags, Notifier};
+    use crate::store::Message;
+
+    #[test]
+    fn parses_and_prints_flags() {
+        let flags = EventFlags::parse("KEA").unwrap();
+        assert!(flags.contains(EventFlags::KEYSPACE | EventFlags::EXPIRED));
+        assert!(!flags.contains(EventFlags::KEY_MISS));
+        assert_eq!(flags.to_string(), "AKE");
+
+        assert_eq!(EventFlags::parse("xEg").unwrap().to_string(), "gxE");
+        assert_eq!(EventFlags::parse("").unwrap(), EventFlags::NONE);
+        assert_eq!(
+            EventFlags::parse("g$lshzxetKEmn").unwrap().to_string(),
+            "AKEmn"
+        );
+        assert!(EventFlags::parse("Kq").is_none());
+    }
+
+    #[test]
+    fn publishes_selected_events() {
+        let notifier = Notifier::new(EventFlags::parse("Ex").unwrap());
+        let (subscriber, mut rx) = mpsc::unbounded_channel();
+        notifier.with_pubsub(|pubsub| {
+            pubsub.psubscribe("__key*__:*", 1, &subscriber);
+        });
+
+        notifier.notify(EventFlags::GENERIC, "del", 0, "ignored");
+        notifier.notify(EventFlags::EXPIRED, "expired", 3, "session");
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            Message::Pattern {
+                pattern: "__key*__:*".into(),
+                channel: "__keyevent@3__:expired".into(),
+                payload: "session".into(),
+            }
+        );
+        assert!(rx.try_recv().is_err());
+
+        notifier.set_flags(EventFlags::parse("KA").unwrap());
+        notifier.notify(EventFlags::GENERIC, "del", 0, "cache");
+        assert!(matches!(
+            rx.try_recv().unwrap(),
+            Message::Pattern { channel, payload, .. }
+                if channel == "__keyspace@0__:cache" && payload == "del"
+        ));
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+
+use tokio::sync::mpsc;
+
+use crate::glob;
+
+/// Delivers published messages to a subscribed client.
+pub type Subscriber = mpsc::UnboundedSender<Message>;
+
+/// A message published to a channel, as delivered to one subscriber, or a client-side caching
+/// invalidation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// Received through a subscription to the channel itself.
+    Channel { channel: String, payload: String },
+    /// Received through a subscription to a pattern matching the channel.
+    Pattern {
+        pattern: String,
+        channel: String,
+        payload: String,
+    },
+    /// Received through a subscription to a shard channel.
+    Shard { channel: String, payload: String },
+    /// Keys read by a tracking client were modified, `None` meaning all keys like after
+    /// FLUSHALL.
+    Invalidate(Option<Vec<String>>),
+    /// The client invalidations of this tracking client were redirected to disconnected.
+    TrackingRedirectBroken(u64),
+}
+
+/// Subscriptions of all clients to channels and channel patterns, by client ID.
+///
+/// Shard channels are a separate namespace: messages published with SPUBLISH only reach
+/// SSUBSCRIBE subscribers, and patterns never match shard channels.
+#[derive(Debug, Default)]
+pub struct PubSub {
+    channels: HashMap<String, HashMap<u64, Subscriber>>,
+    patterns: HashMap<String, HashMap<u64, Subscriber>>,
+    shard_channels: HashMap<String, HashMap<u64, Subscriber>>,
+}
+
+impl PubSub {
+    /// Subscribes a client to `channel`, returning `false` if it already was.
+    pub fn subscribe(&mut self, channel: &str, client: u64, subscriber: &Subscriber) -> bool {
+        add(&mut self.channels, channel, client, subscriber)
+    }
+
+    /// Unsubscribes a client from `channel`, returning `false` if it wasn't subscribed.
+    pub fn unsubscribe(&mut self, channel: &str, client: u64) -> bool {
+        remove(&mut self.channels, channel, client)
+    }
+
+    /// Subscribes a client to all channels matching the glob-style `pattern`.
+    pub fn psubscribe(&mut self, pattern: &str, client: u64, subscriber: &Subscriber) -> bool {
+        add(&mut self.patterns, pattern, client, subscriber)
+    }
+
+    pub fn punsubscribe(&mut self, pattern: &str, client: u64) -> bool {
+        remove(&mut self.patterns, pattern, client)
+    }
+
+    pub fn ssubscribe(&mut self, channel: &str, client: u64, subscriber: &Subscriber) -> bool {
+        add(&mut self.shard_channels, channel, client, subscriber)
+    }
+
+    pub fn sunsubscribe(&mut self, channel: &str, client: u64) -> bool {
+        remove(&mut self.shard_channels, channel, client)
+    }
+
+    /// Sends `payload` to the subscribers of `channel` and of the patterns matching it,
+    /// returning how many received it. A client subscribed in several ways receives it once
+    /// for each subscription.
+    pub fn publish(&mut self, channel: &str, payload: &str) -> usize {
+        let mut received = 0;
+
+        if let Some(subscribers) = self.channels.get_mut(channel) {
+            received += deliver(subscribers, || Message::Channel {
+                channel: channel.to_owned(),
+                payload: payload.to_owned(),
+            });
+            if subscribers.is_empty() {
+                self.channels.remove(channel);
+            }
+        }
+
+        self.patterns.retain(|pattern, subscribers| {
+            if glob::matches(pattern.as_bytes(), channel.as_bytes()) {
+                received += deliver(subscribers, || Message::Pattern {
+                    pattern: pattern.clone(),
+                    channel: channel.to_owned(),
+                    payload: payload.to_owned(),
+                });
+            }
+            !subscribers.is_empty()
+        });
+
+        received
+    }
+
+    /// Sends `payload` to the subscribers of the shard channel `channel`.
+    pub fn spublish(&mut self, channel: &str, payload: &str) -> usize {
+        let Some(subscribers) = self.shard_channels.get_mut(channel) else {
+            return 0;
+        };
+
+        let received = deliver(subscribers, || Message::Shard {
+            channel: channel.to_owned(),
+            payload: payload.to_owned(),
+        });
+        if subscribers.is_empty() {
+            self.shard_channels.remove(channel);
+        }
+        received
+    }
+
+    /// Channels with at least one subscriber, optionally only those matching `pattern`.
+    pub fn channels(&self, pattern: Option<&str>) -> Vec<String> {
+        matching(&self.channels, pattern)
+    }
+
+    /// Number of clients subscribed to `channel`, not counting pattern subscriptions.
+    pub fn subscribers(&self, channel: &str) -> usize {
+        self.channels.get(channel).map_or(0, HashMap::len)
+    }
+
+    /// Shard channels with at least one subscriber, optionally only those matching `pattern`.
+    pub fn shard_channels(&self, pattern: Option<&str>) -> Vec<String> {
+        matching(&self.shard_channels, pattern)
+    }
+
+    pub fn shard_subscribers(&self, channel: &str) -> usize {
+        self.shard_channels.get(channel).map_or(0, HashMap::len)
+    }
+
+    /// Number of distinct patterns with at least one subscriber.
+    pub fn patterns(&self) -> usize {
+        self.patterns.len()
+    }
+}
+
+fn add(
+    subscriptions: &mut HashMap<String, HashMap<u64, Subscriber>>,
+    name: &str,
+    client: u64,
+    subscriber: &Subscriber,
+) -> bool {
+    subscriptions
+        .entry(name.to_owned())
+        .or_default()
+        .insert(client, subscriber.clone())
+        .is_none()
+}
+
+fn remove(
+    subscriptions: &mut HashMap<String, HashMap<u64, Subscriber>>,
+    name: &str,
+    client: u64,
+) -> bool {
+    let Some(subscribers) = subscriptions.get_mut(name) else {
+        return false;
+    };
+
+    let removed = subscribers.remove(&client).is_some();
+    if subscribers.is_empty() {
+        subscriptions.remove(name);
+    }
+    removed
+}
+
+fn matching(
+    subscriptions: &HashMap<String, HashMap<u64, Subscriber>>,
+    pattern: Option<&str>,
+) -> Vec<String> {
+    subscriptions
+        .keys()
+        .filter(|name| {
+            pattern.is_none_or(|pattern| glob::matches(pattern.as_bytes(), name.as_bytes()))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Sends a message to each subscriber, dropping those that disconnected.
+fn deliver(subscribers: &mut HashMap<u64, Subscriber>, message: impl Fn() -> Message) -> usize {
+    subscribers.retain(|_, subscriber| subscriber.send(message()).is_ok());
+    subscribers.len()
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::sync::mpsc;
+
+    use super::{Message, PubSub};
+
+    #[test]
+    fn delivers_to_channels_and_patterns() {
+        let mut pubsub = PubSub::default();
+        let (first, mut first_rx) = mpsc::unbounded_channel();
+        let (second, mut second_rx) = mpsc::unbounded_channel();
+
+        assert!(pubsub.subscribe("news", 1, &first));
+        assert!(!pubsub.subscribe("news", 1, &first));
+        assert!(pubsub.psubscribe("n*", 1, &first));
+        assert!(pubsub.psubscribe("n*", 2, &second));
+
+        assert_eq!(pubsub.publish("news", "hello"), 3);
+        assert_eq!(pubsub.publish("other", "ignored"), 0);
+        assert_eq!(
+            first_rx.try_recv().unwrap(),
+            Message::Channel {
+                channel: "news".into(),
+                payload: "hello".into()
+            }
+        );
+        assert!(matches!(first_rx.try_recv(), Ok(Message::Pattern { .. })));
+        assert!(first_rx.try_recv().is_err());
+        assert_eq!(
+            second_rx.try_recv().unwrap(),
+            Message::Pattern {
+                pattern: "n*".into(),
+                channel: "news".into(),
+                payload: "hello".into()
+            }
+        );
+
+        assert_eq!(pubsub.channels(None), vec!["news"]);
+        assert!(pubsub.channels(Some("x*")).is_empty());
+        assert_eq!(pubsub.subscribers("news"), 1);
+        assert_eq!(pubsub.patterns(), 1);
+    }
+
+    #[test]
+    fn unsubscribes_and_drops_disconnected_clients() {
+        let mut pubsub = PubSub::default();
+        let (first, first_rx) = mpsc::unbounded_channel();
+        let (second, _second_rx) = mpsc::unbounded_channel();
+
+        pubsub.subscribe("news", 1, &first);
+        pubsub.subscribe("news", 2, &second);
+        assert!(pubsub.unsubscribe("news", 2));
+        assert!(!pubsub.unsubscribe("news", 2));
+
+        drop(first_rx);
+        assert_eq!(pubsub.publish("news", "hello"), 0);
+        assert!(pubsub.channels(None).is_empty());
+    }
+
+    #[test]
+    fn shard_channels_are_separate() {
+        let mut pubsub = PubSub::default();
+        let (subscriber, mut rx) = mpsc::unbounded_channel();
+
+        pubsub.ssubscribe("orders", 1, &subscriber);
+        pubsub.psubscribe("*", 2, &subscriber);
+        assert_eq!(pubsub.publish("orders", "plain"), 1);
+        assert!(matches!(rx.try_recv(), Ok(Message::Pattern { .. })));
+
+        assert_eq!(pubsub.spublish("orders", "sharded"), 1);
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            Message::Shard {
+                channel: "orders".into(),
+                payload: "sharded".into()
+            }
+        );
+        assert!(rx.try_recv().is_err());
+
+        assert!(pubsub.channels(None).is_empty());
+        assert_eq!(pubsub.shard_channels(Some("ord*")), vec!["orders"]);
+        assert_eq!(pubsub.shard_subscribers("orders"), 1);
+        assert!(pubsub.sunsubscribe("orders", 1));
+        assert_eq!(pubsub.spublish("orders", "dropped"), 0);
+    }
+}
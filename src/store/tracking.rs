@@ -0,0 +1,320 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use super::{Message, Subscriber};
+
+/// Number of keys remembered for client-side caching unless configured otherwise with
+/// `tracking-table-max-keys`.
+pub const DEFAULT_MAX_KEYS: usize = 1_000_000;
+
+/// How a client tracks keys, as set with CLIENT TRACKING.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackingOptions {
+    /// Client that receives the invalidation messages instead of the tracking client.
+    pub redirect: Option<u64>,
+    /// Invalidates every key matching one of `prefixes`, instead of only keys the client read.
+    pub bcast: bool,
+    pub prefixes: Vec<String>,
+    /// Only tracks keys read right after CLIENT CACHING YES.
+    pub optin: bool,
+    /// Tracks all keys except those read right after CLIENT CACHING NO.
+    pub optout: bool,
+    /// Skips invalidations for keys the client modified itself.
+    pub noloop: bool,
+}
+
+#[derive(Debug)]
+struct Table {
+    /// Every connected client, which tracking clients can redirect invalidations to.
+    clients: HashMap<u64, Subscriber>,
+    tracking: HashMap<u64, TrackingOptions>,
+    /// Clients that read each key, until the key is invalidated.
+    keys: HashMap<String, HashSet<u64>>,
+    /// Clients in BCAST mode by prefix, the empty prefix matching every key.
+    prefixes: HashMap<String, HashSet<u64>>,
+    max_keys: usize,
+}
+
+impl Table {
+    /// Sends an invalidation for `keys`, or for all keys if `None`, to the client tracking them
+    /// or the one it redirects to. A client whose redirect target disconnected is told so.
+    fn deliver(&self, client: u64, keys: Option<Vec<String>>) {
+        let Some(options) = self.tracking.get(&client) else {
+            return;
+        };
+
+        let target = options.redirect.unwrap_or(client);
+        let delivered = self
+            .clients
+            .get(&target)
+            .is_some_and(|subscriber| subscriber.send(Message::Invalidate(keys)).is_ok());
+        if let (false, Some(redirect)) = (delivered, options.redirect) {
+            if let Some(subscriber) = self.clients.get(&client) {
+                let _ = subscriber.send(Message::TrackingRedirectBroken(redirect));
+            }
+        }
+    }
+
+    /// Invalidates arbitrary keys until the table is within its bound again.
+    fn evict(&mut self) {
+        if self.max_keys == 0 {
+            return;
+        }
+
+        while self.keys.len() > self.max_keys {
+            let key = self
+                .keys
+                .keys()
+                .next()
+                .expect("The table isn't empty")
+                .clone();
+            let clients = self.keys.remove(&key).unwrap_or_default();
+            for client in clients {
+                self.deliver(client, Some(vec![key.clone()]));
+            }
+        }
+    }
+}
+
+/// Keys read by clients that use client-side caching, shared by all databases like in Redis.
+/// Clones share the table.
+#[derive(Debug, Clone)]
+pub struct Tracking {
+    table: Arc<Mutex<Table>>,
+    /// Whether any client tracks keys, so that writes only lock the table if needed.
+    active: Arc<AtomicBool>,
+}
+
+impl Default for Tracking {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_KEYS)
+    }
+}
+
+impl Tracking {
+    /// Creates an empty table remembering up to `max_keys` keys, 0 meaning no limit.
+    pub fn new(max_keys: usize) -> Self {
+        Self {
+            table: Arc::new(Mutex::new(Table {
+                clients: HashMap::new(),
+                tracking: HashMap::new(),
+                keys: HashMap::new(),
+                prefixes: HashMap::new(),
+                max_keys,
+            })),
+            active: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn table(&self) -> std::sync::MutexGuard<'_, Table> {
+        self.table.lock().expect("Tracking lock poisoned")
+    }
+
+    /// Registers a connected client, which invalidations can then be redirected to.
+    pub fn connect(&self, client: u64, subscriber: &Subscriber) {
+        self.table().clients.insert(client, subscriber.clone());
+    }
+
+    pub fn disconnect(&self, client: u64) {
+        self.disable(client);
+        self.table().clients.remove(&client);
+    }
+
+    /// Turns tracking on for `client`, replacing its previous options. Fails if the redirect
+    /// target isn't connected.
+    pub fn enable(&self, client: u64, options: TrackingOptions) -> bool {
+        let mut table = self.table();
+        if options
+            .redirect
+            .is_some_and(|redirect| !table.clients.contains_key(&redirect))
+        {
+            return false;
+        }
+
+        for prefixes in table.prefixes.values_mut() {
+            prefixes.remove(&client);
+        }
+        if options.bcast {
+            let prefixes = if options.prefixes.is_empty() {
+                vec![String::new()]
+            } else {
+                options.prefixes.clone()
+            };
+            for prefix in prefixes {
+                table.prefixes.entry(prefix).or_default().insert(client);
+            }
+        }
+        table.prefixes.retain(|_, clients| !clients.is_empty());
+
+        table.tracking.insert(client, options);
+        self.active.store(true, Ordering::Relaxed);
+        true
+    }
+
+    /// Turns tracking off for `client`. Keys it read are forgotten lazily.
+    pub fn disable(&self, client: u64) {
+        let mut table = self.table();
+        if table.tracking.remove(&client).is_none() {
+            return;
+        }
+
+        for prefixes in table.prefixes.values_mut() {
+            prefixes.remove(&client);
+        }
+        table.prefixes.retain(|_, clients| !clients.is_empty());
+        if table.tracking.is_empty() {
+            table.keys.clear();
+            self.active.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// The client invalidations of `client` go to: -1 if it doesn't track keys, 0 if it
+    /// receives them itself.
+    pub fn redirect(&self, client: u64) -> i64 {
+        match self.table().tracking.get(&client) {
+            None => -1,
+            Some(options) => options.redirect.map_or(0, |redirect| redirect as i64),
+        }
+    }
+
+    /// Remembers that `client` read `keys`, so that it's told once they're modified.
+    pub fn remember(&self, client: u64, keys: impl IntoIterator<Item = String>) {
+        let mut table = self.table();
+        if !table.tracking.contains_key(&client) {
+            return;
+        }
+
+        for key in keys {
+            table.keys.entry(key).or_default().insert(client);
+        }
+        table.evict();
+    }
+
+    /// Tells the clients that read `key`, or track a prefix of it, that it was modified by
+    /// `writer`, which is `None` for modifications like expiry.
+    pub fn invalidate(&self, key: &str, writer: Option<u64>) {
+        if !self.active.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut table = self.table();
+        let mut clients = table.keys.remove(key).unwrap_or_default();
+        for (prefix, subscribed) in &table.prefixes {
+            if key.starts_with(prefix.as_str()) {
+                clients.extend(subscribed);
+            }
+        }
+
+        for client in clients {
+            let noloop = table
+                .tracking
+                .get(&client)
+                .is_some_and(|options| options.noloop);
+            if !(noloop && writer == Some(client)) {
+                table.deliver(client, Some(vec![key.to_owned()]));
+            }
+        }
+    }
+
+    /// Tells every tracking client that all keys were modified, like after FLUSHALL.
+    pub fn invalidate_all(&self) {
+        if !self.active.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut table = self.table();
+        table.keys.clear();
+        for &client in table.tracking.keys() {
+            table.deliver(client, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::sync::mpsc;
+
+    use super::{Tracking, TrackingOptions};
+    use crate::store::Message;
+
+    fn invalidated(key: &str) -> Message {
+        Message::Invalidate(Some(vec![key.to_owned()]))
+    }
+
+    #[test]
+    fn invalidates_read_keys_once() {
+        let tracking = Tracking::default();
+        let (subscriber, mut rx) = mpsc::unbounded_channel();
+        tracking.connect(1, &subscriber);
+        assert!(tracking.enable(1, TrackingOptions::default()));
+
+        tracking.remember(1, ["a".to_owned(), "b".to_owned()]);
+        tracking.invalidate("a", None);
+        tracking.invalidate("a", None);
+        tracking.invalidate("c", None);
+        assert_eq!(rx.try_recv().unwrap(), invalidated("a"));
+        assert!(rx.try_recv().is_err());
+
+        tracking.invalidate_all();
+        assert_eq!(rx.try_recv().unwrap(), Message::Invalidate(None));
+        tracking.invalidate("b", None);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn broadcasts_redirects_and_skips_own_writes() {
+        let tracking = Tracking::default();
+        let (first, mut first_rx) = mpsc::unbounded_channel();
+        let (second, mut second_rx) = mpsc::unbounded_channel();
+        tracking.connect(1, &first);
+        tracking.connect(2, &second);
+
+        let options = TrackingOptions {
+            redirect: Some(2),
+            bcast: true,
+            prefixes: vec!["user:".into()],
+            noloop: true,
+            ..Default::default()
+        };
+        assert!(!tracking.enable(
+            1,
+            TrackingOptions {
+                redirect: Some(3),
+                ..options.clone()
+            }
+        ));
+        assert!(tracking.enable(1, options));
+        assert_eq!(tracking.redirect(1), 2);
+        assert_eq!(tracking.redirect(2), -1);
+
+        tracking.invalidate("user:1", Some(2));
+        tracking.invalidate("user:2", Some(1));
+        tracking.invalidate("order:1", Some(2));
+        assert_eq!(second_rx.try_recv().unwrap(), invalidated("user:1"));
+        assert!(second_rx.try_recv().is_err());
+
+        tracking.disconnect(2);
+        tracking.invalidate("user:3", None);
+        assert_eq!(
+            first_rx.try_recv().unwrap(),
+            Message::TrackingRedirectBroken(2)
+        );
+    }
+
+    #[test]
+    fn bounds_the_number_of_keys() {
+        let tracking = Tracking::new(2);
+        let (subscriber, mut rx) = mpsc::unbounded_channel();
+        tracking.connect(1, &subscriber);
+        tracking.enable(1, TrackingOptions::default());
+
+        tracking.remember(1, ["a", "b", "c"].map(String::from));
+        assert!(matches!(rx.try_recv(), Ok(Message::Invalidate(Some(_)))));
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -1,12 +1,18 @@
 use std::{
-    collections::{BTreeMap, HashMap},
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
     num::{ParseIntError, TryFromIntError},
     ops::Bound,
-    time::{SystemTime, SystemTimeError, UNIX_EPOCH},
+    time::{Duration, SystemTime, SystemTimeError, UNIX_EPOCH},
 };
 
 use thiserror::Error;
-use tokio::sync::mpsc;
+
+use crate::rdb;
+pub(crate) use items::is_valid_node;
+use items::Items;
+
+mod items;
 
 #[derive(Debug, Clone, Error, PartialEq)]
 #[non_exhaustive]
@@ -35,6 +41,17 @@ pub enum InsertionError {
     IdTooLow,
 }
 
+/// Why XSETID can't set the last ID of a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum SetIdError {
+    #[error("The ID specified in XSETID is smaller than the provided max_deleted_entry_id")]
+    BelowMaxDeletedId,
+    #[error("The entries_added specified in XSETID is smaller than the target stream length")]
+    BelowLength,
+    #[error("The ID specified in XSETID is smaller than the target stream top item")]
+    BelowLastItem,
+}
+
 impl PartialEq for InsertionError {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -69,7 +86,7 @@ impl TryFrom<&str> for ProvidedItemId {
             .next()
             .expect(".split() always returns at least one item");
         let counter = parts.next().ok_or(ItemIdParseError::MissingDash)?;
-        if !parts.next().is_none() {
+        if parts.next().is_some() {
             return Err(ItemIdParseError::TooManyDashes);
         }
 
@@ -95,7 +112,7 @@ impl From<ItemId> for ProvidedItemId {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
 pub struct ItemId(u64, u64);
 
 impl ItemId {
@@ -103,8 +120,13 @@ impl ItemId {
         Self(timestamp, sequence)
     }
 
-    fn next(&self) -> Self {
-        Self(self.0, self.1 + 1)
+    pub fn timestamp(&self) -> u64 {
+        self.0
+    }
+
+    /// The ID after this one with the same timestamp, `None` if the sequence number is maxed out.
+    fn next(&self) -> Option<Self> {
+        self.1.checked_add(1).map(|sequence| Self(self.0, sequence))
     }
 }
 
@@ -131,7 +153,7 @@ impl TryFrom<&str> for ItemId {
             .next()
             .expect(".split() always returns at least one item");
         let counter = parts.next().ok_or(ItemIdParseError::MissingDash)?;
-        if !parts.next().is_none() {
+        if parts.next().is_some() {
             return Err(ItemIdParseError::TooManyDashes);
         }
 
@@ -146,66 +168,610 @@ impl TryFrom<&str> for ItemId {
     }
 }
 
-pub type ItemData = HashMap<String, String>;
-pub type InsertListener = mpsc::Sender<(String, ItemId, ItemData)>;
+/// Fields of an item with their values, in the order they were added. Field names may repeat.
+pub type ItemData = Vec<(String, String)>;
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct Item<'a> {
+pub struct Item {
     pub(crate) id: ItemId,
-    pub(crate) elements: &'a ItemData,
+    pub(crate) elements: ItemData,
 }
 
-impl<'a> Item<'a> {
-    pub fn new(id: ItemId, elements: &'a ItemData) -> Self {
+impl Item {
+    pub fn new(id: ItemId, elements: ItemData) -> Self {
         Self { id, elements }
     }
 }
 
+/// An item delivered to a consumer of a group that wasn't acknowledged yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingEntry {
+    pub consumer: String,
+    pub delivered_at: SystemTime,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Consumer {
+    /// IDs of the group's pending entries delivered to this consumer.
+    pending: BTreeSet<ItemId>,
+    /// Last time the consumer tried to read or claim items.
+    pub seen_at: SystemTime,
+    /// Last time the consumer actually got items, `None` if it never did.
+    pub active_at: Option<SystemTime>,
+}
+
+impl Consumer {
+    fn new(now: SystemTime) -> Self {
+        Self {
+            pending: BTreeSet::new(),
+            seen_at: now,
+            active_at: None,
+        }
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// IDs of the consumer's pending entries, in order.
+    pub fn pending(&self) -> impl Iterator<Item = &ItemId> {
+        self.pending.iter()
+    }
+}
+
+/// How XCLAIM and XAUTOCLAIM pick the pending entries they claim and update them.
 #[derive(Debug, Clone)]
+pub struct ClaimOptions {
+    /// Only entries that weren't delivered for at least this long are claimed.
+    pub min_idle: Duration,
+    /// Delivery time of the claimed entries.
+    pub delivered_at: SystemTime,
+    /// Delivery count of the claimed entries, instead of incrementing it.
+    pub retry_count: Option<u64>,
+    /// Whether claiming counts as a delivery, which it doesn't when only IDs are replied with.
+    pub deliver: bool,
+    /// Claims items that aren't pending as well, creating their pending entries.
+    pub force: bool,
+}
+
+/// Outcome of XAUTOCLAIM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoClaim {
+    /// Where the next call continues scanning, `0-0` once all pending entries were scanned.
+    pub next: ItemId,
+    pub claimed: Vec<ItemId>,
+    /// Pending entries removed because their item was deleted.
+    pub deleted: Vec<ItemId>,
+}
+
+/// Which items XREADGROUP reads from a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupRead {
+    /// `>`: items never delivered to the group, which become pending for the consumer.
+    New,
+    /// The consumer's pending entries after the ID, delivered again.
+    Pending(ItemId),
+}
+
+/// A group of consumers sharing the items of a stream, each item being delivered to one of
+/// them and staying pending until it's acknowledged.
+#[derive(Debug, Clone)]
+pub struct ConsumerGroup {
+    last_delivered: ItemId,
+    /// Number of items the group read, `None` if it isn't known like after XGROUP SETID.
+    entries_read: Option<u64>,
+    pending: BTreeMap<ItemId, PendingEntry>,
+    consumers: BTreeMap<String, Consumer>,
+}
+
+impl ConsumerGroup {
+    fn new(last_delivered: ItemId, entries_read: Option<u64>) -> Self {
+        Self {
+            last_delivered,
+            entries_read,
+            pending: BTreeMap::new(),
+            consumers: BTreeMap::new(),
+        }
+    }
+
+    /// ID of the last item delivered to the group, new items come after it.
+    pub fn last_delivered(&self) -> ItemId {
+        self.last_delivered
+    }
+
+    pub fn set_last_delivered(&mut self, id: ItemId, entries_read: Option<u64>) {
+        self.last_delivered = id;
+        self.entries_read = entries_read;
+    }
+
+    pub fn entries_read(&self) -> Option<u64> {
+        self.entries_read
+    }
+
+    /// Pending entries of all consumers, by ID.
+    pub fn pending(&self) -> &BTreeMap<ItemId, PendingEntry> {
+        &self.pending
+    }
+
+    pub fn consumers(&self) -> impl Iterator<Item = (&String, &Consumer)> {
+        self.consumers.iter()
+    }
+
+    pub fn has_consumer(&self, name: &str) -> bool {
+        self.consumers.contains_key(name)
+    }
+
+    /// Adds a consumer, returning `false` if it already exists.
+    pub fn create_consumer(&mut self, name: &str, now: SystemTime) -> bool {
+        if self.has_consumer(name) {
+            return false;
+        }
+        self.consumers.insert(name.to_owned(), Consumer::new(now));
+        true
+    }
+
+    /// Removes a consumer together with its pending entries, returning how many it had.
+    pub fn delete_consumer(&mut self, name: &str) -> usize {
+        let Some(consumer) = self.consumers.remove(name) else {
+            return 0;
+        };
+        for id in &consumer.pending {
+            self.pending.remove(id);
+        }
+        consumer.pending.len()
+    }
+
+    /// Acknowledges a pending entry, returning `false` if it wasn't pending.
+    pub fn ack(&mut self, id: ItemId) -> bool {
+        let Some(entry) = self.pending.remove(&id) else {
+            return false;
+        };
+        if let Some(consumer) = self.consumers.get_mut(&entry.consumer) {
+            consumer.pending.remove(&id);
+        }
+        true
+    }
+
+    fn is_idle(&self, id: ItemId, options: &ClaimOptions, now: SystemTime) -> bool {
+        self.pending.get(&id).is_some_and(|entry| {
+            now.duration_since(entry.delivered_at).unwrap_or_default() >= options.min_idle
+        })
+    }
+
+    /// Moves the pending entry `id` to `consumer`, creating both if needed.
+    fn claim(&mut self, id: ItemId, consumer: &str, options: &ClaimOptions, now: SystemTime) {
+        self.create_consumer(consumer, now);
+        let entry = self.pending.entry(id).or_insert_with(|| PendingEntry {
+            consumer: consumer.to_owned(),
+            delivered_at: now,
+            delivery_count: 0,
+        });
+        if entry.consumer != consumer {
+            if let Some(previous) = self.consumers.get_mut(&entry.consumer) {
+                previous.pending.remove(&id);
+            }
+            entry.consumer = consumer.to_owned();
+        }
+        entry.delivered_at = options.delivered_at;
+        match options.retry_count {
+            Some(count) => entry.delivery_count = count,
+            None if options.deliver => entry.delivery_count += 1,
+            None => {}
+        }
+
+        let consumer = self
+            .consumers
+            .get_mut(consumer)
+            .expect("The consumer was just created");
+        consumer.pending.insert(id);
+        consumer.active_at = Some(now);
+    }
+
+    /// Makes `id` pending for `consumer`, taking it from the consumer it was pending for.
+    fn deliver(&mut self, id: ItemId, consumer: &str, now: SystemTime) {
+        let entry = PendingEntry {
+            consumer: consumer.to_owned(),
+            delivered_at: now,
+            delivery_count: 1,
+        };
+        if let Some(previous) = self.pending.insert(id, entry) {
+            if let Some(previous) = self.consumers.get_mut(&previous.consumer) {
+                previous.pending.remove(&id);
+            }
+        }
+        if let Some(consumer) = self.consumers.get_mut(consumer) {
+            consumer.pending.insert(id);
+        }
+    }
+}
+
+/// Whether no ID lies between `start` and `end`, in which case `BTreeMap::range` would panic.
+pub fn is_empty_range(start: Bound<ItemId>, end: Bound<ItemId>) -> bool {
+    match (start, end) {
+        (Bound::Included(start), Bound::Included(end)) => start > end,
+        (Bound::Included(start) | Bound::Excluded(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end)) => start >= end,
+        _ => false,
+    }
+}
+
+/// Number of items approximate trimming removes at most unless a LIMIT is given, 100 times the
+/// number of entries Redis keeps in a stream node.
+pub const DEFAULT_TRIM_LIMIT: usize = 100 * 100;
+
+/// Items trimming removes, the oldest ones first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimThreshold {
+    /// Keeps at most this many items.
+    MaxLen(usize),
+    /// Removes items with a lower ID.
+    MinId(ItemId),
+}
+
+/// `MAXLEN|MINID [=|~] threshold [LIMIT count]` of XTRIM and XADD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trim {
+    pub threshold: TrimThreshold,
+    /// Number of items removed at most, only set for approximate trimming.
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct Stream {
-    name: String,
-    items: BTreeMap<ItemId, ItemData>,
-    listeners: Vec<InsertListener>,
+    items: Items,
+    groups: BTreeMap<String, ConsumerGroup>,
+    /// Highest ID ever added, which new IDs have to be greater than even once it's deleted.
+    last_id: ItemId,
+    max_deleted_id: ItemId,
+    entries_added: u64,
 }
 
 impl Stream {
-    pub fn new(name: String) -> Self {
+    pub fn new() -> Self {
         Stream {
-            name,
-            items: BTreeMap::new(),
-            listeners: Vec::new(),
+            items: Items::default(),
+            groups: BTreeMap::new(),
+            last_id: ItemId(0, 0),
+            max_deleted_id: ItemId(0, 0),
+            entries_added: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Number of macro nodes the items are stored in.
+    pub fn node_count(&self) -> usize {
+        self.items.node_count()
+    }
+
+    /// ID of the last item ever added, `0-0` if there was none.
+    pub fn last_id(&self) -> ItemId {
+        self.last_id
+    }
+
+    /// Highest ID of the items deleted by XDEL or trimming, `0-0` if there was none.
+    pub fn max_deleted_id(&self) -> ItemId {
+        self.max_deleted_id
+    }
+
+    /// Number of items added over the lifetime of the stream, including deleted ones.
+    pub fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    pub fn get(&self, id: ItemId) -> Option<ItemData> {
+        self.items.get(id)
+    }
+
+    pub fn group(&self, name: &str) -> Option<&ConsumerGroup> {
+        self.groups.get(name)
+    }
+
+    pub fn group_mut(&mut self, name: &str) -> Option<&mut ConsumerGroup> {
+        self.groups.get_mut(name)
+    }
+
+    pub fn groups(&self) -> impl Iterator<Item = (&String, &ConsumerGroup)> {
+        self.groups.iter()
+    }
+
+    /// ID of the first item, `0-0` if there's none.
+    pub fn first_id(&self) -> ItemId {
+        self.items.first().unwrap_or(ItemId(0, 0))
+    }
+
+    /// Sets the ID new items have to be greater than, and the counters of XINFO, like XSETID.
+    pub fn set_last_id(
+        &mut self,
+        id: ItemId,
+        entries_added: Option<u64>,
+        max_deleted_id: Option<ItemId>,
+    ) -> Result<(), SetIdError> {
+        if max_deleted_id.is_some_and(|max_deleted_id| id < max_deleted_id) {
+            return Err(SetIdError::BelowMaxDeletedId);
+        }
+        if entries_added.is_some_and(|entries_added| entries_added < self.len() as u64) {
+            return Err(SetIdError::BelowLength);
+        }
+        if self.items.last().is_some_and(|last| id < last) {
+            return Err(SetIdError::BelowLastItem);
+        }
+
+        self.last_id = id;
+        self.entries_added = entries_added.unwrap_or(self.entries_added);
+        self.max_deleted_id = max_deleted_id.unwrap_or(self.max_deleted_id);
+        Ok(())
+    }
+
+    /// Adds a consumer group that delivers the items after `last_delivered`, returning `false`
+    /// if a group with the name exists.
+    pub fn create_group(
+        &mut self,
+        name: &str,
+        last_delivered: ItemId,
+        entries_read: Option<u64>,
+    ) -> bool {
+        if self.groups.contains_key(name) {
+            return false;
+        }
+        let group = ConsumerGroup::new(last_delivered, entries_read);
+        self.groups.insert(name.to_owned(), group);
+        true
+    }
+
+    /// Number of items added to the stream that `group` didn't read yet, `None` if deleted
+    /// items make it impossible to tell.
+    pub fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        let entries_read = group
+            .entries_read
+            .filter(|_| !self.has_deleted_from(group.last_delivered))
+            .or_else(|| self.entries_until(group.last_delivered))?;
+        Some(self.entries_added.saturating_sub(entries_read))
+    }
+
+    /// Whether an item from `id` on was deleted, which the number of items a group read can't
+    /// be counted across.
+    fn has_deleted_from(&self, id: ItemId) -> bool {
+        !self.items.is_empty() && self.max_deleted_id != ItemId(0, 0) && id <= self.max_deleted_id
+    }
+
+    /// Number of items added up to `id`, if the counters of the stream tell, like Redis
+    /// estimates the number of items a group read.
+    fn entries_until(&self, id: ItemId) -> Option<u64> {
+        if self.entries_added == 0 || (self.items.is_empty() && id <= self.last_id) {
+            return Some(self.entries_added);
+        }
+        match id.cmp(&self.last_id) {
+            Ordering::Equal => return Some(self.entries_added),
+            Ordering::Greater => return None,
+            Ordering::Less => {}
         }
+
+        // Without deleted items after the first one, all items from it on are still there
+        let first = self.first_id();
+        if self.max_deleted_id == ItemId(0, 0) || self.max_deleted_id < first {
+            let before_first = self.entries_added.saturating_sub(self.len() as u64);
+            match id.cmp(&first) {
+                Ordering::Less => return Some(before_first),
+                Ordering::Equal => return Some(before_first + 1),
+                Ordering::Greater => {}
+            }
+        }
+        None
+    }
+
+    /// Number of items a group that read `entries_read` of them has read after `ids` were
+    /// delivered to it.
+    fn count_read(
+        &self,
+        mut entries_read: Option<u64>,
+        ids: impl Iterator<Item = ItemId>,
+    ) -> Option<u64> {
+        for id in ids {
+            entries_read = match entries_read {
+                Some(read) if !self.has_deleted_from(id) => Some(read + 1),
+                _ => self.entries_until(id),
+            };
+        }
+        entries_read
+    }
+
+    pub fn destroy_group(&mut self, name: &str) -> bool {
+        self.groups.remove(name).is_some()
     }
 
-    pub fn insert(&mut self, id: ProvidedItemId, data: ItemData) -> Result<ItemId, InsertionError> {
+    /// Claims the pending entries `ids` of `group` that are idle long enough for `consumer`,
+    /// like XCLAIM, returning the claimed IDs. Entries whose item was deleted are removed
+    /// instead. `None` if there's no such group.
+    pub fn claim(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        ids: &[ItemId],
+        options: &ClaimOptions,
+        now: SystemTime,
+    ) -> Option<Vec<ItemId>> {
+        let group = self.groups.get_mut(group)?;
+        let mut claimed = Vec::new();
+        for &id in ids {
+            if !self.items.contains(id) {
+                group.ack(id);
+                continue;
+            }
+            let pending = group.pending.contains_key(&id);
+            if (pending && group.is_idle(id, options, now)) || (!pending && options.force) {
+                group.claim(id, consumer, options, now);
+                claimed.push(id);
+            }
+        }
+
+        if let Some(consumer) = group.consumers.get_mut(consumer) {
+            consumer.seen_at = now;
+        }
+        Some(claimed)
+    }
+
+    /// Scans the pending entries of `group` from `start`, claiming up to `count` idle ones for
+    /// `consumer` like XAUTOCLAIM. At most ten entries per claimed one are scanned, so that a
+    /// call doesn't take too long. `None` if there's no such group.
+    pub fn auto_claim(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        start: Bound<ItemId>,
+        count: usize,
+        options: &ClaimOptions,
+        now: SystemTime,
+    ) -> Option<AutoClaim> {
+        let group = self.groups.get_mut(group)?;
+        let attempts = count.saturating_mul(10);
+        let scanned: Vec<_> = group
+            .pending
+            .range((start, Bound::Unbounded))
+            .map(|(id, _)| *id)
+            .take(attempts.saturating_add(1))
+            .collect();
+
+        let (mut claimed, mut deleted) = (Vec::new(), Vec::new());
+        let mut next = scanned.len();
+        for (i, &id) in scanned.iter().enumerate() {
+            if i == attempts || claimed.len() == count {
+                next = i;
+                break;
+            }
+            if !self.items.contains(id) {
+                group.ack(id);
+                deleted.push(id);
+            } else if group.is_idle(id, options, now) {
+                group.claim(id, consumer, options, now);
+                claimed.push(id);
+            }
+        }
+
+        if let Some(consumer) = group.consumers.get_mut(consumer) {
+            consumer.seen_at = now;
+        }
+        Some(AutoClaim {
+            next: scanned.get(next).copied().unwrap_or(ItemId(0, 0)),
+            claimed,
+            deleted,
+        })
+    }
+
+    /// Reads items for `consumer` of `group`, creating the consumer if needed, like XREADGROUP.
+    /// New items are added to the consumer's pending entries unless `noack` is set. Pending
+    /// entries whose item was deleted come without data. `None` if there's no such group.
+    pub fn read_group(
+        &mut self,
+        name: &str,
+        consumer: &str,
+        read: GroupRead,
+        count: Option<usize>,
+        noack: bool,
+        now: SystemTime,
+    ) -> Option<Vec<(ItemId, Option<ItemData>)>> {
+        let group = self.groups.get_mut(name)?;
+        group.create_consumer(consumer, now);
+        let count = count.unwrap_or(usize::MAX);
+
+        let new = read == GroupRead::New;
+        let read: Vec<_> = match read {
+            GroupRead::New => {
+                let items: Vec<_> = self
+                    .items
+                    .range(Bound::Excluded(group.last_delivered), Bound::Unbounded)
+                    .take(count)
+                    .collect();
+                for item in &items {
+                    group.last_delivered = item.id;
+                    if !noack {
+                        group.deliver(item.id, consumer, now);
+                    }
+                }
+                items
+                    .into_iter()
+                    .map(|item| (item.id, Some(item.elements)))
+                    .collect()
+            }
+            GroupRead::Pending(after) => {
+                let ids: Vec<_> = group.consumers[consumer]
+                    .pending
+                    .range((Bound::Excluded(after), Bound::Unbounded))
+                    .take(count)
+                    .copied()
+                    .collect();
+                for id in &ids {
+                    let entry = group.pending.get_mut(id).expect("Pending for the consumer");
+                    entry.delivered_at = now;
+                    entry.delivery_count += 1;
+                }
+                ids.into_iter().map(|id| (id, self.items.get(id))).collect()
+            }
+        };
+
+        let consumer = group
+            .consumers
+            .get_mut(consumer)
+            .expect("The consumer was just created");
+        consumer.seen_at = now;
+        if !read.is_empty() {
+            consumer.active_at = Some(now);
+        }
+
+        if new {
+            let ids = read.iter().map(|(id, _)| *id);
+            let entries_read = self.count_read(self.groups[name].entries_read, ids);
+            self.groups
+                .get_mut(name)
+                .expect("The group exists")
+                .entries_read = entries_read;
+        }
+        Some(read)
+    }
+
+    /// Inserts an item, generating the parts of its ID that were not provided from `now`.
+    pub fn insert(
+        &mut self,
+        id: ProvidedItemId,
+        data: ItemData,
+        now: SystemTime,
+    ) -> Result<ItemId, InsertionError> {
         let id = match id {
             ProvidedItemId::AutoGenerated => {
-                let now_ms: u64 = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)?
-                    .as_millis()
-                    .try_into()?;
-
-                if let Some((last_key, _)) = self.items.last_key_value() {
-                    if last_key.0 >= now_ms {
-                        last_key.next()
-                    } else {
-                        ItemId(now_ms, 0)
-                    }
+                let now_ms: u64 = now.duration_since(UNIX_EPOCH)?.as_millis().try_into()?;
+
+                if self.last_id.0 >= now_ms {
+                    // Moves on to the next millisecond once the sequence numbers run out
+                    self.last_id
+                        .next()
+                        .or_else(|| Some(ItemId(self.last_id.0.checked_add(1)?, 0)))
+                        .ok_or(InsertionError::IdIsNotGreaterThanHighestStored(
+                            self.last_id,
+                        ))?
                 } else {
                     ItemId(now_ms, 0)
                 }
             }
             ProvidedItemId::AutoSequence(timestamp) => {
-                if let Some((last_key, _)) = self.items.last_key_value() {
-                    if last_key.0 > timestamp {
-                        return Err(InsertionError::IdIsNotGreaterThanHighestStored(*last_key));
-                    }
+                let last_id = self.last_id;
+                if last_id.0 > timestamp {
+                    return Err(InsertionError::IdIsNotGreaterThanHighestStored(last_id));
+                }
 
-                    if last_key.0 == timestamp {
-                        last_key.next()
-                    } else {
-                        ItemId(timestamp, 0)
-                    }
+                if last_id.0 == timestamp && last_id != ItemId(0, 0) {
+                    last_id
+                        .next()
+                        .ok_or(InsertionError::IdIsNotGreaterThanHighestStored(last_id))?
                 } else {
                     ItemId(timestamp, if timestamp == 0 { 1 } else { 0 })
                 }
@@ -214,35 +780,145 @@ impl Stream {
                 if id == ItemId(0, 0) {
                     return Err(InsertionError::IdTooLow);
                 }
-                if let Some((last_key, _)) = self.items.last_key_value() {
-                    if last_key >= &id {
-                        return Err(InsertionError::IdIsNotGreaterThanHighestStored(*last_key));
-                    }
+                if self.last_id >= id {
+                    return Err(InsertionError::IdIsNotGreaterThanHighestStored(
+                        self.last_id,
+                    ));
                 }
                 id
             }
         };
 
-        self.items.insert(id, data.clone());
-        for listener in self.listeners.drain(0..) {
-            let _ = listener.try_send((self.name.clone(), id, data.clone()));
-        }
+        self.items.push(id, &data);
+        self.last_id = id;
+        self.entries_added += 1;
 
         Ok(id)
     }
 
+    /// Items between `start` and `end`, which is empty if `start` comes after `end`.
     pub fn range(
         &self,
         start: Bound<ItemId>,
         end: Bound<ItemId>,
-    ) -> impl Iterator<Item = Item<'_>> {
-        let range = self.items.range((start, end));
+    ) -> impl DoubleEndedIterator<Item = Item> + '_ {
+        self.items.range(start, end)
+    }
+
+    /// Deletes the items `ids`, returning how many existed.
+    pub fn delete(&mut self, ids: &[ItemId]) -> usize {
+        let mut deleted = 0;
+        for id in ids {
+            if self.items.remove(*id) {
+                self.max_deleted_id = self.max_deleted_id.max(*id);
+                deleted += 1;
+            }
+        }
+        deleted
+    }
+
+    /// Removes the oldest items beyond the threshold of `trim`, returning how many were removed.
+    pub fn trim(&mut self, trim: Trim) -> usize {
+        let limit = trim.limit.unwrap_or(usize::MAX);
+        let excess = match trim.threshold {
+            TrimThreshold::MaxLen(len) => self.items.len().saturating_sub(len).min(limit),
+            TrimThreshold::MinId(id) => self
+                .items
+                .range(Bound::Unbounded, Bound::Excluded(id))
+                .take(limit)
+                .count(),
+        };
 
-        range.map(|(id, elements)| Item { id: *id, elements })
+        if let Some(last_removed) = self.items.remove_first(excess) {
+            self.max_deleted_id = self.max_deleted_id.max(last_removed);
+        }
+        excess
     }
 
-    pub fn notify_on_insert(&mut self, listener: InsertListener) {
-        self.listeners.push(listener);
+    /// Restores a stream saved in an RDB file.
+    pub fn from_rdb(value: &rdb::StreamValue) -> Self {
+        let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+        let id = |(ms, seq): rdb::StreamId| ItemId(ms, seq);
+
+        let mut stream = Self::new();
+        let nodes = value.nodes.iter();
+        stream.items =
+            Items::from_nodes(nodes.map(|(master_id, node)| (id(*master_id), node.clone())));
+        stream.last_id = id(value.last_id);
+        stream.max_deleted_id = id(value.max_deleted_id);
+        stream.entries_added = value.entries_added;
+
+        for saved in &value.groups {
+            let mut group = ConsumerGroup::new(id(saved.last_delivered), saved.entries_read);
+            for consumer in &saved.consumers {
+                let mut restored = Consumer::new(consumer.seen_at);
+                restored.active_at = consumer.active_at;
+                group.consumers.insert(text(&consumer.name), restored);
+            }
+            for pending in &saved.pending {
+                let consumer = text(&pending.consumer);
+                group
+                    .consumers
+                    .entry(consumer.clone())
+                    .or_insert_with(|| Consumer::new(pending.delivered_at))
+                    .pending
+                    .insert(id(pending.id));
+                let entry = PendingEntry {
+                    consumer,
+                    delivered_at: pending.delivered_at,
+                    delivery_count: pending.delivery_count,
+                };
+                group.pending.insert(id(pending.id), entry);
+            }
+            stream.groups.insert(text(&saved.name), group);
+        }
+        stream
+    }
+
+    /// The stream as saved in RDB files.
+    pub fn to_rdb(&self) -> rdb::StreamValue {
+        let id = |id: ItemId| (id.0, id.1);
+        let nodes = self
+            .items
+            .nodes()
+            .map(|(master_id, node)| (id(master_id), node.to_vec()));
+
+        let groups = self.groups.iter().map(|(name, group)| {
+            let pending = group
+                .pending
+                .iter()
+                .map(|(item, entry)| rdb::StreamPending {
+                    id: id(*item),
+                    consumer: entry.consumer.clone().into_bytes(),
+                    delivered_at: entry.delivered_at,
+                    delivery_count: entry.delivery_count,
+                });
+            let consumers = group
+                .consumers
+                .iter()
+                .map(|(name, consumer)| rdb::StreamConsumer {
+                    name: name.clone().into_bytes(),
+                    seen_at: consumer.seen_at,
+                    active_at: consumer.active_at,
+                });
+            rdb::StreamGroup {
+                name: name.clone().into_bytes(),
+                last_delivered: id(group.last_delivered),
+                entries_read: group.entries_read,
+                pending: pending.collect(),
+                consumers: consumers.collect(),
+            }
+        });
+
+        rdb::StreamValue {
+            nodes: nodes.collect(),
+            length: self.items.len() as u64,
+            last_id: id(self.last_id),
+            first_id: id(self.first_id()),
+            max_deleted_id: id(self.max_deleted_id),
+            entries_added: self.entries_added,
+            groups: groups.collect(),
+        }
     }
 }
 
@@ -261,52 +937,362 @@ mod test {
 
     #[test]
     fn insertion_of_0_0() {
-        let mut sut = Stream::new(String::new());
+        let mut sut = Stream::new();
 
         assert!(sut
-            .insert("0-0".try_into().unwrap(), ItemData::new())
+            .insert(
+                "0-0".try_into().unwrap(),
+                ItemData::new(),
+                SystemTime::now()
+            )
             .is_err());
     }
 
     #[test]
     fn id_generation_zero_ts() {
-        let mut sut = Stream::new(String::new());
+        let mut sut = Stream::new();
 
         assert_eq!(
-            sut.insert("0-*".try_into().unwrap(), ItemData::new()),
+            sut.insert(
+                "0-*".try_into().unwrap(),
+                ItemData::new(),
+                SystemTime::now()
+            ),
             Ok("0-1".try_into().unwrap())
         );
     }
 
     #[test]
     fn id_generation() {
-        let mut sut = Stream::new(String::new());
+        let mut sut = Stream::new();
 
         assert_eq!(
-            sut.insert("2-*".try_into().unwrap(), ItemData::new()),
+            sut.insert(
+                "2-*".try_into().unwrap(),
+                ItemData::new(),
+                SystemTime::now()
+            ),
             Ok("2-0".try_into().unwrap())
         );
 
         assert_eq!(
-            sut.insert("2-*".try_into().unwrap(), ItemData::new()),
+            sut.insert(
+                "2-*".try_into().unwrap(),
+                ItemData::new(),
+                SystemTime::now()
+            ),
             Ok("2-1".try_into().unwrap())
         );
 
         assert_eq!(
-            sut.insert("3-*".try_into().unwrap(), ItemData::new()),
+            sut.insert(
+                "3-*".try_into().unwrap(),
+                ItemData::new(),
+                SystemTime::now()
+            ),
             Ok("3-0".try_into().unwrap())
         );
 
         assert_eq!(
-            sut.insert("3-*".try_into().unwrap(), ItemData::new()),
+            sut.insert(
+                "3-*".try_into().unwrap(),
+                ItemData::new(),
+                SystemTime::now()
+            ),
             Ok("3-1".try_into().unwrap())
         );
 
         assert_eq!(
-            sut.insert("2-*".try_into().unwrap(), ItemData::new()),
+            sut.insert(
+                "2-*".try_into().unwrap(),
+                ItemData::new(),
+                SystemTime::now()
+            ),
             Err(InsertionError::IdIsNotGreaterThanHighestStored(ItemId(
                 3, 1
             )))
         );
     }
+
+    #[test]
+    fn consumer_groups() {
+        let mut sut = Stream::new();
+        let now = SystemTime::now();
+        for id in ["1-0", "2-0", "3-0"] {
+            sut.insert(id.try_into().unwrap(), ItemData::new(), now)
+                .unwrap();
+        }
+        assert!(sut.create_group("g", ItemId(1, 0), None));
+        assert!(!sut.create_group("g", ItemId(0, 0), None));
+        assert!(sut
+            .read_group("h", "a", GroupRead::New, None, false, now)
+            .is_none());
+
+        let ids = |read: Vec<(ItemId, Option<ItemData>)>| {
+            read.into_iter().map(|(id, _)| id).collect::<Vec<_>>()
+        };
+        let read = sut.read_group("g", "a", GroupRead::New, Some(1), false, now);
+        assert_eq!(ids(read.unwrap()), [ItemId(2, 0)]);
+        let read = sut.read_group("g", "b", GroupRead::New, None, false, now);
+        assert_eq!(ids(read.unwrap()), [ItemId(3, 0)]);
+        let read = sut.read_group("g", "b", GroupRead::New, None, false, now);
+        assert!(read.unwrap().is_empty());
+
+        let history = GroupRead::Pending(ItemId(0, 0));
+        let read = sut.read_group("g", "a", history, None, false, now);
+        assert_eq!(ids(read.unwrap()), [ItemId(2, 0)]);
+        let group = sut.group_mut("g").unwrap();
+        assert_eq!(group.last_delivered(), ItemId(3, 0));
+        assert_eq!(group.pending()[&ItemId(2, 0)].delivery_count, 2);
+        assert_eq!(group.pending()[&ItemId(3, 0)].consumer, "b");
+
+        assert!(group.ack(ItemId(2, 0)));
+        assert!(!group.ack(ItemId(2, 0)));
+        assert_eq!(group.delete_consumer("b"), 1);
+        assert!(group.pending().is_empty());
+        assert!(sut.destroy_group("g"));
+    }
+
+    #[test]
+    fn claiming_pending_entries() {
+        let mut sut = Stream::new();
+        let at = |ms| UNIX_EPOCH + Duration::from_millis(ms);
+        for ms in 1..=4 {
+            sut.insert(ItemId(ms, 0).into(), ItemData::new(), at(0))
+                .unwrap();
+        }
+        sut.create_group("g", ItemId(0, 0), None);
+        sut.read_group("g", "a", GroupRead::New, Some(3), false, at(0));
+
+        let mut options = ClaimOptions {
+            min_idle: Duration::from_millis(100),
+            delivered_at: at(50),
+            retry_count: None,
+            deliver: true,
+            force: false,
+        };
+        let ids = [ItemId(1, 0), ItemId(4, 0)];
+        assert_eq!(sut.claim("g", "b", &ids, &options, at(50)), Some(vec![]));
+        assert_eq!(
+            sut.claim("g", "b", &ids, &options, at(100)),
+            Some(vec![ItemId(1, 0)])
+        );
+        options.force = true;
+        options.retry_count = Some(7);
+        assert_eq!(
+            sut.claim("g", "c", &ids[1..], &options, at(100)),
+            Some(vec![ItemId(4, 0)])
+        );
+        let group = sut.group("g").unwrap();
+        assert_eq!(group.pending()[&ItemId(1, 0)].consumer, "b");
+        assert_eq!(group.pending()[&ItemId(1, 0)].delivery_count, 2);
+        assert_eq!(group.pending()[&ItemId(1, 0)].delivered_at, at(50));
+        assert_eq!(group.pending()[&ItemId(4, 0)].delivery_count, 7);
+
+        // Only 2-0 and 3-0 are still idle long enough
+        options = ClaimOptions {
+            min_idle: Duration::from_millis(200),
+            delivered_at: at(200),
+            retry_count: None,
+            force: false,
+            ..options
+        };
+        let claimed = sut.auto_claim("g", "d", Bound::Unbounded, 1, &options, at(200));
+        assert_eq!(
+            claimed,
+            Some(AutoClaim {
+                next: ItemId(3, 0),
+                claimed: vec![ItemId(2, 0)],
+                deleted: vec![],
+            })
+        );
+        let claimed = sut.auto_claim(
+            "g",
+            "d",
+            Bound::Included(ItemId(3, 0)),
+            5,
+            &options,
+            at(200),
+        );
+        assert_eq!(claimed.unwrap().claimed, [ItemId(3, 0)]);
+    }
+
+    #[test]
+    fn auto_generated_ids_follow_the_clock() {
+        let mut sut = Stream::new();
+        let at = |ms| UNIX_EPOCH + std::time::Duration::from_millis(ms);
+
+        assert_eq!(
+            sut.insert(ProvidedItemId::AutoGenerated, ItemData::new(), at(5)),
+            Ok(ItemId(5, 0))
+        );
+        assert_eq!(
+            sut.insert(ProvidedItemId::AutoGenerated, ItemData::new(), at(5)),
+            Ok(ItemId(5, 1))
+        );
+        // A clock that went backwards doesn't produce smaller IDs
+        assert_eq!(
+            sut.insert(ProvidedItemId::AutoGenerated, ItemData::new(), at(3)),
+            Ok(ItemId(5, 2))
+        );
+        assert_eq!(
+            sut.insert(ProvidedItemId::AutoGenerated, ItemData::new(), at(7)),
+            Ok(ItemId(7, 0))
+        );
+    }
+
+    #[test]
+    fn sequence_numbers_running_out() {
+        let mut sut = Stream::new();
+        let at = |ms| UNIX_EPOCH + std::time::Duration::from_millis(ms);
+
+        let id = ItemId(5, u64::MAX);
+        sut.insert(id.into(), ItemData::new(), at(5)).unwrap();
+        assert_eq!(
+            sut.insert(ProvidedItemId::AutoSequence(5), ItemData::new(), at(5)),
+            Err(InsertionError::IdIsNotGreaterThanHighestStored(id))
+        );
+        assert_eq!(
+            sut.insert(ProvidedItemId::AutoGenerated, ItemData::new(), at(5)),
+            Ok(ItemId(6, 0))
+        );
+
+        let id = ItemId(u64::MAX, u64::MAX);
+        sut.insert(id.into(), ItemData::new(), at(5)).unwrap();
+        assert_eq!(
+            sut.insert(ProvidedItemId::AutoGenerated, ItemData::new(), at(5)),
+            Err(InsertionError::IdIsNotGreaterThanHighestStored(id))
+        );
+    }
+
+    #[test]
+    fn deleting_and_trimming() {
+        let mut sut = Stream::new();
+        let now = SystemTime::now();
+        for id in ["1-0", "2-0", "3-0", "4-0", "5-0"] {
+            sut.insert(id.try_into().unwrap(), ItemData::new(), now)
+                .unwrap();
+        }
+
+        assert_eq!(sut.delete(&[ItemId(5, 0), ItemId(6, 0)]), 1);
+        assert_eq!(sut.last_id(), ItemId(5, 0));
+        assert_eq!(sut.max_deleted_id(), ItemId(5, 0));
+        assert!(sut
+            .insert("5-*".try_into().unwrap(), ItemData::new(), now)
+            .is_ok_and(|id| id == ItemId(5, 1)));
+
+        let trim = |threshold, limit| Trim { threshold, limit };
+        assert_eq!(sut.trim(trim(TrimThreshold::MaxLen(1), Some(2))), 2);
+        assert_eq!(sut.trim(trim(TrimThreshold::MinId(ItemId(4, 0)), None)), 1);
+        assert_eq!(sut.trim(trim(TrimThreshold::MaxLen(1), None)), 1);
+        assert_eq!(sut.range(Bound::Unbounded, Bound::Unbounded).count(), 1);
+        assert_eq!(sut.max_deleted_id(), ItemId(5, 0));
+        assert_eq!(sut.entries_added(), 6);
+    }
+
+    #[test]
+    fn ranges() {
+        let mut sut = Stream::new();
+        let now = SystemTime::now();
+        for id in ["1-0", "1-1", "2-0"] {
+            sut.insert(id.try_into().unwrap(), ItemData::new(), now)
+                .unwrap();
+        }
+
+        let ids = |start, end| {
+            sut.range(start, end)
+                .map(|item| item.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(Bound::Excluded(ItemId(1, 0)), Bound::Unbounded),
+            [ItemId(1, 1), ItemId(2, 0)]
+        );
+        assert_eq!(
+            ids(Bound::Unbounded, Bound::Included(ItemId(1, u64::MAX))),
+            [ItemId(1, 0), ItemId(1, 1)]
+        );
+        assert!(ids(Bound::Included(ItemId(2, 0)), Bound::Included(ItemId(1, 0))).is_empty());
+        assert!(ids(Bound::Excluded(ItemId(1, 1)), Bound::Excluded(ItemId(1, 1))).is_empty());
+
+        let last = sut.range(Bound::Unbounded, Bound::Unbounded).next_back();
+        assert_eq!(last.map(|item| item.id), Some(ItemId(2, 0)));
+    }
+
+    #[test]
+    fn fields_keep_their_order() {
+        let mut sut = Stream::new();
+        let now = SystemTime::now();
+        let data: ItemData = [("b", "1"), ("a", "2"), ("b", "3")]
+            .map(|(field, value)| (field.into(), value.into()))
+            .into();
+        sut.insert("1-0".try_into().unwrap(), data.clone(), now)
+            .unwrap();
+        assert_eq!(sut.get(ItemId(1, 0)), Some(data.clone()));
+
+        sut.create_group("g", ItemId(0, 0), None);
+        sut.read_group("g", "a", GroupRead::New, None, false, now);
+        let restored = Stream::from_rdb(&sut.to_rdb());
+        assert_eq!(restored.get(ItemId(1, 0)), Some(data));
+        assert_eq!(restored.to_rdb(), sut.to_rdb());
+    }
+
+    #[test]
+    fn lag_of_groups() {
+        let mut sut = Stream::new();
+        let now = SystemTime::now();
+        for ms in 1..=3 {
+            sut.insert(ItemId(ms, 0).into(), ItemData::new(), now)
+                .unwrap();
+        }
+        sut.create_group("g", ItemId(0, 0), None);
+        assert_eq!(sut.lag(sut.group("g").unwrap()), Some(3));
+
+        sut.read_group("g", "a", GroupRead::New, Some(2), false, now);
+        let group = sut.group("g").unwrap();
+        assert_eq!(group.entries_read(), Some(2));
+        assert_eq!(sut.lag(group), Some(1));
+
+        // A deleted item the group didn't read makes it unknown how many are left
+        sut.delete(&[ItemId(3, 0)]);
+        assert_eq!(sut.lag(sut.group("g").unwrap()), None);
+        sut.read_group("g", "a", GroupRead::New, None, false, now);
+        assert_eq!(sut.lag(sut.group("g").unwrap()), None);
+        sut.group_mut("g")
+            .unwrap()
+            .set_last_delivered(ItemId(3, 0), None);
+        assert_eq!(sut.lag(sut.group("g").unwrap()), Some(0));
+    }
+
+    #[test]
+    fn setting_the_last_id() {
+        let mut sut = Stream::new();
+        let now = SystemTime::now();
+        sut.insert(ItemId(2, 0).into(), ItemData::new(), now)
+            .unwrap();
+        sut.insert(ItemId(3, 0).into(), ItemData::new(), now)
+            .unwrap();
+
+        assert_eq!(
+            sut.set_last_id(ItemId(2, 5), None, None),
+            Err(SetIdError::BelowLastItem)
+        );
+        assert_eq!(
+            sut.set_last_id(ItemId(4, 0), Some(1), None),
+            Err(SetIdError::BelowLength)
+        );
+        assert_eq!(
+            sut.set_last_id(ItemId(4, 0), None, Some(ItemId(5, 0))),
+            Err(SetIdError::BelowMaxDeletedId)
+        );
+
+        sut.set_last_id(ItemId(4, 0), Some(10), Some(ItemId(1, 0)))
+            .unwrap();
+        assert_eq!(sut.last_id(), ItemId(4, 0));
+        assert_eq!(sut.entries_added(), 10);
+        assert_eq!(sut.max_deleted_id(), ItemId(1, 0));
+        assert!(sut
+            .insert(ItemId(4, 0).into(), ItemData::new(), now)
+            .is_err());
+    }
 }
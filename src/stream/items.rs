@@ -0,0 +1,532 @@
+//! Storage of stream items like Redis does it: macro nodes of up to [`NODE_MAX_ITEMS`] items
+//! encoded in a listpack, indexed by the ID of their first item, the master ID. Nodes have the
+//! same layout in memory and in RDB files, so they are saved and loaded as they are.
+
+use std::{
+    collections::BTreeMap,
+    ops::{Bound, RangeBounds},
+};
+
+use super::{is_empty_range, Item, ItemData, ItemId};
+use crate::listpack::{self, Reader, Writer};
+
+/// Number of items in a node at most, deleted ones included, Redis' default
+/// `stream-node-max-entries`.
+const NODE_MAX_ITEMS: usize = 100;
+/// Size in bytes a node grows to at most before new items go to the next one, Redis' default
+/// `stream-node-max-bytes`.
+const NODE_MAX_BYTES: usize = 4096;
+
+/// Flag of items that were deleted but are still stored in their node.
+const DELETED: i64 = 1;
+/// Flag of items with the master fields, which are stored as values only.
+const SAME_FIELDS: i64 = 2;
+
+/// Positions of the numbers of items and deleted items in the master entry of a node.
+const COUNT_INDEX: usize = 0;
+const DELETED_INDEX: usize = 1;
+
+/// An item as stored in a node, with the position of its flags in the listpack.
+struct Entry {
+    index: usize,
+    flags: i64,
+    item: Item,
+}
+
+/// Items sharing a listpack, which starts with a master entry: the number of items, the number
+/// of deleted ones, and the fields of the first item, the master fields, followed by a 0. Each
+/// item is then stored as its flags and the differences of its ID to the master ID, followed
+/// by its values if it has the master fields, or else by its number of fields and the fields
+/// with their values, and ends with the number of elements it took.
+#[derive(Debug, Clone)]
+struct Node {
+    listpack: Vec<u8>,
+    master_fields: Vec<String>,
+    len: usize,
+    deleted: usize,
+}
+
+impl Node {
+    fn new(master_fields: Vec<String>) -> Self {
+        let mut writer = Writer::new();
+        writer.push_integer(0);
+        writer.push_integer(0);
+        writer.push_integer(master_fields.len() as i64);
+        for field in &master_fields {
+            writer.push(field.as_bytes());
+        }
+        writer.push_integer(0);
+
+        Self {
+            listpack: writer.finish(),
+            master_fields,
+            len: 0,
+            deleted: 0,
+        }
+    }
+
+    /// Takes over a node's listpack, `None` if it isn't a valid node.
+    fn from_listpack(master_id: ItemId, listpack: Vec<u8>) -> Option<Self> {
+        let (master_fields, entries) = decode(master_id, &listpack)?;
+        let len = entries
+            .iter()
+            .filter(|entry| entry.flags & DELETED == 0)
+            .count();
+        Some(Self {
+            deleted: entries.len() - len,
+            listpack,
+            master_fields,
+            len,
+        })
+    }
+
+    fn is_full(&self) -> bool {
+        self.len + self.deleted >= NODE_MAX_ITEMS || self.listpack.len() >= NODE_MAX_BYTES
+    }
+
+    fn push(&mut self, master_id: ItemId, id: ItemId, data: &ItemData) {
+        let same_fields = data.len() == self.master_fields.len()
+            && data
+                .iter()
+                .zip(&self.master_fields)
+                .all(|((field, _), master)| field == master);
+
+        let mut writer = Writer::reopen(std::mem::take(&mut self.listpack));
+        writer.push_integer(if same_fields { SAME_FIELDS } else { 0 });
+        // The differences wrap around into the signed integers of listpacks, like in Redis
+        writer.push_integer(id.0.wrapping_sub(master_id.0) as i64);
+        writer.push_integer(id.1.wrapping_sub(master_id.1) as i64);
+        if same_fields {
+            for (_, value) in data {
+                writer.push(value.as_bytes());
+            }
+            writer.push_integer(3 + data.len() as i64);
+        } else {
+            writer.push_integer(data.len() as i64);
+            for (field, value) in data {
+                writer.push(field.as_bytes());
+                writer.push(value.as_bytes());
+            }
+            writer.push_integer(4 + 2 * data.len() as i64);
+        }
+        self.listpack = writer.finish();
+
+        self.len += 1;
+        self.set_integer(COUNT_INDEX, self.len as i64);
+    }
+
+    /// Flags an item as deleted, keeping it in the listpack like Redis does.
+    fn delete(&mut self, entry: &Entry) {
+        self.len -= 1;
+        self.deleted += 1;
+        self.set_integer(entry.index, entry.flags | DELETED);
+        self.set_integer(COUNT_INDEX, self.len as i64);
+        self.set_integer(DELETED_INDEX, self.deleted as i64);
+    }
+
+    fn set_integer(&mut self, index: usize, value: i64) {
+        listpack::replace_integer(&mut self.listpack, index, value)
+            .expect("Nodes store integers there");
+    }
+
+    /// Decodes the items of the node, whose master ID is `master_id`, deleted ones included.
+    fn entries(&self, master_id: ItemId) -> Vec<Entry> {
+        let (_, entries) = decode(master_id, &self.listpack).expect("Nodes are valid");
+        entries
+    }
+
+    /// The items of the node that weren't deleted.
+    fn items(&self, master_id: ItemId) -> impl DoubleEndedIterator<Item = Item> {
+        self.entries(master_id)
+            .into_iter()
+            .filter(|entry| entry.flags & DELETED == 0)
+            .map(|entry| entry.item)
+    }
+}
+
+/// The elements of a listpack read one after the other, counting them.
+#[derive(Clone)]
+struct Elements<'a> {
+    reader: Reader<'a>,
+    index: usize,
+}
+
+impl Elements<'_> {
+    fn integer(&mut self) -> Option<i64> {
+        self.index += 1;
+        self.reader.next()?.integer()
+    }
+
+    fn text(&mut self) -> Option<String> {
+        self.index += 1;
+        Some(String::from_utf8_lossy(&self.reader.next()?.into_bytes()).into_owned())
+    }
+
+    fn skip(&mut self, count: usize) -> Option<()> {
+        self.index += count;
+        self.reader.advance(count)
+    }
+
+    /// Reads the master entry up to the first item, returning the number of items, deleted ones
+    /// included, and the elements holding the master fields.
+    fn master_entry(&mut self) -> Option<(i64, Self)> {
+        let len = self.integer()?;
+        let deleted = self.integer()?;
+        let master_fields = self.clone();
+        let fields = usize::try_from(self.integer()?).ok()?;
+        // The master entry ends with a 0 in place of an item's number of elements
+        self.skip(fields + 1)?;
+        Some((len.checked_add(deleted)?, master_fields))
+    }
+
+    /// Reads the fields and values of an item with the given flags.
+    fn item_data(&mut self, flags: i64, master_fields: &[String]) -> Option<ItemData> {
+        let data = if flags & SAME_FIELDS != 0 {
+            master_fields
+                .iter()
+                .map(|field| Some((field.clone(), self.text()?)))
+                .collect::<Option<_>>()?
+        } else {
+            (0..self.integer()?)
+                .map(|_| Some((self.text()?, self.text()?)))
+                .collect::<Option<_>>()?
+        };
+        self.integer()?;
+        Some(data)
+    }
+
+    /// Reads the master fields, from the elements returned by [`Self::master_entry`].
+    fn master_fields(mut self) -> Option<Vec<String>> {
+        (0..self.integer()?).map(|_| self.text()).collect()
+    }
+}
+
+/// Decodes the master fields and the items of a node, `None` if it's malformed.
+fn decode(master_id: ItemId, listpack: &[u8]) -> Option<(Vec<String>, Vec<Entry>)> {
+    let mut elements = Elements {
+        reader: Reader::new(listpack)?,
+        index: 0,
+    };
+    let (count, master_fields) = elements.master_entry()?;
+    let master_fields = master_fields.master_fields()?;
+
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let index = elements.index;
+        let flags = elements.integer()?;
+        let id = ItemId(
+            master_id.0.wrapping_add(elements.integer()? as u64),
+            master_id.1.wrapping_add(elements.integer()? as u64),
+        );
+        let data = elements.item_data(flags, &master_fields)?;
+        entries.push(Entry {
+            index,
+            flags,
+            item: Item::new(id, data),
+        });
+    }
+    Some((master_fields, entries))
+}
+
+/// Finds the item `id` in a node, only decoding its fields and values. `None` if it's not
+/// there or was deleted.
+fn find(master_id: ItemId, listpack: &[u8], id: ItemId) -> Option<ItemData> {
+    let mut elements = Elements {
+        reader: Reader::new(listpack)?,
+        index: 0,
+    };
+    let (count, master_fields) = elements.master_entry()?;
+    let master_len = master_fields.clone().integer()?;
+
+    for _ in 0..count {
+        let flags = elements.integer()?;
+        let item_id = ItemId(
+            master_id.0.wrapping_add(elements.integer()? as u64),
+            master_id.1.wrapping_add(elements.integer()? as u64),
+        );
+        // Items are ordered by ID, so the rest can only come after it
+        if item_id > id {
+            return None;
+        }
+        if item_id == id && flags & DELETED == 0 {
+            return elements.item_data(flags, &master_fields.master_fields()?);
+        }
+
+        // Skips the values or the fields with their values, and the number of elements
+        let values = if flags & SAME_FIELDS != 0 {
+            master_len
+        } else {
+            2 * elements.integer()?
+        };
+        elements.skip(usize::try_from(values).ok()? + 1)?;
+    }
+    None
+}
+
+/// Whether `listpack` holds a valid node, like the ones RDB files store.
+pub(crate) fn is_valid_node(master_id: ItemId, listpack: &[u8]) -> bool {
+    listpack::parse(listpack).is_some() && decode(master_id, listpack).is_some()
+}
+
+/// The items of a stream, in nodes by their master ID.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Items {
+    nodes: BTreeMap<ItemId, Node>,
+    len: usize,
+}
+
+impl Items {
+    /// Takes over the nodes of a stream saved in an RDB file, which have to be valid. Nodes
+    /// without any items left are dropped.
+    pub(super) fn from_nodes(nodes: impl IntoIterator<Item = (ItemId, Vec<u8>)>) -> Self {
+        let mut items = Self::default();
+        for (master_id, listpack) in nodes {
+            let node = Node::from_listpack(master_id, listpack).expect("Nodes are checked");
+            if node.len > 0 {
+                items.len += node.len;
+                items.nodes.insert(master_id, node);
+            }
+        }
+        items
+    }
+
+    /// The listpacks of the nodes by their master ID, as saved in RDB files.
+    pub(super) fn nodes(&self) -> impl Iterator<Item = (ItemId, &[u8])> {
+        self.nodes
+            .iter()
+            .map(|(master_id, node)| (*master_id, node.listpack.as_slice()))
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(super) fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Adds an item after the others, so its ID has to be greater than theirs.
+    pub(super) fn push(&mut self, id: ItemId, data: &ItemData) {
+        match self.nodes.last_entry() {
+            Some(mut node) if !node.get().is_full() => {
+                let master_id = *node.key();
+                node.get_mut().push(master_id, id, data);
+            }
+            _ => {
+                let mut node = Node::new(data.iter().map(|(field, _)| field.clone()).collect());
+                node.push(id, id, data);
+                self.nodes.insert(id, node);
+            }
+        }
+        self.len += 1;
+    }
+
+    /// Items between `start` and `end`, none if `start` comes after `end`. Only the nodes that
+    /// may hold such items are decoded.
+    pub(super) fn range(
+        &self,
+        start: Bound<ItemId>,
+        end: Bound<ItemId>,
+    ) -> impl DoubleEndedIterator<Item = Item> + '_ {
+        let nodes = (!is_empty_range(start, end)).then(|| {
+            // The node holding the start of the range may have a lower master ID
+            let first = match start {
+                Bound::Included(id) | Bound::Excluded(id) => self.nodes.range(..=id).next_back(),
+                Bound::Unbounded => None,
+            };
+            let first = first.map_or(Bound::Unbounded, |(master_id, _)| {
+                Bound::Included(*master_id)
+            });
+            self.nodes.range((first, end))
+        });
+
+        nodes
+            .into_iter()
+            .flatten()
+            .flat_map(|(master_id, node)| node.items(*master_id))
+            .filter(move |item| (start, end).contains(&item.id))
+    }
+
+    /// The fields and values of an item, which only decodes the node holding it up to the item.
+    pub(super) fn get(&self, id: ItemId) -> Option<ItemData> {
+        let (master_id, node) = self.nodes.range(..=id).next_back()?;
+        find(*master_id, &node.listpack, id)
+    }
+
+    pub(super) fn contains(&self, id: ItemId) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub(super) fn first(&self) -> Option<ItemId> {
+        let (master_id, node) = self.nodes.first_key_value()?;
+        node.items(*master_id).next().map(|item| item.id)
+    }
+
+    pub(super) fn last(&self) -> Option<ItemId> {
+        let (master_id, node) = self.nodes.last_key_value()?;
+        node.items(*master_id).last().map(|item| item.id)
+    }
+
+    /// Flags an item as deleted, returning `false` if there's none with the ID. Nodes are
+    /// removed once all of their items are deleted.
+    pub(super) fn remove(&mut self, id: ItemId) -> bool {
+        let Some((&master_id, node)) = self.nodes.range_mut(..=id).next_back() else {
+            return false;
+        };
+        let entry = node
+            .entries(master_id)
+            .into_iter()
+            .find(|entry| entry.flags & DELETED == 0 && entry.item.id == id);
+        let Some(entry) = entry else {
+            return false;
+        };
+
+        node.delete(&entry);
+        if node.len == 0 {
+            self.nodes.remove(&master_id);
+        }
+        self.len -= 1;
+        true
+    }
+
+    /// Removes the `count` oldest items, returning the ID of the last one removed. Whole nodes
+    /// are dropped, while the items of a node that keeps some are flagged as deleted.
+    pub(super) fn remove_first(&mut self, mut count: usize) -> Option<ItemId> {
+        let mut last = None;
+        while count > 0 {
+            let Some(mut node) = self.nodes.first_entry() else {
+                break;
+            };
+            let master_id = *node.key();
+
+            if node.get().len <= count {
+                let node = node.remove();
+                last = node.items(master_id).last().map(|item| item.id);
+                count -= node.len;
+                self.len -= node.len;
+                continue;
+            }
+
+            let node = node.get_mut();
+            let entries = node.entries(master_id).into_iter();
+            for entry in entries
+                .filter(|entry| entry.flags & DELETED == 0)
+                .take(count)
+            {
+                node.delete(&entry);
+                last = Some(entry.item.id);
+            }
+            self.len -= count;
+            count = 0;
+        }
+        last
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn data(fields: &[(&str, &str)]) -> ItemData {
+        fields
+            .iter()
+            .map(|(field, value)| (field.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn items_span_nodes() {
+        let mut sut = Items::default();
+        for i in 0..250 {
+            let value = i.to_string();
+            sut.push(ItemId(1000 + i / 3, i % 3), &data(&[("n", &value)]));
+        }
+        sut.push(ItemId(2000, 0), &data(&[("other", "x"), ("n", "007")]));
+
+        assert_eq!(sut.len(), 251);
+        assert_eq!(sut.nodes.len(), 3);
+        assert_eq!(sut.get(ItemId(1033, 1)), Some(data(&[("n", "100")])));
+        assert_eq!(
+            sut.get(ItemId(2000, 0)),
+            Some(data(&[("other", "x"), ("n", "007")]))
+        );
+        assert_eq!(sut.get(ItemId(1033, 3)), None);
+
+        let ids: Vec<_> = sut
+            .range(
+                Bound::Excluded(ItemId(1032, 2)),
+                Bound::Included(ItemId(1033, 2)),
+            )
+            .map(|item| item.id)
+            .collect();
+        assert_eq!(ids, [ItemId(1033, 0), ItemId(1033, 1), ItemId(1033, 2)]);
+        let last = sut.range(Bound::Unbounded, Bound::Unbounded).next_back();
+        assert_eq!(last.map(|item| item.id), Some(ItemId(2000, 0)));
+    }
+
+    #[test]
+    fn nodes_have_the_redis_layout() {
+        let mut sut = Items::default();
+        sut.push(ItemId(5, 1), &data(&[("a", "1"), ("b", "x")]));
+        sut.push(ItemId(7, 0), &data(&[("a", "2"), ("b", "y")]));
+        sut.push(ItemId(7, 1), &data(&[("c", "z")]));
+        assert!(sut.remove(ItemId(7, 0)));
+
+        let mut expected = Writer::new();
+        // Master entry: 2 items, 1 deleted, the fields of the first item and a 0
+        for element in ["2", "1", "2", "a", "b", "0"] {
+            expected.push(element.as_bytes());
+        }
+        // Items: flags, ID differences, values or fields with values, number of elements
+        for element in ["2", "0", "0", "1", "x", "5"] {
+            expected.push(element.as_bytes());
+        }
+        for element in ["3", "2", "-1", "2", "y", "5"] {
+            expected.push(element.as_bytes());
+        }
+        for element in ["0", "2", "0", "1", "c", "z", "6"] {
+            expected.push(element.as_bytes());
+        }
+        let nodes: Vec<_> = sut.nodes().collect();
+        assert_eq!(nodes, [(ItemId(5, 1), expected.finish().as_slice())]);
+
+        let restored = Items::from_nodes([(ItemId(5, 1), nodes[0].1.to_vec())]);
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored.get(ItemId(7, 1)), Some(data(&[("c", "z")])));
+        assert_eq!(
+            restored.get(ItemId(5, 1)),
+            Some(data(&[("a", "1"), ("b", "x")]))
+        );
+        assert_eq!(restored.get(ItemId(7, 0)), None);
+        assert!(!is_valid_node(ItemId(5, 1), &nodes[0].1[..20]));
+    }
+
+    #[test]
+    fn removing_items() {
+        let mut sut = Items::default();
+        for i in 0..150 {
+            sut.push(ItemId(i + 1, 0), &data(&[("n", "1")]));
+        }
+
+        assert!(sut.remove(ItemId(1, 0)));
+        assert!(!sut.remove(ItemId(1, 0)));
+        assert_eq!(sut.first(), Some(ItemId(2, 0)));
+        assert!(sut.contains(ItemId(50, 0)));
+        assert!(!sut.contains(ItemId(1, 0)));
+
+        // Removes the rest of the first node and part of the second one
+        assert_eq!(sut.remove_first(120), Some(ItemId(121, 0)));
+        assert_eq!(sut.len(), 29);
+        assert_eq!(sut.nodes.len(), 1);
+        assert_eq!(sut.first(), Some(ItemId(122, 0)));
+        assert_eq!(sut.last(), Some(ItemId(150, 0)));
+
+        assert_eq!(sut.remove_first(100), Some(ItemId(150, 0)));
+        assert!(sut.is_empty());
+        assert!(sut.nodes.is_empty());
+    }
+}